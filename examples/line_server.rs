@@ -5,8 +5,8 @@ use std::io::{self, Read, Write};
 use server_fx::bind_transport::BindTransport;
 use server_fx::framed::Framed;
 use server_fx::codec::{Decode, Encode};
-use server_fx::server::TcpServer;
-use server_fx::pollable::{IntoPollable, Pollable, PollableResult};
+use server_fx::server::{ServerConfig, TcpServer};
+use server_fx::pollable::{IntoPollable, PollableResult};
 use server_fx::handler::Handler;
 
 struct LineCodec;
@@ -19,7 +19,7 @@ impl Decode for LineCodec {
             .position(|v| *v == b'\r' || *v == b'\n')
         {
             let v = buffer.drain(..pos).collect::<Vec<_>>();
-            while buffer.get(0).take()
+            while buffer.first()
                 .map(|&b| b == b'\n')
                 .unwrap_or(false)
             {
@@ -34,8 +34,9 @@ impl Decode for LineCodec {
 impl Encode for LineCodec {
     type Item = Vec<u8>;
 
-    fn encode(&self, item: Self::Item, buffer: &mut Vec<u8>) {
+    fn encode(&self, item: Self::Item, buffer: &mut Vec<u8>) -> io::Result<()> {
         buffer.extend(&item);
+        Ok(())
     }
 }
 
@@ -52,6 +53,13 @@ impl<Io> BindTransport<Io> for LineProto where
     fn bind_transport(&self, io: Io) -> Self::Result {
         Ok(Framed::new(io, LineCodec))
     }
+
+    fn bind_transport_with(&self, io: Io, config: &ServerConfig) -> Self::Result {
+        Ok(Framed::with_capacity(io,
+                                 LineCodec,
+                                 config.read_buffer_size,
+                                 config.write_buffer_size))
+    }
 }
 
 struct Server;
@@ -1,10 +1,13 @@
 extern crate pulldown_cmark;
 
-use std::path::PathBuf;
-use std::io::Read;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use server_fx::blocking::spawn_blocking;
+use server_fx::http::response::{Deferred, IntoRouteResponse, RouteResponse};
 use server_fx::http::router::{Parameters, RouteHandler};
-use server_fx::http::types::{Request, Response, ResponseBuilder};
+use server_fx::http::types::{Request, ResponseBuilder, StatusCode};
+use server_fx::pollable::{IntoPollable, Pollable};
 
 use self::pulldown_cmark::{html, Parser};
 
@@ -20,42 +23,36 @@ impl ContentRouteHandler {
     }
 }
 
-fn get_param_value<'a>(name: &'a str, params: &'a Parameters) -> Option<&'a str> {
-    params.iter().position(|n| n.0 == name)
-        .map(|n| &*params[n].1)
+/// Reads the markdown at `path`, and renders it as HTML.
+fn render(path: &Path) -> Result<String, StatusCode> {
+    let markdown = fs::read_to_string(path)
+        .map_err(|_| StatusCode::NotFound)?;
+
+    let mut html_buf = String::new();
+    html::push_html(&mut html_buf, Parser::new(&markdown));
+    Ok(html_buf)
 }
 
 impl RouteHandler for ContentRouteHandler {
+    type Response = RouteResponse;
 
-    fn handle(&self, _: Request, params: &Parameters) -> Response {
-        let path = match get_param_value("page", params) {
-            Some(v) => self.base_path.join(format!("{}.md", v)),
-            None => {
-                return ResponseBuilder::new(404, "Not found")
-                    .build();
-            }
+    fn handle(&self, _: Request, params: &Parameters) -> RouteResponse {
+        let page = match params.get::<String>("page") {
+            Ok(page) if !page.starts_with('.') && !page.contains(['/', '\\']) => page,
+            _ => return StatusCode::NotFound.into_route_response(),
         };
 
-        if !path.exists() {
-                return ResponseBuilder::new(404, "Not found")
-                    .build();
-        }
-
-        let mut html_buf = String::new();
-        let mut data_buf = vec![];
-        ::std::fs::File::open(path)
-            .unwrap()
-            .read_to_end(&mut data_buf)
-            .unwrap();
-
-        let parser = Parser::new(::std::str::from_utf8(&data_buf).unwrap());
-        html::push_html(&mut html_buf, parser);
-
-        let mut resp = ResponseBuilder::new(200, "OK")
-            .build_with_stream(html_buf.into_bytes());
+        let path = self.base_path.join(format!("{}.md", page));
 
-        resp.add_header("Content-Type", "text/html");
+        // Reading the page blocks, so it's done off the worker's
+        // thread.
+        let page = spawn_blocking(move || render(&path))
+            .map_err(|_| StatusCode::InternalServerError)
+            .and_then(|html| html.map(|html| ResponseBuilder::new(StatusCode::Ok)
+                .header("Content-Type", "text/html")
+                .build_with_stream(html.into_bytes()))
+                .into_pollable());
 
-        resp
+        Deferred(page).into_route_response()
     }
 }
@@ -1,11 +1,12 @@
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::ffi::OsStr;
 
-use server_fx::handler::Handler;
+use server_fx::blocking::spawn_blocking;
+use server_fx::http::body::FileBody;
+use server_fx::http::response::{Deferred, IntoRouteResponse, RouteResponse};
 use server_fx::http::types;
+use server_fx::http::router::{Parameters, RouteHandler};
 use server_fx::pollable::{IntoPollable, Pollable};
-use server_fx::http::router::{HandleRouteResult, Parameters, Router, RouteHandler};
 
 pub(crate) struct SimpleHtmlRouteHandler {
     base_path: PathBuf,
@@ -20,7 +21,7 @@ impl SimpleHtmlRouteHandler {
 }
 
 fn mime_type_for_extension(ext: Option<&OsStr>) -> Option<&'static str> {
-    static MIME_MAP: &'static [(&'static str, &'static str)] = &[
+    static MIME_MAP: &[(&str, &str)] = &[
         ("html", "text/html"),
         ("css", "text/css"),
         ("js", "text/javascript"),
@@ -31,61 +32,38 @@ fn mime_type_for_extension(ext: Option<&OsStr>) -> Option<&'static str> {
 }
 
 impl RouteHandler for SimpleHtmlRouteHandler {
+    type Response = RouteResponse;
+
     fn handle(&self, 
-              request: types::Request, 
-              _params: &Parameters) 
-        -> types::Response 
+              _request: types::Request, 
+              params: &Parameters) 
+        -> RouteResponse 
     {
-        let abs_path = self.base_path.join(&request.path()[1..]);
-        let mime = mime_type_for_extension(abs_path.extension());
-
-        if !abs_path.exists() || mime.is_none() {
-            let mut response = types::ResponseBuilder::new(404, "Not found")
-                .build();
-
-            response.add_header("Connection", "close");
-            return response;
+        let filepath = params.get::<String>("filepath").unwrap_or_default();
+        // Only plain names are joined on to the base path. A root, or
+        // drive prefix, would replace it, and `..` would climb out of it.
+        if !Path::new(&filepath).components().all(|c| matches!(c, Component::Normal(_))) {
+            return types::StatusCode::NotFound.into_route_response();
         }
 
-        let mut buf = vec![];
-        ::std::fs::File::open(&abs_path)
-            .expect(&format!("Cannot find '{}'", abs_path.to_str().unwrap()))
-            .read_to_end(&mut buf)
-            .unwrap();
-
-        let mut response = types::ResponseBuilder::new(200, "OK")
-            .build_with_stream(buf);
-        response.add_header("Content-Type", mime.unwrap());
-
-        response
-    }
-}
-
-pub(super) struct HttpServer(pub(super) Router);
-
-impl Handler for HttpServer {
-    type Request = types::Request;
-    type Response = (types::Response, types::BodyChunk);
-    type Error = io::Error;
-    type Pollable = Box<Pollable<Item=Self::Response, Error=io::Error>>;
-
-    fn handle(&self, request: Self::Request) -> Self::Pollable {
-
-        let resp = match self.0.route(request) {
-            HandleRouteResult::NotHandled(_) => {
-                let mut response = types::ResponseBuilder::new(404, "Not Found")
-                    .build();
-
-                response.add_header("Connection", "close");
-                response
-            },
-            HandleRouteResult::Handled(r) => r,
+        let abs_path = self.base_path.join(&filepath);
+        let mime = match mime_type_for_extension(abs_path.extension()) {
+            Some(mime) => mime,
+            None => return types::StatusCode::NotFound.into_route_response(),
         };
 
-        Box::new(
-            resp.into_pollable()
-                .map_err(|_| io::Error::from(io::ErrorKind::Other))
-        )
+        // Opening the file blocks, so it's done off the worker's
+        // thread. `FileBody` reads it on the same pool, a chunk at a time.
+        let file = spawn_blocking(move || match abs_path.is_file() {
+                true => FileBody::open(&abs_path).map_err(|_| types::StatusCode::NotFound),
+                false => Err(types::StatusCode::NotFound),
+            })
+            .map_err(|_| types::StatusCode::InternalServerError)
+            .and_then(move |file| file.map(|file| types::ResponseBuilder::new(types::StatusCode::Ok)
+                .header("Content-Type", mime)
+                .build_with_body(file))
+                .into_pollable());
+
+        Deferred(file).into_route_response()
     }
 }
-
@@ -4,32 +4,35 @@ mod handler;
 mod proto;
 mod content_handler;
 
-use server_fx::http::types;
-use server_fx::server::TcpServer;
-use server_fx::http::router::{
-    Route, 
-    Router, 
-};
+use server_fx::server::{Overload, TcpServer};
+use server_fx::http::logger::Logger;
+use server_fx::http::router::Router;
+use server_fx::http::types::{ResponseBuilder, StatusCode};
 
-use handler::{HttpServer, SimpleHtmlRouteHandler};
+use handler::SimpleHtmlRouteHandler;
 use proto::HttpProto;
 use content_handler::ContentRouteHandler;
 
 fn main() {
-    let routes = vec![
-        Route::new(
-            types::HttpMethod::Get, 
-            "/static/*", 
-            SimpleHtmlRouteHandler::new("./examples/simple_http"),
-        ),
-        Route::new(
-            types::HttpMethod::Get,
-            "/content/:page",
-            ContentRouteHandler::new("./examples/simple_http/markdown"),
-        ),
-    ];
+    let router = Router::builder()
+        .middleware(Logger::new())
+        .get("/static/*filepath",
+             SimpleHtmlRouteHandler::new("./examples/simple_http/static"))
+        .get("/content/:page",
+             ContentRouteHandler::new("./examples/simple_http/markdown"))
+        .error_page(StatusCode::NotFound, |_| ResponseBuilder::new(StatusCode::Ok)
+            .header("Content-Type", "text/html")
+            .build_with_content("<h1>Page not found</h1>"))
+        .build();
 
-    TcpServer::new(HttpProto)
-        .serve("127.0.0.1:5050", move || HttpServer(Router::new(routes)))
+    TcpServer::builder(HttpProto)
+        .threads(4)
+        .max_connections(1024)
+        .on_overload(Overload::Respond(
+            b"HTTP/1.1 503 Service Unavailable\r\n\
+              Content-Length: 0\r\n\
+              Connection: close\r\n\r\n".to_vec()))
+        .build()
+        .serve("127.0.0.1:5050", move || router)
         .unwrap();
 }
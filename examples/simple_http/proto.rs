@@ -1,51 +1,40 @@
 use std::io;
 
-use server_fx::codec::{Decode, Encode};
+use server_fx::http::codec::HttpCodec;
 use server_fx::http::types;
+use server_fx::http::transport::HttpTransport;
 use server_fx::bind_transport::BindTransport;
+use server_fx::connected::{Connected, ConnectionState};
 use server_fx::framed::Framed;
-
-pub(crate) struct HttpCodec;
-
-impl Decode for HttpCodec {
-    type Item = types::Request;
-
-    fn decode(&self, buffer: &mut Vec<u8>) -> Option<Self::Item> {
-        types::parse_request(buffer)
-    }
-}
-
-impl Encode for HttpCodec {
-    type Item = (types::Response, types::BodyChunk);
-
-    fn encode(&self, response: Self::Item, buffer: &mut Vec<u8>) {
-        let mut s = format!("{} {} {}\r\n",
-                        response.0.version(),
-                        response.0.status_code(),
-                        response.0.status_text());
-        for (n, v) in response.0.headers() {
-            s.push_str(format!("{}: {}\r\n", n, v).as_ref());
-        }
-        s.push_str(format!("Content-Length: {}\r\n", response.1.len()).as_ref());
-        s.push_str(format!("\r\n").as_ref());
-
-        buffer.extend(s.as_bytes());
-        buffer.extend(response.1);
-    }
-}
+use server_fx::server::ServerConfig;
 
 pub(crate) struct HttpProto;
 
 impl<Io> BindTransport<Io> for HttpProto where
-    Io: io::Read + io::Write + 'static
+    Io: io::Read + io::Write + Connected + 'static
 {
     type Request = types::Request;
-    type Response = (types::Response, types::BodyChunk);
-    type Transport = Framed<Io, HttpCodec>;
+    type Response = types::Response;
+    type Transport = HttpTransport<Framed<Io, HttpCodec>>;
     type Result = Result<Self::Transport, io::Error>;
 
     fn bind_transport(&self, io: Io) -> Self::Result {
-        Ok(Framed::new(io, HttpCodec))
+        self.bind_transport_with(io, &ServerConfig::default())
     }
-}
 
+    fn bind_transport_with(&self, io: Io, config: &ServerConfig) -> Self::Result {
+        let codec = HttpCodec::new().server("server-fx");
+        let info = io.connection_info()?;
+        let transport = HttpTransport::new(Framed::with_capacity(io,
+                                                                 codec,
+                                                                 config.read_buffer_size,
+                                                                 config.write_buffer_size))
+            .extension(info.clone())
+            .connection_state(ConnectionState::new(info));
+
+        Ok(match config.max_requests_per_connection {
+            Some(max) => transport.max_requests(max),
+            None => transport,
+        })
+    }
+}
@@ -0,0 +1,231 @@
+//! Pinning a server's workers to CPUs.
+//!
+//! Each worker polls its connections in a loop, so one that the system
+//! moves between cores leaves its connections' state in the caches of
+//! the cores it's left. On large machines, pinning each worker to a
+//! core, or to the cores of a NUMA node, keeps that state close. See
+//! `ServerBuilder::affinity`.
+
+use std::io;
+use std::mem;
+use std::os::unix::thread::JoinHandleExt;
+use std::thread::JoinHandle;
+
+use libc;
+
+/// The CPUs that each of a server's workers runs on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Affinity {
+    /// Each worker runs on a core of its own, taken in turn from those
+    /// the process is allowed to run on. Workers share cores once
+    /// there are more of them than cores.
+    PerCore,
+    /// Each worker runs on any of the CPUs in a set, taken in turn.
+    /// E.g. a set for each NUMA node spreads the workers across the
+    /// nodes, leaving the system to move each one between the cores of
+    /// its node.
+    Sets(Vec<Vec<usize>>),
+}
+
+impl Affinity {
+    /// The CPUs that the `index`th worker runs on.
+    fn cpus(&self, index: usize) -> io::Result<Vec<usize>> {
+        match *self {
+            Affinity::PerCore => {
+                let allowed = allowed()?;
+                Ok(vec![allowed[index % allowed.len()]])
+            },
+            Affinity::Sets(ref sets) if !sets.is_empty() && sets.iter().all(|s| !s.is_empty()) =>
+                Ok(sets[index % sets.len()].clone()),
+            Affinity::Sets(_) => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                    "Every worker needs at least one CPU")),
+        }
+    }
+}
+
+/// Pins `thread`, the `index`th worker, to the CPUs `affinity` gives
+/// it.
+pub(crate) fn pin<T>(thread: &JoinHandle<T>, affinity: &Affinity, index: usize)
+    -> io::Result<()>
+{
+    let mut set = empty();
+    for cpu in affinity.cpus(index)? {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("There's no CPU {}", cpu)));
+        }
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+
+    let size = mem::size_of::<libc::cpu_set_t>();
+    match unsafe { libc::pthread_setaffinity_np(thread.as_pthread_t(), size, &set) } {
+        0 => Ok(()),
+        e => Err(io::Error::from_raw_os_error(e)),
+    }
+}
+
+fn empty() -> libc::cpu_set_t {
+    unsafe {
+        let mut set = mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        set
+    }
+}
+
+/// The CPUs that the calling thread is allowed to run on.
+fn allowed() -> io::Result<Vec<usize>> {
+    let mut set = empty();
+    if unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((0..libc::CPU_SETSIZE as usize)
+       .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+       .collect())
+}
+
+#[cfg(test)]
+mod affinity_should {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use server::TcpServer;
+
+    struct Proto;
+
+    impl ::bind_transport::BindTransport<net::TcpStream> for Proto {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Transport = ::framed::Framed<net::TcpStream, Codec>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+            Ok(::framed::Framed::new(s, Codec))
+        }
+    }
+
+    struct Codec;
+
+    impl ::codec::Decode for Codec {
+        type Item = Vec<u8>;
+
+        fn decode(&self, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+            Some(mem::take(buffer))
+        }
+    }
+
+    impl ::codec::Encode for Codec {
+        type Item = Vec<u8>;
+
+        fn encode(&self, item: Vec<u8>, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item);
+            Ok(())
+        }
+    }
+
+    /// Responds with the CPUs the worker is allowed to run on.
+    struct Cpus;
+
+    impl ::handler::Handler for Cpus {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = io::Result<Vec<u8>>;
+
+        fn handle(&self, _: Vec<u8>) -> Self::Pollable {
+            Ok(format!("{:?}\n", allowed()?).into_bytes())
+        }
+    }
+
+    #[test]
+    fn pin_threads_to_the_cpus_in_their_set() {
+        let cpu = allowed().unwrap()[0];
+        let (sender, receiver) = channel::<()>();
+        let thread = thread::spawn(move || {
+            receiver.recv().unwrap();
+            allowed().unwrap()
+        });
+
+        pin(&thread, &Affinity::Sets(vec![vec![cpu]]), 0).unwrap();
+        sender.send(()).unwrap();
+        assert_eq!(vec![cpu], thread.join().unwrap());
+    }
+
+    #[test]
+    fn run_workers_on_their_own_core() {
+        let cpu = allowed().unwrap()[0];
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .affinity(Affinity::PerCore)
+            .build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let running = thread::spawn(move || server.run(|| Cpus));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.write_all(b"Where?").unwrap();
+        let mut cpus = vec![];
+        while !cpus.ends_with(b"\n") {
+            let mut byte = [0; 1];
+            client.read_exact(&mut byte).unwrap();
+            cpus.push(byte[0]);
+        }
+        assert_eq!(format!("[{}]\n", cpu).into_bytes(), cpus);
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn keep_the_workers_it_started_when_resizing_fails() {
+        let cpu = allowed().unwrap()[0];
+        let sets = vec![vec![cpu], vec![cpu], vec![libc::CPU_SETSIZE as usize]];
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .affinity(Affinity::Sets(sets))
+            .build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let running = thread::spawn(move || server.run(|| Cpus));
+
+        // The third worker can't be pinned.
+        handle.resize(3);
+        for _ in 0..500 {
+            if handle.workers().len() == 2 {
+                break;
+            }
+            thread::sleep(::std::time::Duration::from_millis(10));
+        }
+        assert_eq!(2, handle.workers().len());
+
+        for _ in 0..4 {
+            let mut client = net::TcpStream::connect(addr).unwrap();
+            client.write_all(b"Where?").unwrap();
+            let mut cpus = [0; 1];
+            client.read_exact(&mut cpus).unwrap();
+            assert_eq!(b"[", &cpus);
+        }
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn refuse_cpus_that_dont_exist() {
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .affinity(Affinity::Sets(vec![vec![libc::CPU_SETSIZE as usize]]))
+            .build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+
+        let e = server.run(|| Cpus).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+    }
+}
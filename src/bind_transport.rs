@@ -1,5 +1,6 @@
 use std::io;
 use pollable::{IntoPollable, Pollable};
+use server::ServerConfig;
 use sink::Sink;
 
 pub trait BindTransport<S> where
@@ -7,8 +8,17 @@ pub trait BindTransport<S> where
 {
     type Request;
     type Response;
-    type Transport: Pollable<Item=Self::Request> + Sink<Item=Self::Response> + 'static;
+    /// The transport yields `None` once the peer has no more requests
+    /// to send, at which point the connection is closed.
+    type Transport: Pollable<Item=Option<Self::Request>> + Sink<Item=Self::Response> + 'static;
     type Result: IntoPollable<Item=Self::Transport>;
 
     fn bind_transport(&self, s: S) -> Self::Result;
+
+    /// Binds `s` for a server running with `config`. Transports built
+    /// on a `Framed` should override this to pass on the buffer sizes,
+    /// with `Framed::with_capacity`. By default, `config` is ignored.
+    fn bind_transport_with(&self, s: S, _: &ServerConfig) -> Self::Result {
+        self.bind_transport(s)
+    }
 }
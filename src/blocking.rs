@@ -0,0 +1,266 @@
+//! Running blocking work off a server's workers.
+//!
+//! A worker serves all of its connections from one thread, so a handler
+//! that blocks, e.g. reading a file or querying a database, stalls
+//! every other connection on it. [`spawn_blocking`] runs a closure on a
+//! separate pool of threads instead, and returns a pollable that
+//! resolves to what the closure returns. Handlers return it, or chain
+//! their response on to it, and the worker carries on polling its
+//! other connections in the meantime. The connection that's waiting
+//! is woken once the closure returns.
+//!
+//! [`spawn_blocking`]: fn.spawn_blocking.html
+
+use std::io;
+use std::panic::{self, catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use pollable::Pollable;
+use readiness::Wakeup;
+use result::PollResult;
+
+/// The most threads the pool that `spawn_blocking` uses starts.
+const DEFAULT_THREADS: usize = 64;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Shared {
+    jobs: Mutex<Receiver<Job>>,
+    threads: AtomicUsize,
+    /// Threads waiting for a job that no job has been sent for yet.
+    idle: AtomicUsize,
+    /// Jobs sent while every thread was busy.
+    queued: AtomicUsize,
+    max_threads: usize,
+}
+
+/// Threads that run blocking closures. Threads are started as they're
+/// needed, up to a maximum, and kept until the pool is dropped.
+/// Closures spawned while every thread is busy wait for one to finish.
+#[derive(Clone)]
+pub struct BlockingPool {
+    jobs: Sender<Job>,
+    shared: Arc<Shared>,
+}
+
+impl BlockingPool {
+    /// Creates a pool of up to `max_threads` threads.
+    ///
+    /// # Panics
+    ///
+    /// If `max_threads` is 0.
+    pub fn new(max_threads: usize) -> BlockingPool {
+        assert!(max_threads > 0, "A blocking pool needs at least one thread");
+        let (jobs, receiver) = channel();
+        BlockingPool {
+            jobs,
+            shared: Arc::new(Shared {
+                jobs: Mutex::new(receiver),
+                threads: AtomicUsize::new(0),
+                idle: AtomicUsize::new(0),
+                queued: AtomicUsize::new(0),
+                max_threads,
+            }),
+        }
+    }
+
+    /// Runs `f` on one of the pool's threads. The pollable returned
+    /// resolves to what `f` returns, or panics if `f` does. It fails if
+    /// the pool has no threads, and can't start one.
+    pub fn spawn<F, T>(&self, f: F) -> Blocking<T> where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static
+    {
+        let (sender, result) = channel();
+        let wakeup = Wakeup::new();
+        let done = wakeup.clone();
+        let job = Box::new(move || {
+            let _ = sender.send(catch_unwind(AssertUnwindSafe(f)));
+            done.wake();
+        });
+
+        // Dropping the job, if no thread can run it, fails the
+        // pollable.
+        if take(&self.shared.idle) || self.start_thread() || self.queue() {
+            let _ = self.jobs.send(job);
+        }
+
+        Blocking {
+            result,
+            wakeup,
+        }
+    }
+
+    /// The number of threads the pool has started.
+    pub fn threads(&self) -> usize {
+        self.shared.threads.load(Ordering::SeqCst)
+    }
+
+    /// Starts a thread for a job, if the pool can have more.
+    fn start_thread(&self) -> bool {
+        let shared = &self.shared;
+        let below_max = shared.threads.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            match n < shared.max_threads {
+                true => Some(n + 1),
+                false => None,
+            }
+        });
+
+        if below_max.is_err() {
+            return false;
+        }
+
+        let worker = shared.clone();
+        let spawned = thread::Builder::new()
+            .name(String::from("server-fx blocking"))
+            .spawn(move || work(&worker));
+        if spawned.is_err() {
+            shared.threads.fetch_sub(1, Ordering::SeqCst);
+        }
+        spawned.is_ok()
+    }
+
+    /// Counts a job that waits for the next thread to finish, if there
+    /// are any threads.
+    fn queue(&self) -> bool {
+        let threads = self.threads() > 0;
+        if threads {
+            self.shared.queued.fetch_add(1, Ordering::SeqCst);
+        }
+        threads
+    }
+}
+
+/// Decrements `counter`, returning `false` if it's already 0.
+fn take(counter: &AtomicUsize) -> bool {
+    counter.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok()
+}
+
+/// Runs jobs until the pool is dropped. A thread that finishes a job
+/// takes a queued one, if there are any, before it's counted as idle.
+fn work(shared: &Shared) {
+    loop {
+        let job = match shared.jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        job();
+        if !take(&shared.queued) {
+            shared.idle.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Runs `f` on a pool of threads shared by the whole process, that
+/// starts up to 64 threads. See [`BlockingPool::spawn`].
+///
+/// [`BlockingPool::spawn`]: struct.BlockingPool.html#method.spawn
+pub fn spawn_blocking<F, T>(f: F) -> Blocking<T> where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static
+{
+    static POOL: OnceLock<BlockingPool> = OnceLock::new();
+    POOL.get_or_init(|| BlockingPool::new(DEFAULT_THREADS)).spawn(f)
+}
+
+/// The result of a closure run by a [`BlockingPool`].
+///
+/// [`BlockingPool`]: struct.BlockingPool.html
+pub struct Blocking<T> {
+    result: Receiver<thread::Result<T>>,
+    wakeup: Wakeup,
+}
+
+impl<T> Pollable for Blocking<T> {
+    type Item = T;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<T>, io::Error> {
+        self.wakeup.wait();
+        match self.result.try_recv() {
+            Ok(Ok(item)) => Ok(PollResult::Ready(item)),
+            Ok(Err(panic)) => panic::resume_unwind(panic),
+            Err(TryRecvError::Empty) => Ok(PollResult::NotReady),
+            Err(TryRecvError::Disconnected) =>
+                Err(io::Error::other("The blocking pool couldn't run the closure")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod blocking_pool_should {
+    use super::*;
+    use std::time::Duration;
+
+    fn wait<T>(mut blocking: Blocking<T>) -> T {
+        for _ in 0..500 {
+            if let PollResult::Ready(item) = blocking.poll().unwrap() {
+                return item;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("The closure didn't finish");
+    }
+
+    #[test]
+    fn run_closures_on_another_thread() {
+        let caller = thread::current().id();
+        let ran_on = wait(spawn_blocking(|| thread::current().id()));
+        assert_ne!(caller, ran_on);
+    }
+
+    #[test]
+    fn not_be_ready_until_the_closure_returns() {
+        let pool = BlockingPool::new(1);
+        let (sender, receiver) = channel::<()>();
+        let mut blocking = pool.spawn(move || receiver.recv().map(|_| "Done"));
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(matches!(blocking.poll().unwrap(), PollResult::NotReady));
+        sender.send(()).unwrap();
+        assert_eq!(Ok("Done"), wait(blocking));
+    }
+
+    #[test]
+    fn start_no_more_threads_than_its_maximum() {
+        let pool = BlockingPool::new(2);
+        let (sender, receiver) = channel::<()>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let waiting: Vec<_> = (0..3).map(|n| {
+            let receiver = receiver.clone();
+            pool.spawn(move || {
+                receiver.lock().unwrap().recv().unwrap();
+                n
+            })
+        }).collect();
+
+        assert_eq!(2, pool.threads());
+        for _ in 0..3 {
+            sender.send(()).unwrap();
+        }
+        let mut done: Vec<_> = waiting.into_iter().map(wait).collect();
+        done.sort();
+        assert_eq!(vec![0, 1, 2], done);
+        assert_eq!(2, pool.threads());
+    }
+
+    #[test]
+    #[should_panic(expected = "Broken")]
+    fn panic_when_the_closure_did() {
+        let pool = BlockingPool::new(1);
+        wait(pool.spawn::<_, ()>(|| panic!("Broken")));
+    }
+
+    #[test]
+    fn keep_its_threads_when_closures_panic() {
+        let pool = BlockingPool::new(1);
+        let panicked = pool.spawn::<_, ()>(|| panic!("Broken"));
+        let _ = catch_unwind(AssertUnwindSafe(|| wait(panicked)));
+        assert_eq!(4, wait(pool.spawn(|| 2 + 2)));
+        assert_eq!(1, pool.threads());
+    }
+}
@@ -0,0 +1,72 @@
+//! Sharing a worker fairly between its connections.
+//!
+//! A pollable that loops for as long as it can make progress, such as a
+//! `Framed` reading from a fast sender, could keep its worker from
+//! every other connection for as long as the data keeps coming. So
+//! each time a worker polls a connection, it gives the poll a budget of
+//! units of work, e.g. reads or writes. Pollables that loop [`spend`] a
+//! unit on each iteration, and return `NotReady` once the budget has
+//! run out. The worker polls the connection again on its next turn,
+//! once every other connection has had its turn.
+//!
+//! Pollables that aren't polled by a worker have no budget, and can
+//! loop as long as they like.
+//!
+//! [`spend`]: fn.spend.html
+
+use std::cell::Cell;
+
+/// The units of work each poll of a connection is given.
+pub const BUDGET: usize = 128;
+
+thread_local! {
+    /// The units of work left in the current poll's budget, if it has
+    /// one.
+    static REMAINING: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Spends a unit of the current poll's budget, returning `false` if
+/// there's none left. A pollable that gets `false` should return
+/// `NotReady` without doing the work, rather than looping again.
+pub fn spend() -> bool {
+    REMAINING.with(|remaining| match remaining.get() {
+        Some(0) => false,
+        Some(n) => {
+            remaining.set(Some(n - 1));
+            true
+        },
+        None => true,
+    })
+}
+
+/// Calls `f` with a budget of `units`, returning what it returns, and
+/// whether it ran out of budget.
+pub fn with_budget<F, T>(units: usize, f: F) -> (T, bool) where
+    F: FnOnce() -> T
+{
+    let outer = REMAINING.with(|remaining| remaining.replace(Some(units)));
+    let result = f();
+    let spent = REMAINING.with(|remaining| remaining.replace(outer)) == Some(0);
+    (result, spent)
+}
+
+#[cfg(test)]
+mod budget_should {
+    use super::*;
+
+    #[test]
+    fn be_unlimited_outside_a_budgeted_call() {
+        assert!((0..2 * BUDGET).all(|_| spend()));
+    }
+
+    #[test]
+    fn run_out_once_every_unit_is_spent() {
+        let (spent, exhausted) = with_budget(3, || (0..5).filter(|_| spend()).count());
+        assert_eq!(3, spent);
+        assert!(exhausted);
+
+        let ((), exhausted) = with_budget(3, || assert!(spend()));
+        assert!(!exhausted);
+        assert!(spend());
+    }
+}
@@ -0,0 +1,647 @@
+//! Connecting to servers, e.g. to call another service from a handler,
+//! or to build a proxy.
+//!
+//! A [`TcpClient`] connects without blocking, and binds the stream with
+//! a [`BindClient`] protocol, as a [`TcpServer`] binds the streams it
+//! accepts. The result is a pollable, so it can be returned from a
+//! handler and polled by the worker serving the connection.
+//!
+//! A [`Dialer`] connects in the same way, but leaves the stream
+//! unbound, e.g. for a tunnel that relays bytes rather than requests.
+//!
+//! [`TcpClient`]: struct.TcpClient.html
+//! [`BindClient`]: trait.BindClient.html
+//! [`TcpServer`]: ../server/struct.TcpServer.html
+//! [`Dialer`]: struct.Dialer.html
+
+use std::io;
+use std::mem;
+use std::net::{self, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::vec;
+
+use pollable::{IntoPollable, Pollable};
+use readiness::{self, Watch};
+use resolver::{Endpoint, Resolver, Resolving, ThreadedResolver, ToEndpoint};
+use result::PollResult;
+use sink::{SendOne, Sink};
+
+/// Binds a connected stream to a transport that sends requests and
+/// yields their responses. The client's side of [`BindTransport`].
+///
+/// [`BindTransport`]: ../bind_transport/trait.BindTransport.html
+pub trait BindClient<S> where
+    S: io::Read + io::Write + 'static
+{
+    type Request;
+    type Response;
+    /// The transport yields `None` once the server has no more
+    /// responses to send.
+    type Transport: Sink<Item=Self::Request> + Pollable<Item=Option<Self::Response>> + 'static;
+    type Result: IntoPollable<Item=Self::Transport>;
+
+    fn bind_client(&self, s: S) -> Self::Result;
+}
+
+/// Opens connections to servers, without binding them to a protocol.
+/// Dialers are cheap to clone.
+#[derive(Clone)]
+pub struct Dialer {
+    resolver: Arc<dyn Resolver + Send + Sync>,
+    attempt_delay: Duration,
+}
+
+impl Default for Dialer {
+    fn default() -> Dialer {
+        Dialer {
+            resolver: Arc::new(ThreadedResolver),
+            attempt_delay: Duration::from_millis(250),
+        }
+    }
+}
+
+impl Dialer {
+    pub fn new() -> Dialer {
+        Dialer::default()
+    }
+
+    /// Sets how long an attempt to connect to one address is given
+    /// before another is started alongside it. Defaults to 250ms, as
+    /// RFC 8305 recommends.
+    pub fn attempt_delay(mut self, delay: Duration) -> Dialer {
+        self.attempt_delay = delay;
+        self
+    }
+
+    /// Sets how names are resolved. Defaults to a
+    /// [`ThreadedResolver`].
+    ///
+    /// [`ThreadedResolver`]: ../resolver/struct.ThreadedResolver.html
+    pub fn resolver<R>(mut self, resolver: R) -> Dialer where
+        R: Resolver + Send + Sync + 'static,
+    {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Connects to `addr`, resolving to the stream. Names are looked
+    /// up with the dialer's [`Resolver`].
+    ///
+    /// If `addr` resolves to more than one address, they're raced, as
+    /// in "Happy Eyeballs" (RFC 8305): IPv6 and IPv4 addresses are
+    /// tried alternately, starting with IPv6, and another attempt is
+    /// started whenever one fails or the attempt delay passes without
+    /// one connecting. The first to connect is used.
+    ///
+    /// [`Resolver`]: ../resolver/trait.Resolver.html
+    pub fn dial<A: ToEndpoint>(&self, addr: A) -> Dial {
+        let state = match addr.to_endpoint() {
+            Ok(Endpoint::Addrs(addrs)) =>
+                DialState::Connecting(Attempts::new(interleave(addrs), self.attempt_delay)),
+            Ok(Endpoint::Name(host, port)) =>
+                DialState::Resolving(self.resolver.resolve(&host, port), self.attempt_delay),
+            Err(e) => DialState::Failed(e),
+        };
+
+        Dial { state }
+    }
+}
+
+/// Connects to servers, binding each connection with the same
+/// protocol. Clients are cheap to clone.
+pub struct TcpClient<P> {
+    proto: Arc<P>,
+    dialer: Dialer,
+}
+
+impl<P> Clone for TcpClient<P> {
+    fn clone(&self) -> TcpClient<P> {
+        TcpClient {
+            proto: self.proto.clone(),
+            dialer: self.dialer.clone(),
+        }
+    }
+}
+
+impl<P> TcpClient<P>
+    where P: BindClient<net::TcpStream>,
+          io::Error: From<<P::Result as IntoPollable>::Error>,
+{
+    pub fn new(proto: P) -> TcpClient<P> {
+        TcpClient::builder(proto).build()
+    }
+
+    pub fn builder(proto: P) -> TcpClientBuilder<P> {
+        TcpClientBuilder {
+            proto,
+            dialer: Dialer::new(),
+        }
+    }
+
+    /// Connects to `addr`, resolving to the bound transport. The
+    /// stream is opened as the client's [`Dialer`] would.
+    ///
+    /// [`Dialer`]: struct.Dialer.html
+    pub fn connect<A: ToEndpoint>(&self, addr: A) -> Connect<P> {
+        Connect {
+            state: ConnectState::Dialing(self.proto.clone(), self.dialer.dial(addr)),
+        }
+    }
+
+    /// The dialer the client opens connections with.
+    pub fn dialer(&self) -> &Dialer {
+        &self.dialer
+    }
+
+    /// Connects to `addr`, sends `request`, and resolves to the first
+    /// response. The connection is closed afterwards.
+    pub fn call<A: ToEndpoint>(&self, addr: A, request: P::Request) -> Call<P> {
+        Call {
+            state: CallState::Connecting(self.connect(addr), Some(request)),
+        }
+    }
+}
+
+/// Configures a [`TcpClient`].
+///
+/// [`TcpClient`]: struct.TcpClient.html
+pub struct TcpClientBuilder<P> {
+    proto: P,
+    dialer: Dialer,
+}
+
+impl<P> TcpClientBuilder<P>
+    where P: BindClient<net::TcpStream>,
+          io::Error: From<<P::Result as IntoPollable>::Error>,
+{
+    /// Sets how long an attempt to connect to one address is given
+    /// before another is started alongside it. Defaults to 250ms, as
+    /// RFC 8305 recommends.
+    pub fn attempt_delay(mut self, delay: Duration) -> TcpClientBuilder<P> {
+        self.dialer = self.dialer.attempt_delay(delay);
+        self
+    }
+
+    /// Sets how names are resolved. Defaults to a
+    /// [`ThreadedResolver`].
+    ///
+    /// [`ThreadedResolver`]: ../resolver/struct.ThreadedResolver.html
+    pub fn resolver<R>(mut self, resolver: R) -> TcpClientBuilder<P> where
+        R: Resolver + Send + Sync + 'static,
+    {
+        self.dialer = self.dialer.resolver(resolver);
+        self
+    }
+
+    pub fn build(self) -> TcpClient<P> {
+        TcpClient {
+            proto: Arc::new(self.proto),
+            dialer: self.dialer,
+        }
+    }
+}
+
+/// A stream being connected. See `Dialer::dial`.
+pub struct Dial {
+    state: DialState,
+}
+
+enum DialState {
+    Resolving(Resolving, Duration),
+    Connecting(Attempts),
+    Failed(io::Error),
+    Done,
+}
+
+/// A connection being established. See `TcpClient::connect`.
+pub struct Connect<P> where
+    P: BindClient<net::TcpStream>,
+{
+    state: ConnectState<P>,
+}
+
+enum ConnectState<P> where
+    P: BindClient<net::TcpStream>,
+{
+    Dialing(Arc<P>, Dial),
+    Binding(<P::Result as IntoPollable>::Pollable),
+    Done,
+}
+
+/// Orders `addrs` so that IPv6 and IPv4 addresses alternate, starting
+/// with IPv6. Addresses of each family keep their order.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+
+    let mut interleaved = vec![];
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// The attempts to connect to each of a server's addresses, some of
+/// which may be in progress at once.
+struct Attempts {
+    addrs: vec::IntoIter<SocketAddr>,
+    pending: Vec<(mio::net::TcpStream, Watch)>,
+    delay: Duration,
+    /// When another attempt is started, if none has connected.
+    next: Instant,
+    /// Why the last attempt failed.
+    error: Option<io::Error>,
+}
+
+impl Attempts {
+    fn new(addrs: Vec<SocketAddr>, delay: Duration) -> Attempts {
+        Attempts {
+            addrs: addrs.into_iter(),
+            pending: vec![],
+            delay,
+            next: Instant::now(),
+            error: None,
+        }
+    }
+
+    /// Returns the first stream to connect, if there is one yet.
+    /// Fails once every attempt has. Until then, the connection being
+    /// polled is woken once an attempt connects or fails, or it's time
+    /// for the next.
+    fn poll(&mut self) -> io::Result<Option<mio::net::TcpStream>> {
+        loop {
+            let mut index = 0;
+            while index < self.pending.len() {
+                match connected(&self.pending[index].0) {
+                    Ok(true) => return Ok(Some(self.pending.swap_remove(index).0)),
+                    Ok(false) => index += 1,
+                    Err(e) => {
+                        self.pending.swap_remove(index);
+                        self.error = Some(e);
+                    },
+                }
+            }
+
+            if !self.pending.is_empty() && Instant::now() < self.next {
+                readiness::wake_at(self.next);
+                self.watch();
+                return Ok(None);
+            }
+
+            match self.addrs.next() {
+                Some(addr) => match mio::net::TcpStream::connect(addr) {
+                    Ok(stream) => {
+                        self.pending.push((stream, Watch::new()));
+                        self.next = Instant::now() + self.delay;
+                    },
+                    Err(e) => self.error = Some(e),
+                },
+                None if self.pending.is_empty() => return Err(self.error.take()
+                    .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                                                      "no addresses to connect to"))),
+                None => {
+                    self.watch();
+                    return Ok(None);
+                },
+            }
+        }
+    }
+
+    /// Watches the attempts in progress.
+    fn watch(&mut self) {
+        for &mut (ref stream, ref mut watch) in &mut self.pending {
+            watch.watch(stream);
+        }
+    }
+}
+
+/// Whether a connection that was started without blocking has been
+/// established.
+fn connected(stream: &mio::net::TcpStream) -> io::Result<bool> {
+    if let Some(e) = stream.take_error()? {
+        return Err(e);
+    }
+
+    match stream.peer_addr() {
+        Ok(_) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::NotConnected => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl Pollable for Dial {
+    type Item = net::TcpStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, DialState::Done) {
+                DialState::Resolving(mut resolving, delay) => match resolving.poll()? {
+                    PollResult::Ready(addrs) =>
+                        DialState::Connecting(Attempts::new(interleave(addrs), delay)),
+                    PollResult::NotReady => {
+                        self.state = DialState::Resolving(resolving, delay);
+                        return Ok(PollResult::NotReady);
+                    },
+                },
+                DialState::Connecting(mut attempts) => match attempts.poll()? {
+                    Some(stream) => return Ok(PollResult::Ready(net::TcpStream::from(stream))),
+                    None => {
+                        self.state = DialState::Connecting(attempts);
+                        return Ok(PollResult::NotReady);
+                    },
+                },
+                DialState::Failed(e) => return Err(e),
+                DialState::Done => panic!("Poll called on finished dial"),
+            };
+        }
+    }
+}
+
+impl<P> Pollable for Connect<P> where
+    P: BindClient<net::TcpStream>,
+    io::Error: From<<P::Result as IntoPollable>::Error>,
+{
+    type Item = P::Transport;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, ConnectState::Done) {
+                ConnectState::Dialing(proto, mut dial) => match dial.poll()? {
+                    PollResult::Ready(stream) =>
+                        ConnectState::Binding(proto.bind_client(stream).into_pollable()),
+                    PollResult::NotReady => {
+                        self.state = ConnectState::Dialing(proto, dial);
+                        return Ok(PollResult::NotReady);
+                    },
+                },
+                ConnectState::Binding(mut binding) => match binding.poll()? {
+                    PollResult::Ready(transport) => return Ok(PollResult::Ready(transport)),
+                    PollResult::NotReady => {
+                        self.state = ConnectState::Binding(binding);
+                        return Ok(PollResult::NotReady);
+                    },
+                },
+                ConnectState::Done => panic!("Poll called on finished connect"),
+            };
+        }
+    }
+}
+
+/// Sends one request on a transport, and waits for its response. It
+/// resolves to the response and the transport, so the connection can
+/// be used again.
+pub struct Exchange<T: Sink> {
+    state: ExchangeState<T>,
+}
+
+enum ExchangeState<T: Sink> {
+    Sending(SendOne<T, T::Item>),
+    Receiving(T),
+    Done,
+}
+
+impl<T: Sink> Exchange<T> {
+    pub fn new(transport: T, request: T::Item) -> Exchange<T> {
+        Exchange {
+            state: ExchangeState::Sending(transport.send_one(request)),
+        }
+    }
+}
+
+impl<T, R> Pollable for Exchange<T> where
+    T: Sink + Pollable<Item=Option<R>> + 'static,
+    io::Error: From<<T as Sink>::Error>,
+    io::Error: From<<T as Pollable>::Error>,
+{
+    type Item = (R, T);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, ExchangeState::Done) {
+                ExchangeState::Sending(mut sending) => match sending.poll()? {
+                    PollResult::Ready(()) => ExchangeState::Receiving(sending.into_inner()),
+                    PollResult::NotReady => {
+                        self.state = ExchangeState::Sending(sending);
+                        return Ok(PollResult::NotReady);
+                    },
+                },
+                ExchangeState::Receiving(mut transport) => match transport.poll()? {
+                    PollResult::Ready(Some(response)) =>
+                        return Ok(PollResult::Ready((response, transport))),
+                    PollResult::Ready(None) =>
+                        return Err(io::ErrorKind::UnexpectedEof.into()),
+                    PollResult::NotReady => {
+                        self.state = ExchangeState::Receiving(transport);
+                        return Ok(PollResult::NotReady);
+                    },
+                },
+                ExchangeState::Done => panic!("Poll called on finished exchange"),
+            };
+        }
+    }
+}
+
+/// A request on a connection of its own. See `TcpClient::call`.
+pub struct Call<P> where
+    P: BindClient<net::TcpStream>,
+{
+    state: CallState<P>,
+}
+
+enum CallState<P> where
+    P: BindClient<net::TcpStream>,
+{
+    Connecting(Connect<P>, Option<P::Request>),
+    Exchanging(Exchange<P::Transport>),
+}
+
+impl<P> Pollable for Call<P> where
+    P: BindClient<net::TcpStream>,
+    io::Error: From<<P::Result as IntoPollable>::Error>,
+    io::Error: From<<P::Transport as Sink>::Error>,
+    io::Error: From<<P::Transport as Pollable>::Error>,
+{
+    type Item = P::Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        loop {
+            self.state = match self.state {
+                CallState::Connecting(ref mut connect, ref mut request) => match connect.poll()? {
+                    PollResult::Ready(transport) => CallState::Exchanging(Exchange::new(
+                        transport,
+                        request.take().expect("Poll called on finished call"))),
+                    PollResult::NotReady => return Ok(PollResult::NotReady),
+                },
+                CallState::Exchanging(ref mut exchange) => return match exchange.poll()? {
+                    PollResult::Ready((response, _)) => Ok(PollResult::Ready(response)),
+                    PollResult::NotReady => Ok(PollResult::NotReady),
+                },
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tcp_client_should {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::thread;
+    use std::time::Duration;
+
+    use framed::Framed;
+
+    struct Proto;
+
+    impl BindClient<net::TcpStream> for Proto {
+        type Request = String;
+        type Response = String;
+        type Transport = Framed<net::TcpStream, Lines>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_client(&self, s: net::TcpStream) -> Self::Result {
+            Ok(Framed::new(s, Lines))
+        }
+    }
+
+    struct Lines;
+
+    impl ::codec::Decode for Lines {
+        type Item = String;
+
+        fn decode(&self, buffer: &mut Vec<u8>) -> Option<String> {
+            let end = buffer.iter().position(|&b| b == b'\n')?;
+            let line = buffer.drain(..end + 1).collect::<Vec<_>>();
+            Some(String::from_utf8_lossy(&line[..end]).into_owned())
+        }
+    }
+
+    impl ::codec::Encode for Lines {
+        type Item = String;
+
+        fn encode(&self, item: String, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item.into_bytes());
+            buffer.push(b'\n');
+            Ok(())
+        }
+    }
+
+    fn wait<P: Pollable>(mut pollable: P) -> Result<P::Item, P::Error> {
+        loop {
+            if let PollResult::Ready(item) = pollable.poll()? {
+                return Ok(item);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// A server that responds to each line with its length, until the
+    /// client closes the connection.
+    fn server() -> SocketAddr {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer: Vec<u8> = vec![];
+            let mut chunk = [0; 64];
+            while let Ok(read) = stream.read(&mut chunk) {
+                if read == 0 {
+                    break;
+                }
+                buffer.extend(&chunk[..read]);
+                while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                    buffer.drain(..end + 1);
+                    stream.write_all(format!("{}\n", end).as_bytes()).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn exchange_requests_on_a_connection() {
+        let addr = server();
+        let client = TcpClient::new(Proto);
+
+        let transport = wait(client.connect(addr)).unwrap();
+        let (response, transport) = wait(Exchange::new(transport, "hello".into())).unwrap();
+        assert_eq!("5", response);
+        let (response, _) = wait(Exchange::new(transport, "hi".into())).unwrap();
+        assert_eq!("2", response);
+    }
+
+    #[test]
+    fn call_with_a_request() {
+        let addr = server();
+        let response = wait(TcpClient::new(Proto).call(addr, "hello, world".into())).unwrap();
+        assert_eq!("12", response);
+    }
+
+    #[test]
+    fn try_each_address_in_turn() {
+        let closed = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let addr = server();
+
+        let transport = wait(TcpClient::new(Proto).connect(&[closed, addr][..]));
+        assert!(transport.is_ok());
+    }
+
+    #[test]
+    fn race_addresses_that_are_slow_to_connect() {
+        // A listener with a full backlog ignores new connections, so
+        // they neither connect nor fail.
+        let slow = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        slow.bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into()).unwrap();
+        slow.listen(0).unwrap();
+        let slow = net::TcpListener::from(slow);
+        let _queued = net::TcpStream::connect(slow.local_addr().unwrap()).unwrap();
+        let addr = server();
+
+        let client = TcpClient::builder(Proto).attempt_delay(Duration::from_millis(20)).build();
+        let transport = wait(client.connect(&[slow.local_addr().unwrap(), addr][..])).unwrap();
+        assert_eq!(addr, transport.into_stream().peer_addr().unwrap());
+    }
+
+    /// Resolves every name to the same address.
+    struct Fixed(SocketAddr);
+
+    impl Resolver for Fixed {
+        fn resolve(&self, _: &str, port: u16) -> Resolving {
+            Box::new(Ok(vec![SocketAddr::new(self.0.ip(), port)]).into_pollable())
+        }
+    }
+
+    #[test]
+    fn resolve_names_with_the_resolver() {
+        let addr = server();
+        let client = TcpClient::builder(Proto).resolver(Fixed(addr)).build();
+
+        let response = wait(client.call(("service.internal", addr.port()), "hey".into())).unwrap();
+        assert_eq!("3", response);
+    }
+
+    #[test]
+    fn alternate_address_families() {
+        let addrs = ["10.0.0.1:80", "10.0.0.2:80", "10.0.0.3:80", "[::1]:80", "[::2]:80"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect::<Vec<SocketAddr>>();
+
+        assert_eq!(vec![addrs[3], addrs[0], addrs[4], addrs[1], addrs[2]], interleave(addrs));
+    }
+
+    #[test]
+    fn fail_when_no_address_connects() {
+        let closed = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let error = wait(TcpClient::new(Proto).connect(closed)).err().unwrap();
+        assert_eq!(io::ErrorKind::ConnectionRefused, error.kind());
+    }
+}
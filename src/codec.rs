@@ -1,11 +1,32 @@
+use std::io;
+
 pub trait Decode {
     type Item;
 
     fn decode(&self, buffer: &mut Vec<u8>) -> Option<Self::Item>;
+
+    /// Checks `buffer`, which no item could be decoded from, could
+    /// still hold one once more arrives. A codec that can tell it
+    /// never will, e.g. because it holds a malformed request, returns
+    /// an `InvalidData` error, which fails the stream. By default,
+    /// more data is always waited for.
+    fn validate(&self, _buffer: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether `buffer`, which no item could be decoded from, holds the
+    /// whole head of one. By default, items have no head apart from
+    /// the rest of them.
+    fn head_complete(&self, _buffer: &[u8]) -> bool {
+        false
+    }
 }
 
 pub trait Encode {
     type Item;
 
-    fn encode(&self, item: Self::Item, buffer: &mut Vec<u8>);
+    /// Encodes `item` onto the end of `buffer`. An encoder that
+    /// refuses `item` should return an error without writing anything
+    /// to `buffer`.
+    fn encode(&self, item: Self::Item, buffer: &mut Vec<u8>) -> io::Result<()>;
 }
@@ -0,0 +1,173 @@
+//! Details of the connection a request arrived on, for handlers.
+//!
+//! Handlers are shared by every connection, so they don't otherwise
+//! know who they're talking to. A protocol can take a stream's
+//! [`ConnectionInfo`] as it binds it, and pass it on with each request.
+//! E.g. an HTTP protocol's `bind_transport` might return
+//! `HttpTransport::new(Framed::new(io, codec)).extension(info)`, where
+//! `info` is `io.connection_info()?`. Handlers then extract it with
+//! `Extension<ConnectionInfo>`, to allow or log requests by the
+//! client's address.
+//!
+//! To keep state for the life of a connection, an HTTP protocol can
+//! share a [`ConnectionState`] with each of its requests, with
+//! `HttpTransport::connection_state`. Handlers extract it with
+//! `Extension<ConnectionState>`, to limit how many requests a client
+//! makes on one connection, or remember what it negotiated.
+//!
+//! Streams that implement [`Shutdown`] can also be half-closed, e.g. so
+//! a tunnel can tell one peer that the other has finished sending.
+//!
+//! [`ConnectionInfo`]: struct.ConnectionInfo.html
+//! [`ConnectionState`]: struct.ConnectionState.html
+//! [`Shutdown`]: trait.Shutdown.html
+
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::io;
+use std::net::{self, SocketAddr};
+use std::rc::Rc;
+
+use http::extensions::Extensions;
+
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use tls::TlsInfo;
+
+/// The addresses of a connection, and its TLS session, if it has one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    pub peer_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub tls: Option<TlsInfo>,
+}
+
+/// A stream that can describe its connection.
+pub trait Connected {
+    fn connection_info(&self) -> io::Result<ConnectionInfo>;
+}
+
+impl Connected for net::TcpStream {
+    fn connection_info(&self) -> io::Result<ConnectionInfo> {
+        Ok(ConnectionInfo {
+            peer_addr: self.peer_addr()?,
+            local_addr: self.local_addr()?,
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls: None,
+        })
+    }
+}
+
+/// State shared by the requests on one connection. Clones share the
+/// same state.
+#[derive(Clone)]
+pub struct ConnectionState(Rc<State>);
+
+struct State {
+    info: ConnectionInfo,
+    extensions: RefCell<Extensions>,
+    requests: Cell<u64>,
+}
+
+impl ConnectionState {
+    pub fn new(info: ConnectionInfo) -> ConnectionState {
+        ConnectionState(Rc::new(State {
+            info,
+            extensions: RefCell::new(Extensions::new()),
+            requests: Cell::new(0),
+        }))
+    }
+
+    pub fn info(&self) -> &ConnectionInfo {
+        &self.0.info
+    }
+
+    /// Values kept for as long as the connection is open.
+    ///
+    /// # Panics
+    ///
+    /// If they're borrowed with `extensions_mut`.
+    pub fn extensions(&self) -> Ref<'_, Extensions> {
+        self.0.extensions.borrow()
+    }
+
+    /// # Panics
+    ///
+    /// If the extensions are already borrowed.
+    pub fn extensions_mut(&self) -> RefMut<'_, Extensions> {
+        self.0.extensions.borrow_mut()
+    }
+
+    /// How many requests have arrived on the connection, including the
+    /// one being handled.
+    pub fn requests(&self) -> u64 {
+        self.0.requests.get()
+    }
+
+    pub(crate) fn count_request(&self) {
+        self.0.requests.set(self.0.requests.get() + 1);
+    }
+}
+
+/// A stream whose directions can be closed separately. After
+/// `shutdown(net::Shutdown::Write)`, the peer reads the end of the
+/// stream, but can still send data to be read.
+pub trait Shutdown {
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()>;
+}
+
+impl Shutdown for net::TcpStream {
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+        net::TcpStream::shutdown(self, how)
+    }
+}
+
+#[cfg(test)]
+mod connected_should {
+    use super::*;
+
+    #[test]
+    fn describe_tcp_streams() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+
+        let info = accepted.connection_info().unwrap();
+        assert_eq!(client.local_addr().unwrap(), info.peer_addr);
+        assert_eq!(listener.local_addr().unwrap(), info.local_addr);
+    }
+
+    #[test]
+    fn share_state_between_clones() {
+        let info = ConnectionInfo {
+            peer_addr: "127.0.0.1:4000".parse().unwrap(),
+            local_addr: "127.0.0.1:80".parse().unwrap(),
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls: None,
+        };
+        let state = ConnectionState::new(info.clone());
+        let clone = state.clone();
+
+        clone.count_request();
+        clone.extensions_mut().insert("negotiated");
+        assert_eq!(1, state.requests());
+        assert_eq!(Some(&"negotiated"), state.extensions().get::<&str>());
+        assert_eq!(&info, state.info());
+    }
+
+    #[test]
+    fn half_close_tcp_streams() {
+        use std::io::{Read, Write};
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut accepted, _) = listener.accept().unwrap();
+
+        Shutdown::shutdown(&mut client, net::Shutdown::Write).unwrap();
+        assert_eq!(0, accepted.read(&mut [0; 1]).unwrap());
+
+        accepted.write_all(b"Still open").unwrap();
+        let mut buf = [0; 10];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"Still open", &buf);
+    }
+}
@@ -1,47 +1,125 @@
 use std::sync::Arc;
 
+use budget;
 use handler::Handler;
 use pollable::{IntoPollable, Pollable};
 use result::PollResult;
 use sink::{SendOne, Sink};
+use upgrade::Upgrading;
 
 pub enum Connection<H, S> where
     H: Handler,
-    S: Pollable<Item=H::Request> + Sink<Item=H::Response> + 'static
+    S: Pollable<Item=Option<H::Request>> + Sink<Item=H::Response> + 'static
 {
     Reading(S, Arc<H>),
     Handling(S, Arc<H>, <H::Pollable as IntoPollable>::Pollable),
     Writing(SendOne<S, H::Response>, Arc<H>),
+    /// Served by another protocol, after a response asked for an
+    /// upgrade.
+    Upgraded(Upgrading<<S as Sink>::Error>),
     Done,
 }
 
 impl<H, S> Connection<H, S> where
     H: Handler,
-    S: Pollable<Item=H::Request> + Sink<Item=H::Response> + 'static
+    S: Pollable<Item=Option<H::Request>> + Sink<Item=H::Response> + 'static
 {
     pub fn new(s: S, handler: Arc<H>) -> Connection<H, S> {
         Connection::Reading(s, handler)
     }
+
+    /// Abandons the request being handled, as it's taken too long.
+    /// Returns whether the connection can go on, to write the handler's
+    /// `timed_out` response.
+    pub fn time_out(&mut self) -> bool {
+        use std::mem;
+
+        match mem::replace(self, Connection::Done) {
+            Connection::Handling(s, h, _) => match h.timed_out() {
+                Some(response) => {
+                    *self = Connection::Writing(s.send_one(response), h);
+                    true
+                },
+                None => false,
+            },
+            other => {
+                *self = other;
+                false
+            },
+        }
+    }
+
+    /// Asks the transport to close the connection once the request
+    /// it's serving, if any, has been answered.
+    pub fn drain(&mut self) {
+        match *self {
+            Connection::Reading(ref mut s, _) | Connection::Handling(ref mut s, ..) => s.drain(),
+            Connection::Writing(ref mut send, _) => send.get_mut().drain(),
+            _ => {},
+        }
+    }
+
+    /// Whether the head of the request being read has arrived.
+    pub fn head_received(&self) -> bool {
+        match *self {
+            Connection::Reading(ref s, _) => s.head_received(),
+            _ => false,
+        }
+    }
+
+    pub fn activity(&self) -> Activity {
+        match *self {
+            Connection::Reading(..) => Activity::Reading,
+            Connection::Handling(..) => Activity::Handling,
+            Connection::Writing(..) => Activity::Writing,
+            Connection::Upgraded(..) => Activity::Upgraded,
+            Connection::Done => Activity::Done,
+        }
+    }
+}
+
+/// What a [`Connection`] is waiting on.
+///
+/// [`Connection`]: enum.Connection.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activity {
+    /// Waiting for the next request from the peer.
+    Reading,
+    /// Waiting for the handler's response.
+    Handling,
+    /// Waiting for the response to be written to the peer.
+    Writing,
+    /// Served by another protocol.
+    Upgraded,
+    Done,
 }
 
 impl<H, S> Pollable for Connection<H, S> where 
     H: Handler,
-    S: Pollable<Item=H::Request> + Sink<Item=H::Response> + 'static,
+    S: Pollable<Item=Option<H::Request>> + Sink<Item=H::Response> + 'static,
     H::Error: From<<S as Pollable>::Error>,
     H::Error: From<<S as Sink>::Error>,
 {
     type Item = ();
     type Error = H::Error; //<S as Sink>::Error;
 
+    /// Takes a step, e.g. from reading a request to handling it, and
+    /// spends a unit of the budget on it.
     fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
         use std::mem;
 
+        if !budget::spend() {
+            return Ok(PollResult::NotReady);
+        }
+
         let next = match mem::replace(self, Connection::Done) {
             Connection::Reading(mut stream, handler) => 
                 match stream.poll()? {
                     PollResult::NotReady => 
                         Connection::Reading(stream, handler),
-                    PollResult::Ready(request) => {
+                    PollResult::Ready(None) => 
+                        return Ok(PollResult::Ready(())),
+                    PollResult::Ready(Some(request)) => {
                         let pollable = handler.handle(request)
                             .into_pollable();
                         Connection::Handling(stream, handler, pollable)
@@ -56,9 +134,17 @@ impl<H, S> Pollable for Connection<H, S> where
                 },
             Connection::Writing(mut sink, h) => 
                 match sink.poll()? {
-                    PollResult::Ready(_) => Connection::Reading(sink.into_inner(), h), //return Ok(PollResult::Ready(())),
+                    PollResult::Ready(_) => match sink.into_inner().upgrade() {
+                        Ok(upgraded) => Connection::Upgraded(upgraded),
+                        Err(s) => Connection::Reading(s, h),
+                    },
                     PollResult::NotReady => Connection::Writing(sink, h),
                 },
+            Connection::Upgraded(mut upgraded) =>
+                match upgraded.poll()? {
+                    PollResult::Ready(()) => return Ok(PollResult::Ready(())),
+                    PollResult::NotReady => Connection::Upgraded(upgraded),
+                },
             Connection::Done => panic!("Poll called on finished result"),
         };
 
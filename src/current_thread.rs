@@ -0,0 +1,365 @@
+//! Serving connections on a single thread.
+//!
+//! A [`CurrentThreadServer`] accepts and polls its connections on the
+//! thread that runs it, so neither its protocol nor its handler need
+//! to be `Send` or `Sync`. Handlers can share state through an `Rc`,
+//! rather than an `Arc` and a lock.
+//!
+//! [`CurrentThreadServer`]: struct.CurrentThreadServer.html
+
+use std::io;
+use std::net::{self, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::time::Instant;
+
+use mio::{Events, Interest, Token};
+
+use bind_transport::BindTransport;
+use handler::Handler;
+use load::LoadCounters;
+use pollable::{IntoPollable, Pollable};
+use server::{BoundServer, ServerBuilder, ServerHandle, TcpServer, RESUME_INTERVAL, STOP};
+use sink::Sink;
+use thread_pool::Worker;
+
+/// A [`TcpServer`] that runs on one thread, without a pool of workers.
+/// It's configured with the same [`ServerBuilder`], except that the
+/// number of threads is ignored.
+///
+/// [`TcpServer`]: ../server/struct.TcpServer.html
+/// [`ServerBuilder`]: ../server/struct.ServerBuilder.html
+pub struct CurrentThreadServer<P> {
+    server: TcpServer<P>,
+}
+
+impl<P> From<TcpServer<P>> for CurrentThreadServer<P> {
+    fn from(server: TcpServer<P>) -> CurrentThreadServer<P> {
+        CurrentThreadServer { server }
+    }
+}
+
+impl<P> CurrentThreadServer<P>
+    where P: BindTransport<net::TcpStream>,
+{
+    pub fn new(proto: P) -> CurrentThreadServer<P> {
+        TcpServer::builder(proto).build_current_thread()
+    }
+
+    /// Configures the server. Finish with `build_current_thread`.
+    pub fn builder(proto: P) -> ServerBuilder<P> {
+        TcpServer::builder(proto)
+    }
+
+    pub fn serve<S, F, H>(self, s: S, f: F) -> io::Result<()> where
+        S: ToSocketAddrs,
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response>,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        self.bind(s)?.run(f)
+    }
+
+    /// Binds a listener to `s`, without serving connections yet.
+    pub fn bind<S: ToSocketAddrs>(self, s: S) -> io::Result<BoundCurrentThreadServer<P>> {
+        Ok(BoundCurrentThreadServer {
+            bound: self.server.bind(s)?,
+        })
+    }
+
+    /// Prepares to serve listeners that are already bound. See
+    /// `TcpServer::bind_listeners`.
+    pub fn bind_listeners(self, listeners: Vec<net::TcpListener>)
+        -> io::Result<BoundCurrentThreadServer<P>>
+    {
+        Ok(BoundCurrentThreadServer {
+            bound: self.server.bind_listeners(listeners)?,
+        })
+    }
+}
+
+/// A [`CurrentThreadServer`] whose listeners are bound, ready to serve
+/// connections with `run`.
+///
+/// [`CurrentThreadServer`]: struct.CurrentThreadServer.html
+pub struct BoundCurrentThreadServer<P> {
+    bound: BoundServer<P>,
+}
+
+impl<P> BoundCurrentThreadServer<P>
+    where P: BindTransport<net::TcpStream>,
+{
+    /// The address of the first listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.bound.local_addr()
+    }
+
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.bound.local_addrs()
+    }
+
+    /// A handle that stops the server. Handles are `Send`, so the
+    /// server can be stopped from another thread.
+    pub fn handle(&self) -> ServerHandle {
+        self.bound.handle()
+    }
+
+    /// Serves connections on this thread until the server is stopped,
+    /// with the handler that `f` creates.
+    pub fn run<F, H>(self, f: F) -> io::Result<()> where
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response>,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        let BoundServer { server, poll, mut listeners, handle } = self.bound;
+
+        // Connections are identified by their index, as they are in a
+        // pool's worker, so the listeners are moved out of their way.
+        for (index, listener) in listeners.iter_mut().enumerate() {
+            poll.registry().reregister(listener, listener_token(index), Interest::READABLE)?;
+        }
+
+        let load = Arc::new(LoadCounters::default());
+        handle.loads.set(vec![load.clone()]);
+        // The worker's connections are woken with the server's waker,
+        // as they share a `Poll`.
+        let mut worker = Worker::new(server.config.clone(), server.proto.clone(),
+                                     Arc::new(f()), server.hooks.clone(), poll,
+                                     handle.waker(), Arc::new(AtomicUsize::new(0)), load);
+
+        let mut events = Events::with_capacity(1024);
+        let mut paused = false;
+        let mut drained = false;
+        // When the server stops, once it's shutting down.
+        let mut deadline: Option<Instant> = None;
+        loop {
+            // A paused server checks its listeners every so often, as
+            // it does when running a pool.
+            let mut timeout = match paused {
+                true => Some(worker.timeout().map_or(RESUME_INTERVAL, |t| t.min(RESUME_INTERVAL))),
+                false => worker.timeout(),
+            };
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                timeout = Some(timeout.map_or(left, |t| t.min(left)));
+            }
+
+            if let Err(e) = worker.poll_mut().poll(&mut events, timeout) {
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+
+            // Dropping the worker closes its connections.
+            if handle.is_stopped() {
+                return Ok(());
+            }
+
+            for spawn in handle.tasks.take() {
+                worker.spawn(spawn);
+            }
+
+            if !drained && handle.is_draining() {
+                worker.drain();
+                drained = true;
+            }
+
+            // New connections are refused while the open ones finish.
+            if let (None, Some(grace)) = (deadline, handle.grace()) {
+                listeners.clear();
+                worker.drain();
+                deadline = Some(Instant::now() + grace);
+            }
+
+            let mut ready = vec![];
+            for event in events.iter() {
+                match listener_index(event.token(), listeners.len()) {
+                    Some(index) => ready.push(index),
+                    None => worker.ready(event.token()),
+                }
+            }
+
+            if paused {
+                ready = (0..listeners.len()).collect();
+            }
+
+            paused = false;
+            for index in ready {
+                if !server.accept(&listeners[index], &mut worker)? {
+                    paused = true;
+                    break;
+                }
+            }
+
+            worker.turn();
+
+            if let Some(deadline) = deadline {
+                if worker.is_idle() || Instant::now() >= deadline {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Listeners take the tokens just below the one that stops the server.
+fn listener_token(index: usize) -> Token {
+    Token(STOP.0 - 1 - index)
+}
+
+fn listener_index(Token(token): Token, listeners: usize) -> Option<usize> {
+    match token < STOP.0 && token >= STOP.0 - listeners {
+        true => Some(STOP.0 - 1 - token),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod current_thread_server_should {
+    use super::*;
+    use std::cell::Cell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    struct Proto;
+
+    impl BindTransport<net::TcpStream> for Proto {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Transport = ::framed::Framed<net::TcpStream, Codec>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+            Ok(::framed::Framed::new(s, Codec))
+        }
+    }
+
+    struct Codec;
+
+    impl ::codec::Decode for Codec {
+        type Item = Vec<u8>;
+
+        fn decode(&self, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+            Some(::std::mem::take(buffer))
+        }
+    }
+
+    impl ::codec::Encode for Codec {
+        type Item = Vec<u8>;
+
+        fn encode(&self, item: Vec<u8>, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item);
+            Ok(())
+        }
+    }
+
+    /// Responds with how many requests it's handled, on any connection.
+    struct Count(Rc<Cell<u8>>);
+
+    impl Handler for Count {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = io::Result<Vec<u8>>;
+
+        fn handle(&self, _: Vec<u8>) -> Self::Pollable {
+            self.0.set(self.0.get() + 1);
+            Ok(vec![self.0.get()])
+        }
+    }
+
+    #[test]
+    fn share_state_that_is_not_send_between_connections() {
+        let (sender, receiver) = channel();
+        let running = thread::spawn(move || {
+            let server = CurrentThreadServer::new(Proto).bind("127.0.0.1:0").unwrap();
+            sender.send((server.local_addr().unwrap(), server.handle())).unwrap();
+            server.run(|| Count(Rc::new(Cell::new(0))))
+        });
+        let (addr, handle) = receiver.recv().unwrap();
+
+        let mut counts = vec![];
+        for _ in 0..2 {
+            let mut client = net::TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            for _ in 0..2 {
+                let mut count = [0];
+                client.write_all(b"hi").unwrap();
+                client.read_exact(&mut count).unwrap();
+                counts.push(count[0]);
+            }
+        }
+        assert_eq!(vec![1, 2, 3, 4], counts);
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn close_connections_as_they_finish_when_shutting_down() {
+        let server = CurrentThreadServer::new(Proto).bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let running = thread::spawn(move || server.run(|| Count(Rc::new(Cell::new(0)))));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0]).unwrap();
+
+        let started = Instant::now();
+        handle.shutdown(Duration::from_secs(10));
+        assert_eq!(0, client.read(&mut [0]).unwrap());
+        running.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn run_tasks_on_its_own_thread() {
+        use executor::Executor;
+
+        let server = CurrentThreadServer::new(Proto).bind("127.0.0.1:0").unwrap();
+        let handle = server.handle();
+        let running = thread::spawn(move || {
+            let id = thread::current().id();
+            server.run(|| Count(Rc::new(Cell::new(0)))).map(|_| id)
+        });
+
+        let (sender, receiver) = channel();
+        handle.spawn_with(move || Ok::<_, ()>(sender.send(thread::current().id())));
+        let ran_on = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        handle.stop();
+        assert_eq!(running.join().unwrap().unwrap(), ran_on);
+    }
+
+    #[test]
+    fn close_idle_connections() {
+        let server = CurrentThreadServer::builder(Proto)
+            .keep_alive_timeout(Duration::from_millis(50))
+            .build_current_thread()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let running = thread::spawn(move || server.run(|| Count(Rc::new(Cell::new(0)))));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0]).unwrap();
+        assert_eq!(0, client.read(&mut [0]).unwrap());
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+    }
+}
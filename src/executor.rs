@@ -0,0 +1,322 @@
+//! Running background work on a server's workers.
+//!
+//! Besides serving connections, a server's workers can run tasks:
+//! pollables that aren't tied to a connection, such as refreshing a
+//! cache every so often, calling out to another service, or a job
+//! that's run later. A task runs on the same thread as the worker's
+//! connections, and is polled until it resolves. A task that's waiting
+//! on something arranges to be woken by it, as a connection's pollables
+//! do, or is polled on each turn of the worker's loop. See
+//! [`readiness`].
+//!
+//! Tasks are spawned through an [`Executor`], e.g. a `ServerHandle`,
+//! from any thread. A handler can also use [`spawn_local`] to run a
+//! task on its own worker, without it having to be `Send`.
+//!
+//! [`Executor`]: trait.Executor.html
+//! [`spawn_local`]: fn.spawn_local.html
+//! [`readiness`]: ../readiness/index.html
+
+use std::cell::RefCell;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use pollable::{IntoPollable, Pollable};
+use result::PollResult;
+
+/// A pollable that a worker runs. What it resolves to is dropped.
+pub type Task = Box<dyn Pollable<Item=(), Error=()>>;
+
+/// Creates a task, on the worker that runs it.
+pub type Spawn = Box<dyn FnOnce() -> Task + Send>;
+
+/// Runs tasks.
+pub trait Executor {
+    /// Runs the task that `spawn` creates.
+    fn execute(&self, spawn: Spawn);
+
+    /// Runs `pollable` until it resolves. What it resolves to, or
+    /// fails with, is dropped, so a task that has something to report
+    /// should do so itself.
+    fn spawn<P>(&self, pollable: P) where
+        P: IntoPollable + Send + 'static,
+        P::Pollable: 'static,
+        Self: Sized
+    {
+        self.spawn_with(move || pollable)
+    }
+
+    /// Runs the pollable that `f` creates, on the worker that runs it,
+    /// so the pollable needn't be `Send`.
+    fn spawn_with<F, P>(&self, f: F) where
+        F: FnOnce() -> P + Send + 'static,
+        P: IntoPollable,
+        P::Pollable: 'static,
+        Self: Sized
+    {
+        self.execute(Box::new(move || Box::new(Detached(f().into_pollable())) as Task))
+    }
+}
+
+/// A pollable whose result is dropped.
+struct Detached<P>(P);
+
+impl<P: Pollable> Pollable for Detached<P> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Result<PollResult<()>, ()> {
+        match self.0.poll() {
+            Ok(PollResult::Ready(_)) => Ok(PollResult::Ready(())),
+            Ok(PollResult::NotReady) => Ok(PollResult::NotReady),
+            Err(_) => Err(()),
+        }
+    }
+}
+
+thread_local! {
+    /// The tasks spawned on this thread's worker since its last turn,
+    /// if it's running a worker.
+    static LOCAL: RefCell<Option<Vec<Task>>> = const { RefCell::new(None) };
+}
+
+/// Runs `pollable` on the worker of the current thread, e.g. from a
+/// handler. What it resolves to, or fails with, is dropped.
+///
+/// # Panics
+///
+/// If the current thread isn't running a worker.
+pub fn spawn_local<P>(pollable: P) where
+    P: IntoPollable,
+    P::Pollable: 'static
+{
+    LOCAL.with(|local| match *local.borrow_mut() {
+        Some(ref mut tasks) => tasks.push(Box::new(Detached(pollable.into_pollable()))),
+        None => panic!("spawn_local was called outside a worker"),
+    });
+}
+
+/// Lets tasks be spawned locally on the current thread, until it's
+/// dropped.
+pub(crate) struct LocalTasks(());
+
+impl LocalTasks {
+    pub(crate) fn new() -> LocalTasks {
+        LOCAL.with(|local| *local.borrow_mut() = Some(vec![]));
+        LocalTasks(())
+    }
+
+    /// The tasks spawned since this was last called.
+    pub(crate) fn take(&self) -> Vec<Task> {
+        LOCAL.with(|local| local.borrow_mut().as_mut().map(mem::take).unwrap_or_default())
+    }
+}
+
+impl Drop for LocalTasks {
+    fn drop(&mut self) {
+        LOCAL.with(|local| *local.borrow_mut() = None);
+    }
+}
+
+/// Tasks spawned through a server's handle, until the server takes
+/// them to run.
+#[derive(Clone, Default)]
+pub(crate) struct Inbox(Arc<Mutex<Vec<Spawn>>>);
+
+impl Inbox {
+    pub(crate) fn push(&self, spawn: Spawn) {
+        self.0.lock().unwrap().push(spawn);
+    }
+
+    pub(crate) fn take(&self) -> Vec<Spawn> {
+        mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod executor_should {
+    use super::*;
+    use std::io;
+    use std::net;
+    use std::rc::Rc;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+    use std::time::Duration;
+    use server::TcpServer;
+
+    struct Proto;
+
+    impl ::bind_transport::BindTransport<net::TcpStream> for Proto {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Transport = ::framed::Framed<net::TcpStream, Codec>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+            Ok(::framed::Framed::new(s, Codec))
+        }
+    }
+
+    struct Codec;
+
+    impl ::codec::Decode for Codec {
+        type Item = Vec<u8>;
+
+        fn decode(&self, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+            Some(mem::take(buffer))
+        }
+    }
+
+    impl ::codec::Encode for Codec {
+        type Item = Vec<u8>;
+
+        fn encode(&self, item: Vec<u8>, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item);
+            Ok(())
+        }
+    }
+
+    /// Spawns a task on its worker for each request, that reports the
+    /// request once it's been polled a few times.
+    struct Report(Mutex<Sender<Vec<u8>>>);
+
+    impl ::handler::Handler for Report {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = io::Result<Vec<u8>>;
+
+        fn handle(&self, request: Vec<u8>) -> Self::Pollable {
+            let report = self.0.lock().unwrap().clone();
+            // Tasks spawned locally needn't be `Send`.
+            let request = Rc::new(request);
+            spawn_local(Countdown(3, move || report.send(request.to_vec())));
+            Ok(vec![])
+        }
+    }
+
+    /// Calls its closure once it's been polled enough times.
+    struct Countdown<F>(usize, F);
+
+    impl<F, T> Pollable for Countdown<F> where
+        F: FnMut() -> T
+    {
+        type Item = T;
+        type Error = ();
+
+        fn poll(&mut self) -> Result<PollResult<T>, ()> {
+            match self.0 {
+                0 => Ok(PollResult::Ready((self.1)())),
+                _ => {
+                    self.0 -= 1;
+                    Ok(PollResult::NotReady)
+                },
+            }
+        }
+    }
+
+    fn recv<T>(receiver: &Receiver<T>) -> T {
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn run_tasks_spawned_from_a_handle_on_the_workers() {
+        let server = TcpServer::builder(Proto).threads(2).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let handle = server.handle();
+        let (sender, receiver) = channel();
+
+        // Tasks spawned before the server runs wait for its workers.
+        let early = sender.clone();
+        handle.spawn(Countdown(1, move || early.send(thread::current().id())));
+
+        let (unused, _) = channel();
+        let running = thread::spawn(move || server.run(move || Report(Mutex::new(unused))));
+        let first = recv(&receiver);
+
+        for _ in 0..2 {
+            let sender = sender.clone();
+            handle.spawn_with(move || Countdown(2, move || sender.send(thread::current().id())));
+        }
+        let (second, third) = (recv(&receiver), recv(&receiver));
+        assert_ne!(thread::current().id(), first);
+        assert_ne!(second, third);
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn run_tasks_spawned_locally_on_the_same_worker() {
+        use std::io::Write;
+
+        let (sender, receiver) = channel();
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let running = thread::spawn(move || server.run(move || Report(Mutex::new(sender))));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.write_all(b"Hello").unwrap();
+        assert_eq!(b"Hello".to_vec(), recv(&receiver));
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn poll_tasks_once_they_are_woken() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use readiness::Wakeup;
+
+        /// Resolves once it's been woken, counting its polls.
+        struct Woken(Wakeup, Arc<AtomicBool>, Arc<AtomicUsize>, Sender<()>);
+
+        impl Pollable for Woken {
+            type Item = ();
+            type Error = ();
+
+            fn poll(&mut self) -> Result<PollResult<()>, ()> {
+                self.2.fetch_add(1, Ordering::SeqCst);
+                self.0.wait();
+                match self.1.load(Ordering::SeqCst) {
+                    true => self.3.send(()).map(PollResult::Ready).map_err(|_| ()),
+                    false => Ok(PollResult::NotReady),
+                }
+            }
+        }
+
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let handle = server.handle();
+        let (unused, _) = channel();
+        let running = thread::spawn(move || server.run(move || Report(Mutex::new(unused))));
+
+        let wakeup = Wakeup::new();
+        let (woken, polls) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+        let (sender, receiver) = channel();
+        handle.spawn(Woken(wakeup.clone(), woken.clone(), polls.clone(), sender));
+
+        // A task that's waiting isn't polled until it's woken.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(1, polls.load(Ordering::SeqCst));
+
+        woken.store(true, Ordering::SeqCst);
+        wakeup.wake();
+        recv(&receiver);
+        assert_eq!(2, polls.load(Ordering::SeqCst));
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "outside a worker")]
+    fn refuse_to_spawn_locally_outside_a_worker() {
+        spawn_local(Countdown(0, || ()));
+    }
+}
@@ -1,5 +1,8 @@
 use std::io::{self, Read, Write};
+use std::net;
+use budget;
 use codec::{Decode, Encode};
+use connected::Shutdown;
 use pollable::Pollable;
 use sink::{Sink, SinkResult};
 use result::PollResult;
@@ -12,17 +15,44 @@ pub struct Framed<S, D> {
     decoder: D,
     recv_buffer: Vec<u8>,
     send_buffer: Vec<u8>,
+    /// How much can be buffered to send before items are refused.
+    send_limit: usize,
 }
 
 impl<S, D> Framed<S, D> {
     pub fn new(stream: S, codec: D) -> Framed<S, D> {
+        Framed::with_capacity(stream, codec, 1024, 1024)
+    }
+
+    /// Creates a `Framed` whose buffers for incoming and outgoing data
+    /// start with the given capacities, in bytes. Items are encoded
+    /// onto the outgoing buffer until it holds `write_capacity` bytes,
+    /// so small items sent together are written together.
+    pub fn with_capacity(stream: S,
+                         codec: D,
+                         read_capacity: usize,
+                         write_capacity: usize)
+        -> Framed<S, D>
+    {
         Framed {
-            stream: stream,
+            stream,
             decoder: codec,
-            recv_buffer: Vec::with_capacity(1024),
-            send_buffer: Vec::with_capacity(1024),
+            recv_buffer: Vec::with_capacity(read_capacity),
+            send_buffer: Vec::with_capacity(write_capacity),
+            send_limit: write_capacity,
         }
     }
+
+    /// Returns a reference to the stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns the stream, along with any data that's been read from
+    /// it but not decoded.
+    pub fn into_parts(self) -> (S, Vec<u8>) {
+        (self.stream, self.recv_buffer)
+    }
 }
 
 impl<S, D> Framed<S, D>
@@ -34,29 +64,46 @@ impl<S, D> Framed<S, D>
     }
 }
 
+/// Yields `None` once the peer closes the stream between items. A
+/// stream closed part of the way through an item is an
+/// `UnexpectedEof` error. Each read spends a unit of the budget.
 impl<S, D> Pollable for Framed<S, D>
     where S: Read,
           D: Decode,
 {
-    type Item = D::Item;
+    type Item = Option<D::Item>;
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let mut buf = [0_u8; 256];
 
         loop {
+            // Items that arrived together are decoded before reading
+            // again, as nothing more may arrive.
+            if !self.recv_buffer.is_empty() {
+                if let Some(item) = self.decoder.decode(&mut self.recv_buffer) {
+                    return Ok(PollResult::Ready(Some(item)));
+                }
+                self.decoder.validate(&self.recv_buffer)?;
+            }
+
+            if !budget::spend() {
+                return Ok(PollResult::NotReady);
+            }
+
             let bytes_read = match try_poll_io!(self.stream.read(&mut buf)) {
+                0 if self.recv_buffer.is_empty() => return Ok(PollResult::Ready(None)),
                 0 => return Err(io::ErrorKind::UnexpectedEof.into()),
                 n => n,
             };
 
             self.recv_buffer.extend(&buf[..bytes_read]);
-
-            if let Some(request) = self.decoder.decode(&mut self.recv_buffer) {
-                return Ok(PollResult::Ready(request));
-            }
         }
     }
+
+    fn head_received(&self) -> bool {
+        !self.recv_buffer.is_empty() && self.decoder.head_complete(&self.recv_buffer)
+    }
 }
 
 impl<S, E> Sink for Framed<S, E>
@@ -66,26 +113,147 @@ impl<S, E> Sink for Framed<S, E>
     type Item = E::Item;
     type Error = io::Error;
 
+    /// Items are refused once the send buffer holds as much as the
+    /// `Framed` was created to buffer, until `poll_complete` has
+    /// written some of it. Until then, they're buffered after those
+    /// sent before them, which may take it past that.
     fn start_send(&mut self, item: Self::Item) -> StartSend<Self::Item, Self::Error> {
-        if self.send_buffer.len() != 0 {
+        if !self.send_buffer.is_empty() && self.send_buffer.len() >= self.send_limit {
             return Ok(SinkResult::NotReady(item));
         }
-        self.decoder.encode(item, &mut self.send_buffer);
+        self.decoder.encode(item, &mut self.send_buffer)?;
         Ok(SinkResult::Ready)
     }
 
+    /// Each write spends a unit of the budget.
     fn poll_complete(&mut self) -> Poll<(), Self::Error> {
-        match try_poll_io!(self.stream.write(&self.send_buffer)) {
-            0 => Ok(PollResult::Ready(())),
-            n => {
-                self.send_buffer.drain(..n);
-                if self.send_buffer.len() == 0 {
-                    Ok(PollResult::NotReady)
-                }
-                else {
-                    Ok(PollResult::Ready(()))
-                }
+        while !self.send_buffer.is_empty() {
+            if !budget::spend() {
+                return Ok(PollResult::NotReady);
+            }
+
+            match try_poll_io!(self.stream.write(&self.send_buffer)) {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => {
+                    self.send_buffer.drain(..n);
+                },
             }
         }
+
+        try_poll_io!(self.stream.flush());
+        Ok(PollResult::Ready(()))
+    }
+}
+
+/// Items that are still buffered are written before the stream's
+/// write side is closed. If they can't all be written yet, this fails
+/// with `WouldBlock`, and can be tried again.
+impl<S, E> Shutdown for Framed<S, E>
+    where S: Write + Shutdown,
+          E: Encode,
+{
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+        if how != net::Shutdown::Read {
+            if let PollResult::NotReady = self.poll_complete()? {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+        self.stream.shutdown(how)
+    }
+}
+
+#[cfg(test)]
+mod framed_should {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Decodes lines.
+    struct Lines;
+
+    impl Decode for Lines {
+        type Item = Vec<u8>;
+
+        fn decode(&self, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+            let end = buffer.iter().position(|&b| b == b'\n')?;
+            Some(buffer.drain(..end + 1).collect())
+        }
+    }
+
+    #[test]
+    fn end_cleanly_when_closed_between_items() {
+        let mut framed = Framed::new(Cursor::new(b"one\n".to_vec()), Lines);
+
+        assert_eq!(PollResult::Ready(Some(b"one\n".to_vec())), framed.poll().unwrap());
+        assert_eq!(PollResult::Ready(None), framed.poll().unwrap());
+    }
+
+    #[test]
+    fn decode_items_that_arrived_together() {
+        let mut framed = Framed::new(Cursor::new(b"one\ntwo\n".to_vec()), Lines);
+
+        assert_eq!(PollResult::Ready(Some(b"one\n".to_vec())), framed.poll().unwrap());
+        assert_eq!(PollResult::Ready(Some(b"two\n".to_vec())), framed.poll().unwrap());
+        assert_eq!(PollResult::Ready(None), framed.poll().unwrap());
+    }
+
+    #[test]
+    fn stop_reading_once_the_budget_is_spent() {
+        use budget::with_budget;
+
+        let mut framed = Framed::new(io::repeat(b'x'), Lines);
+        let (result, exhausted) = with_budget(4, || framed.poll().unwrap());
+        assert_eq!(PollResult::NotReady, result);
+        assert!(exhausted);
+        assert_eq!(4 * 256, framed.recv_buffer.len());
+    }
+
+    /// Records each write.
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Encode for Lines {
+        type Item = Vec<u8>;
+
+        fn encode(&self, item: Vec<u8>, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item);
+            buffer.push(b'\n');
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_items_sent_together_at_once() {
+        let mut framed = Framed::with_capacity(Writes::default(), Lines, 16, 10);
+
+        for item in &["one", "two", "three"] {
+            assert!(matches!(framed.start_send(item.as_bytes().to_vec()).unwrap(),
+                             SinkResult::Ready));
+        }
+        match framed.start_send(b"four".to_vec()).unwrap() {
+            SinkResult::NotReady(item) => assert_eq!(b"four".to_vec(), item),
+            SinkResult::Ready => panic!("The send buffer is full"),
+        }
+
+        assert_eq!(PollResult::Ready(()), framed.poll_complete().unwrap());
+        assert_eq!(vec![b"one\ntwo\nthree\n".to_vec()], framed.stream.0);
+    }
+
+    #[test]
+    fn fail_when_closed_part_way_through_an_item() {
+        let mut framed = Framed::new(Cursor::new(b"one\ntw".to_vec()), Lines);
+
+        assert_eq!(PollResult::Ready(Some(b"one\n".to_vec())), framed.poll().unwrap());
+        assert_eq!(io::ErrorKind::UnexpectedEof, framed.poll().unwrap_err().kind());
     }
 }
@@ -8,4 +8,11 @@ pub trait Handler {
     type Pollable: IntoPollable<Item=Self::Response, Error=Self::Error>;
 
     fn handle(&self, request: Self::Request) -> Self::Pollable;
+
+    /// The response to a request that's taken longer than the server's
+    /// `request_timeout`, sent in place of the one being waited on. By
+    /// default, there's none, and the connection is closed.
+    fn timed_out(&self) -> Option<Self::Response> {
+        None
+    }
 }
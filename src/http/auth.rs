@@ -0,0 +1,190 @@
+use http::base64;
+use http::middleware::{Middleware, Next};
+use http::response::RouteResponse;
+use http::types::{Request, ResponseBuilder, StatusCode};
+
+/// The credentials of an `Authorization` header using `scheme`.
+fn credentials<'r>(request: &'r Request, scheme: &str) -> Option<&'r str> {
+    let value = request.header_value("Authorization")?.trim();
+    let split = value.find(' ')?;
+    let (name, credentials) = value.split_at(split);
+
+    match name.eq_ignore_ascii_case(scheme) {
+        true => Some(credentials.trim()),
+        false => None,
+    }
+}
+
+fn unauthorized(challenge: String) -> RouteResponse {
+    RouteResponse::from(ResponseBuilder::new(StatusCode::Unauthorized)
+        .header("WWW-Authenticate", &challenge)
+        .build())
+}
+
+/// Middleware requiring HTTP Basic authentication (RFC 7617).
+///
+/// `verify` is called with the user ID and password of each request,
+/// and returns the authenticated principal, or `None` if the
+/// credentials are wrong. The principal is inserted into the request's
+/// extensions for the handlers that follow. Requests without valid
+/// credentials are answered with a `401 Unauthorized` and a
+/// `WWW-Authenticate` challenge for `realm`.
+pub struct BasicAuth<F> {
+    realm: String,
+    verify: F,
+}
+
+impl<F> BasicAuth<F> {
+    pub fn new(realm: &str, verify: F) -> BasicAuth<F> {
+        BasicAuth {
+            realm: String::from(realm),
+            verify,
+        }
+    }
+}
+
+impl<F, P> Middleware for BasicAuth<F> where
+    F: Fn(&str, &str) -> Option<P>,
+    P: 'static
+{
+    fn call(&self, mut request: Request, next: Next) -> RouteResponse {
+        let principal = credentials(&request, "Basic")
+            .and_then(|c| base64::decode(c, false))
+            .and_then(|c| String::from_utf8(c).ok())
+            .and_then(|c| {
+                let split = c.find(':')?;
+                (self.verify)(&c[..split], &c[split + 1..])
+            });
+
+        match principal {
+            Some(principal) => {
+                request.extensions_mut().insert(principal);
+                next.run(request)
+            },
+            None => unauthorized(
+                format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm)),
+        }
+    }
+}
+
+/// Middleware requiring a bearer token (RFC 6750).
+///
+/// `validate` is called with the token of each request, and returns
+/// the authenticated principal, or `None` if the token isn't valid.
+/// The principal is inserted into the request's extensions for the
+/// handlers that follow. Requests without a valid token are answered
+/// with a `401 Unauthorized` and a `WWW-Authenticate` challenge.
+pub struct BearerAuth<F> {
+    realm: String,
+    validate: F,
+}
+
+impl<F> BearerAuth<F> {
+    pub fn new(realm: &str, validate: F) -> BearerAuth<F> {
+        BearerAuth {
+            realm: String::from(realm),
+            validate,
+        }
+    }
+}
+
+impl<F, P> Middleware for BearerAuth<F> where
+    F: Fn(&str) -> Option<P>,
+    P: 'static
+{
+    fn call(&self, mut request: Request, next: Next) -> RouteResponse {
+        let token = match credentials(&request, "Bearer") {
+            Some(token) => token,
+            None => return unauthorized(format!("Bearer realm=\"{}\"", self.realm)),
+        };
+
+        match (self.validate)(token) {
+            Some(principal) => {
+                request.extensions_mut().insert(principal);
+                next.run(request)
+            },
+            None => unauthorized(
+                format!("Bearer realm=\"{}\", error=\"invalid_token\"", self.realm)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod auth_should {
+    use super::*;
+    use http::router::{HandleRouteResult, Parameters, Router};
+    use http::types::{self, HttpMethod, RequestBuilder};
+    use pollable::Pollable;
+    use result::PollResult;
+
+    #[derive(Debug, PartialEq)]
+    struct User(String);
+
+    fn greet(request: Request, _: &Parameters) -> String {
+        format!("Hello, {}", request.extensions().get::<User>().unwrap().0)
+    }
+
+    fn send(router: &Router, authorization: Option<&str>) -> types::Response {
+        let mut builder = RequestBuilder::new(HttpMethod::Get, "/");
+        if let Some(value) = authorization {
+            builder = builder.header("Authorization", value);
+        }
+
+        match router.route(builder.build()) {
+            HandleRouteResult::Handled(mut response) => match response.poll().unwrap() {
+                PollResult::Ready(response) => response,
+                PollResult::NotReady => panic!("Response not ready"),
+            },
+            HandleRouteResult::NotHandled(_) => panic!("Route not handled"),
+        }
+    }
+
+    fn body_of(response: types::Response) -> Vec<u8> {
+        response.into_parts().1.as_bytes().unwrap().to_vec()
+    }
+
+    #[test]
+    fn authenticate_basic_credentials() {
+        let router = Router::builder()
+            .middleware(BasicAuth::new("admin", |user: &str, password: &str| {
+                match (user, password) {
+                    ("Aladdin", "open sesame") => Some(User(String::from(user))),
+                    _ => None,
+                }
+            }))
+            .get("/", greet)
+            .build();
+
+        let response = send(&router, Some("basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+        assert_eq!(b"Hello, Aladdin".to_vec(), body_of(response));
+
+        for authorization in &[None, Some("Basic QWxhZGRpbjp3cm9uZw=="), Some("Basic !!")] {
+            let response = send(&router, *authorization);
+            assert_eq!(401, response.status_code());
+            assert_eq!(Some("Basic realm=\"admin\", charset=\"UTF-8\""),
+                       response.header_value("WWW-Authenticate"));
+        }
+    }
+
+    #[test]
+    fn authenticate_bearer_tokens() {
+        let router = Router::builder()
+            .middleware(BearerAuth::new("api", |token: &str| match token {
+                "s3cr3t" => Some(User(String::from("service"))),
+                _ => None,
+            }))
+            .get("/", greet)
+            .build();
+
+        let response = send(&router, Some("Bearer s3cr3t"));
+        assert_eq!(b"Hello, service".to_vec(), body_of(response));
+
+        let missing = send(&router, None);
+        assert_eq!(401, missing.status_code());
+        assert_eq!(Some("Bearer realm=\"api\""), missing.header_value("WWW-Authenticate"));
+
+        let invalid = send(&router, Some("Bearer guess"));
+        assert_eq!(Some("Bearer realm=\"api\", error=\"invalid_token\""),
+                   invalid.header_value("WWW-Authenticate"));
+    }
+}
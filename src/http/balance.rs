@@ -0,0 +1,304 @@
+//! Spreading requests across a set of upstream servers.
+//!
+//! A `ReverseProxy` can forward requests to several [`Upstream`]s,
+//! asking a [`Balancer`] which of them to forward each to. The crate
+//! has balancers that take turns ([`RoundRobin`]), favour the least
+//! busy ([`LeastConnections`]), share requests out by weight
+//! ([`Weighted`]), or keep requests with the same key on the same
+//! server ([`ConsistentHash`]). Others can be written by implementing
+//! `Balancer`. E.g.
+//!
+//! ```rust,no_run
+//! # use server_fx::http::balance::{LeastConnections, Upstream};
+//! # use server_fx::http::proxy::ReverseProxy;
+//! let proxy = ReverseProxy::balanced(vec![Upstream::new("10.0.0.1:8080"),
+//!                                         Upstream::new("10.0.0.2:8080").weighted(2)],
+//!                                    LeastConnections::new());
+//! ```
+//!
+//! [`Upstream`]: struct.Upstream.html
+//! [`Balancer`]: trait.Balancer.html
+//! [`RoundRobin`]: struct.RoundRobin.html
+//! [`LeastConnections`]: struct.LeastConnections.html
+//! [`Weighted`]: struct.Weighted.html
+//! [`ConsistentHash`]: struct.ConsistentHash.html
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use http::types::Request;
+
+/// A server that requests can be forwarded to.
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    address: String,
+    weight: usize,
+    active: Arc<AtomicUsize>,
+    /// Until when the server is avoided, after failing.
+    down_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Upstream {
+    /// The server at `address`, a `host:port`.
+    pub fn new(address: &str) -> Upstream {
+        Upstream {
+            address: String::from(address),
+            weight: 1,
+            active: Arc::default(),
+            down_until: Arc::default(),
+        }
+    }
+
+    /// Gives the server a share of the requests `weight` times that of
+    /// a server with the default weight, of 1, for the balancers that
+    /// take weights into account.
+    ///
+    /// # Panics
+    ///
+    /// If `weight` is 0.
+    pub fn weighted(mut self, weight: usize) -> Upstream {
+        assert!(weight > 0, "An upstream's weight must be at least 1");
+        self.weight = weight;
+        self
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    /// The number of requests that are being forwarded to the server,
+    /// and whose responses haven't been read in full.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Whether the server is taken to be up, as it hasn't failed
+    /// recently.
+    pub fn is_healthy(&self) -> bool {
+        match *self.down_until.lock().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    /// Takes the server to be down for `timeout`, after it's failed.
+    pub(crate) fn fail(&self, timeout: Duration) {
+        *self.down_until.lock().unwrap() = Some(Instant::now() + timeout);
+    }
+
+    /// Counts a request as active until the returned guard is dropped.
+    pub(crate) fn start(&self) -> Active {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Active(self.active.clone())
+    }
+}
+
+/// A request being forwarded to an upstream. See `Upstream::start`.
+pub(crate) struct Active(Arc<AtomicUsize>);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Chooses which upstream each request is forwarded to.
+pub trait Balancer {
+    /// Returns the index, in `upstreams`, of the server that `request`
+    /// is forwarded to. `upstreams` is never empty.
+    fn pick(&self, request: &Request, upstreams: &[Upstream]) -> usize;
+}
+
+/// Forwards requests to each upstream in turn.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl RoundRobin {
+    pub fn new() -> RoundRobin {
+        RoundRobin::default()
+    }
+}
+
+impl Balancer for RoundRobin {
+    fn pick(&self, _: &Request, upstreams: &[Upstream]) -> usize {
+        self.next.fetch_add(1, Ordering::SeqCst) % upstreams.len()
+    }
+}
+
+/// Forwards requests to the upstream with the fewest active requests,
+/// for its weight. Upstreams that are equally busy take turns.
+#[derive(Debug, Default)]
+pub struct LeastConnections {
+    next: AtomicUsize,
+}
+
+impl LeastConnections {
+    pub fn new() -> LeastConnections {
+        LeastConnections::default()
+    }
+}
+
+impl Balancer for LeastConnections {
+    fn pick(&self, _: &Request, upstreams: &[Upstream]) -> usize {
+        let start = self.next.fetch_add(1, Ordering::SeqCst);
+        (0..upstreams.len())
+            .map(|i| (start + i) % upstreams.len())
+            .fold(None, |least: Option<usize>, i| match least {
+                // a / wa < b / wb, without dividing.
+                Some(l) if upstreams[l].active() * upstreams[i].weight() <=
+                    upstreams[i].active() * upstreams[l].weight() => Some(l),
+                _ => Some(i),
+            })
+            .unwrap()
+    }
+}
+
+/// Forwards requests to each upstream in turn, as many times in a row
+/// as its weight.
+#[derive(Debug, Default)]
+pub struct Weighted {
+    next: AtomicUsize,
+}
+
+impl Weighted {
+    pub fn new() -> Weighted {
+        Weighted::default()
+    }
+}
+
+impl Balancer for Weighted {
+    fn pick(&self, _: &Request, upstreams: &[Upstream]) -> usize {
+        let total = upstreams.iter().map(Upstream::weight).sum::<usize>();
+        let mut n = self.next.fetch_add(1, Ordering::SeqCst) % total;
+        upstreams.iter()
+            .position(|u| match n.checked_sub(u.weight()) {
+                Some(rest) => {
+                    n = rest;
+                    false
+                },
+                None => true,
+            })
+            .unwrap()
+    }
+}
+
+/// Takes the key of a request.
+type Key = Box<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+/// Forwards requests with the same key to the same upstream, e.g. to
+/// make the most of each server's cache.
+///
+/// Each request goes to the upstream whose address hashes highest
+/// along with its key. When an upstream is added or removed, only the
+/// keys that hash highest with it move. Requests without a key take
+/// turns.
+pub struct ConsistentHash {
+    key: Key,
+    fallback: RoundRobin,
+}
+
+impl ConsistentHash {
+    /// Hashes each request on the key `f` takes from it.
+    pub fn new<F>(f: F) -> ConsistentHash where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static
+    {
+        ConsistentHash {
+            key: Box::new(f),
+            fallback: RoundRobin::new(),
+        }
+    }
+
+    /// Hashes each request on the value of its `name` header.
+    pub fn header(name: &str) -> ConsistentHash {
+        let name = String::from(name);
+        ConsistentHash::new(move |request| request.header_value(&name).map(String::from))
+    }
+}
+
+impl Balancer for ConsistentHash {
+    fn pick(&self, request: &Request, upstreams: &[Upstream]) -> usize {
+        let key = match (self.key)(request) {
+            Some(key) => key,
+            None => return self.fallback.pick(request, upstreams),
+        };
+
+        (0..upstreams.len())
+            .max_by_key(|&i| {
+                let mut hasher = DefaultHasher::new();
+                (upstreams[i].address(), &key).hash(&mut hasher);
+                hasher.finish()
+            })
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod balancer_should {
+    use super::*;
+    use http::types::{HttpMethod, RequestBuilder};
+
+    fn upstreams(weights: &[usize]) -> Vec<Upstream> {
+        weights.iter()
+            .enumerate()
+            .map(|(i, &w)| Upstream::new(&format!("10.0.0.{}:80", i)).weighted(w))
+            .collect()
+    }
+
+    fn picks<B: Balancer>(balancer: &B, upstreams: &[Upstream], n: usize) -> Vec<usize> {
+        let request = RequestBuilder::new(HttpMethod::Get, "/").build();
+        (0..n).map(|_| balancer.pick(&request, upstreams)).collect()
+    }
+
+    #[test]
+    fn take_turns() {
+        assert_eq!(vec![0, 1, 2, 0], picks(&RoundRobin::new(), &upstreams(&[1, 1, 1]), 4));
+        assert_eq!(vec![0, 0, 1, 0, 0, 1], picks(&Weighted::new(), &upstreams(&[2, 1]), 6));
+    }
+
+    #[test]
+    fn pick_the_least_busy_upstream_for_its_weight() {
+        let upstreams = upstreams(&[1, 1, 2]);
+        let _busy = (upstreams[0].start(), upstreams[2].start(), upstreams[2].start());
+
+        assert_eq!(vec![1, 1], picks(&LeastConnections::new(), &upstreams, 2));
+        let _also_busy = upstreams[1].start();
+        assert_eq!(vec![0, 1], picks(&LeastConnections::new(), &upstreams[1..], 2));
+    }
+
+    #[test]
+    fn count_requests_until_they_finish() {
+        let upstream = Upstream::new("10.0.0.1:80");
+        let active = upstream.start();
+        assert_eq!(1, upstream.clone().active());
+        drop(active);
+        assert_eq!(0, upstream.active());
+    }
+
+    #[test]
+    fn keep_requests_with_the_same_key_on_the_same_upstream() {
+        let balancer = ConsistentHash::header("X-User");
+        let all = upstreams(&[1, 1, 1, 1]);
+        let request = |user: usize| RequestBuilder::new(HttpMethod::Get, "/")
+            .header("X-User", &user.to_string())
+            .build();
+
+        let before = (0..100).map(|u| balancer.pick(&request(u), &all)).collect::<Vec<_>>();
+        assert_eq!(before, (0..100).map(|u| balancer.pick(&request(u), &all)).collect::<Vec<_>>());
+        assert!((0..4).all(|i| before.contains(&i)));
+
+        // Only the users of the upstream that's gone move.
+        let after = (0..100).map(|u| balancer.pick(&request(u), &all[..3])).collect::<Vec<_>>();
+        for (b, a) in before.iter().zip(after) {
+            assert!(*b == 3 || *b == a);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+const STANDARD: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn alphabet(url_safe: bool) -> &'static [u8; 64] {
+    match url_safe {
+        true => URL_SAFE,
+        false => STANDARD,
+    }
+}
+
+/// Encodes `input` as base64. The URL-safe alphabet is used without
+/// padding, and the standard alphabet with it.
+#[cfg_attr(not(feature = "sessions"), allow(dead_code))]
+pub(crate) fn encode(input: &[u8], url_safe: bool) -> String {
+    let alphabet = alphabet(url_safe);
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+
+    for chunk in input.chunks(3) {
+        let n = chunk.iter()
+            .enumerate()
+            .fold(0_u32, |n, (i, b)| n | (u32::from(*b) << (16 - i * 8)));
+
+        for i in 0..chunk.len() + 1 {
+            output.push(alphabet[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+        }
+
+        if !url_safe {
+            for _ in chunk.len()..3 {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+/// Decodes base64 `input`, with or without padding. Returns `None` if
+/// `input` isn't valid base64 in the given alphabet.
+pub(crate) fn decode(input: &str, url_safe: bool) -> Option<Vec<u8>> {
+    let alphabet = alphabet(url_safe);
+    let input = input.trim_end_matches('=').as_bytes();
+    if input.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut n = 0_u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = alphabet.iter().position(|a| a == c)?;
+            n |= (value as u32) << (18 - i * 6);
+        }
+
+        for i in 0..chunk.len() - 1 {
+            output.push((n >> (16 - i * 8)) as u8);
+        }
+    }
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod base64_should {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"Aladdin:open sesame", "QWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+        ];
+
+        for &(plain, encoded) in cases {
+            assert_eq!(encoded, encode(plain, false));
+            assert_eq!(Some(plain.to_vec()), decode(encoded, false));
+            assert_eq!(Some(plain.to_vec()), decode(encoded.trim_end_matches('='), false));
+        }
+    }
+
+    #[test]
+    fn use_the_url_safe_alphabet_without_padding() {
+        assert_eq!("-_8", encode(&[0xfb, 0xff], true));
+        assert_eq!(Some(vec![0xfb, 0xff]), decode("-_8", true));
+        assert_eq!(None, decode("+/8", true));
+    }
+
+    #[test]
+    fn reject_invalid_input() {
+        assert_eq!(None, decode("Zm9v!", false));
+        assert_eq!(None, decode("Z", false));
+    }
+}
@@ -0,0 +1,384 @@
+use std::fs::File;
+use std::io::{self, Read, Take};
+use std::path::Path;
+
+use blocking::{spawn_blocking, Blocking};
+use http::types::BodyChunk;
+use pollable::{IntoPollable, Pollable};
+use result::PollResult;
+
+enum Kind {
+    Empty,
+    Full(Option<BodyChunk>),
+    Streaming(Box<dyn Pollable<Item=Option<BodyChunk>, Error=io::Error>>),
+}
+
+/// The body of a HTTP request or response.
+///
+/// A `Body` is polled repeatedly, yielding a chunk of data each
+/// time it becomes ready, until it yields `None` to signal the end
+/// of the body. A body can be fully buffered (E.g. created from a
+/// `Vec<u8>`), or streamed from any pollable producing chunks.
+pub struct Body {
+    kind: Kind,
+    content_length: Option<usize>,
+}
+
+impl Body {
+    /// Creates a body with no content.
+    pub fn empty() -> Body {
+        Body {
+            kind: Kind::Empty,
+            content_length: Some(0),
+        }
+    }
+
+    /// Creates a body that is streamed from `body`. `body` is polled
+    /// for chunks until it yields `None`. The total length isn't
+    /// known up front.
+    pub fn from_pollable<P>(body: P) -> Body where
+        P: IntoPollable<Item=Option<BodyChunk>, Error=io::Error>,
+        P::Pollable: 'static
+    {
+        Body {
+            kind: Kind::Streaming(Box::new(body.into_pollable())),
+            content_length: None,
+        }
+    }
+
+    /// Creates a body that is streamed from `body`, which will produce
+    /// exactly `content_length` bytes in total.
+    pub fn sized<P>(content_length: usize, body: P) -> Body where
+        P: IntoPollable<Item=Option<BodyChunk>, Error=io::Error>,
+        P::Pollable: 'static
+    {
+        Body {
+            content_length: Some(content_length),
+            ..Body::from_pollable(body)
+        }
+    }
+
+    /// Creates a body that is streamed from `reader` in bounded chunks.
+    /// See [`ReadBody`].
+    ///
+    /// [`ReadBody`]: struct.ReadBody.html
+    pub fn from_reader<R>(reader: R) -> Body where
+        R: Read + 'static
+    {
+        Body::from_pollable(ReadBody::new(reader))
+    }
+
+    /// The total length of the body, in bytes, if it is known up front.
+    pub fn content_length(&self) -> Option<usize> {
+        self.content_length
+    }
+
+    /// Returns the content of a fully buffered body that hasn't yet
+    /// been polled. Returns `None` for streamed bodies.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self.kind {
+            Kind::Empty => Some(&[]),
+            Kind::Full(Some(ref buffer)) => Some(buffer),
+            _ => None,
+        }
+    }
+}
+
+/// The maximum number of bytes a [`ReadBody`] produces per poll.
+///
+/// [`ReadBody`]: struct.ReadBody.html
+pub const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// A body pollable that reads from any `Read` in bounded chunks.
+///
+/// Each poll performs at most one read of up to `READ_CHUNK_SIZE`
+/// bytes, so large sources are never buffered in memory. A reader
+/// returning `WouldBlock` makes the body `NotReady`.
+pub struct ReadBody<R> {
+    reader: R,
+    chunk_size: usize,
+}
+
+impl<R> ReadBody<R> {
+    pub fn new(reader: R) -> ReadBody<R> {
+        ReadBody::with_chunk_size(reader, READ_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(reader: R, chunk_size: usize) -> ReadBody<R> {
+        assert!(chunk_size > 0, "chunk_size must be greater than zero");
+        ReadBody {
+            reader,
+            chunk_size,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> Pollable for ReadBody<R> where
+    R: Read
+{
+    type Item = Option<BodyChunk>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        let mut chunk = vec![0; self.chunk_size];
+
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(PollResult::Ready(None)),
+                Ok(n) => {
+                    chunk.truncate(n);
+                    return Ok(PollResult::Ready(Some(chunk)));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock =>
+                    return Ok(PollResult::NotReady),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// A chunk read from a [`FileBody`]'s file, and the file to read the
+/// rest from.
+///
+/// [`FileBody`]: struct.FileBody.html
+type ChunkRead = Blocking<(Take<File>, io::Result<Option<BodyChunk>>)>;
+
+/// A body pollable that streams a file.
+///
+/// The file is read a chunk at a time by the pool that [`spawn_blocking`]
+/// uses, one chunk ahead of the consumer, so a slow disk never blocks
+/// the worker polling the body. Reading stops early if the body is
+/// dropped, and fails if the file is truncated while it is being read.
+///
+/// [`spawn_blocking`]: ../../blocking/fn.spawn_blocking.html
+pub struct FileBody {
+    /// The chunk being read, and the file to read the rest from, until
+    /// the file's been read.
+    next: Option<ChunkRead>,
+    len: u64,
+}
+
+impl FileBody {
+    /// Opens the file at `path` to be streamed as a body.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileBody> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        Ok(FileBody {
+            next: Some(read_chunk(file.take(len))),
+            len,
+        })
+    }
+
+    /// The length of the file, in bytes, when it was opened.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Pollable for FileBody {
+    type Item = Option<BodyChunk>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        let (reader, chunk) = match self.next {
+            Some(ref mut next) => match next.poll()? {
+                PollResult::Ready(read) => read,
+                PollResult::NotReady => return Ok(PollResult::NotReady),
+            },
+            None => return Ok(PollResult::Ready(None)),
+        };
+
+        self.next = None;
+        let chunk = chunk?;
+        if chunk.is_some() {
+            self.next = Some(read_chunk(reader));
+        }
+        Ok(PollResult::Ready(chunk))
+    }
+}
+
+/// Reads the next chunk of `reader` on the blocking pool, handing the
+/// reader back with it.
+fn read_chunk(mut reader: Take<File>) -> ChunkRead {
+    spawn_blocking(move || {
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        let result = loop {
+            match reader.read(&mut chunk) {
+                // The file shrank after it was opened, so it can no
+                // longer fill the advertised length...
+                Ok(0) if reader.limit() > 0 =>
+                    break Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(0) => break Ok(None),
+                Ok(n) => {
+                    chunk.truncate(n);
+                    break Ok(Some(chunk));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => break Err(e),
+            }
+        };
+        (reader, result)
+    })
+}
+
+impl From<FileBody> for Body {
+    fn from(file: FileBody) -> Body {
+        Body::sized(file.len() as usize, file)
+    }
+}
+
+impl Default for Body {
+    fn default() -> Body {
+        Body::empty()
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(buffer: Vec<u8>) -> Body {
+        Body {
+            content_length: Some(buffer.len()),
+            kind: Kind::Full(Some(buffer)),
+        }
+    }
+}
+
+impl<'a> From<&'a [u8]> for Body {
+    fn from(buffer: &'a [u8]) -> Body {
+        Body::from(buffer.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(s: String) -> Body {
+        Body::from(s.into_bytes())
+    }
+}
+
+impl<'a> From<&'a str> for Body {
+    fn from(s: &'a str) -> Body {
+        Body::from(s.as_bytes())
+    }
+}
+
+impl Pollable for Body {
+    type Item = Option<BodyChunk>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        match self.kind {
+            Kind::Empty => Ok(PollResult::Ready(None)),
+            Kind::Full(ref mut buffer) => Ok(PollResult::Ready(buffer.take())),
+            Kind::Streaming(ref mut body) => body.poll(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod body_should {
+    use super::*;
+
+    #[test]
+    fn yield_buffered_content_once() {
+        let mut body = Body::from("Hello, World!");
+
+        assert_eq!(Some(13), body.content_length());
+        assert_eq!(
+            PollResult::Ready(Some(b"Hello, World!".to_vec())),
+            body.poll().unwrap()
+        );
+        assert_eq!(PollResult::Ready(None), body.poll().unwrap());
+    }
+
+    #[test]
+    fn stream_chunks_from_a_pollable() {
+        struct Chunks(Vec<BodyChunk>);
+
+        impl Pollable for Chunks {
+            type Item = Option<BodyChunk>;
+            type Error = io::Error;
+
+            fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+                if self.0.is_empty() {
+                    return Ok(PollResult::Ready(None));
+                }
+                Ok(PollResult::Ready(Some(self.0.remove(0))))
+            }
+        }
+
+        let mut body = Body::from_pollable(
+            Chunks(vec![b"Hello, ".to_vec(), b"World!".to_vec()]));
+
+        assert_eq!(None, body.content_length());
+        assert_eq!(
+            PollResult::Ready(Some(b"Hello, ".to_vec())),
+            body.poll().unwrap()
+        );
+        assert_eq!(
+            PollResult::Ready(Some(b"World!".to_vec())),
+            body.poll().unwrap()
+        );
+        assert_eq!(PollResult::Ready(None), body.poll().unwrap());
+    }
+
+    #[test]
+    fn read_in_bounded_chunks() {
+        let mut body = ReadBody::with_chunk_size(&b"Hello, World!"[..], 5);
+
+        assert_eq!(PollResult::Ready(Some(b"Hello".to_vec())), body.poll().unwrap());
+        assert_eq!(PollResult::Ready(Some(b", Wor".to_vec())), body.poll().unwrap());
+        assert_eq!(PollResult::Ready(Some(b"ld!".to_vec())), body.poll().unwrap());
+        assert_eq!(PollResult::Ready(None), body.poll().unwrap());
+    }
+
+    #[test]
+    fn not_be_ready_when_the_reader_would_block() {
+        struct Blocking;
+
+        impl Read for Blocking {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let mut body = Body::from_reader(Blocking);
+        assert_eq!(PollResult::NotReady, body.poll().unwrap());
+    }
+
+    #[test]
+    fn stream_a_file() {
+        use std::fs;
+        use std::io::Write;
+
+        let path = ::std::env::temp_dir()
+            .join(format!("server-fx-file-body-{}", ::std::process::id()));
+        let content = (0..READ_CHUNK_SIZE * 2 + 100)
+            .map(|n| n as u8)
+            .collect::<Vec<_>>();
+        fs::File::create(&path).unwrap().write_all(&content).unwrap();
+
+        let mut body = Body::from(FileBody::open(&path).unwrap());
+        assert_eq!(Some(content.len()), body.content_length());
+
+        let mut received = vec![];
+        loop {
+            match body.poll().unwrap() {
+                PollResult::Ready(Some(chunk)) => received.extend(chunk),
+                PollResult::Ready(None) => break,
+                PollResult::NotReady => ::std::thread::yield_now(),
+            }
+        }
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(content, received);
+    }
+}
@@ -0,0 +1,657 @@
+//! Sending requests to HTTP servers, e.g. from a proxy.
+//!
+//! A connection to a server is a `Framed` stream with a
+//! [`ClientCodec`], which encodes the [`RequestFrame`]s of requests,
+//! and decodes the [`ResponseFrame`]s of responses. [`Fetch`] sends a
+//! request on a connection, and resolves to the response once its head
+//! has arrived. The response's body is streamed from the connection as
+//! it's polled, so it can be passed on without first being buffered.
+//! Once it has been, the connection can be handed back, e.g. to a
+//! `Pool`, to send another request on.
+//!
+//! [`ClientCodec`]: struct.ClientCodec.html
+//! [`RequestFrame`]: enum.RequestFrame.html
+//! [`ResponseFrame`]: enum.ResponseFrame.html
+//! [`Fetch`]: struct.Fetch.html
+
+use std::cell::Cell;
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use codec::{Decode, Encode};
+use framed::Framed;
+use http::body::Body;
+use http::parser;
+use http::transport::has_connection_option;
+use http::types::{self, BodyChunk, HttpMethod, HttpVersion, Request, RequestHead,
+                  Response, ResponseHead};
+use pollable::Pollable;
+use readiness::Watch;
+use result::PollResult;
+use sink::{Sink, SinkResult};
+
+/// The units a request is broken into when it's written to a server.
+pub enum RequestFrame {
+    /// The request line and headers of a request, along with the
+    /// length of its body. A length of `None` means the body will
+    /// follow as a series of `RequestFrame::Chunk`s.
+    Head(Box<RequestHead>, Option<usize>),
+    /// Body data for a request whose length is known.
+    Data(BodyChunk),
+    /// Body data for a request whose length isn't known.
+    Chunk(BodyChunk),
+    /// Terminates a chunked body.
+    LastChunk,
+}
+
+/// How the body of a response is delimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    /// The response has no body, e.g. because it answers a `HEAD`
+    /// request, or is a `304 Not Modified`.
+    Empty,
+    /// The body is `Content-Length` bytes long.
+    Sized(usize),
+    /// The body is `chunked`.
+    Chunked,
+    /// The body ends when the server closes the connection.
+    Close,
+}
+
+/// The units a response is read in.
+pub enum ResponseFrame {
+    /// The status line and headers of a response, and how its body is
+    /// delimited.
+    Head(ResponseHead, Framing),
+    /// Some of the body of a response.
+    Data(BodyChunk),
+    /// The end of a chunked body. A sized body ends once all of it has
+    /// arrived, and a body delimited by closing the connection ends
+    /// when the connection does.
+    End,
+}
+
+/// What the codec expects to decode next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reading {
+    Head,
+    Sized(usize),
+    /// The size line of the next chunk.
+    ChunkSize,
+    ChunkData(usize),
+    /// The line break that follows a chunk's data.
+    ChunkEnd,
+    Trailers,
+    Close,
+}
+
+/// Whether `name` is a header that delimits a body, which a codec
+/// writes itself.
+fn is_framing(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Content-Length") ||
+        name.eq_ignore_ascii_case("Transfer-Encoding")
+}
+
+/// How the body of `head` is delimited, if it answers a `HEAD` request
+/// when `head_request` is set. `None` if its `Content-Length` is
+/// malformed.
+fn framing(head: &ResponseHead, head_request: bool) -> Option<Framing> {
+    let status = head.status().as_u16();
+    if head_request || head.status().is_informational() || status == 204 || status == 304 {
+        return Some(Framing::Empty);
+    }
+
+    let chunked = head.header_value("Transfer-Encoding")
+        .and_then(|v| v.rsplit(',').next())
+        .map(|c| c.trim().eq_ignore_ascii_case("chunked"));
+    match (chunked, head.header_value("Content-Length")) {
+        (Some(true), _) => Some(Framing::Chunked),
+        (Some(false), _) | (None, None) => Some(Framing::Close),
+        (None, Some(length)) => match length.trim().parse() {
+            Ok(0) => Some(Framing::Empty),
+            Ok(n) => Some(Framing::Sized(n)),
+            Err(_) => None,
+        },
+    }
+}
+
+/// Takes the line at the start of `buffer`, without its line break, if
+/// all of it has arrived.
+fn take_line(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let end = buffer.windows(2).position(|w| w == b"\r\n")?;
+    let mut line: Vec<u8> = buffer.drain(..end + 2).collect();
+    line.truncate(end);
+    Some(line)
+}
+
+/// A codec that encodes the [`RequestFrame`]s of HTTP requests, and
+/// decodes the [`ResponseFrame`]s of their responses.
+///
+/// The codec writes the `Content-Length` (or `Transfer-Encoding:
+/// chunked`) header matching the body of each request, in place of any
+/// the request carries. Interim responses, such as `100 Continue`, are
+/// skipped.
+///
+/// [`RequestFrame`]: enum.RequestFrame.html
+/// [`ResponseFrame`]: enum.ResponseFrame.html
+pub struct ClientCodec {
+    reading: Cell<Reading>,
+    /// Whether the request being answered is a `HEAD` request, whose
+    /// response has no body.
+    head_request: Cell<bool>,
+    /// Why the last response couldn't be decoded.
+    error: Cell<Option<&'static str>>,
+}
+
+impl Default for ClientCodec {
+    fn default() -> ClientCodec {
+        ClientCodec {
+            reading: Cell::new(Reading::Head),
+            head_request: Cell::new(false),
+            error: Cell::new(None),
+        }
+    }
+}
+
+impl ClientCodec {
+    pub fn new() -> ClientCodec {
+        ClientCodec::default()
+    }
+
+    fn decode_head(&self, buffer: &mut Vec<u8>) -> Option<ResponseFrame> {
+        loop {
+            let (head, _) = types::parse_response(buffer)?.into_parts();
+            if head.status().is_informational() && head.status().as_u16() != 101 {
+                continue;
+            }
+
+            let framing = match framing(&head, self.head_request.get()) {
+                Some(framing) => framing,
+                None => {
+                    self.error.set(Some("Malformed Content-Length"));
+                    return None;
+                },
+            };
+            self.reading.set(match framing {
+                Framing::Empty => Reading::Head,
+                Framing::Sized(n) => Reading::Sized(n),
+                Framing::Chunked => Reading::ChunkSize,
+                Framing::Close => Reading::Close,
+            });
+            return Some(ResponseFrame::Head(head, framing));
+        }
+    }
+}
+
+impl Decode for ClientCodec {
+    type Item = ResponseFrame;
+
+    fn decode(&self, buffer: &mut Vec<u8>) -> Option<ResponseFrame> {
+        loop {
+            match self.reading.get() {
+                Reading::Head => return self.decode_head(buffer),
+                Reading::Sized(remaining) => {
+                    let n = cmp::min(remaining, buffer.len());
+                    if n == 0 {
+                        return None;
+                    }
+                    self.reading.set(match remaining - n {
+                        0 => Reading::Head,
+                        remaining => Reading::Sized(remaining),
+                    });
+                    return Some(ResponseFrame::Data(buffer.drain(..n).collect()));
+                },
+                Reading::ChunkSize => {
+                    let line = take_line(buffer)?;
+                    let size = ::std::str::from_utf8(&line).ok()
+                        .and_then(|l| l.split(';').next())
+                        .and_then(|s| usize::from_str_radix(s.trim(), 16).ok());
+                    match size {
+                        Some(0) => self.reading.set(Reading::Trailers),
+                        Some(size) => self.reading.set(Reading::ChunkData(size)),
+                        None => {
+                            self.error.set(Some("Malformed chunk size"));
+                            return None;
+                        },
+                    }
+                },
+                Reading::ChunkData(remaining) => {
+                    let n = cmp::min(remaining, buffer.len());
+                    if n == 0 {
+                        return None;
+                    }
+                    self.reading.set(match remaining - n {
+                        0 => Reading::ChunkEnd,
+                        remaining => Reading::ChunkData(remaining),
+                    });
+                    return Some(ResponseFrame::Data(buffer.drain(..n).collect()));
+                },
+                Reading::ChunkEnd => {
+                    if !take_line(buffer)?.is_empty() {
+                        self.error.set(Some("Chunk is longer than its size"));
+                        return None;
+                    }
+                    self.reading.set(Reading::ChunkSize);
+                },
+                Reading::Trailers => if take_line(buffer)?.is_empty() {
+                    self.reading.set(Reading::Head);
+                    return Some(ResponseFrame::End);
+                },
+                Reading::Close => return match buffer.is_empty() {
+                    true => None,
+                    false => Some(ResponseFrame::Data(mem::take(buffer))),
+                },
+            }
+        }
+    }
+
+    /// A response is malformed if its head is complete, but can't be
+    /// parsed, or its body isn't framed as it should be.
+    fn validate(&self, buffer: &[u8]) -> io::Result<()> {
+        if let Some(reason) = self.error.take() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, reason));
+        }
+
+        if self.reading.get() != Reading::Head || !self.head_complete(buffer) {
+            return Ok(());
+        }
+
+        let mut headers = [parser::Header::default(); types::MAX_HEADERS];
+        let mut response = parser::Response::new(&mut headers);
+        match response.parse(buffer) {
+            Some(_) if types::is_well_formed(&response) => Ok(()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Malformed response")),
+        }
+    }
+
+    /// The head ends with an empty line.
+    fn head_complete(&self, buffer: &[u8]) -> bool {
+        buffer.windows(4).any(|w| w == b"\r\n\r\n")
+    }
+}
+
+impl Encode for ClientCodec {
+    type Item = RequestFrame;
+
+    fn encode(&self, frame: RequestFrame, buffer: &mut Vec<u8>) -> io::Result<()> {
+        match frame {
+            RequestFrame::Head(request, length) => {
+                let method = request.method();
+                let uri = request.uri();
+                let target = match (method, uri.path()) {
+                    (HttpMethod::Connect, _) => uri.authority().unwrap_or("").to_owned(),
+                    (_, "") => String::from("/"),
+                    _ => uri.path_and_query(),
+                };
+
+                let mut s = format!("{} {} {}\r\n", method, target, request.version());
+                for (n, v) in request.headers().filter(|&(n, _)| !is_framing(n)) {
+                    s.push_str(&format!("{}: {}\r\n", n, v));
+                }
+                match length {
+                    // Requests that don't usually have a body aren't
+                    // given an empty one.
+                    Some(0) if !method_has_body(method) => {},
+                    Some(n) => s.push_str(&format!("Content-Length: {}\r\n", n)),
+                    None => s.push_str("Transfer-Encoding: chunked\r\n"),
+                }
+                s.push_str("\r\n");
+
+                self.head_request.set(method == HttpMethod::Head);
+                buffer.extend(s.as_bytes());
+            },
+            RequestFrame::Data(data) => buffer.extend(data),
+            RequestFrame::Chunk(data) => {
+                buffer.extend(format!("{:x}\r\n", data.len()).as_bytes());
+                buffer.extend(data);
+                buffer.extend(b"\r\n");
+            },
+            RequestFrame::LastChunk => buffer.extend(b"0\r\n\r\n"),
+        }
+
+        Ok(())
+    }
+}
+
+fn method_has_body(method: HttpMethod) -> bool {
+    matches!(method, HttpMethod::Post | HttpMethod::Put | HttpMethod::Patch)
+}
+
+/// Whether a message of `version`, whose `Connection` header is
+/// `connection`, leaves its connection open once it's been sent.
+fn keeps_alive(version: HttpVersion, connection: Option<&str>) -> bool {
+    let connection = connection.unwrap_or("");
+    if has_connection_option(connection, "close") {
+        return false;
+    }
+
+    match version {
+        HttpVersion::Http11 => true,
+        HttpVersion::Http1 => has_connection_option(connection, "keep-alive"),
+    }
+}
+
+/// Takes a connection once it's free for another request.
+type Release<S> = Box<dyn FnOnce(Framed<S, ClientCodec>)>;
+
+/// Sends a request on a connection to a server, and resolves to the
+/// response once its head has arrived. The response's body is read
+/// from the connection as it's polled. While it waits for the server,
+/// the connection's stream is watched, if it's a TCP stream.
+pub struct Fetch<S> {
+    state: FetchState<S>,
+    /// Whether the request leaves the connection open.
+    keep_alive: bool,
+    release: Option<Release<S>>,
+    watch: Watch,
+}
+
+enum FetchState<S> {
+    Sending {
+        connection: Framed<S, ClientCodec>,
+        pending: Option<RequestFrame>,
+        body: Option<Body>,
+        chunked: bool,
+    },
+    Receiving(Framed<S, ClientCodec>),
+    Done,
+}
+
+impl<S> Fetch<S> {
+    pub fn new(connection: Framed<S, ClientCodec>, request: Request) -> Fetch<S> {
+        let (head, body) = request.into_parts();
+        let length = body.content_length();
+        let keep_alive = keeps_alive(head.version(), head.header_value("Connection"));
+
+        Fetch {
+            state: FetchState::Sending {
+                connection,
+                pending: Some(RequestFrame::Head(Box::new(head), length)),
+                body: Some(body),
+                chunked: length.is_none(),
+            },
+            keep_alive,
+            release: None,
+            watch: Watch::new(),
+        }
+    }
+
+    /// Hands the connection to `f` once the response's body has been
+    /// read, if neither the request nor the response closes it. E.g. to
+    /// return it to a `Pool`.
+    pub fn on_release<F>(mut self, f: F) -> Fetch<S> where
+        F: FnOnce(Framed<S, ClientCodec>) + 'static
+    {
+        self.release = Some(Box::new(f));
+        self
+    }
+}
+
+impl<S> Pollable for Fetch<S> where
+    S: Read + Write + 'static
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Response>, io::Error> {
+        loop {
+            self.state = match self.state {
+                FetchState::Sending {
+                    ref mut connection,
+                    ref mut pending,
+                    ref mut body,
+                    chunked,
+                } => {
+                    if let Some(frame) = pending.take() {
+                        if let SinkResult::NotReady(frame) = connection.start_send(frame)? {
+                            *pending = Some(frame);
+                            if let PollResult::NotReady = connection.poll_complete()? {
+                                self.watch.watch(connection.get_ref());
+                                return Ok(PollResult::NotReady);
+                            }
+                        }
+                        continue;
+                    }
+
+                    match body.as_mut().map(Pollable::poll) {
+                        Some(Err(e)) => return Err(e),
+                        Some(Ok(PollResult::NotReady)) => {
+                            connection.poll_complete()?;
+                            return Ok(PollResult::NotReady);
+                        },
+                        Some(Ok(PollResult::Ready(Some(ref chunk)))) if chunk.is_empty() =>
+                            continue,
+                        Some(Ok(PollResult::Ready(Some(chunk)))) => {
+                            *pending = Some(match chunked {
+                                true => RequestFrame::Chunk(chunk),
+                                false => RequestFrame::Data(chunk),
+                            });
+                            continue;
+                        },
+                        Some(Ok(PollResult::Ready(None))) => {
+                            *body = None;
+                            if chunked {
+                                *pending = Some(RequestFrame::LastChunk);
+                            }
+                            continue;
+                        },
+                        None => match connection.poll_complete()? {
+                            PollResult::Ready(()) => match mem::replace(&mut self.state, FetchState::Done) {
+                                FetchState::Sending { connection, .. } =>
+                                    FetchState::Receiving(connection),
+                                _ => unreachable!(),
+                            },
+                            PollResult::NotReady => {
+                                self.watch.watch(connection.get_ref());
+                                return Ok(PollResult::NotReady);
+                            },
+                        },
+                    }
+                },
+                FetchState::Receiving(ref mut connection) => match connection.poll()? {
+                    PollResult::Ready(Some(ResponseFrame::Head(head, framing))) => {
+                        let connection = match mem::replace(&mut self.state, FetchState::Done) {
+                            FetchState::Receiving(connection) => connection,
+                            _ => unreachable!(),
+                        };
+                        let release = match self.keep_alive &&
+                            framing != Framing::Close &&
+                            keeps_alive(head.version(), head.header_value("Connection"))
+                        {
+                            true => self.release.take(),
+                            false => None,
+                        };
+                        return Ok(PollResult::Ready(Response::from_parts(
+                            head, streamed(connection, framing, release))));
+                    },
+                    PollResult::Ready(Some(_)) => return Err(io::Error::new(
+                        io::ErrorKind::InvalidData, "Body data arrived before a response")),
+                    PollResult::Ready(None) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    PollResult::NotReady => {
+                        self.watch.watch(connection.get_ref());
+                        return Ok(PollResult::NotReady);
+                    },
+                },
+                FetchState::Done => panic!("Poll called on finished fetch"),
+            };
+        }
+    }
+}
+
+/// A body read from the connection it arrived on. The connection is
+/// passed to `release`, if there is one, once the body has been read.
+fn streamed<S>(connection: Framed<S, ClientCodec>,
+               framing: Framing,
+               release: Option<Release<S>>)
+    -> Body where
+    S: Read + 'static
+{
+    let mut body = Streamed {
+        connection: Some(connection),
+        framing,
+        release,
+        watch: Watch::new(),
+    };
+
+    match framing {
+        Framing::Empty => {
+            body.finish();
+            Body::empty()
+        },
+        Framing::Sized(n) => Body::sized(n, body),
+        Framing::Chunked | Framing::Close => Body::from_pollable(body),
+    }
+}
+
+/// The body of a response, as it's read from the connection. The
+/// connection is let go once the body has been read.
+struct Streamed<S> {
+    connection: Option<Framed<S, ClientCodec>>,
+    framing: Framing,
+    release: Option<Release<S>>,
+    watch: Watch,
+}
+
+impl<S> Streamed<S> {
+    fn finish(&mut self) {
+        if let (Some(connection), Some(release)) = (self.connection.take(), self.release.take()) {
+            release(connection);
+        }
+    }
+}
+
+impl<S: Read + 'static> Pollable for Streamed<S> {
+    type Item = Option<BodyChunk>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Option<BodyChunk>>, io::Error> {
+        let frame = match self.connection {
+            Some(ref mut connection) => connection.poll()?,
+            None => return Ok(PollResult::Ready(None)),
+        };
+
+        match frame {
+            PollResult::Ready(Some(ResponseFrame::Data(chunk))) => {
+                if let Framing::Sized(ref mut remaining) = self.framing {
+                    *remaining -= chunk.len();
+                    if *remaining == 0 {
+                        self.finish();
+                    }
+                }
+                Ok(PollResult::Ready(Some(chunk)))
+            },
+            PollResult::Ready(Some(ResponseFrame::End)) => {
+                self.finish();
+                Ok(PollResult::Ready(None))
+            },
+            PollResult::Ready(Some(ResponseFrame::Head(..))) => Err(io::Error::new(
+                io::ErrorKind::InvalidData, "A response arrived before the last had ended")),
+            PollResult::Ready(None) if self.framing == Framing::Close => {
+                self.connection = None;
+                Ok(PollResult::Ready(None))
+            },
+            PollResult::Ready(None) => Err(io::ErrorKind::UnexpectedEof.into()),
+            PollResult::NotReady => {
+                if let Some(ref connection) = self.connection {
+                    self.watch.watch(connection.get_ref());
+                }
+                Ok(PollResult::NotReady)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod client_codec_should {
+    use super::*;
+    use http::types::RequestBuilder;
+
+    fn decode_all(codec: &ClientCodec, mut buffer: Vec<u8>) -> Vec<ResponseFrame> {
+        let mut frames = vec![];
+        while let Some(frame) = codec.decode(&mut buffer) {
+            frames.push(frame);
+        }
+        assert!(buffer.is_empty());
+        frames
+    }
+
+    fn body(frames: &[ResponseFrame]) -> Vec<u8> {
+        frames.iter()
+            .filter_map(|f| match *f {
+                ResponseFrame::Data(ref data) => Some(&data[..]),
+                _ => None,
+            })
+            .flat_map(|d| d.iter().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn encode_requests_with_framing_matching_their_bodies() {
+        let codec = ClientCodec::new();
+        let (head, _) = RequestBuilder::new(HttpMethod::Post, "http://example.com/search?q=rust")
+            .header("Content-Length", "100")
+            .build()
+            .into_parts();
+
+        let mut buffer = vec![];
+        codec.encode(RequestFrame::Head(Box::new(head), None), &mut buffer).unwrap();
+        codec.encode(RequestFrame::Chunk(b"Hello".to_vec()), &mut buffer).unwrap();
+        codec.encode(RequestFrame::LastChunk, &mut buffer).unwrap();
+
+        assert_eq!(
+            "POST /search?q=rust HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n0\r\n\r\n",
+            String::from_utf8(buffer).unwrap());
+    }
+
+    #[test]
+    fn decode_responses_however_their_bodies_are_framed() {
+        let codec = ClientCodec::new();
+        let frames = decode_all(&codec, b"HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello\
+            HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nHi\r\n3;x=y\r\n!!!\r\n0\r\nA: b\r\n\r\n\
+            HTTP/1.1 200 OK\r\n\r\nUntil closed".to_vec());
+
+        let framings = frames.iter()
+            .filter_map(|f| match *f {
+                ResponseFrame::Head(ref head, framing) => Some((head.status().as_u16(), framing)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(200, Framing::Sized(5)), (200, Framing::Chunked), (200, Framing::Close)],
+                   framings);
+        assert_eq!(b"HelloHi!!!Until closed".to_vec(), body(&frames));
+        assert_eq!(1, frames.iter().filter(|f| matches!(f, ResponseFrame::End)).count());
+    }
+
+    #[test]
+    fn expect_no_body_in_answer_to_a_head_request() {
+        let codec = ClientCodec::new();
+        let (head, _) = RequestBuilder::new(HttpMethod::Head, "/").build().into_parts();
+        codec.encode(RequestFrame::Head(Box::new(head), Some(0)), &mut vec![]).unwrap();
+
+        let frames = decode_all(&codec, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n".to_vec());
+        assert!(matches!(frames[..], [ResponseFrame::Head(_, Framing::Empty)]));
+    }
+
+    #[test]
+    fn refuse_malformed_response_heads() {
+        for raw in &[&b"HTTP/1.1 OK Fine\r\n\r\n"[..],
+                     &b"HTTP/1.1 20 OK\r\n\r\n"[..],
+                     &b"HTTP/1.1 200 OK\r\nX-A: \xff\r\n\r\n"[..]] {
+            let codec = ClientCodec::new();
+            let mut buffer = raw.to_vec();
+            assert!(codec.decode(&mut buffer).is_none());
+            assert_eq!(io::ErrorKind::InvalidData, codec.validate(&buffer).unwrap_err().kind());
+        }
+    }
+
+    #[test]
+    fn refuse_malformed_chunks() {
+        let codec = ClientCodec::new();
+        let mut buffer = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n".to_vec();
+        assert!(codec.decode(&mut buffer).is_some());
+        assert!(codec.decode(&mut buffer).is_none());
+        assert_eq!(io::ErrorKind::InvalidData, codec.validate(&buffer).unwrap_err().kind());
+    }
+}
@@ -0,0 +1,614 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::SystemTime;
+
+use codec::{Decode, Encode};
+use http::date::http_date;
+use http::parser;
+use http::transport::Frame;
+use http::types::{self, HttpVersion, ResponseHead, StatusCode};
+
+/// Why a request was refused before it reached a handler: it was too
+/// large, or its HTTP version isn't supported. The transport answers it
+/// with the refusal's `status`, rather than `400 Bad Request`.
+#[derive(Debug)]
+pub struct Refused(StatusCode);
+
+impl Refused {
+    pub fn status(&self) -> StatusCode {
+        self.0
+    }
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Request refused: {}", self.0)
+    }
+}
+
+impl Error for Refused {}
+
+fn refused(status: StatusCode) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Refused(status))
+}
+
+fn malformed(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn conflicting_framing(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("Conflicting framing headers: {}", reason))
+}
+
+/// Responses with these statuses never have a body, so they don't
+/// carry any framing headers.
+fn forbids_body(head: &ResponseHead) -> bool {
+    let status = head.status();
+    status.is_informational() || status.as_u16() == 204 || status.as_u16() == 304
+}
+
+/// Works out which framing header, if any, needs to be added to
+/// `head` for a body of `length` bytes (`None` meaning chunked, or
+/// close-delimited for HTTP/1.0).
+/// Framing headers already present on `head` are accepted as long as
+/// they agree with the body.
+fn framing_header(head: &ResponseHead, length: Option<usize>)
+    -> io::Result<Option<(&'static str, String)>>
+{
+    let content_length = head.header_value("Content-Length");
+    let transfer_encoding = head.header_value("Transfer-Encoding");
+
+    if content_length.is_some() && transfer_encoding.is_some() {
+        return Err(conflicting_framing(
+            "both Content-Length and Transfer-Encoding are set"));
+    }
+
+    if forbids_body(head) {
+        return match (length, transfer_encoding) {
+            (Some(0), None) => Ok(None),
+            // A `304`'s `Content-Length` is that of the response it
+            // stands in for, so whatever the handler set is kept...
+            (_, None) if head.status() == StatusCode::NotModified => Ok(None),
+            _ => Err(conflicting_framing(
+                "the response status doesn't allow a body")),
+        };
+    }
+
+    match length {
+        Some(n) => {
+            if transfer_encoding.is_some() {
+                return Err(conflicting_framing(
+                    "Transfer-Encoding is set on a sized body"));
+            }
+
+            match content_length.map(|v| v.trim().parse::<usize>()) {
+                None => Ok(Some(("Content-Length", format!("{}", n)))),
+                Some(Ok(v)) if v == n => Ok(None),
+                Some(_) => Err(conflicting_framing(
+                    "Content-Length doesn't match the body")),
+            }
+        },
+        None if head.version() == HttpVersion::Http1 => {
+            // HTTP/1.0 has no chunked encoding, so the body is
+            // delimited by closing the connection...
+            match (content_length, transfer_encoding) {
+                (None, None) => Ok(None),
+                _ => Err(conflicting_framing(
+                    "framing headers are set on a close-delimited body")),
+            }
+        },
+        None => {
+            if content_length.is_some() {
+                return Err(conflicting_framing(
+                    "Content-Length is set on a chunked body"));
+            }
+
+            match transfer_encoding {
+                None => Ok(Some(("Transfer-Encoding", String::from("chunked")))),
+                Some(v) if v.rsplit(',')
+                    .next()
+                    .map(|c| c.trim().eq_ignore_ascii_case("chunked"))
+                    .unwrap_or(false) => Ok(None),
+                Some(_) => Err(conflicting_framing(
+                    "Transfer-Encoding doesn't end with chunked")),
+            }
+        },
+    }
+}
+
+/// A codec that decodes HTTP requests and encodes the [`Frame`]s of
+/// HTTP responses.
+///
+/// When encoding the head of a response, `HttpCodec` adds the
+/// `Content-Length` (or `Transfer-Encoding: chunked`) header matching
+/// the body, a `Date` header, and the configured `Server` header,
+/// unless the response already carries them. Responses whose framing
+/// headers disagree with their body are refused with an
+/// `InvalidData` error.
+///
+/// Requests are buffered in full before they're decoded, so their size
+/// is limited. A request whose head is larger than `max_head_size` is
+/// refused with `431 Request Header Fields Too Large`, and one whose
+/// body is larger than `max_body_size` with `413 Payload Too Large`, as
+/// soon as either is known. What's been checked of a request is kept
+/// until it's decoded, so an `HttpCodec` decodes the requests of one
+/// stream. A request for an HTTP version other than
+/// 1.x is refused with `505 HTTP Version Not Supported`, and one whose
+/// version is malformed with `400 Bad Request`.
+///
+/// [`Frame`]: ../transport/enum.Frame.html
+pub struct HttpCodec {
+    server: Option<String>,
+    max_head_size: usize,
+    max_body_size: usize,
+    checked: Cell<Checked>,
+}
+
+/// How much of the request at the start of the buffer has been
+/// checked, so that what's checked isn't checked again as the rest of
+/// the request arrives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Checked {
+    /// None of it, as its head hasn't all arrived.
+    Head,
+    /// The `head` bytes of its head, which gives its body's `size`.
+    Sized { head: usize, size: usize },
+    /// The `head` bytes of its head, and as much of its chunked body
+    /// as `progress` has got through.
+    Chunked { head: usize, progress: types::ChunkedProgress },
+}
+
+impl Checked {
+    /// The number of bytes of the request that have been checked.
+    fn len(&self) -> usize {
+        match *self {
+            Checked::Head => 0,
+            Checked::Sized { head, .. } => head,
+            Checked::Chunked { head, ref progress } => head + progress.position(),
+        }
+    }
+}
+
+impl Default for HttpCodec {
+    fn default() -> HttpCodec {
+        HttpCodec {
+            server: None,
+            max_head_size: 16 * 1024,
+            max_body_size: 1024 * 1024,
+            checked: Cell::new(Checked::Head),
+        }
+    }
+}
+
+impl HttpCodec {
+    pub fn new() -> HttpCodec {
+        HttpCodec::default()
+    }
+
+    /// Sets the value of the `Server` header added to each response.
+    pub fn server(mut self, name: &str) -> HttpCodec {
+        self.server = Some(String::from(name));
+        self
+    }
+
+    /// Sets the largest head, in bytes, a request can have. Defaults to
+    /// 16 KiB.
+    pub fn max_head_size(mut self, size: usize) -> HttpCodec {
+        self.max_head_size = size;
+        self
+    }
+
+    /// Sets the largest body, in bytes, a request can have. Defaults to
+    /// 1 MiB.
+    pub fn max_body_size(mut self, size: usize) -> HttpCodec {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Checks as much of the request at the start of `buffer` as has
+    /// arrived since it was last checked, returning whether all of it
+    /// has. What's been checked is forgotten once the request's refused.
+    fn check(&self, buffer: &[u8]) -> io::Result<bool> {
+        let checked = match self.checked.get() {
+            // A buffer that's shorter than what was checked isn't the
+            // one that was checked...
+            checked if checked.len() > buffer.len() => Checked::Head,
+            checked => checked,
+        };
+
+        let result = self.check_from(checked, buffer);
+        self.checked.set(result.as_ref().map(|&(checked, _)| checked).unwrap_or(Checked::Head));
+        result.map(|(_, complete)| complete)
+    }
+
+    fn check_from(&self, checked: Checked, buffer: &[u8]) -> io::Result<(Checked, bool)> {
+        let checked = match checked {
+            Checked::Head => self.check_head(buffer)?,
+            checked => checked,
+        };
+
+        let (checked, body_size, complete) = match checked {
+            Checked::Head => return Ok((checked, false)),
+            Checked::Sized { head, size } => (checked, size, buffer.len() - head >= size),
+            Checked::Chunked { head, mut progress } => {
+                let (size, complete) = types::chunked_body_size(&buffer[head..], &mut progress)
+                    .ok_or_else(|| malformed("Malformed chunked body"))?;
+                (Checked::Chunked { head, progress }, size, complete)
+            },
+        };
+
+        match body_size > self.max_body_size {
+            true => Err(refused(StatusCode::PayloadTooLarge)),
+            false => Ok((checked, complete)),
+        }
+    }
+
+    /// Checks the head of the request at the start of `buffer`, once
+    /// all of it has arrived. A request is malformed if its head can't
+    /// be parsed, or uses a method or version that isn't supported, or
+    /// isn't UTF-8, or its body isn't framed as it should be.
+    fn check_head(&self, buffer: &[u8]) -> io::Result<Checked> {
+        let head = match buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(n) => n + 4,
+            None if buffer.len() > self.max_head_size =>
+                return Err(refused(StatusCode::RequestHeaderFieldsTooLarge)),
+            None => return Ok(Checked::Head),
+        };
+        if head > self.max_head_size {
+            return Err(refused(StatusCode::RequestHeaderFieldsTooLarge));
+        }
+
+        let mut headers = [parser::Header::default(); types::MAX_HEADERS];
+        let mut request = parser::Request::new(&mut headers);
+        match request.parse(buffer) {
+            Some(_) if types::is_unsupported_version(request.version()) =>
+                return Err(refused(StatusCode::HttpVersionNotSupported)),
+            Some(_) if types::is_supported(&request) => {},
+            _ => return Err(malformed("Malformed request")),
+        }
+
+        match types::body_framing(request.headers()) {
+            Some(types::BodyFraming::Sized(size)) => Ok(Checked::Sized { head, size }),
+            Some(types::BodyFraming::Chunked) =>
+                Ok(Checked::Chunked { head, progress: types::ChunkedProgress::default() }),
+            None => Err(malformed("Malformed body framing")),
+        }
+    }
+}
+
+impl Decode for HttpCodec {
+    type Item = types::Request;
+
+    /// Requests are only parsed once all of them has arrived. Those
+    /// that aren't valid are left for `validate` to refuse.
+    fn decode(&self, buffer: &mut Vec<u8>) -> Option<Self::Item> {
+        match self.check(buffer) {
+            Ok(true) => {
+                self.checked.set(Checked::Head);
+                types::parse_request(buffer)
+            },
+            _ => None,
+        }
+    }
+
+    /// Only what's arrived since the request was last checked is
+    /// checked. See `check_head` for what makes a request malformed.
+    fn validate(&self, buffer: &[u8]) -> io::Result<()> {
+        self.check(buffer).map(|_| ())
+    }
+
+    /// The head ends with an empty line.
+    fn head_complete(&self, buffer: &[u8]) -> bool {
+        buffer.windows(4).any(|w| w == b"\r\n\r\n")
+    }
+}
+
+impl Encode for HttpCodec {
+    type Item = Frame;
+
+    fn encode(&self, frame: Self::Item, buffer: &mut Vec<u8>) -> io::Result<()> {
+        match frame {
+            Frame::Head(response, length) => {
+                let framing = framing_header(&response, length)?;
+
+                let mut s = format!("{} {} {}\r\n",
+                                response.version(),
+                                response.status_code(),
+                                response.status_text());
+                for (n, v) in response.headers() {
+                    s.push_str(&format!("{}: {}\r\n", n, v));
+                }
+                if let Some((n, v)) = framing {
+                    s.push_str(&format!("{}: {}\r\n", n, v));
+                }
+                if response.header_value("Date").is_none() {
+                    s.push_str(&format!("Date: {}\r\n", http_date(SystemTime::now())));
+                }
+                if let Some(ref server) = self.server {
+                    if response.header_value("Server").is_none() {
+                        s.push_str(&format!("Server: {}\r\n", server));
+                    }
+                }
+                s.push_str("\r\n");
+
+                buffer.extend(s.as_bytes());
+            },
+            Frame::Data(data) => buffer.extend(data),
+            Frame::Chunk(data) => {
+                buffer.extend(format!("{:x}\r\n", data.len()).as_bytes());
+                buffer.extend(data);
+                buffer.extend(b"\r\n");
+            },
+            Frame::LastChunk => buffer.extend(b"0\r\n\r\n"),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod http_codec_should {
+    use super::*;
+    use http::types::{ResponseBuilder, StatusCode};
+    use pollable::Pollable;
+    use result::PollResult;
+
+    fn encode_head(codec: &HttpCodec, builder: ResponseBuilder, length: Option<usize>)
+        -> io::Result<String>
+    {
+        let (head, _) = builder.build().into_parts();
+        let mut buffer = vec![];
+        codec.encode(Frame::Head(head, length), &mut buffer)?;
+        Ok(String::from_utf8(buffer).unwrap())
+    }
+
+    #[test]
+    fn reject_malformed_request_heads() {
+        let validate = |buffer: &[u8]| HttpCodec::new().validate(buffer);
+        assert!(validate(b"GET / HTTP/1.1\r\nHost: a").is_ok());
+        assert!(validate(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab").is_ok());
+
+        let error = validate(b"NONSENSE\r\n\r\n").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert!(validate(b"TRACE / HTTP/1.1\r\n\r\n").is_err());
+        assert!(validate(b"GET /\xff HTTP/1.1\r\n\r\n").is_err());
+        assert!(validate(b"GET / HTTP/1.1\r\nX-A: \xff\r\n\r\n").is_err());
+
+        let mut head = String::from("GET / HTTP/1.1\r\n");
+        for i in 0..types::MAX_HEADERS + 1 {
+            head.push_str(&format!("X-{}: {}\r\n", i, i));
+        }
+        head.push_str("\r\n");
+        assert!(validate(head.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn reject_requests_whose_body_could_be_read_more_than_one_way() {
+        let requests: &[&[u8]] = &[
+            b"POST / HTTP/1.1\r\nContent-Length: 1x\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello",
+            b"POST / HTTP/1.1\r\nContent-Length: 5, 0\r\n\r\nhello",
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+5\r\nhello\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n 5 \r\nhello\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n;x\r\nhello\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhello\r\n0\r\n\r\n",
+        ];
+
+        for raw in requests {
+            let codec = HttpCodec::new();
+            let mut buffer = raw.to_vec();
+            assert!(codec.decode(&mut buffer).is_none());
+            let error = codec.validate(&buffer).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+            assert!(error.get_ref().unwrap().downcast_ref::<Refused>().is_none());
+        }
+
+        let mut buffer = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello".to_vec();
+        assert!(HttpCodec::new().decode(&mut buffer).is_some());
+    }
+
+    #[test]
+    fn refuse_requests_that_are_too_large() {
+        let codec = || HttpCodec::new().max_head_size(64).max_body_size(8);
+        let status = |buffer: &[u8]| codec().validate(buffer).err()
+            .map(|e| e.get_ref().unwrap().downcast_ref::<Refused>().unwrap().status());
+
+        assert_eq!(None, status(b"POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\n"));
+        assert_eq!(Some(StatusCode::PayloadTooLarge),
+                   status(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n"));
+        assert_eq!(Some(StatusCode::PayloadTooLarge),
+                   status(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n"));
+        assert_eq!(Some(StatusCode::PayloadTooLarge),
+                   status(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                            5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"));
+
+        let long = format!("GET / HTTP/1.1\r\nX-Long: {}", "a".repeat(64));
+        assert_eq!(Some(StatusCode::RequestHeaderFieldsTooLarge), status(long.as_bytes()));
+        let long = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(48));
+        assert_eq!(Some(StatusCode::RequestHeaderFieldsTooLarge), status(long.as_bytes()));
+
+        let mut buffer = b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n123456789".to_vec();
+        assert!(codec().decode(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn refuse_versions_other_than_http_1() {
+        let status = |buffer: &[u8]| HttpCodec::new().validate(buffer).err()
+            .map(|e| e.get_ref().unwrap().downcast_ref::<Refused>().map(Refused::status));
+
+        assert_eq!(None, status(b"GET / HTTP/1.0\r\n\r\n"));
+        assert_eq!(None, status(b"GET / HTTP/1.9\r\n\r\n"));
+        assert_eq!(Some(Some(StatusCode::HttpVersionNotSupported)),
+                   status(b"GET / HTTP/2.0\r\n\r\n"));
+        assert_eq!(Some(None), status(b"GET / HTTP/1.1x\r\n\r\n"));
+        assert_eq!(Some(None), status(b"GET / HTTP/1.\r\n\r\n"));
+        assert_eq!(Some(None), status(b"GET / FTP/1.1\r\n\r\n"));
+
+        let mut buffer = b"GET / HTTP/1.1x\r\n\r\n".to_vec();
+        assert!(HttpCodec::new().decode(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn check_a_chunked_body_from_where_it_left_off() {
+        let codec = HttpCodec::new();
+        let mut request = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for _ in 0..100 {
+            request.extend(b"1;ext\r\na\r\n");
+        }
+        request.extend(b"0\r\nX-Trailer: 1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+
+        let mut buffer = vec![];
+        let mut decoded = vec![];
+        for piece in request.chunks(7) {
+            buffer.extend(piece);
+            while let Some(request) = codec.decode(&mut buffer) {
+                decoded.push(request);
+            }
+            codec.validate(&buffer).unwrap();
+
+            // Only the chunk, or line, that's part of the way through
+            // arriving is left to be checked.
+            if let Checked::Chunked { .. } = codec.checked.get() {
+                assert!(buffer.len() - codec.checked.get().len() < 16);
+            }
+        }
+
+        assert_eq!(2, decoded.len());
+        assert_eq!(PollResult::Ready(Some(vec![b'a'; 100])),
+                   decoded[0].body_mut().poll().unwrap());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn refuse_chunked_bodies_with_overlong_lines() {
+        let mut buffer = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1;".to_vec();
+        buffer.extend(vec![b'x'; 16 * 1024]);
+        assert!(HttpCodec::new().validate(&buffer).is_err());
+    }
+
+    #[test]
+    fn tell_when_a_head_has_arrived() {
+        let codec = HttpCodec::new();
+        assert!(!codec.head_complete(b"GET / HTTP/1.1\r\nHost: a\r\n"));
+        assert!(codec.head_complete(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab"));
+    }
+
+    #[test]
+    fn add_content_length_and_date() {
+        let head = encode_head(&HttpCodec::new(),
+                               ResponseBuilder::new(StatusCode::Ok),
+                               Some(5)).unwrap();
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\nContent-Length: 5\r\n"));
+        assert!(head.contains("\r\nDate: "));
+        assert!(!head.contains("Server:"));
+        assert!(head.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn add_chunked_encoding_for_unsized_bodies() {
+        let head = encode_head(&HttpCodec::new(),
+                               ResponseBuilder::new(StatusCode::Ok),
+                               None).unwrap();
+
+        assert!(head.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+    }
+
+    #[test]
+    fn add_the_server_header_when_configured() {
+        let head = encode_head(&HttpCodec::new().server("server-fx"),
+                               ResponseBuilder::new(StatusCode::Ok),
+                               Some(0)).unwrap();
+
+        assert!(head.contains("\r\nServer: server-fx\r\n"));
+    }
+
+    #[test]
+    fn not_duplicate_existing_headers() {
+        let head = encode_head(&HttpCodec::new().server("server-fx"),
+                               ResponseBuilder::new(StatusCode::Ok)
+                                   .header("Content-Length", "5")
+                                   .header("Server", "custom"),
+                               Some(5)).unwrap();
+
+        assert_eq!(1, head.matches("Content-Length").count());
+        assert_eq!(1, head.matches("Server").count());
+        assert!(head.contains("\r\nServer: custom\r\n"));
+    }
+
+    #[test]
+    fn close_delimit_unsized_http10_bodies() {
+        let head = encode_head(&HttpCodec::new(),
+                               ResponseBuilder::new(StatusCode::Ok)
+                                   .version(HttpVersion::Http1),
+                               None).unwrap();
+
+        assert!(head.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(!head.contains("Content-Length"));
+        assert!(!head.contains("Transfer-Encoding"));
+    }
+
+    #[test]
+    fn omit_framing_when_the_status_forbids_a_body() {
+        let head = encode_head(&HttpCodec::new(),
+                               ResponseBuilder::new(StatusCode::NoContent),
+                               Some(0)).unwrap();
+
+        assert!(!head.contains("Content-Length"));
+        assert!(!head.contains("Transfer-Encoding"));
+    }
+
+    #[test]
+    fn keep_the_content_length_of_not_modified_responses() {
+        let head = encode_head(&HttpCodec::new(),
+                               ResponseBuilder::new(StatusCode::NotModified)
+                                   .header("Content-Length", "1024"),
+                               Some(0)).unwrap();
+
+        assert!(head.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(1, head.matches("Content-Length").count());
+        assert!(head.contains("\r\nContent-Length: 1024\r\n"));
+
+        let head = encode_head(&HttpCodec::new(),
+                               ResponseBuilder::new(StatusCode::NotModified),
+                               None).unwrap();
+        assert!(!head.contains("Content-Length"));
+        assert!(!head.contains("Transfer-Encoding"));
+    }
+
+    #[test]
+    fn refuse_conflicting_framing_headers() {
+        let codec = HttpCodec::new();
+        let conflicts = vec![
+            (ResponseBuilder::new(StatusCode::Ok)
+                .header("Content-Length", "4"), Some(5)),
+            (ResponseBuilder::new(StatusCode::Ok)
+                .header("Content-Length", "5"), None),
+            (ResponseBuilder::new(StatusCode::Ok)
+                .header("Transfer-Encoding", "chunked"), Some(5)),
+            (ResponseBuilder::new(StatusCode::Ok)
+                .header("Content-Length", "5")
+                .header("Transfer-Encoding", "chunked"), None),
+            (ResponseBuilder::new(StatusCode::NoContent), None),
+        ];
+
+        for (builder, length) in conflicts {
+            let mut buffer = vec![];
+            let (head, _) = builder.build().into_parts();
+            let err = codec.encode(Frame::Head(head, length), &mut buffer)
+                .unwrap_err();
+
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            assert!(buffer.is_empty());
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::io::{self, Write};
+use std::mem;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use http::body::Body;
+use http::middleware::{Middleware, Next};
+use http::response::RouteResponse;
+use http::types::{BodyChunk, Request, Response};
+use pollable::Pollable;
+use result::PollResult;
+
+/// The default size below which bodies aren't compressed.
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// A content coding supported by [`Compression`].
+///
+/// [`Compression`]: struct.Compression.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coding {
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`.
+    Deflate,
+}
+
+impl Coding {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Picks the coding to use from an `Accept-Encoding` header value,
+/// preferring `gzip`. Codings with a `q` of `0` are refused, and `*`
+/// stands for any coding not otherwise listed.
+pub fn negotiate(accept_encoding: &str) -> Option<Coding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut any = None;
+
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let q = params
+            .filter_map(|p| {
+                let p = p.trim();
+                match p.len() > 2 && p[..2].eq_ignore_ascii_case("q=") {
+                    true => p[2..].trim().parse::<f32>().ok(),
+                    false => None,
+                }
+            })
+            .next()
+            .unwrap_or(1.0);
+
+        if name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(q);
+        }
+        else if name.eq_ignore_ascii_case("deflate") {
+            deflate = Some(q);
+        }
+        else if name == "*" {
+            any = Some(q);
+        }
+    }
+
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    match (gzip, deflate) {
+        (g, d) if g > 0.0 && g >= d => Some(Coding::Gzip),
+        (_, d) if d > 0.0 => Some(Coding::Deflate),
+        _ => None,
+    }
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || ["application/json",
+            "application/javascript",
+            "application/xml",
+            "application/x-www-form-urlencoded",
+            "image/svg+xml"].contains(&media_type.as_str())
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(coding: Coding) -> Encoder {
+        let level = flate2::Compression::default();
+        match coding {
+            Coding::Gzip => Encoder::Gzip(GzEncoder::new(vec![], level)),
+            Coding::Deflate => Encoder::Deflate(ZlibEncoder::new(vec![], level)),
+        }
+    }
+
+    /// Compresses `data`, returning whatever output is ready so far.
+    fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            Encoder::Gzip(ref mut e) => {
+                e.write_all(data)?;
+                Ok(mem::take(e.get_mut()))
+            },
+            Encoder::Deflate(ref mut e) => {
+                e.write_all(data)?;
+                Ok(mem::take(e.get_mut()))
+            },
+        }
+    }
+
+    /// Returns the rest of the output.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(e) => e.finish(),
+            Encoder::Deflate(e) => e.finish(),
+        }
+    }
+}
+
+/// Compresses a body as it's streamed.
+struct CompressedBody {
+    body: Body,
+    encoder: Option<Encoder>,
+}
+
+impl Pollable for CompressedBody {
+    type Item = Option<BodyChunk>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        loop {
+            let encoder = match self.encoder {
+                Some(ref mut encoder) => encoder,
+                None => return Ok(PollResult::Ready(None)),
+            };
+
+            match self.body.poll()? {
+                PollResult::NotReady => return Ok(PollResult::NotReady),
+                PollResult::Ready(Some(chunk)) => {
+                    let output = encoder.write(&chunk)?;
+                    if !output.is_empty() {
+                        return Ok(PollResult::Ready(Some(output)));
+                    }
+                },
+                PollResult::Ready(None) => {
+                    let output = self.encoder.take().unwrap().finish()?;
+                    return Ok(PollResult::Ready(Some(output)));
+                },
+            }
+        }
+    }
+}
+
+/// Compresses `body` with `coding`. A buffered body stays buffered,
+/// so its new length is still known up front.
+fn compress(body: Body, coding: Coding) -> io::Result<Body> {
+    if let Some(content) = body.as_bytes() {
+        let mut encoder = Encoder::new(coding);
+        let mut output = encoder.write(content)?;
+        output.extend(encoder.finish()?);
+        return Ok(Body::from(output));
+    }
+
+    Ok(Body::from_pollable(CompressedBody {
+        body,
+        encoder: Some(Encoder::new(coding)),
+    }))
+}
+
+/// Middleware that compresses response bodies with `gzip` or
+/// `deflate`, as negotiated by the request's `Accept-Encoding` header.
+///
+/// Only bodies with a compressible `Content-Type` (text, JSON,
+/// JavaScript, XML and SVG) are compressed, and only when they're at
+/// least `min_size` bytes long, or of unknown length. Responses that
+/// already have a `Content-Encoding` are left alone. Compressed
+/// responses get a `Content-Encoding` header, and any
+/// `Content-Length` is removed so the codec can frame the new body.
+/// Every compressible response gets `Vary: Accept-Encoding`.
+///
+/// Requires the `compression` feature.
+pub struct Compression {
+    min_size: usize,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression {
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// Sets the size below which bodies aren't compressed.
+    pub fn min_size(mut self, min_size: usize) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+fn add_vary(response: &mut Response) {
+    let vary = match response.header_value("Vary") {
+        Some(v) if v.split(',').any(|v| {
+            let v = v.trim();
+            v == "*" || v.eq_ignore_ascii_case("Accept-Encoding")
+        }) => return,
+        Some(v) => format!("{}, Accept-Encoding", v),
+        None => String::from("Accept-Encoding"),
+    };
+    response.set_header("Vary", &vary);
+}
+
+fn compress_response(mut response: Response, coding: Option<Coding>, min_size: usize) -> Response {
+    let status = response.status_code();
+    let compressible = response.header_value("Content-Type")
+        .map(is_compressible)
+        .unwrap_or(false);
+
+    if !compressible || status < 200 || status == 204 || status == 206 || status == 304 {
+        return response;
+    }
+
+    add_vary(&mut response);
+
+    let coding = match coding {
+        Some(coding) => coding,
+        None => return response,
+    };
+
+    let too_small = response.body().content_length()
+        .map(|n| n < min_size)
+        .unwrap_or(false);
+    if too_small || response.header_value("Content-Encoding").is_some() {
+        return response;
+    }
+
+    let (mut head, body) = response.into_parts();
+    match compress(body, coding) {
+        Ok(body) => {
+            head.remove_header("Content-Length");
+            head.set_header("Content-Encoding", coding.as_str());
+            Response::from_parts(head, body)
+        },
+        Err(_) => Response::from_parts(head, Body::empty()),
+    }
+}
+
+impl Middleware for Compression {
+    fn call(&self, request: Request, next: Next) -> RouteResponse {
+        let coding = request.header_value("Accept-Encoding").and_then(negotiate);
+        let min_size = self.min_size;
+
+        next.run(request).map(move |response| compress_response(response, coding, min_size))
+    }
+}
+
+#[cfg(test)]
+mod compression_should {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use http::types::{ResponseBuilder, StatusCode};
+
+    const TEXT: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ";
+
+    fn text_response(repeat: usize) -> Response {
+        ResponseBuilder::new(StatusCode::Ok)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .header("Content-Length", &format!("{}", TEXT.len() * repeat))
+            .build_with_content(TEXT.repeat(repeat))
+    }
+
+    fn read_body(mut body: Body) -> Vec<u8> {
+        let mut content = vec![];
+        while let PollResult::Ready(Some(chunk)) = body.poll().unwrap() {
+            content.extend(chunk);
+        }
+        content
+    }
+
+    #[test]
+    fn negotiate_a_coding() {
+        assert_eq!(Some(Coding::Gzip), negotiate("gzip, deflate, br"));
+        assert_eq!(Some(Coding::Deflate), negotiate("deflate, gzip;q=0.5"));
+        assert_eq!(Some(Coding::Deflate), negotiate("gzip;q=0, *"));
+        assert_eq!(Some(Coding::Gzip), negotiate("*"));
+        assert_eq!(None, negotiate("br, identity"));
+        assert_eq!(None, negotiate("gzip;q=0, deflate;Q=0"));
+    }
+
+    #[test]
+    fn compress_buffered_bodies() {
+        let response = compress_response(text_response(50), Some(Coding::Gzip), 1024);
+
+        assert_eq!(Some("gzip"), response.header_value("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), response.header_value("Vary"));
+        assert_eq!(None, response.header_value("Content-Length"));
+
+        let compressed = response.body().as_bytes().unwrap().to_vec();
+        assert_eq!(Some(compressed.len()), response.body().content_length());
+        assert!(compressed.len() < TEXT.len() * 50);
+
+        let mut content = String::new();
+        GzDecoder::new(&compressed[..]).read_to_string(&mut content).unwrap();
+        assert_eq!(TEXT.repeat(50), content);
+    }
+
+    #[test]
+    fn compress_streamed_bodies() {
+        let chunks = vec![TEXT.as_bytes().to_vec(); 3];
+        let response = ResponseBuilder::new(StatusCode::Ok)
+            .header("Content-Type", "application/json")
+            .build_with_body(Body::from_pollable(::http::body::ReadBody::new(
+                ::std::io::Cursor::new(chunks.concat()))));
+
+        let response = compress_response(response, Some(Coding::Deflate), 1024);
+        assert_eq!(Some("deflate"), response.header_value("Content-Encoding"));
+        assert_eq!(None, response.body().content_length());
+
+        let compressed = read_body(response.into_parts().1);
+        let mut content = String::new();
+        ZlibDecoder::new(&compressed[..]).read_to_string(&mut content).unwrap();
+        assert_eq!(TEXT.repeat(3), content);
+    }
+
+    #[test]
+    fn leave_ineligible_responses_alone() {
+        let small = compress_response(text_response(1), Some(Coding::Gzip), 1024);
+        assert_eq!(None, small.header_value("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), small.header_value("Vary"));
+
+        let refused = compress_response(text_response(50), None, 1024);
+        assert_eq!(None, refused.header_value("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), refused.header_value("Vary"));
+
+        let mut image = text_response(50);
+        image.set_header("Content-Type", "image/png");
+        let image = compress_response(image, Some(Coding::Gzip), 1024);
+        assert_eq!(None, image.header_value("Content-Encoding"));
+        assert_eq!(None, image.header_value("Vary"));
+
+        let mut encoded = text_response(50);
+        encoded.set_header("Content-Encoding", "br");
+        encoded.set_header("Vary", "Origin");
+        let encoded = compress_response(encoded, Some(Coding::Gzip), 1024);
+        assert_eq!(Some("br"), encoded.header_value("Content-Encoding"));
+        assert_eq!(Some("Origin, Accept-Encoding"), encoded.header_value("Vary"));
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+
+use http::types::{Request, Response};
+
+/// Parses the value of a `Cookie` header into its `name=value` pairs,
+/// in the order they appear. Quotes around values are removed.
+pub fn parse(header: &str) -> Vec<(String, String)> {
+    header.split(';')
+        .filter_map(|pair| {
+            let split = pair.find('=')?;
+            let name = pair[..split].trim();
+            let value = pair[split + 1..].trim();
+            let value = match value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                true => &value[1..value.len() - 1],
+                false => value,
+            };
+
+            match name.is_empty() {
+                true => None,
+                false => Some((String::from(name), String::from(value))),
+            }
+        })
+        .collect()
+}
+
+impl<B> Request<B> {
+    /// The cookies sent with the request, from all of its `Cookie`
+    /// headers.
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.headers()
+            .filter(|h| h.0.eq_ignore_ascii_case("Cookie"))
+            .flat_map(|h| parse(h.1))
+            .collect()
+    }
+
+    /// The value of the first cookie named `name`.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies()
+            .into_iter()
+            .find(|c| c.0 == name)
+            .map(|c| c.1)
+    }
+}
+
+impl<B> Response<B> {
+    /// Adds a `Set-Cookie` header for `cookie`.
+    pub fn set_cookie(&mut self, cookie: &Cookie) {
+        self.add_header("Set-Cookie", &cookie.to_string());
+    }
+}
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        };
+        f.write_str(value)
+    }
+}
+
+/// A cookie to set on the client, as sent in a `Set-Cookie` header.
+///
+/// The cookie's `Display` output is the header's value. E.g.
+/// `session=abc; Path=/; HttpOnly`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<u64>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: String::from(name),
+            value: String::from(value),
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that removes any cookie named `name` from the client.
+    pub fn removal(name: &str) -> Cookie {
+        Cookie::new(name, "").max_age(0)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(String::from(path));
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(String::from(domain));
+        self
+    }
+
+    /// Sets how many seconds the cookie lasts. Without this, the
+    /// cookie lasts until the client's session ends.
+    pub fn max_age(mut self, seconds: u64) -> Cookie {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cookie_should {
+    use super::*;
+    use http::types::{HttpMethod, RequestBuilder, ResponseBuilder, StatusCode};
+
+    #[test]
+    fn parse_request_cookies() {
+        let request = RequestBuilder::new(HttpMethod::Get, "/")
+            .header("Cookie", "theme=dark; session=\"abc=\"")
+            .header("cookie", "lang=en;;broken")
+            .build();
+
+        assert_eq!(
+            vec![("theme".to_string(), "dark".to_string()),
+                 ("session".to_string(), "abc=".to_string()),
+                 ("lang".to_string(), "en".to_string())],
+            request.cookies()
+        );
+        assert_eq!(Some(String::from("en")), request.cookie("lang"));
+        assert_eq!(None, request.cookie("missing"));
+    }
+
+    #[test]
+    fn format_set_cookie_values() {
+        let cookie = Cookie::new("session", "abc")
+            .path("/")
+            .domain("example.com")
+            .max_age(3600)
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+
+        let mut response = ResponseBuilder::new(StatusCode::Ok).build();
+        response.set_cookie(&cookie);
+        response.set_cookie(&Cookie::removal("old"));
+
+        assert_eq!(
+            vec!["session=abc; Path=/; Domain=example.com; Max-Age=3600; Secure; HttpOnly; SameSite=Lax",
+                 "old=; Max-Age=0"],
+            response.headers()
+                .filter(|h| h.0 == "Set-Cookie")
+                .map(|h| h.1)
+                .collect::<Vec<_>>()
+        );
+    }
+}
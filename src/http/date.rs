@@ -0,0 +1,64 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+static MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Converts a count of days since the unix epoch into a
+/// `(year, month, day)` date in the proleptic Gregorian calendar.
+fn civil_from_days(days: u64) -> (u64, usize, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month as usize, day)
+}
+
+/// Formats `time` as an IMF-fixdate, the preferred format for the
+/// `Date` header. E.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// Times before the unix epoch are formatted as the epoch itself.
+pub fn http_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let days = secs / 86_400;
+    let secs_of_day = secs % 86_400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            DAYS[((days + 4) % 7) as usize],
+            day,
+            MONTHS[month - 1],
+            year,
+            secs_of_day / 3600,
+            (secs_of_day / 60) % 60,
+            secs_of_day % 60)
+}
+
+#[cfg(test)]
+mod http_date_should {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn format_as_imf_fixdate() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", http_date(time));
+    }
+
+    #[test]
+    fn format_leap_days() {
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!("Tue, 29 Feb 2000 00:00:00 GMT", http_date(time));
+    }
+}
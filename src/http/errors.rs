@@ -0,0 +1,163 @@
+//! Answering failed requests with error responses, rather than closing
+//! the connection without a word.
+//!
+//! A handler whose pollable fails ends its connection, and the peer is
+//! left to guess why. Wrapping the handler in [`ErrorResponses`] logs
+//! the error instead, and responds with `500 Internal Server Error`
+//! before closing. Requests that can't be parsed are answered with
+//! `400 Bad Request` by the [`HttpTransport`], and those that take
+//! longer than the server's `request_timeout` with [`timed_out`].
+//!
+//! [`ErrorResponses`]: struct.ErrorResponses.html
+//! [`HttpTransport`]: ../transport/struct.HttpTransport.html
+//! [`timed_out`]: fn.timed_out.html
+
+use std::fmt;
+use std::sync::Arc;
+
+use handler::Handler;
+use http::types::{Request, Response, ResponseBuilder, StatusCode};
+use pollable::{IntoPollable, Pollable};
+use result::PollResult;
+
+/// The response to a request that's taken too long: `503 Service
+/// Unavailable`, closing the connection. HTTP handlers return this
+/// from `Handler::timed_out`.
+pub fn timed_out() -> Response {
+    ResponseBuilder::new(StatusCode::ServiceUnavailable)
+        .header("Connection", "close")
+        .build()
+}
+
+/// Records the error a handler failed with.
+type ErrorLog = dyn Fn(&dyn fmt::Display) + Send + Sync;
+
+/// A handler that responds with `500 Internal Server Error`, and closes
+/// the connection, when `H` fails.
+pub struct ErrorResponses<H> {
+    handler: H,
+    log: Arc<ErrorLog>,
+}
+
+impl<H> ErrorResponses<H> {
+    /// Logs each error to `stderr`.
+    pub fn new(handler: H) -> ErrorResponses<H> {
+        ErrorResponses::with(handler, |e| eprintln!("Handler failed: {}", e))
+    }
+
+    /// Passes each error to `log`.
+    pub fn with<F>(handler: H, log: F) -> ErrorResponses<H> where
+        F: Fn(&dyn fmt::Display) + Send + Sync + 'static
+    {
+        ErrorResponses {
+            handler,
+            log: Arc::new(log),
+        }
+    }
+}
+
+impl<H> Handler for ErrorResponses<H> where
+    H: Handler<Request=Request, Response=Response>,
+    H::Error: fmt::Display,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = H::Error;
+    type Pollable = Recover<<H::Pollable as IntoPollable>::Pollable>;
+
+    fn handle(&self, request: Request) -> Self::Pollable {
+        Recover {
+            inner: self.handler.handle(request).into_pollable(),
+            log: self.log.clone(),
+        }
+    }
+
+    fn timed_out(&self) -> Option<Response> {
+        self.handler.timed_out()
+    }
+}
+
+/// The response of an [`ErrorResponses`] handler.
+///
+/// [`ErrorResponses`]: struct.ErrorResponses.html
+pub struct Recover<P> {
+    inner: P,
+    log: Arc<ErrorLog>,
+}
+
+impl<P> Pollable for Recover<P> where
+    P: Pollable<Item=Response>,
+    P::Error: fmt::Display,
+{
+    type Item = Response;
+    type Error = P::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        match self.inner.poll() {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                (self.log)(&e);
+                Ok(PollResult::Ready(ResponseBuilder::new(StatusCode::InternalServerError)
+                    .header("Connection", "close")
+                    .build()))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod error_responses_should {
+    use super::*;
+    use std::io;
+    use std::sync::Mutex;
+    use http::types::{HttpMethod, RequestBuilder};
+
+    struct Failing;
+
+    impl Handler for Failing {
+        type Request = Request;
+        type Response = Response;
+        type Error = io::Error;
+        type Pollable = io::Result<Response>;
+
+        fn handle(&self, request: Request) -> Self::Pollable {
+            match request.path() {
+                "/broken" => Err(io::Error::other("Broken")),
+                _ => Ok(ResponseBuilder::new(StatusCode::Ok).build()),
+            }
+        }
+    }
+
+    #[test]
+    fn respond_with_500_and_log_when_the_handler_fails() {
+        let logged = Arc::new(Mutex::new(vec![]));
+        let sink = logged.clone();
+        let handler = ErrorResponses::with(Failing, move |e| {
+            sink.lock().unwrap().push(e.to_string())
+        });
+
+        let send = |path| {
+            let request = RequestBuilder::new(HttpMethod::Get, path).build();
+            match handler.handle(request).poll().unwrap() {
+                PollResult::Ready(response) => response,
+                PollResult::NotReady => panic!("Response wasn't ready"),
+            }
+        };
+
+        assert_eq!(StatusCode::Ok, send("/").status());
+        let response = send("/broken");
+        assert_eq!(StatusCode::InternalServerError, response.status());
+        assert_eq!(Some("close"), response.header_value("Connection"));
+        assert_eq!(vec![String::from("Broken")], *logged.lock().unwrap());
+    }
+
+    #[test]
+    fn respond_with_503_to_requests_that_time_out() {
+        use http::router::Router;
+
+        let response = ErrorResponses::new(Router::builder().build()).timed_out().unwrap();
+        assert_eq!(StatusCode::ServiceUnavailable, response.status());
+        assert_eq!(Some("close"), response.header_value("Connection"));
+        assert!(Failing.timed_out().is_none());
+    }
+}
@@ -0,0 +1,83 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// A map holding at most one value of each type.
+///
+/// `Extensions` are attached to requests and responses so that 
+/// middleware can pass data (E.g. an authenticated user, or a request
+/// ID) along to the handlers that follow it.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Inserts `value`, returning any previous value of the same type.
+    pub fn insert<T: 'static>(&mut self, value: T) -> Option<T> {
+        self.map.insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    /// Removes the value of type `T`, returning it if it was present.
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.map.remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+#[cfg(test)]
+mod extensions_should {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct User(&'static str);
+
+    #[test]
+    fn store_one_value_per_type() {
+        let mut ext = Extensions::new();
+
+        assert_eq!(None, ext.insert(User("alice")));
+        assert_eq!(None, ext.insert(42_u32));
+        assert_eq!(Some(User("alice")), ext.insert(User("bob")));
+
+        assert_eq!(2, ext.len());
+        assert_eq!(Some(&User("bob")), ext.get::<User>());
+        assert_eq!(Some(&42), ext.get::<u32>());
+        assert_eq!(None, ext.get::<u64>());
+    }
+
+    #[test]
+    fn remove_values() {
+        let mut ext = Extensions::new();
+        ext.insert(User("alice"));
+
+        *ext.get_mut::<User>().unwrap() = User("carol");
+
+        assert_eq!(Some(User("carol")), ext.remove::<User>());
+        assert!(ext.is_empty());
+    }
+}
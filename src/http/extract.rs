@@ -0,0 +1,519 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use http::query::Query;
+use http::response::{IntoResponse, IntoRouteResponse, RouteResponse};
+use http::router::{FromParam, ParamError, Parameters, RouteHandler};
+use http::types::{HttpMethod, Request, Response, StatusCode};
+use http::uri::{percent_decode, Uri};
+
+/// Extracts a value from a request, for the arguments of a handler
+/// wrapped by [`extract`].
+///
+/// Extraction can fail with a `Rejection`, which is returned as the
+/// response instead of calling the handler. Extractors only borrow the
+/// request, so a handler can take any number of them.
+///
+/// [`extract`]: fn.extract.html
+pub trait FromRequest: Sized {
+    type Rejection: IntoResponse;
+
+    fn from_request(request: &Request, params: &Parameters)
+        -> Result<Self, Self::Rejection>;
+}
+
+/// The reason a built-in extractor failed. Converts into a response
+/// with its status, and its message as a plain-text body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    status: StatusCode,
+    message: String,
+}
+
+impl Rejection {
+    pub fn new(status: StatusCode, message: &str) -> Rejection {
+        Rejection {
+            status,
+            message: String::from(message),
+        }
+    }
+
+    /// A `400 Bad Request` rejection.
+    pub fn bad_request(message: &str) -> Rejection {
+        Rejection::new(StatusCode::BadRequest, message)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ::std::error::Error for Rejection {}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        (self.status, self.message).into_response()
+    }
+}
+
+impl From<ParamError> for Rejection {
+    fn from(error: ParamError) -> Rejection {
+        Rejection::bad_request(&error.to_string())
+    }
+}
+
+/// Extracts nothing if `T` can't be extracted, rather than rejecting
+/// the request.
+impl<T: FromRequest> FromRequest for Option<T> {
+    type Rejection = Rejection;
+
+    fn from_request(request: &Request, params: &Parameters) -> Result<Self, Rejection> {
+        Ok(T::from_request(request, params).ok())
+    }
+}
+
+/// Passes the rejection of `T` to the handler, rather than rejecting
+/// the request.
+impl<T: FromRequest> FromRequest for Result<T, T::Rejection> {
+    type Rejection = Rejection;
+
+    fn from_request(request: &Request, params: &Parameters) -> Result<Self, Rejection> {
+        Ok(T::from_request(request, params))
+    }
+}
+
+impl FromRequest for HttpMethod {
+    type Rejection = Rejection;
+
+    fn from_request(request: &Request, _: &Parameters) -> Result<Self, Rejection> {
+        Ok(request.method())
+    }
+}
+
+impl FromRequest for Uri {
+    type Rejection = Rejection;
+
+    fn from_request(request: &Request, _: &Parameters) -> Result<Self, Rejection> {
+        Ok(request.uri().clone())
+    }
+}
+
+impl FromRequest for Query {
+    type Rejection = Rejection;
+
+    fn from_request(request: &Request, _: &Parameters) -> Result<Self, Rejection> {
+        Ok(request.query())
+    }
+}
+
+/// Conversion from all of a route's path parameters, in the order
+/// they appear in its pattern. Implemented for tuples of up to four
+/// [`FromParam`] types.
+///
+/// [`FromParam`]: ../router/trait.FromParam.html
+pub trait FromParams: Sized {
+    fn from_params(params: &Parameters) -> Result<Self, ParamError>;
+}
+
+/// The decoded path parameters of a request. E.g. `Path((id,)):
+/// Path<(u32,)>` for a route to `/users/:id`. Values are percent-decoded
+/// before they're converted. A parameter that's missing or fails to
+/// convert rejects the request with a `400 Bad Request`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path<T>(pub T);
+
+impl<T: FromParams> FromRequest for Path<T> {
+    type Rejection = Rejection;
+
+    fn from_request(_: &Request, params: &Parameters) -> Result<Self, Rejection> {
+        Ok(Path(T::from_params(params)?))
+    }
+}
+
+macro_rules! from_params_for_tuple {
+    ($($t:ident),+) => {
+        impl<$($t: FromParam),+> FromParams for ($($t,)+) {
+            fn from_params(params: &Parameters) -> Result<Self, ParamError> {
+                let mut params = params.iter();
+                let mut position = 0;
+                Ok(($({
+                    position += 1;
+                    let (name, value) = params.next()
+                        .ok_or_else(|| ParamError::Missing(format!("#{}", position)))?;
+                    $t::from_param(&percent_decode(value))
+                        .map_err(|e| ParamError::Invalid(String::from(name), e))?
+                },)+))
+            }
+        }
+    }
+}
+
+from_params_for_tuple!(A);
+from_params_for_tuple!(A, B);
+from_params_for_tuple!(A, B, C);
+from_params_for_tuple!(A, B, C, D);
+
+/// A header that can be extracted with [`TypedHeader`].
+///
+/// [`TypedHeader`]: struct.TypedHeader.html
+pub trait Header: Sized {
+    /// The header's name. E.g. `Content-Type`.
+    fn name() -> &'static str;
+
+    /// Parses the header's value, returning `None` if it's invalid.
+    fn from_value(value: &str) -> Option<Self>;
+}
+
+/// A header of the request, parsed as an `H`. A missing or invalid
+/// header rejects the request with a `400 Bad Request`. Wrap it in an
+/// `Option` if the header isn't required.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedHeader<H>(pub H);
+
+impl<H: Header> FromRequest for TypedHeader<H> {
+    type Rejection = Rejection;
+
+    fn from_request(request: &Request, _: &Parameters) -> Result<Self, Rejection> {
+        let value = request.header_value(H::name())
+            .ok_or_else(|| Rejection::bad_request(
+                &format!("Missing header '{}'", H::name())))?;
+
+        H::from_value(value)
+            .map(TypedHeader)
+            .ok_or_else(|| Rejection::bad_request(
+                &format!("Invalid header '{}'", H::name())))
+    }
+}
+
+macro_rules! text_header {
+    ($(#[$doc:meta])* $t:ident, $name:expr) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $t(pub String);
+
+        impl Header for $t {
+            fn name() -> &'static str {
+                $name
+            }
+
+            fn from_value(value: &str) -> Option<$t> {
+                Some($t(String::from(value)))
+            }
+        }
+    }
+}
+
+text_header!(
+    /// The `Content-Type` header.
+    ContentType, "Content-Type");
+text_header!(
+    /// The `User-Agent` header.
+    UserAgent, "User-Agent");
+text_header!(
+    /// The `Host` header.
+    Host, "Host");
+
+/// A value of type `T` from the request's extensions, as inserted by
+/// middleware. E.g. a `Session`. Rejects the request with a `500
+/// Internal Server Error` if there's no such value, as that means
+/// the middleware wasn't set up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extension<T>(pub T);
+
+impl<T: Clone + 'static> FromRequest for Extension<T> {
+    type Rejection = Rejection;
+
+    fn from_request(request: &Request, _: &Parameters) -> Result<Self, Rejection> {
+        request.extensions().get::<T>()
+            .cloned()
+            .map(Extension)
+            .ok_or_else(|| Rejection::new(
+                StatusCode::InternalServerError, "Missing request extension"))
+    }
+}
+
+/// A route handler whose arguments are extracted from the request.
+/// See [`extract`].
+///
+/// [`extract`]: fn.extract.html
+pub struct Extract<F, A> {
+    f: F,
+    args: PhantomData<fn() -> A>,
+}
+
+/// Wraps `f` as a route handler whose arguments are extracted from
+/// the request with [`FromRequest`]. `f` can take up to six
+/// arguments, and return anything a route handler can. E.g.
+///
+/// ```rust
+/// # use server_fx::http::extract::{extract, Path, TypedHeader, UserAgent};
+/// # use server_fx::http::router::Router;
+/// let router = Router::builder()
+///     .get("/users/:id", extract(|Path((id,)): Path<(u32,)>,
+///                                 agent: Option<TypedHeader<UserAgent>>| {
+///         format!("User {} seen by {:?}", id, agent.map(|a| (a.0).0))
+///     }))
+///     .build();
+/// ```
+///
+/// The first argument that fails to extract rejects the request, and
+/// its rejection is the response.
+///
+/// [`FromRequest`]: trait.FromRequest.html
+pub fn extract<F, A>(f: F) -> Extract<F, A> {
+    Extract {
+        f,
+        args: PhantomData,
+    }
+}
+
+macro_rules! route_handler_for_extract {
+    ($($a:ident),+) => {
+        impl<F, R, $($a),+> RouteHandler for Extract<F, ($($a,)+)> where
+            F: Fn($($a),+) -> R,
+            R: IntoRouteResponse,
+            $($a: FromRequest),+
+        {
+            type Response = RouteResponse;
+
+            #[allow(non_snake_case)]
+            fn handle<'a>(&'a self,
+                          request: Request,
+                          params: &Parameters<'a>)
+                -> RouteResponse
+            {
+                $(
+                    let $a = match $a::from_request(&request, params) {
+                        Ok(value) => value,
+                        Err(rejection) =>
+                            return RouteResponse::ready(rejection),
+                    };
+                )+
+                (self.f)($($a),+).into_route_response()
+            }
+        }
+    }
+}
+
+route_handler_for_extract!(A);
+route_handler_for_extract!(A, B);
+route_handler_for_extract!(A, B, C);
+route_handler_for_extract!(A, B, C, D);
+route_handler_for_extract!(A, B, C, D, E);
+route_handler_for_extract!(A, B, C, D, E, G);
+
+#[cfg(feature = "serde")]
+pub use self::serde_extractors::{Json, TypedQuery};
+
+#[cfg(feature = "serde")]
+mod serde_extractors {
+    use serde::de::{self, DeserializeOwned, IntoDeserializer, Unexpected, Visitor};
+    use serde::de::value::{Error, MapDeserializer};
+
+    use super::{FromRequest, Rejection};
+    use http::router::Parameters;
+    use http::types::Request;
+
+    /// The request body, deserialized from JSON. A body that isn't
+    /// valid JSON for `T`, or isn't buffered, rejects the request with
+    /// a `400 Bad Request`.
+    ///
+    /// Requires the `serde` feature.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Json<T>(pub T);
+
+    impl<T: DeserializeOwned> FromRequest for Json<T> {
+        type Rejection = Rejection;
+
+        fn from_request(request: &Request, _: &Parameters) -> Result<Self, Rejection> {
+            request.json()
+                .map(Json)
+                .map_err(|e| Rejection::bad_request(&format!("Invalid JSON body: {}", e)))
+        }
+    }
+
+    /// The request's query string, deserialized as a `T`. E.g. a
+    /// struct with a field for each parameter. Values are parsed into
+    /// numbers and booleans as the fields require. A query that
+    /// doesn't fit `T` rejects the request with a `400 Bad Request`.
+    ///
+    /// Requires the `serde` feature.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct TypedQuery<T>(pub T);
+
+    impl<T: DeserializeOwned> FromRequest for TypedQuery<T> {
+        type Rejection = Rejection;
+
+        fn from_request(request: &Request, _: &Parameters) -> Result<Self, Rejection> {
+            let query = request.query();
+            let pairs = query.iter().map(|p| (p.0.as_str(), QueryValue(&p.1)));
+
+            T::deserialize(MapDeserializer::<_, Error>::new(pairs))
+                .map(TypedQuery)
+                .map_err(|e| Rejection::bad_request(&format!("Invalid query: {}", e)))
+        }
+    }
+
+    /// Deserializes a query value, parsing it as whatever type is
+    /// asked for.
+    struct QueryValue<'a>(&'a str);
+
+    impl<'de, 'a> IntoDeserializer<'de, Error> for QueryValue<'a> {
+        type Deserializer = QueryValue<'a>;
+
+        fn into_deserializer(self) -> QueryValue<'a> {
+            self
+        }
+    }
+
+    macro_rules! parse_value {
+        ($($method:ident => $visit:ident,)+) => {
+            $(
+                fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                    match self.0.parse() {
+                        Ok(value) => visitor.$visit(value),
+                        Err(_) => Err(de::Error::invalid_value(
+                            Unexpected::Str(self.0), &visitor)),
+                    }
+                }
+            )+
+        }
+    }
+
+    impl<'de, 'a> de::Deserializer<'de> for QueryValue<'a> {
+        type Error = Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            visitor.visit_str(self.0)
+        }
+
+        parse_value! {
+            deserialize_bool => visit_bool,
+            deserialize_i8 => visit_i8,
+            deserialize_i16 => visit_i16,
+            deserialize_i32 => visit_i32,
+            deserialize_i64 => visit_i64,
+            deserialize_u8 => visit_u8,
+            deserialize_u16 => visit_u16,
+            deserialize_u32 => visit_u32,
+            deserialize_u64 => visit_u64,
+            deserialize_f32 => visit_f32,
+            deserialize_f64 => visit_f64,
+            deserialize_char => visit_char,
+        }
+
+        fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            visitor.visit_some(self)
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(self,
+                                             _: &'static str,
+                                             _: &'static [&'static str],
+                                             visitor: V)
+            -> Result<V::Value, Error>
+        {
+            visitor.visit_enum(self.0.into_deserializer())
+        }
+
+        serde::forward_to_deserialize_any! {
+            str string bytes byte_buf unit unit_struct newtype_struct seq
+            tuple tuple_struct map struct identifier ignored_any
+        }
+    }
+}
+
+#[cfg(test)]
+mod extract_should {
+    use super::*;
+    use http::router::Router;
+    use http::types::RequestBuilder;
+
+    fn get(router: &Router, request: Request) -> (u16, String) {
+        use handler::Handler;
+        use pollable::Pollable;
+        use result::PollResult;
+
+        match router.handle(request).poll().unwrap() {
+            PollResult::Ready(response) => {
+                let status = response.status().as_u16();
+                let body = response.into_parts().1.as_bytes().unwrap().to_vec();
+                (status, String::from_utf8(body).unwrap())
+            },
+            PollResult::NotReady => panic!("Response not ready"),
+        }
+    }
+
+    fn request(path: &str) -> Request {
+        RequestBuilder::new(HttpMethod::Get, path).build()
+    }
+
+    #[test]
+    fn extract_handler_arguments() {
+        let router = Router::builder()
+            .get("/users/:id/:name", extract(
+                |Path((id, name)): Path<(u32, String)>, query: Query, method: HttpMethod| {
+                    format!("{} {} {} {}", method, id, name, query.value("tab").unwrap_or("-"))
+                }))
+            .build();
+
+        assert_eq!((200, String::from("GET 42 Jo Bloggs repos")),
+                   get(&router, request("/users/42/Jo%20Bloggs?tab=repos")));
+        assert_eq!((400, String::from("Invalid parameter 'id': invalid digit found in string")),
+                   get(&router, request("/users/x/Jo")));
+    }
+
+    #[test]
+    fn extract_headers_and_extensions() {
+        let router = Router::builder()
+            .middleware(|mut request: Request, next: ::http::middleware::Next| {
+                request.extensions_mut().insert(7_u32);
+                next.run(request)
+            })
+            .get("/", extract(|TypedHeader(agent): TypedHeader<UserAgent>,
+                               Extension(n): Extension<u32>,
+                               host: Option<TypedHeader<Host>>| {
+                format!("{} {} {}", agent.0, n, host.is_some())
+            }))
+            .build();
+
+        let with_agent = RequestBuilder::new(HttpMethod::Get, "/")
+            .header("User-Agent", "curl")
+            .build();
+        assert_eq!((200, String::from("curl 7 false")), get(&router, with_agent));
+        assert_eq!((400, String::from("Missing header 'User-Agent'")),
+                   get(&router, request("/")));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_json_bodies_and_queries() {
+        use std::collections::BTreeMap;
+
+        let router = Router::builder()
+            .post("/sum", extract(|Json(values): Json<Vec<u32>>| {
+                values.iter().sum::<u32>().to_string()
+            }))
+            .get("/sum", extract(|TypedQuery(values): TypedQuery<BTreeMap<String, u32>>| {
+                values.values().sum::<u32>().to_string()
+            }))
+            .build();
+
+        let post = |body: &'static [u8]| RequestBuilder::new(HttpMethod::Post, "/sum")
+            .build_with_buffer(body.iter().cloned());
+
+        assert_eq!((200, String::from("6")), get(&router, post(b"[1, 2, 3]")));
+        assert_eq!(400, get(&router, post(b"[1, 2")).0);
+        assert_eq!((200, String::from("3")), get(&router, request("/sum?a=1&b=2")));
+        assert_eq!(400, get(&router, request("/sum?a=x")).0);
+    }
+}
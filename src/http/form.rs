@@ -0,0 +1,73 @@
+use http::body::Body;
+use http::types::Request;
+use http::uri;
+
+/// The media type of urlencoded form bodies.
+pub const URLENCODED: &str = "application/x-www-form-urlencoded";
+
+/// Decodes an `application/x-www-form-urlencoded` body into its
+/// key/value pairs, in the order they appear.
+///
+/// `+` is decoded as a space and `%XX` escapes are decoded as bytes.
+/// Any bytes that don't form valid UTF-8 once decoded are replaced
+/// with `U+FFFD`.
+pub fn parse(body: &[u8]) -> Vec<(String, String)> {
+    uri::decode_pairs(body)
+}
+
+impl Request<Body> {
+    /// Decodes the request body as a urlencoded form. Returns `None`
+    /// if the request's `Content-Type` isn't
+    /// `application/x-www-form-urlencoded`, or if the body is still
+    /// being streamed.
+    pub fn form(&self) -> Option<Vec<(String, String)>> {
+        let is_form = self.header_value("Content-Type")
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().eq_ignore_ascii_case(URLENCODED))
+            .unwrap_or(false);
+
+        if !is_form {
+            return None;
+        }
+
+        self.body().as_bytes().map(parse)
+    }
+}
+
+#[cfg(test)]
+mod form_should {
+    use super::*;
+    use http::types::{HttpMethod, RequestBuilder};
+
+    #[test]
+    fn decode_pairs() {
+        assert_eq!(
+            vec![("name".to_string(), "Jo Bloggs".to_string()),
+                 ("email".to_string(), "jo@example.com".to_string()),
+                 ("empty".to_string(), "".to_string()),
+                 ("a&b".to_string(), "1+1=2".to_string())],
+            parse(b"name=Jo+Bloggs&email=jo%40example.com&empty&a%26b=1%2B1%3D2")
+        );
+    }
+
+    #[test]
+    fn decode_a_form_request() {
+        let request = RequestBuilder::new(HttpMethod::Post, "/")
+            .header("Content-Type", "application/x-www-form-urlencoded; charset=utf-8")
+            .build_with_buffer(b"greeting=hello+world".iter().cloned());
+
+        assert_eq!(
+            Some(vec![("greeting".to_string(), "hello world".to_string())]),
+            request.form()
+        );
+    }
+
+    #[test]
+    fn ignore_other_content_types() {
+        let request = RequestBuilder::new(HttpMethod::Post, "/")
+            .header("Content-Type", "application/json")
+            .build_with_buffer(b"{}".iter().cloned());
+
+        assert_eq!(None, request.form());
+    }
+}
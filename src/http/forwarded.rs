@@ -0,0 +1,300 @@
+//! Passing messages on, as a proxy does.
+//!
+//! Some headers only apply to the connection a message arrives on,
+//! such as `Connection` and the headers it lists. A proxy removes them,
+//! with [`strip_request`] and [`strip_response`], before passing a
+//! message on. It then adds headers that tell the server where the
+//! request came from, with [`Forwarded`].
+//!
+//! [`strip_request`]: fn.strip_request.html
+//! [`strip_response`]: fn.strip_response.html
+//! [`Forwarded`]: struct.Forwarded.html
+
+use std::net::IpAddr;
+
+use connected::ConnectionInfo;
+use http::types::{HttpVersion, Request, Response};
+
+/// The headers that only apply to the connection a message arrives on,
+/// besides those its `Connection` header lists.
+pub const HOP_BY_HOP: &[&str] = &[
+    "Connection", "Keep-Alive", "Proxy-Connection", "Proxy-Authenticate",
+    "Proxy-Authorization", "TE", "Trailer", "Transfer-Encoding", "Upgrade",
+];
+
+/// The names of the headers of a message, whose `Connection` header is
+/// `connection`, that only apply to the connection it arrived on.
+fn hop_by_hop(connection: Option<&str>) -> Vec<String> {
+    let listed = connection.unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    HOP_BY_HOP.iter()
+        .cloned()
+        .chain(listed)
+        .map(String::from)
+        .collect()
+}
+
+/// Removes the headers of `request` that only applied to the connection
+/// it arrived on.
+pub fn strip_request<B>(request: &mut Request<B>) {
+    for name in hop_by_hop(request.header_value("Connection")) {
+        request.remove_header(&name);
+    }
+}
+
+/// Removes the headers of `response` that only applied to the
+/// connection it arrived on.
+pub fn strip_response<B>(response: &mut Response<B>) {
+    for name in hop_by_hop(response.header_value("Connection")) {
+        response.remove_header(&name);
+    }
+}
+
+/// The values of every header named `name`, joined into one list.
+fn joined<'a, I>(headers: I, name: &str) -> Option<String> where
+    I: Iterator<Item=(&'a str, &'a str)>
+{
+    let values = headers
+        .filter(|&(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>();
+
+    match values.is_empty() {
+        true => None,
+        false => Some(values.join(", ")),
+    }
+}
+
+/// `list`, with `value` appended to it, if there's a list.
+fn append(list: Option<String>, value: &str) -> String {
+    match list {
+        Some(list) => format!("{}, {}", list, value),
+        None => String::from(value),
+    }
+}
+
+/// `value`, quoted if it isn't a token, for a `Forwarded` header.
+fn quoted(value: &str) -> String {
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if !value.is_empty() && value.chars().all(is_tchar) {
+        return String::from(value);
+    }
+
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+fn protocol_version(version: HttpVersion) -> &'static str {
+    match version {
+        HttpVersion::Http1 => "1.0",
+        HttpVersion::Http11 => "1.1",
+    }
+}
+
+/// Adds the headers that tell a server where a request that was
+/// forwarded to it came from: `X-Forwarded-For`, `X-Forwarded-Proto`,
+/// `Forwarded` and `Via`.
+///
+/// Clients can send these headers themselves, to pass for someone
+/// else, so by default those a request arrives with are replaced. Only
+/// when each client is a proxy that's trusted should the headers be
+/// `trust`ed, and appended to instead.
+#[derive(Debug, Clone)]
+pub struct Forwarded {
+    pseudonym: String,
+    trust: bool,
+    disclose: bool,
+}
+
+impl Default for Forwarded {
+    fn default() -> Forwarded {
+        Forwarded {
+            pseudonym: String::from("server-fx"),
+            trust: false,
+            disclose: true,
+        }
+    }
+}
+
+impl Forwarded {
+    pub fn new() -> Forwarded {
+        Forwarded::default()
+    }
+
+    /// Sets the name the proxy gives itself in `Via` headers. Defaults
+    /// to `server-fx`.
+    pub fn pseudonym(mut self, pseudonym: &str) -> Forwarded {
+        self.pseudonym = String::from(pseudonym);
+        self
+    }
+
+    /// Appends to the forwarding headers that requests arrive with,
+    /// rather than replacing them. Defaults to `false`.
+    pub fn trust(mut self, trust: bool) -> Forwarded {
+        self.trust = trust;
+        self
+    }
+
+    /// Sets whether clients' addresses, and the protocols they used,
+    /// are passed on. If not, only `Via` is added. A forward proxy may
+    /// keep its clients' addresses private. Defaults to `true`.
+    pub fn disclose(mut self, disclose: bool) -> Forwarded {
+        self.disclose = disclose;
+        self
+    }
+
+    /// Adds the headers to `request`, before it's forwarded. The client
+    /// and protocol are those of the request's `ConnectionInfo`, if it
+    /// has one.
+    pub fn request<B>(&self, request: &mut Request<B>) {
+        let via = append(joined(request.headers(), "Via"),
+                         &format!("{} {}", protocol_version(request.version()), self.pseudonym));
+        request.set_header("Via", &via);
+
+        if !self.disclose {
+            return;
+        }
+
+        let info = request.extensions().get::<ConnectionInfo>().cloned();
+        let (client, proto) = match info {
+            Some(info) => (Some(info.peer_addr.ip()), proto(&info)),
+            None => (None, "http"),
+        };
+
+        let existing = |name: &str| match self.trust {
+            true => joined(request.headers(), name),
+            false => None,
+        };
+        let forwarded_for = existing("X-Forwarded-For");
+        let forwarded_proto = existing("X-Forwarded-Proto");
+        let forwarded = existing("Forwarded");
+
+        match client {
+            Some(client) => request.set_header("X-Forwarded-For", &append(forwarded_for, &client.to_string())),
+            None => match forwarded_for {
+                Some(list) => request.set_header("X-Forwarded-For", &list),
+                None => { request.remove_header("X-Forwarded-For"); },
+            },
+        }
+        // The protocol the first client used is the one that matters.
+        request.set_header("X-Forwarded-Proto", forwarded_proto.as_deref().unwrap_or(proto));
+
+        let mut element = vec![];
+        if let Some(client) = client {
+            element.push(format!("for={}", node(client)));
+        }
+        if let Some(host) = request.header_value("Host") {
+            element.push(format!("host={}", quoted(host)));
+        }
+        element.push(format!("proto={}", proto));
+        request.set_header("Forwarded", &append(forwarded, &element.join(";")));
+    }
+
+    /// Adds a `Via` header to `response`, before it's passed back to
+    /// the client.
+    pub fn response<B>(&self, response: &mut Response<B>) {
+        let via = append(joined(response.headers(), "Via"),
+                         &format!("{} {}", protocol_version(response.version()), self.pseudonym));
+        response.set_header("Via", &via);
+    }
+}
+
+/// `client`, as a node of a `Forwarded` header.
+fn node(client: IpAddr) -> String {
+    match client {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    }
+}
+
+/// The protocol a client used on the connection `info` describes.
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+fn proto(info: &ConnectionInfo) -> &'static str {
+    match info.tls {
+        Some(_) => "https",
+        None => "http",
+    }
+}
+
+#[cfg(not(any(feature = "tls", feature = "native-tls")))]
+fn proto(_: &ConnectionInfo) -> &'static str {
+    "http"
+}
+
+#[cfg(test)]
+mod forwarded_should {
+    use super::*;
+    use http::types::{HttpMethod, RequestBuilder, ResponseBuilder, StatusCode};
+
+    fn from(client: &str) -> Request {
+        let mut request = RequestBuilder::new(HttpMethod::Get, "/")
+            .version(HttpVersion::Http1)
+            .header("Host", "example.com:8080")
+            .header("X-Forwarded-For", "203.0.113.7")
+            .header("X-Forwarded-Proto", "https")
+            .build();
+        request.extensions_mut().insert(ConnectionInfo {
+            peer_addr: client.parse().unwrap(),
+            local_addr: "127.0.0.1:80".parse().unwrap(),
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls: None,
+        });
+        request
+    }
+
+    #[test]
+    fn strip_headers_that_only_applied_to_the_connection() {
+        let mut request = RequestBuilder::new(HttpMethod::Get, "/")
+            .header("Connection", "keep-alive, X-Secret")
+            .header("Keep-Alive", "timeout=5")
+            .header("X-Secret", "hunter2")
+            .header("Accept", "*/*")
+            .build();
+        strip_request(&mut request);
+        assert_eq!(vec![("Accept", "*/*")], request.headers().collect::<Vec<_>>());
+
+        let mut response = ResponseBuilder::new(StatusCode::Ok)
+            .header("Transfer-Encoding", "chunked")
+            .header("Upgrade", "websocket")
+            .build();
+        strip_response(&mut response);
+        assert_eq!(0, response.headers().count());
+    }
+
+    #[test]
+    fn replace_the_forwarding_headers_of_clients_that_arent_trusted() {
+        let mut request = from("192.0.2.60:5000");
+        Forwarded::new().request(&mut request);
+
+        assert_eq!(Some("192.0.2.60"), request.header_value("X-Forwarded-For"));
+        assert_eq!(Some("http"), request.header_value("X-Forwarded-Proto"));
+        assert_eq!(Some("for=192.0.2.60;host=\"example.com:8080\";proto=http"),
+                   request.header_value("Forwarded"));
+        assert_eq!(Some("1.0 server-fx"), request.header_value("Via"));
+    }
+
+    #[test]
+    fn append_to_the_forwarding_headers_of_trusted_clients() {
+        let mut request = from("[2001:db8::1]:5000");
+        request.add_header("Via", "1.1 edge");
+        Forwarded::new().trust(true).pseudonym("inner").request(&mut request);
+
+        assert_eq!(Some("203.0.113.7, 2001:db8::1"), request.header_value("X-Forwarded-For"));
+        assert_eq!(Some("https"), request.header_value("X-Forwarded-Proto"));
+        assert_eq!(Some("for=\"[2001:db8::1]\";host=\"example.com:8080\";proto=http"),
+                   request.header_value("Forwarded"));
+        assert_eq!(Some("1.1 edge, 1.0 inner"), request.header_value("Via"));
+    }
+
+    #[test]
+    fn only_add_via_when_not_disclosing_clients() {
+        let mut request = from("192.0.2.60:5000");
+        Forwarded::new().disclose(false).request(&mut request);
+        assert_eq!(Some("203.0.113.7"), request.header_value("X-Forwarded-For"));
+        assert!(request.header_value("Forwarded").is_none());
+        assert_eq!(Some("1.0 server-fx"), request.header_value("Via"));
+    }
+}
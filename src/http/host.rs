@@ -0,0 +1,214 @@
+use std::io;
+
+use handler::Handler;
+use http::errors;
+use http::response::{IntoResponse, RouteResponse};
+use http::router::Router;
+use http::types::{Request, Response, StatusCode};
+
+/// The host name of a `Host` header value, without any port or
+/// trailing dot, in lower case. E.g. `Example.com:8080` gives
+/// `example.com`, and `[::1]:8080` gives `[::1]`.
+pub fn host_name(host: &str) -> String {
+    let host = host.trim();
+    let name = match host.starts_with('[') {
+        true => match host.find(']') {
+            Some(end) => &host[..end + 1],
+            None => host,
+        },
+        false => host.split(':').next().unwrap_or(""),
+    };
+
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// A pattern matching host names. Either an exact name, like
+/// `example.com`, or a wildcard, like `*.example.com`, which matches
+/// any subdomain of `example.com`, at any depth, but not
+/// `example.com` itself.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostPattern {
+    Exact(String),
+    Subdomains(String),
+}
+
+impl HostPattern {
+    pub fn new(pattern: &str) -> HostPattern {
+        let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => HostPattern::Subdomains(String::from(domain)),
+            None => HostPattern::Exact(pattern),
+        }
+    }
+
+    /// Whether `name`, as returned by [`host_name`], matches the
+    /// pattern.
+    ///
+    /// [`host_name`]: fn.host_name.html
+    pub fn matches(&self, name: &str) -> bool {
+        match *self {
+            HostPattern::Exact(ref host) => name == host,
+            HostPattern::Subdomains(ref domain) =>
+                name.len() > domain.len() + 1
+                    && name.ends_with(domain.as_str())
+                    && name[..name.len() - domain.len()].ends_with('.'),
+        }
+    }
+
+    /// Exact names rank first, then wildcards with the most labels.
+    fn specificity(&self) -> (u8, isize) {
+        match *self {
+            HostPattern::Exact(_) => (0, 0),
+            HostPattern::Subdomains(ref domain) =>
+                (1, -(domain.split('.').count() as isize)),
+        }
+    }
+}
+
+/// Dispatches requests to a [`Router`] chosen by their `Host` header,
+/// so several sites can be served by one server.
+///
+/// Exact host names are tried before wildcards, and wildcards with
+/// longer domains before shorter ones, so `api.example.com` can be
+/// served separately from `*.example.com`. Requests for any other
+/// host are passed to the default router, if there is one. Otherwise
+/// they're answered with a `404 Not Found`, or a `400 Bad Request`
+/// if they have no `Host` header at all.
+///
+/// Like a `Router`, a `HostRouter` is a `Handler`. E.g.
+///
+/// ```rust,no_run
+/// # use server_fx::http::host::HostRouter;
+/// # use server_fx::http::router::Router;
+/// let sites = HostRouter::new()
+///     .host("example.com", Router::builder().build())
+///     .host("*.example.com", Router::builder().build())
+///     .default_router(Router::builder().build());
+/// ```
+///
+/// [`Router`]: ../router/struct.Router.html
+#[derive(Default)]
+pub struct HostRouter {
+    hosts: Vec<(HostPattern, Router)>,
+    default: Option<Router>,
+}
+
+impl HostRouter {
+    pub fn new() -> HostRouter {
+        HostRouter::default()
+    }
+
+    /// Routes requests for hosts matching `pattern` through `router`.
+    /// See [`HostPattern`].
+    ///
+    /// [`HostPattern`]: enum.HostPattern.html
+    pub fn host(mut self, pattern: &str, router: Router) -> HostRouter {
+        self.hosts.push((HostPattern::new(pattern), router));
+        self.hosts.sort_by_key(|h| h.0.specificity());
+        self
+    }
+
+    /// Routes requests for any other host through `router`.
+    pub fn default_router(mut self, router: Router) -> HostRouter {
+        self.default = Some(router);
+        self
+    }
+
+    /// The router for requests to `host`, as given in a `Host` header.
+    pub fn router_for(&self, host: &str) -> Option<&Router> {
+        let name = host_name(host);
+        self.hosts.iter()
+            .find(|h| h.0.matches(&name))
+            .map(|h| &h.1)
+            .or(self.default.as_ref())
+    }
+}
+
+impl Handler for HostRouter {
+    type Request = Request;
+    type Response = Response;
+    type Error = io::Error;
+    type Pollable = RouteResponse;
+
+    fn handle(&self, request: Request) -> RouteResponse {
+        let (router, status) = match request.header_value("Host") {
+            Some(host) => (self.router_for(host), StatusCode::NotFound),
+            None => (self.default.as_ref(), StatusCode::BadRequest),
+        };
+
+        match router {
+            Some(router) => router.handle(request),
+            None => RouteResponse::from(status.into_response()),
+        }
+    }
+
+    fn timed_out(&self) -> Option<Response> {
+        Some(errors::timed_out())
+    }
+}
+
+#[cfg(test)]
+mod host_router_should {
+    use super::*;
+    use http::router::Parameters;
+    use http::types::{HttpMethod, RequestBuilder};
+    use pollable::Pollable;
+    use result::PollResult;
+
+    fn site(name: &'static str) -> Router {
+        Router::builder()
+            .get("/", move |_: Request, _: &Parameters| name)
+            .build()
+    }
+
+    fn send(sites: &HostRouter, host: Option<&str>) -> (u16, String) {
+        let mut builder = RequestBuilder::new(HttpMethod::Get, "/");
+        if let Some(host) = host {
+            builder = builder.header("Host", host);
+        }
+
+        match sites.handle(builder.build()).poll().unwrap() {
+            PollResult::Ready(response) => {
+                let status = response.status().as_u16();
+                let body = response.into_parts().1.as_bytes().unwrap().to_vec();
+                (status, String::from_utf8(body).unwrap())
+            },
+            PollResult::NotReady => panic!("Response not ready"),
+        }
+    }
+
+    #[test]
+    fn extract_host_names() {
+        assert_eq!("example.com", host_name("Example.COM:8080"));
+        assert_eq!("example.com", host_name(" example.com. "));
+        assert_eq!("[::1]", host_name("[::1]:8080"));
+    }
+
+    #[test]
+    fn match_wildcard_subdomains() {
+        let pattern = HostPattern::new("*.example.com");
+
+        assert!(pattern.matches("www.example.com"));
+        assert!(pattern.matches("a.b.example.com"));
+        assert!(!pattern.matches("example.com"));
+        assert!(!pattern.matches("badexample.com"));
+    }
+
+    #[test]
+    fn dispatch_by_host() {
+        let sites = HostRouter::new()
+            .host("*.example.com", site("any"))
+            .host("api.example.com", site("api"))
+            .host("example.com", site("main"));
+
+        assert_eq!((200, String::from("main")), send(&sites, Some("example.com")));
+        assert_eq!((200, String::from("api")), send(&sites, Some("API.example.com:80")));
+        assert_eq!((200, String::from("any")), send(&sites, Some("www.example.com")));
+        assert_eq!(404, send(&sites, Some("example.org")).0);
+        assert_eq!(400, send(&sites, None).0);
+
+        let sites = sites.default_router(site("default"));
+        assert_eq!((200, String::from("default")), send(&sites, Some("example.org")));
+        assert_eq!((200, String::from("default")), send(&sites, None));
+    }
+}
@@ -0,0 +1,65 @@
+use serde::de::{DeserializeOwned, Error};
+use serde::Serialize;
+use serde_json;
+
+use http::body::Body;
+use http::types::{Request, Response, ResponseBuilder, StatusCode};
+
+impl Response<Body> {
+    /// Creates a `200 OK` response whose body is `value` serialized as
+    /// JSON, with its `Content-Type` set to `application/json`.
+    pub fn json<T>(value: &T) -> serde_json::Result<Response> where
+        T: Serialize + ?Sized
+    {
+        let content = serde_json::to_vec(value)?;
+        Ok(ResponseBuilder::new(StatusCode::Ok)
+            .header("Content-Type", "application/json")
+            .build_with_content(content))
+    }
+}
+
+impl Request<Body> {
+    /// Deserializes the request body from JSON. Fails if the body
+    /// isn't valid JSON for `T`, or if the body is still being
+    /// streamed.
+    pub fn json<T>(&self) -> serde_json::Result<T> where
+        T: DeserializeOwned
+    {
+        match self.body().as_bytes() {
+            Some(bytes) => serde_json::from_slice(bytes),
+            None => Err(serde_json::Error::custom(
+                "The request body isn't buffered")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod json_should {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use http::types::{HttpMethod, RequestBuilder};
+
+    #[test]
+    fn serialize_a_response_body() {
+        let mut value = BTreeMap::new();
+        value.insert("name", "server-fx");
+
+        let response = Response::json(&value).unwrap();
+
+        assert_eq!(200, response.status_code());
+        assert_eq!(Some("application/json"),
+                   response.header_value("Content-Type"));
+        assert_eq!(Some(&b"{\"name\":\"server-fx\"}"[..]),
+                   response.into_parts().1.as_bytes());
+    }
+
+    #[test]
+    fn deserialize_a_request_body() {
+        let request = RequestBuilder::new(HttpMethod::Post, "/")
+            .build_with_buffer(b"[1, 2, 3]".iter().cloned());
+
+        assert_eq!(vec![1, 2, 3], request.json::<Vec<u32>>().unwrap());
+        assert!(request.json::<String>().is_err());
+    }
+}
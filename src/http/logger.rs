@@ -0,0 +1,173 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use connected::ConnectionInfo;
+use http::middleware::{Middleware, Next};
+use http::response::RouteResponse;
+use http::router::MatchedRoute;
+use http::types::{HttpMethod, Request, StatusCode};
+
+/// What a [`Logger`] records about each request.
+///
+/// [`Logger`]: struct.Logger.html
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// The client's address, if the request has a [`ConnectionInfo`]
+    /// extension.
+    ///
+    /// [`ConnectionInfo`]: ../../connected/struct.ConnectionInfo.html
+    pub peer: Option<SocketAddr>,
+    pub method: HttpMethod,
+    pub path: String,
+    /// The pattern of the route that handled the request, if any.
+    pub route: Option<String>,
+    pub status: StatusCode,
+    /// The length of the response body, if it's known up front.
+    pub size: Option<usize>,
+    /// The time taken for the response to be ready, not including
+    /// writing it to the peer.
+    pub latency: Duration,
+}
+
+/// Formats the entry as a single line. E.g.
+/// `127.0.0.1:50712 GET /users/42 (/users/:id) 200 13B 1.2ms`.
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(peer) = self.peer {
+            write!(f, "{} ", peer)?;
+        }
+        write!(f, "{} {} ", self.method, self.path)?;
+        if let Some(ref route) = self.route {
+            write!(f, "({}) ", route)?;
+        }
+        write!(f, "{} ", self.status.as_u16())?;
+        match self.size {
+            Some(size) => write!(f, "{}B ", size)?,
+            None => write!(f, "- ")?,
+        }
+        write!(f, "{:?}", self.latency)
+    }
+}
+
+/// Middleware that records a [`LogEntry`] for each request.
+///
+/// [`LogEntry`]: struct.LogEntry.html
+#[derive(Clone)]
+pub struct Logger {
+    log: Arc<dyn Fn(&LogEntry) + Send + Sync>,
+}
+
+impl Logger {
+    /// Logs each request to `stderr`.
+    pub fn new() -> Logger {
+        Logger::with(|entry| eprintln!("{}", entry))
+    }
+
+    /// Passes each request's entry to `log`.
+    pub fn with<F>(log: F) -> Logger where
+        F: Fn(&LogEntry) + Send + Sync + 'static
+    {
+        Logger {
+            log: Arc::new(log),
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Logger {
+        Logger::new()
+    }
+}
+
+impl Middleware for Logger {
+    fn call(&self, request: Request, next: Next) -> RouteResponse {
+        let start = Instant::now();
+        let peer = request.extensions().get::<ConnectionInfo>().map(|c| c.peer_addr);
+        let method = request.method();
+        let path = String::from(request.path());
+        let log = self.log.clone();
+
+        next.run(request).map(move |response| {
+            log(&LogEntry {
+                peer,
+                method,
+                path,
+                route: response.extensions().get::<MatchedRoute>().map(|r| r.0.clone()),
+                status: response.status(),
+                size: response.body().content_length(),
+                latency: start.elapsed(),
+            });
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod logger_should {
+    use super::*;
+    use std::sync::Mutex;
+    use http::router::{HandleRouteResult, Parameters, Router};
+    use http::types::RequestBuilder;
+    use pollable::Pollable;
+
+    fn log_requests(router: Router, paths: &[&str]) -> Vec<LogEntry> {
+        let entries = Arc::new(Mutex::new(vec![]));
+        let sink = entries.clone();
+        let router = Router::builder()
+            .middleware(Logger::with(move |entry| sink.lock().unwrap().push(entry.clone())))
+            .mount("/", router)
+            .build();
+
+        for path in paths {
+            let request = RequestBuilder::new(HttpMethod::Get, path).build();
+            if let HandleRouteResult::Handled(mut response) = router.route(request) {
+                response.poll().unwrap();
+            }
+        }
+
+        let entries = entries.lock().unwrap();
+        entries.clone()
+    }
+
+    #[test]
+    fn record_each_request() {
+        let entries = log_requests(
+            Router::builder()
+                .mount("/users", Router::builder()
+                    .get("/:id", |_: Request, _: &Parameters| "Hello")
+                    .build())
+                .build(),
+            &["/users/42", "/missing"]);
+
+        assert_eq!(2, entries.len());
+        assert_eq!(HttpMethod::Get, entries[0].method);
+        assert_eq!("/users/42", entries[0].path);
+        assert_eq!(Some(String::from("/users/:id")), entries[0].route);
+        assert_eq!(StatusCode::Ok, entries[0].status);
+        assert_eq!(Some(5), entries[0].size);
+
+        assert_eq!(None, entries[1].route);
+        assert_eq!(StatusCode::NotFound, entries[1].status);
+    }
+
+    #[test]
+    fn format_entries_as_a_line() {
+        let mut entry = LogEntry {
+            peer: None,
+            method: HttpMethod::Get,
+            path: String::from("/users/42"),
+            route: Some(String::from("/users/:id")),
+            status: StatusCode::Ok,
+            size: Some(13),
+            latency: Duration::from_micros(1200),
+        };
+
+        assert_eq!("GET /users/42 (/users/:id) 200 13B 1.2ms", entry.to_string());
+
+        entry.peer = Some("127.0.0.1:50712".parse().unwrap());
+        assert_eq!("127.0.0.1:50712 GET /users/42 (/users/:id) 200 13B 1.2ms",
+                   entry.to_string());
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::middleware::{Middleware, Next};
+use http::response::RouteResponse;
+use http::router::MatchedRoute;
+use http::types::{HttpMethod, Request};
+
+/// The upper bounds of the latency histogram's buckets, in
+/// microseconds. Slower requests fall into a final, unbounded bucket.
+const BUCKETS: [u64; 12] = [
+    1_000, 5_000, 10_000, 25_000, 50_000, 100_000,
+    250_000, 500_000, 1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+
+/// A histogram of request latencies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    counts: [u64; 13],
+    sum: Duration,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            counts: [0; 13],
+            sum: Duration::from_secs(0),
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros();
+        let bucket = BUCKETS.iter()
+            .position(|&bound| micros <= bound as u128)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += latency;
+    }
+
+    /// Each bucket's upper bound, paired with the number of latencies
+    /// no greater than it, and greater than the previous bound. The
+    /// last bucket has no bound.
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        BUCKETS.iter()
+            .map(|&bound| Some(Duration::from_micros(bound)))
+            .chain(Some(None))
+            .zip(self.counts.iter().cloned())
+            .collect()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(self.sum / count as u32),
+        }
+    }
+}
+
+/// The metrics of a route, as of a [`Metrics::snapshot`].
+///
+/// [`Metrics::snapshot`]: struct.Metrics.html#method.snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteMetrics {
+    pub method: HttpMethod,
+    /// The route's pattern. E.g. `/users/:id`.
+    pub route: String,
+    pub requests: u64,
+    /// The number of requests answered with a `5xx` status.
+    pub errors: u64,
+    /// The time taken for responses to be ready, not including writing
+    /// them to the peer.
+    pub latency: Histogram,
+}
+
+/// Middleware that collects [`RouteMetrics`] for each route and
+/// method that handles requests.
+///
+/// A `Metrics` is cheap to clone, and clones share their metrics, so
+/// one can be added to a router while another serves snapshots. E.g.
+///
+/// ```rust
+/// # use server_fx::http::metrics::Metrics;
+/// # use server_fx::http::router::{Parameters, Router};
+/// # use server_fx::http::types::Request;
+/// let metrics = Metrics::new();
+/// let snapshots = metrics.clone();
+///
+/// let router = Router::builder()
+///     .middleware(metrics)
+///     .get("/metrics", move |_: Request, _: &Parameters| {
+///         format!("{:?}", snapshots.snapshot())
+///     })
+///     .build();
+/// ```
+///
+/// Requests that no route handles aren't counted.
+///
+/// [`RouteMetrics`]: struct.RouteMetrics.html
+#[derive(Clone, Default)]
+pub struct Metrics {
+    routes: Arc<Mutex<BTreeMap<(String, String), RouteMetrics>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// The metrics of each route, ordered by pattern then method.
+    pub fn snapshot(&self) -> Vec<RouteMetrics> {
+        let routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        routes.values().cloned().collect()
+    }
+
+    /// Discards the metrics collected so far.
+    pub fn reset(&self) {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+impl Middleware for Metrics {
+    fn call(&self, request: Request, next: Next) -> RouteResponse {
+        let start = Instant::now();
+        let method = request.method();
+        let routes = self.routes.clone();
+
+        next.run(request).map(move |response| {
+            if let Some(route) = response.extensions().get::<MatchedRoute>() {
+                let mut routes = routes.lock().unwrap_or_else(|e| e.into_inner());
+                let metrics = routes.entry((route.0.clone(), method.to_string()))
+                    .or_insert_with(|| RouteMetrics {
+                        method,
+                        route: route.0.clone(),
+                        requests: 0,
+                        errors: 0,
+                        latency: Histogram::new(),
+                    });
+
+                metrics.requests += 1;
+                if response.status().is_server_error() {
+                    metrics.errors += 1;
+                }
+                metrics.latency.record(start.elapsed());
+            }
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod metrics_should {
+    use super::*;
+    use http::router::{HandleRouteResult, Parameters, Router};
+    use http::types::{RequestBuilder, StatusCode};
+    use pollable::Pollable;
+
+    fn send(router: &Router, method: HttpMethod, path: &str) {
+        let request = RequestBuilder::new(method, path).build();
+        if let HandleRouteResult::Handled(mut response) = router.route(request) {
+            response.poll().unwrap();
+        }
+    }
+
+    #[test]
+    fn count_requests_per_route() {
+        let metrics = Metrics::new();
+        let router = Router::builder()
+            .middleware(metrics.clone())
+            .mount("/users", Router::builder()
+                .get("/:id", |_: Request, params: &Parameters| match params.value("id") {
+                    Some("0") => StatusCode::InternalServerError,
+                    _ => StatusCode::Ok,
+                })
+                .post("/:id", |_: Request, _: &Parameters| "Saved")
+                .build())
+            .build();
+
+        for path in &["/users/1", "/users/2", "/users/0", "/missing"] {
+            send(&router, HttpMethod::Get, path);
+        }
+        send(&router, HttpMethod::Post, "/users/1");
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(2, snapshot.len());
+
+        assert_eq!(HttpMethod::Get, snapshot[0].method);
+        assert_eq!("/users/:id", snapshot[0].route);
+        assert_eq!(3, snapshot[0].requests);
+        assert_eq!(1, snapshot[0].errors);
+        assert_eq!(3, snapshot[0].latency.count());
+
+        assert_eq!(HttpMethod::Post, snapshot[1].method);
+        assert_eq!((1, 0), (snapshot[1].requests, snapshot[1].errors));
+
+        metrics.reset();
+        assert!(metrics.snapshot().is_empty());
+    }
+
+    #[test]
+    fn bucket_latencies() {
+        let mut histogram = Histogram::new();
+        histogram.record(Duration::from_micros(500));
+        histogram.record(Duration::from_millis(30));
+        histogram.record(Duration::from_secs(60));
+
+        let buckets = histogram.buckets();
+        assert_eq!((Some(Duration::from_millis(1)), 1), buckets[0]);
+        assert_eq!((Some(Duration::from_millis(50)), 1), buckets[4]);
+        assert_eq!((None, 1), buckets[12]);
+        assert_eq!(3, histogram.count());
+        assert_eq!(Some(Duration::from_nanos(20_010_166_666)), histogram.mean());
+    }
+}
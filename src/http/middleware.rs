@@ -0,0 +1,56 @@
+use http::response::RouteResponse;
+use http::types::Request;
+
+/// Code that runs around the handlers of a [`Router`].
+///
+/// Middleware receives each request before it's routed, along with
+/// the rest of the chain as [`Next`]. It can answer the request
+/// itself (E.g. to reject it), or modify it and pass it on with
+/// `next.run(request)`. The response that comes back can be altered
+/// with [`RouteResponse::map`] once it's ready.
+///
+/// Closures taking the request and `Next` are also middleware.
+///
+/// [`Router`]: ../router/struct.Router.html
+/// [`Next`]: struct.Next.html
+/// [`RouteResponse::map`]: ../response/struct.RouteResponse.html#method.map
+pub trait Middleware {
+    fn call(&self, request: Request, next: Next) -> RouteResponse;
+}
+
+impl<F> Middleware for F where
+    F: Fn(Request, Next) -> RouteResponse
+{
+    fn call(&self, request: Request, next: Next) -> RouteResponse {
+        self(request, next)
+    }
+}
+
+/// The middleware and handler following a [`Middleware`] in the
+/// chain.
+///
+/// [`Middleware`]: trait.Middleware.html
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware + Send + Sync>],
+    endpoint: &'a dyn Fn(Request) -> RouteResponse,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middleware: &'a [Box<dyn Middleware + Send + Sync>],
+                      endpoint: &'a dyn Fn(Request) -> RouteResponse)
+        -> Next<'a>
+    {
+        Next {
+            middleware,
+            endpoint,
+        }
+    }
+
+    /// Passes `request` on to the rest of the chain.
+    pub fn run(self, request: Request) -> RouteResponse {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.call(request, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(request),
+        }
+    }
+}
@@ -1,3 +1,36 @@
 pub mod types;
 pub mod parser;
 pub mod router;
+pub mod host;
+pub mod swap;
+pub mod body;
+pub mod transport;
+pub mod extensions;
+pub mod codec;
+pub mod response;
+pub mod uri;
+pub mod form;
+pub mod query;
+pub mod middleware;
+pub mod extract;
+pub mod auth;
+pub mod logger;
+pub mod metrics;
+pub mod errors;
+pub mod upgrade;
+pub mod tunnel;
+pub mod client;
+pub mod pool;
+pub mod balance;
+pub mod proxy;
+pub mod forwarded;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod cookie;
+#[cfg(feature = "sessions")]
+pub mod session;
+mod base64;
+pub mod multipart;
+pub mod date;
+#[cfg(feature = "serde")]
+pub mod json;
@@ -0,0 +1,592 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use http::body::Body;
+use http::types::{BodyChunk, Request};
+use pollable::Pollable;
+use result::PollResult;
+
+type Poll<T> = Result<PollResult<T>, io::Error>;
+
+/// The largest header block a part may have.
+const MAX_HEADERS_LEN: usize = 8 * 1024;
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Splits a header value like `form-data; name="a"; filename="b"` into
+/// its parameters, unquoting any quoted values.
+fn header_params(value: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut rest = value.split_once(';').map(|(_, p)| p).unwrap_or("");
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        let eq = match rest.find('=') {
+            Some(p) => p,
+            None => return params,
+        };
+
+        let name = rest[..eq].trim().to_ascii_lowercase();
+        let value_start = &rest[eq + 1..];
+        let mut value = String::new();
+
+        if let Some(quoted) = value_start.strip_prefix('"') {
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => if let Some((_, c)) = chars.next() { value.push(c) },
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    },
+                    c => value.push(c),
+                }
+            }
+            rest = &quoted[end..];
+        }
+        else {
+            let end = value_start.find(';').unwrap_or(value_start.len());
+            value.push_str(value_start[..end].trim());
+            rest = &value_start[end..];
+        }
+
+        params.push((name, value));
+    }
+}
+
+/// Extracts the boundary from a `multipart/*` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if !media_type.to_ascii_lowercase().starts_with("multipart/") {
+        return None;
+    }
+
+    header_params(content_type).into_iter()
+        .find(|p| p.0 == "boundary")
+        .map(|p| p.1)
+        .filter(|b| !b.is_empty())
+}
+
+/// The headers of one part of a multipart body.
+#[derive(Debug, Clone)]
+pub struct Part {
+    headers: Vec<(String, String)>,
+}
+
+impl Part {
+    pub fn headers(&self) -> ::std::slice::Iter<'_, (String, String)> {
+        self.headers.iter()
+    }
+
+    pub fn header_value(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|h| h.0.eq_ignore_ascii_case(name))
+            .map(|h| &*h.1)
+    }
+
+    fn disposition_param(&self, name: &str) -> Option<String> {
+        self.header_value("Content-Disposition")
+            .and_then(|v| header_params(v).into_iter().find(|p| p.0 == name))
+            .map(|p| p.1)
+    }
+
+    /// The form field name from the part's `Content-Disposition`.
+    pub fn name(&self) -> Option<String> {
+        self.disposition_param("name")
+    }
+
+    /// The file name from the part's `Content-Disposition`, present
+    /// when the part is a file upload.
+    pub fn filename(&self) -> Option<String> {
+        self.disposition_param("filename")
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header_value("Content-Type")
+    }
+}
+
+enum State {
+    /// Looking for the next boundary delimiter.
+    Boundary,
+    /// Just past a delimiter, deciding if it closes the body.
+    AfterBoundary,
+    Headers,
+    Data,
+    Done,
+}
+
+/// An incremental parser for `multipart/*` bodies (RFC 7578).
+///
+/// The body, `P`, is polled for chunks only as the parts are
+/// consumed, so the parser never buffers more than a part's headers
+/// and a chunk of its content. Call [`poll_part`] to move to the next
+/// part and read its headers, then [`poll_data`] (or [`data`]) to
+/// stream its content. Any content not read is skipped when moving to
+/// the next part.
+///
+/// The body of a request a server has read is already in memory,
+/// though, as an `HttpCodec` reads each request in full before it's
+/// handled. Uploads larger than the codec's `max_body_size`, of 1 MiB
+/// by default, are refused with `413 Payload Too Large` before they
+/// reach a handler, so raise it to accept larger ones.
+///
+/// [`poll_part`]: #method.poll_part
+/// [`poll_data`]: #method.poll_data
+/// [`data`]: #method.data
+pub struct Multipart<P> {
+    source: P,
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: State,
+    eof: bool,
+}
+
+impl<P> Multipart<P> where
+    P: Pollable<Item=Option<BodyChunk>, Error=io::Error>
+{
+    pub fn new(boundary: &str, source: P) -> Multipart<P> {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend(boundary.as_bytes());
+
+        Multipart {
+            source,
+            delimiter,
+            // The first delimiter isn't preceded by a line break, so
+            // one is added to treat all delimiters the same...
+            buffer: b"\r\n".to_vec(),
+            state: State::Boundary,
+            eof: false,
+        }
+    }
+
+    /// Reads more of the body into the buffer. Fails if the body
+    /// ends before the closing delimiter.
+    fn fill(&mut self) -> Poll<()> {
+        if self.eof {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        match self.source.poll()? {
+            PollResult::Ready(Some(chunk)) => {
+                self.buffer.extend(chunk);
+                Ok(PollResult::Ready(()))
+            },
+            PollResult::Ready(None) => {
+                self.eof = true;
+                Ok(PollResult::Ready(()))
+            },
+            PollResult::NotReady => Ok(PollResult::NotReady),
+        }
+    }
+
+    /// Moves to the next part, returning its headers, or `None` once
+    /// the closing delimiter has been reached.
+    pub fn poll_part(&mut self) -> Poll<Option<Part>> {
+        loop {
+            match self.state {
+                State::Data => match self.poll_data()? {
+                    PollResult::Ready(_) => continue,
+                    PollResult::NotReady => return Ok(PollResult::NotReady),
+                },
+                State::Done => return Ok(PollResult::Ready(None)),
+                State::Boundary => {
+                    match find(&self.buffer, &self.delimiter) {
+                        Some(p) => {
+                            self.buffer.drain(..p + self.delimiter.len());
+                            self.state = State::AfterBoundary;
+                            continue;
+                        },
+                        None => {
+                            let keep = self.delimiter.len() - 1;
+                            if self.buffer.len() > keep {
+                                let n = self.buffer.len() - keep;
+                                self.buffer.drain(..n);
+                            }
+                        },
+                    }
+                },
+                State::AfterBoundary => {
+                    if self.buffer.starts_with(b"--") {
+                        self.state = State::Done;
+                        continue;
+                    }
+
+                    // Skip any transport padding before the line break
+                    if let Some(p) = find(&self.buffer, b"\r\n") {
+                        if self.buffer[..p].iter().any(|b| *b != b' ' && *b != b'\t') {
+                            return Err(invalid_data("Malformed multipart delimiter"));
+                        }
+                        self.buffer.drain(..p + 2);
+                        self.state = State::Headers;
+                        continue;
+                    }
+                },
+                State::Headers => {
+                    if let Some(part) = self.parse_headers()? {
+                        self.state = State::Data;
+                        return Ok(PollResult::Ready(Some(part)));
+                    }
+                },
+            }
+
+            if let PollResult::NotReady = self.fill()? {
+                return Ok(PollResult::NotReady);
+            }
+        }
+    }
+
+    fn parse_headers(&mut self) -> io::Result<Option<Part>> {
+        let end = match self.buffer.starts_with(b"\r\n") {
+            true => 0,
+            false => match find(&self.buffer, b"\r\n\r\n") {
+                Some(p) => p + 2,
+                None if self.buffer.len() > MAX_HEADERS_LEN =>
+                    return Err(invalid_data("Multipart headers are too long")),
+                None => return Ok(None),
+            },
+        };
+
+        let headers = ::std::str::from_utf8(&self.buffer[..end])
+            .map_err(|_| invalid_data("Multipart headers aren't valid UTF-8"))?
+            .split("\r\n")
+            .filter(|l| !l.is_empty())
+            .map(|l| match l.split_once(':') {
+                Some((n, v)) => Ok((n.trim().to_owned(), v.trim().to_owned())),
+                None => Err(invalid_data("Malformed multipart header")),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        self.buffer.drain(..end + 2);
+        Ok(Some(Part { headers }))
+    }
+
+    /// Yields the next chunk of the current part's content, or `None`
+    /// once the part is finished.
+    pub fn poll_data(&mut self) -> Poll<Option<BodyChunk>> {
+        loop {
+            if let State::Data = self.state {} else {
+                return Ok(PollResult::Ready(None));
+            }
+
+            let available = match find(&self.buffer, &self.delimiter) {
+                Some(0) => {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = State::AfterBoundary;
+                    return Ok(PollResult::Ready(None));
+                },
+                Some(p) => p,
+                // Hold back enough to complete a delimiter that's split
+                // across chunks...
+                None => self.buffer.len()
+                    .saturating_sub(self.delimiter.len() - 1),
+            };
+
+            if available > 0 {
+                let chunk = self.buffer.drain(..available).collect();
+                return Ok(PollResult::Ready(Some(chunk)));
+            }
+
+            if let PollResult::NotReady = self.fill()? {
+                return Ok(PollResult::NotReady);
+            }
+        }
+    }
+
+    /// The content of the current part, as a pollable of chunks.
+    pub fn data(&mut self) -> PartData<'_, P> {
+        PartData(self)
+    }
+}
+
+impl Multipart<Body> {
+    /// Creates a parser for the body of `request`. Returns `None` if
+    /// the request doesn't have a `multipart/*` content type with a
+    /// boundary.
+    ///
+    /// The body has been read in full, within the size the server's
+    /// `HttpCodec` allows, so its parts are taken from memory.
+    pub fn from_request(request: Request) -> Option<Multipart<Body>> {
+        let boundary = request.header_value("Content-Type")
+            .and_then(boundary)?;
+
+        Some(Multipart::new(&boundary, request.into_body()))
+    }
+}
+
+/// The content of the current part of a [`Multipart`] body.
+///
+/// [`Multipart`]: struct.Multipart.html
+pub struct PartData<'a, P: 'a>(&'a mut Multipart<P>);
+
+impl<'a, P> Pollable for PartData<'a, P> where
+    P: Pollable<Item=Option<BodyChunk>, Error=io::Error>
+{
+    type Item = Option<BodyChunk>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item> {
+        self.0.poll_data()
+    }
+}
+
+/// A temporary file that is deleted when dropped, unless it is
+/// persisted.
+pub struct TempFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl TempFile {
+    fn create() -> io::Result<TempFile> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = ::std::env::temp_dir().join(format!(
+            "server-fx-multipart-{}-{}",
+            ::std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let file = File::create(&path)?;
+
+        Ok(TempFile {
+            path,
+            file: Some(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the file to `path` so that it is kept.
+    pub fn persist<Q: AsRef<Path>>(mut self, path: Q) -> io::Result<()> {
+        self.file.take();
+        fs::rename(&self.path, path)?;
+        self.path = PathBuf::new();
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        self.file.take();
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The collected content of a part. See [`Spool`].
+///
+/// [`Spool`]: struct.Spool.html
+pub enum SpooledData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+/// Collects the content of a part, keeping it in memory until it
+/// grows beyond a threshold, at which point it is moved to a
+/// temporary file.
+///
+/// Writing to the file blocks the polling thread.
+pub struct Spool {
+    threshold: usize,
+    memory: Vec<u8>,
+    file: Option<TempFile>,
+}
+
+impl Spool {
+    pub fn new(threshold: usize) -> Spool {
+        Spool {
+            threshold,
+            memory: vec![],
+            file: None,
+        }
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if self.file.is_none() && self.memory.len() + chunk.len() > self.threshold {
+            let mut file = TempFile::create()?;
+            file.file.as_mut().unwrap().write_all(&self.memory)?;
+            self.memory.clear();
+            self.file = Some(file);
+        }
+
+        match self.file {
+            Some(ref mut f) => f.file.as_mut().unwrap().write_all(chunk),
+            None => {
+                self.memory.extend(chunk);
+                Ok(())
+            },
+        }
+    }
+
+    /// Collects the rest of the current part of `multipart`.
+    pub fn poll_part<P>(&mut self, multipart: &mut Multipart<P>)
+        -> Poll<SpooledData> where
+        P: Pollable<Item=Option<BodyChunk>, Error=io::Error>
+    {
+        loop {
+            match multipart.poll_data()? {
+                PollResult::Ready(Some(chunk)) => self.write(&chunk)?,
+                PollResult::Ready(None) => break,
+                PollResult::NotReady => return Ok(PollResult::NotReady),
+            }
+        }
+
+        let data = match self.file.take() {
+            Some(mut f) => {
+                f.file.as_mut().unwrap().flush()?;
+                SpooledData::File(f)
+            },
+            None => SpooledData::Memory(::std::mem::take(&mut self.memory)),
+        };
+
+        Ok(PollResult::Ready(data))
+    }
+}
+
+#[cfg(test)]
+mod multipart_should {
+    use super::*;
+    use std::io::Read;
+
+    const BODY: &[u8] = b"preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Holiday\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"photo\"; filename=\"a \\\"b\\\".jpg\"\r\n\
+        Content-Type: image/jpeg\r\n\
+        \r\n\
+        \r\n--XyNot the boundary\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    /// Yields the body a few bytes at a time, with a `NotReady`
+    /// between each chunk.
+    struct Trickle(Vec<u8>, bool);
+
+    impl Pollable for Trickle {
+        type Item = Option<BodyChunk>;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Poll<Self::Item> {
+            self.1 = !self.1;
+            if self.1 {
+                return Ok(PollResult::NotReady);
+            }
+
+            let n = ::std::cmp::min(3, self.0.len());
+            match n {
+                0 => Ok(PollResult::Ready(None)),
+                n => Ok(PollResult::Ready(Some(self.0.drain(..n).collect()))),
+            }
+        }
+    }
+
+    fn wait<T, F>(mut f: F) -> T where
+        F: FnMut() -> Poll<T>
+    {
+        loop {
+            if let PollResult::Ready(v) = f().unwrap() {
+                return v;
+            }
+        }
+    }
+
+    fn read_all<P>(multipart: &mut Multipart<P>) -> Vec<u8> where
+        P: Pollable<Item=Option<BodyChunk>, Error=io::Error>
+    {
+        let mut data = vec![];
+        while let Some(chunk) = wait(|| multipart.poll_data()) {
+            data.extend(chunk);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_parts_incrementally() {
+        let mut multipart = Multipart::new("XyZ", Trickle(BODY.to_vec(), false));
+
+        let part = wait(|| multipart.poll_part()).unwrap();
+        assert_eq!(Some("title".to_string()), part.name());
+        assert_eq!(None, part.filename());
+        assert_eq!(b"Holiday".to_vec(), read_all(&mut multipart));
+
+        let part = wait(|| multipart.poll_part()).unwrap();
+        assert_eq!(Some("photo".to_string()), part.name());
+        assert_eq!(Some("a \"b\".jpg".to_string()), part.filename());
+        assert_eq!(Some("image/jpeg"), part.content_type());
+        assert_eq!(b"\r\n--XyNot the boundary".to_vec(), read_all(&mut multipart));
+
+        assert!(wait(|| multipart.poll_part()).is_none());
+    }
+
+    #[test]
+    fn skip_unread_content() {
+        let mut multipart = Multipart::new("XyZ", Body::from(BODY));
+
+        assert!(wait(|| multipart.poll_part()).is_some());
+        let part = wait(|| multipart.poll_part()).unwrap();
+        assert_eq!(Some("photo".to_string()), part.name());
+        assert!(wait(|| multipart.poll_part()).is_none());
+    }
+
+    #[test]
+    fn fail_on_a_truncated_body() {
+        let end = find(BODY, b"Holiday").unwrap() + 3;
+        let mut multipart = Multipart::new("XyZ", Body::from(&BODY[..end]));
+
+        assert!(wait(|| multipart.poll_part()).is_some());
+        let err = multipart.poll_data()
+            .and_then(|_| multipart.poll_data())
+            .and_then(|_| multipart.poll_data())
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn spool_large_parts_to_disk() {
+        let mut multipart = Multipart::new("XyZ", Body::from(BODY));
+
+        assert!(wait(|| multipart.poll_part()).is_some());
+        match wait(|| Spool::new(16).poll_part(&mut multipart)) {
+            SpooledData::Memory(data) => assert_eq!(b"Holiday".to_vec(), data),
+            SpooledData::File(_) => panic!("Expected the part in memory"),
+        }
+
+        assert!(wait(|| multipart.poll_part()).is_some());
+        let mut spool = Spool::new(4);
+        let path = match wait(|| spool.poll_part(&mut multipart)) {
+            SpooledData::File(file) => {
+                let mut data = String::new();
+                File::open(file.path()).unwrap().read_to_string(&mut data).unwrap();
+                assert_eq!("\r\n--XyNot the boundary", data);
+                file.path().to_owned()
+            },
+            SpooledData::Memory(_) => panic!("Expected the part on disk"),
+        };
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn extract_the_boundary() {
+        assert_eq!(Some("abc".to_string()),
+                   boundary("multipart/form-data; boundary=abc"));
+        assert_eq!(Some("a;b c".to_string()),
+                   boundary("Multipart/Mixed; charset=utf-8; boundary=\"a;b c\""));
+        assert_eq!(None, boundary("text/plain; boundary=abc"));
+        assert_eq!(None, boundary("multipart/form-data"));
+    }
+}
@@ -1,22 +1,18 @@
 use std::mem;
 
 fn header_line_is_empty(data: &[u8]) -> bool {
-    (data.len() > 0 && data[0] == b'\n') ||
+    (!data.is_empty() && data[0] == b'\n') ||
         (data.len() > 1 && data[0] == b'\r' && data[1] == b'\n')
 }
 
 fn skip_newline(data: &[u8]) -> &[u8] {
     let mut to_skip = 0;
-    data.iter()
-        .position(|b| *b == b'\r')
-        .map(|p| {
-            to_skip = p + 1;
-        });
-    data.iter()
-        .position(|b| *b == b'\n')
-        .map(|p| {
-            to_skip = p + 1;
-        });
+    if let Some(p) = data.iter().position(|b| *b == b'\r') {
+        to_skip = p + 1;
+    }
+    if let Some(p) = data.iter().position(|b| *b == b'\n') {
+        to_skip = p + 1;
+    }
 
     &data[to_skip..]
 }
@@ -63,6 +59,9 @@ fn split_at_first_header_separator(data: &[u8]) -> Option<(&[u8], &[u8])> {
         .map(|p| data.split_at(p))
 }
 
+/// The parts of a protocol line, followed by any remaining data.
+pub type ProtocolLine<'a> = (&'a [u8], &'a [u8], &'a [u8], &'a [u8]);
+
 /// A type to parse the *protocol line* of a HTTP request.
 /// E.g.
 ///
@@ -145,7 +144,7 @@ impl<'a> ProtocolParser<'a> {
     /// ```
     ///
     /// [`ProtocolParser::new`]: enum.ProtocolParser.html#method.new
-    pub fn parse(&mut self) -> Option<(&'a [u8], &'a [u8], &'a [u8], &'a [u8])> {
+    pub fn parse(&mut self) -> Option<ProtocolLine<'a>> {
         use self::ProtocolParser::*;
         loop {
             let next = match mem::replace(self, Done) {
@@ -264,8 +263,7 @@ struct Object<'headers, 'buffer: 'headers> {
 
 impl<'h, 'b: 'h> Object<'h, 'b> {
     fn version(&self) -> &[u8] {
-        self.version.as_ref()
-            .map(|v| &**v)
+        self.version
             .expect("'version' is empty")
     }
 
@@ -278,7 +276,7 @@ impl<'h, 'b: 'h> Object<'h, 'b> {
     fn new(headers: &'h mut [Header<'b>]) -> Object<'h, 'b> {
         Object {
             version: None,
-            headers: headers,
+            headers,
         }
     }
 
@@ -286,8 +284,6 @@ impl<'h, 'b: 'h> Object<'h, 'b> {
                     data: &'b [u8], 
                     header_data: &'b [u8]) -> Option<usize>
     {
-        use std::mem::transmute;
-
         let mut parser = HeaderParser::new(header_data);
         let mut header_idx = 0;
 
@@ -295,16 +291,16 @@ impl<'h, 'b: 'h> Object<'h, 'b> {
             let bytes_parsed = (tail.as_ptr() as usize) - 
                                (data.as_ptr() as usize);
 
-            if name.len() == 0 {
-                self.headers =  unsafe { 
-                    transmute(&mut self.headers[..header_idx])
-                };
+            if name.is_empty() {
+                let headers = mem::take(&mut self.headers);
+                self.headers = &mut headers[..header_idx];
 
                 return Some(bytes_parsed)
             }
 
+            // More headers than there's room for can't be parsed.
             if header_idx >= self.headers.len() {
-                panic!("Not enough space for headers!");
+                return None;
             }
 
             self.headers[header_idx] = Header(name, val);
@@ -374,14 +370,12 @@ impl<'h, 'b: 'h> ::std::fmt::Debug for Request<'h, 'b> {
 
 impl<'h, 'b: 'h> Request<'h, 'b> {
     pub fn method(&self) -> &[u8] {
-        self.method.as_ref()
-            .map(|v| &**v)
+        self.method
             .expect("'method' is empty")
     }
 
     pub fn path(&self) -> &[u8] {
-        self.path.as_ref()
-            .map(|v| &**v)
+        self.path
             .expect("'path' is empty")
     }
 
@@ -412,10 +406,9 @@ impl<'h, 'b: 'h> Request<'h, 'b> {
             path = Some(part2);
             Some(part3)
         })
-        .map(|n| {
+        .inspect(|_n| {
             self.method = method;
             self.path = path;
-            n
         })
     }
 }
@@ -429,15 +422,11 @@ pub struct Response<'headers, 'buffer: 'headers> {
 impl<'h, 'b: 'h> Response<'h, 'b> {
     pub fn status_code(&self) -> &[u8] {
         self.status_code
-            .as_ref()
-            .map(|v| &**v)
             .expect("'status_code' is empty")
     }
 
     pub fn status_text(&self) -> &[u8] {
         self.status_text
-            .as_ref()
-            .map(|v| &**v)
             .expect("'status_text' is empty")
     }
 
@@ -468,10 +457,9 @@ impl<'h, 'b: 'h> Response<'h, 'b> {
             status_text = Some(part3);
             Some(part1)
         })
-        .map(|n| {
+        .inspect(|_n| {
             self.status_code = status_code;
             self.status_text = status_text;
-            n
         })
     }
 }
@@ -568,7 +556,7 @@ mod request_parser_should {
         loop {
             let mut headers = vec![Header::default(); header_size];
             let mut parser = Request::new(&mut headers);
-            if let Some(_) = parser.parse(proxy_connect) {
+            if parser.parse(proxy_connect).is_some() {
 
                 assert_eq!(types::HttpMethod::Connect, parser.method().into());
                 assert_eq!("docs.rs:443", str::from_utf8(parser.path()).unwrap());
@@ -0,0 +1,222 @@
+//! Keeping connections to servers open between requests.
+//!
+//! Once a response's body has been read, the connection it arrived on
+//! can be checked in to a [`Pool`], rather than closed. The next
+//! request to the same server checks it out, and is sent without
+//! waiting for a new connection to be established.
+//!
+//! [`Pool`]: struct.Pool.html
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use framed::Framed;
+use http::client::ClientCodec;
+
+/// A connection waiting to be used again.
+struct Idle {
+    stream: net::TcpStream,
+    since: Instant,
+}
+
+/// Whether the server at the other end of `stream` has left it open,
+/// and sent nothing since its last response.
+fn is_open(stream: &net::TcpStream) -> bool {
+    let mut byte = [0; 1];
+    match stream.peek(&mut byte) {
+        Err(ref e) => e.kind() == io::ErrorKind::WouldBlock,
+        Ok(_) => false,
+    }
+}
+
+/// Idle connections to servers, keyed by the `host:port` of each.
+///
+/// A pool keeps at most `max_idle` connections, and at most
+/// `max_idle_per_host` to each server, dropping the connections that
+/// have been idle longest to make room. Connections are also dropped
+/// once they've been idle for `idle_timeout`, as servers tend to close
+/// them around then anyway. Clones of a pool share its connections.
+///
+/// Connections are expected to be non-blocking, as those from a
+/// `Dialer` are.
+#[derive(Clone)]
+pub struct Pool {
+    idle: Arc<Mutex<BTreeMap<String, Vec<Idle>>>>,
+    max_idle: usize,
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+}
+
+impl Default for Pool {
+    fn default() -> Pool {
+        Pool {
+            idle: Arc::default(),
+            max_idle: 64,
+            max_idle_per_host: 8,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Pool {
+    pub fn new() -> Pool {
+        Pool::default()
+    }
+
+    /// Sets how many idle connections are kept, in all. Defaults to 64.
+    pub fn max_idle(mut self, n: usize) -> Pool {
+        self.max_idle = n;
+        self
+    }
+
+    /// Sets how many idle connections are kept to each server. Defaults
+    /// to 8.
+    pub fn max_idle_per_host(mut self, n: usize) -> Pool {
+        self.max_idle_per_host = n;
+        self
+    }
+
+    /// Sets how long a connection is kept while it's idle. Defaults to
+    /// 60 seconds.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Pool {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Takes the most recently used connection to `host`, if there's
+    /// one still open.
+    pub fn checkout(&self, host: &str) -> Option<Framed<net::TcpStream, ClientCodec>> {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.get_mut(host)?;
+        let mut found = None;
+        while let Some(connection) = connections.pop() {
+            if connection.since.elapsed() < self.idle_timeout && is_open(&connection.stream) {
+                found = Some(Framed::new(connection.stream, ClientCodec::new()));
+                break;
+            }
+        }
+
+        if connections.is_empty() {
+            idle.remove(host);
+        }
+        found
+    }
+
+    /// Keeps `connection` to `host` for another request. It's dropped
+    /// instead if the server sent more than its last response.
+    pub fn checkin(&self, host: &str, connection: Framed<net::TcpStream, ClientCodec>) {
+        let (stream, unread) = connection.into_parts();
+        if !unread.is_empty() || self.max_idle == 0 || self.max_idle_per_host == 0 {
+            return;
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        let timeout = self.idle_timeout;
+        idle.retain(|_, connections| {
+            connections.retain(|c| c.since.elapsed() < timeout);
+            !connections.is_empty()
+        });
+
+        let connections = idle.entry(String::from(host)).or_default();
+        connections.push(Idle {
+            stream,
+            since: Instant::now(),
+        });
+        if connections.len() > self.max_idle_per_host {
+            connections.remove(0);
+        }
+
+        while idle.values().map(Vec::len).sum::<usize>() > self.max_idle {
+            let oldest = idle.iter()
+                .min_by_key(|&(_, connections)| connections[0].since)
+                .map(|(host, _)| host.clone())
+                .unwrap();
+            let connections = idle.get_mut(&oldest).unwrap();
+            connections.remove(0);
+            if connections.is_empty() {
+                idle.remove(&oldest);
+            }
+        }
+    }
+
+    /// The number of idle connections in the pool.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod pool_should {
+    use super::*;
+    use std::io::Write;
+    use std::thread;
+
+    /// A connection to a server, and the server's end of it.
+    fn connection() -> (Framed<net::TcpStream, ClientCodec>, net::TcpStream) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (Framed::new(client, ClientCodec::new()), server)
+    }
+
+    #[test]
+    fn hand_back_the_connections_checked_in_to_each_host() {
+        let pool = Pool::new();
+        let (a, _a) = connection();
+        let (b, _b) = connection();
+        let a_addr = a.get_ref().local_addr().unwrap();
+        pool.checkin("a:80", a);
+        pool.checkin("b:80", b);
+
+        let checked_out = pool.checkout("a:80").unwrap();
+        assert_eq!(a_addr, checked_out.get_ref().local_addr().unwrap());
+        assert!(pool.checkout("a:80").is_none());
+        assert_eq!(1, pool.idle());
+    }
+
+    #[test]
+    fn drop_the_longest_idle_connections_to_make_room() {
+        let pool = Pool::new().max_idle(2).max_idle_per_host(2);
+        let servers = ["a:80", "a:80", "a:80", "b:80"].iter()
+            .map(|host| {
+                let (connection, server) = connection();
+                pool.checkin(host, connection);
+                server
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(2, pool.idle());
+        let a = pool.checkout("a:80").unwrap();
+        assert_eq!(servers[2].peer_addr().unwrap(), a.get_ref().local_addr().unwrap());
+        assert!(pool.checkout("a:80").is_none());
+        let b = pool.checkout("b:80").unwrap();
+        assert_eq!(servers[3].peer_addr().unwrap(), b.get_ref().local_addr().unwrap());
+    }
+
+    #[test]
+    fn not_hand_back_connections_that_are_stale() {
+        let expiring = Pool::new().idle_timeout(Duration::from_millis(50));
+        let (expired, _server) = connection();
+        expiring.checkin("a:80", expired);
+        thread::sleep(Duration::from_millis(100));
+        assert!(expiring.checkout("a:80").is_none());
+
+        let pool = Pool::new();
+        let (closed, server) = connection();
+        pool.checkin("a:80", closed);
+        drop(server);
+        thread::sleep(Duration::from_millis(50));
+        assert!(pool.checkout("a:80").is_none());
+
+        let (chatty, mut server) = connection();
+        pool.checkin("a:80", chatty);
+        server.write_all(b"HTTP/1.1 200 OK\r\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(pool.checkout("a:80").is_none());
+        assert_eq!(0, pool.idle());
+    }
+}
@@ -0,0 +1,949 @@
+//! Forwarding requests to other servers.
+//!
+//! A [`ReverseProxy`] forwards the requests it handles to an upstream
+//! server, and answers them with the server's responses. It can handle
+//! every request, as a server's handler, or those a route matches, as
+//! the route's handler. E.g.
+//!
+//! ```rust,no_run
+//! # use server_fx::http::proxy::ReverseProxy;
+//! # use server_fx::http::router::{Route, Router};
+//! let router = Router::builder()
+//!     .route(Route::any("/api/*", ReverseProxy::new("127.0.0.1:8080")))
+//!     .build();
+//! ```
+//!
+//! The response's body is streamed: it's read from the server as it's
+//! written to the client. Once it has been, the connection to the
+//! server is kept in a `Pool` for the next request. The request's body
+//! is written to the server as it's polled, but a request the server
+//! has read arrives with its body already buffered, within the
+//! `HttpCodec`'s `max_body_size`.
+//!
+//! A proxy can also spread requests across several servers, with a
+//! `Balancer` from the `balance` module.
+//!
+//! A [`ForwardProxy`] forwards requests to whichever servers they name,
+//! on behalf of clients configured to use it, and opens tunnels for
+//! their `CONNECT` requests.
+//!
+//! [`ReverseProxy`]: struct.ReverseProxy.html
+//! [`ForwardProxy`]: struct.ForwardProxy.html
+
+use std::io;
+use std::mem;
+use std::net;
+use std::sync::Arc;
+use std::time::Duration;
+
+use client::{Dial, Dialer};
+use framed::Framed;
+use handler::Handler;
+use http::balance::{Active, Balancer, RoundRobin, Upstream};
+use http::body::Body;
+use http::client::{ClientCodec, Fetch};
+use http::forwarded::{self, Forwarded};
+use http::pool::Pool;
+use http::response::RouteResponse;
+use http::router::{Parameters, RouteHandler};
+use http::tunnel::{Connecting, Tunnel};
+use http::types::{BodyChunk, HttpMethod, HttpVersion, Request, RequestBuilder, Response,
+                  ResponseBuilder, StatusCode};
+use pollable::Pollable;
+use result::PollResult;
+use twist::TwisterBuilder;
+
+/// The response to a request that couldn't be forwarded, because of
+/// `e`: `504 Gateway Timeout` if the server took too long, and `502 Bad
+/// Gateway` otherwise.
+pub(crate) fn gateway_error(e: &io::Error) -> Response {
+    match e.kind() {
+        io::ErrorKind::TimedOut => ResponseBuilder::new(StatusCode::GatewayTimeout).build(),
+        _ => ResponseBuilder::new(StatusCode::BadGateway).build(),
+    }
+}
+
+/// Chooses the upstream for each request.
+type Balance = Arc<dyn Balancer + Send + Sync>;
+
+/// Forwards requests to upstream servers.
+///
+/// The `Host` header of each request is replaced with the address of
+/// the server it's forwarded to. Connections to the servers are reused
+/// for later requests, unless the servers close them.
+///
+/// A request is retried on another server if its server can't be
+/// reached. It's also retried if the server dropped the connection
+/// before responding, as long as the request can safely be sent again:
+/// its method is idempotent, and its body is buffered rather than
+/// streamed. A server that fails is avoided for a while afterwards.
+#[derive(Clone)]
+pub struct ReverseProxy {
+    upstreams: Arc<Vec<Upstream>>,
+    balancer: Balance,
+    dialer: Dialer,
+    pool: Pool,
+    retries: usize,
+    fail_timeout: Duration,
+    preserve_host: bool,
+    forwarded: Forwarded,
+}
+
+impl ReverseProxy {
+    /// A proxy to the server at `upstream`, a `host:port`. E.g.
+    /// `"127.0.0.1:8080"` or `"backend.internal:80"`.
+    pub fn new(upstream: &str) -> ReverseProxy {
+        ReverseProxy::balanced(vec![Upstream::new(upstream)], RoundRobin::new())
+    }
+
+    /// A proxy to the servers in `upstreams`, that forwards each
+    /// request to the one `balancer` picks.
+    ///
+    /// # Panics
+    ///
+    /// If `upstreams` is empty.
+    pub fn balanced<B>(upstreams: Vec<Upstream>, balancer: B) -> ReverseProxy where
+        B: Balancer + Send + Sync + 'static
+    {
+        assert!(!upstreams.is_empty(), "A proxy needs at least one upstream");
+        ReverseProxy {
+            upstreams: Arc::new(upstreams),
+            balancer: Arc::new(balancer),
+            dialer: Dialer::new(),
+            pool: Pool::new(),
+            retries: 2,
+            fail_timeout: Duration::from_secs(10),
+            preserve_host: false,
+            forwarded: Forwarded::new(),
+        }
+    }
+
+    /// Sets how the servers are connected to.
+    pub fn dialer(mut self, dialer: Dialer) -> ReverseProxy {
+        self.dialer = dialer;
+        self
+    }
+
+    /// Sets the pool that connections to the servers are kept in
+    /// between requests, e.g. to share one between proxies.
+    pub fn pool(mut self, pool: Pool) -> ReverseProxy {
+        self.pool = pool;
+        self
+    }
+
+    /// Sets how many times a failed request is retried, on other
+    /// servers. Defaults to 2.
+    pub fn retries(mut self, n: usize) -> ReverseProxy {
+        self.retries = n;
+        self
+    }
+
+    /// Sets how long a server that failed is avoided for, while others
+    /// are up. Defaults to 10 seconds.
+    pub fn fail_timeout(mut self, timeout: Duration) -> ReverseProxy {
+        self.fail_timeout = timeout;
+        self
+    }
+
+    /// Keeps the `Host` header of each request, if it has one, rather
+    /// than replacing it with the address of the server it's forwarded
+    /// to. E.g. for servers that host several sites.
+    pub fn preserve_host(mut self, preserve: bool) -> ReverseProxy {
+        self.preserve_host = preserve;
+        self
+    }
+
+    /// Sets how requests are told apart from those the servers receive
+    /// directly, with `X-Forwarded-For` and similar headers.
+    pub fn forwarded(mut self, forwarded: Forwarded) -> ReverseProxy {
+        self.forwarded = forwarded;
+        self
+    }
+
+    /// Forwards `request` to a server, resolving to its response.
+    pub fn forward(&self, mut request: Request) -> Forwarding {
+        forwarded::strip_request(&mut request);
+        self.forwarded.request(&mut request);
+        request.set_version(HttpVersion::Http11);
+
+        let mut forwarding = Forwarding {
+            proxy: self.clone(),
+            state: ForwardingState::Done,
+            replay: None,
+            tried: vec![],
+            retries: self.retries,
+            active: None,
+        };
+        let upstream = self.pick(&request, &[]).expect("A proxy has at least one upstream");
+        forwarding.state = forwarding.send(upstream, request);
+        forwarding
+    }
+
+    /// Picks the server to forward `request` to, from those it hasn't
+    /// been `tried` on. Servers that failed recently are only picked if
+    /// every other server has, too.
+    fn pick(&self, request: &Request, tried: &[String]) -> Option<Upstream> {
+        let untried = || self.upstreams.iter().filter(|u| !tried.iter().any(|t| t == u.address()));
+        let mut candidates = untried().filter(|u| u.is_healthy()).cloned().collect::<Vec<_>>();
+        if candidates.is_empty() {
+            candidates = untried().cloned().collect();
+        }
+
+        match candidates.is_empty() {
+            true => None,
+            false => Some(candidates.swap_remove(self.balancer.pick(request, &candidates))),
+        }
+    }
+}
+
+/// Sends `request` to `upstream` on `connection`, which goes back in
+/// `pool` once the response has been read.
+fn fetch(pool: &Pool,
+         upstream: &str,
+         connection: Framed<net::TcpStream, ClientCodec>,
+         request: Request)
+    -> Fetch<net::TcpStream>
+{
+    let (pool, upstream) = (pool.clone(), String::from(upstream));
+    Fetch::new(connection, request)
+        .on_release(move |connection| pool.checkin(&upstream, connection))
+}
+
+/// Whether requests made with `method` can be repeated without
+/// changing their outcome.
+fn is_idempotent(method: HttpMethod) -> bool {
+    matches!(method,
+             HttpMethod::Get | HttpMethod::Head | HttpMethod::Put | HttpMethod::Delete |
+             HttpMethod::Options)
+}
+
+/// A copy of `request`, with which it can be sent again, if it can
+/// safely be.
+fn replayable(request: &Request) -> Option<Request> {
+    let body = request.body().as_bytes()?;
+    if !is_idempotent(request.method()) {
+        return None;
+    }
+
+    let builder = request.headers()
+        .fold(RequestBuilder::new(request.method(), "")
+                  .uri(request.uri().clone())
+                  .version(request.version()),
+              |builder, (name, value)| builder.header(name, value));
+    Some(builder.build_with_buffer(body.iter().cloned()))
+}
+
+/// Whether `e` means that the server dropped the connection before it
+/// started to respond.
+fn is_unanswered(e: &io::Error) -> bool {
+    matches!(e.kind(),
+             io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+             io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof)
+}
+
+impl Handler for ReverseProxy {
+    type Request = Request;
+    type Response = Response;
+    type Error = io::Error;
+    type Pollable = Forwarding;
+
+    fn handle(&self, request: Request) -> Forwarding {
+        self.forward(request)
+    }
+}
+
+impl RouteHandler for ReverseProxy {
+    type Response = RouteResponse;
+
+    fn handle<'a>(&'a self, request: Request, _: &Parameters<'a>) -> RouteResponse {
+        RouteResponse::pending(self.forward(request))
+    }
+}
+
+/// A request being forwarded to a server. It resolves to the server's
+/// response, or to a `502 Bad Gateway` if no server could be reached,
+/// or answered.
+pub struct Forwarding {
+    proxy: ReverseProxy,
+    state: ForwardingState,
+    /// A copy of the request, to retry it with, if it can be.
+    replay: Option<Request>,
+    /// The addresses of the servers the request failed on.
+    tried: Vec<String>,
+    retries: usize,
+    active: Option<Active>,
+}
+
+enum ForwardingState {
+    Dialing(Upstream, Dial, Option<Request>),
+    /// The request being sent, and whether the connection it's sent on
+    /// was reused.
+    Fetching(Upstream, Fetch<net::TcpStream>, bool),
+    Failed(io::Error),
+    Done,
+}
+
+impl Forwarding {
+    /// Starts sending `request` to `upstream`.
+    fn send(&mut self, upstream: Upstream, mut request: Request) -> ForwardingState {
+        if !self.proxy.preserve_host || request.header_value("Host").is_none() {
+            request.set_header("Host", upstream.address());
+        }
+        self.active = Some(upstream.start());
+
+        let connection = self.proxy.pool.checkout(upstream.address());
+        // A copy to replay the request with is only made if it could be
+        // retried, on another server, or on this one if the connection
+        // fails as it's reused, as the copy holds the whole body.
+        let retriable = self.retries > 0 &&
+            (connection.is_some() || self.proxy.upstreams.len() > 1);
+        if self.replay.is_none() && retriable {
+            self.replay = replayable(&request);
+        }
+
+        match connection {
+            Some(connection) => {
+                let fetch = fetch(&self.proxy.pool, upstream.address(), connection, request);
+                ForwardingState::Fetching(upstream, fetch, true)
+            },
+            None => {
+                let dial = self.proxy.dialer.dial(upstream.address());
+                ForwardingState::Dialing(upstream, dial, Some(request))
+            },
+        }
+    }
+
+    /// Sends `request` to another server, after failing with `e`, if
+    /// there's a request to send, and a server left to try.
+    fn retry(&mut self, request: Option<Request>, e: io::Error) -> ForwardingState {
+        self.active = None;
+        let next = match request {
+            Some(request) if self.retries > 0 =>
+                self.proxy.pick(&request, &self.tried).map(|upstream| (upstream, request)),
+            _ => None,
+        };
+
+        match next {
+            Some((upstream, request)) => {
+                self.retries -= 1;
+                self.send(upstream, request)
+            },
+            None => ForwardingState::Failed(e),
+        }
+    }
+
+    /// Avoids `upstream`, which the request failed on.
+    fn failed_on(&mut self, upstream: &Upstream) {
+        upstream.fail(self.proxy.fail_timeout);
+        self.tried.push(String::from(upstream.address()));
+    }
+}
+
+/// Drops the headers of the server's `response` that only applied to
+/// the server's connection, or to how its body was framed, and adds
+/// those that tell the client it was `forwarded`. A response without a
+/// body, e.g. to a `HEAD` request, keeps its `Content-Length`, as that
+/// describes the body it would have had.
+fn from_upstream(mut response: Response, forwarded: &Forwarded) -> Response {
+    forwarded::strip_response(&mut response);
+    if response.body().content_length() != Some(0) {
+        response.remove_header("Content-Length");
+    }
+    forwarded.response(&mut response);
+    response
+}
+
+/// The body of a server's response, during which the request counts as
+/// active on the server.
+struct Counted {
+    body: Body,
+    active: Option<Active>,
+}
+
+impl Pollable for Counted {
+    type Item = Option<BodyChunk>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Option<BodyChunk>>, io::Error> {
+        let chunk = self.body.poll()?;
+        if let PollResult::Ready(None) = chunk {
+            self.active = None;
+        }
+        Ok(chunk)
+    }
+}
+
+/// `body`, which counts as `active` until it's been read.
+fn counted(body: Body, active: Option<Active>) -> Body {
+    match body.content_length() {
+        Some(0) => body,
+        Some(n) => Body::sized(n, Counted { body, active }),
+        None => Body::from_pollable(Counted { body, active }),
+    }
+}
+
+impl Pollable for Forwarding {
+    type Item = Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Response>, io::Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, ForwardingState::Done) {
+                ForwardingState::Dialing(upstream, mut dial, mut request) => match dial.poll() {
+                    Ok(PollResult::Ready(stream)) => {
+                        let request = request.take().expect("Poll called on finished result");
+                        let fetch = fetch(&self.proxy.pool,
+                                          upstream.address(),
+                                          Framed::new(stream, ClientCodec::new()),
+                                          request);
+                        ForwardingState::Fetching(upstream, fetch, false)
+                    },
+                    Ok(PollResult::NotReady) => {
+                        self.state = ForwardingState::Dialing(upstream, dial, request);
+                        return Ok(PollResult::NotReady);
+                    },
+                    // Nothing has been sent, so the request can be sent
+                    // elsewhere, whatever it is.
+                    Err(e) => {
+                        self.failed_on(&upstream);
+                        self.retry(request, e)
+                    },
+                },
+                ForwardingState::Fetching(upstream, mut fetch, reused) => match fetch.poll() {
+                    Ok(PollResult::Ready(response)) => {
+                        let active = self.active.take();
+                        return Ok(PollResult::Ready(
+                            from_upstream(response, &self.proxy.forwarded).map_body(|b| counted(b, active))));
+                    },
+                    Ok(PollResult::NotReady) => {
+                        self.state = ForwardingState::Fetching(upstream, fetch, reused);
+                        return Ok(PollResult::NotReady);
+                    },
+                    Err(e) => {
+                        // A reused connection may have been closed by
+                        // the server just as it was sent on, which
+                        // isn't the server failing.
+                        if !reused {
+                            self.failed_on(&upstream);
+                        }
+                        let request = match is_unanswered(&e) {
+                            true => self.replay.as_ref().and_then(replayable),
+                            false => None,
+                        };
+                        self.retry(request, e)
+                    },
+                },
+                ForwardingState::Failed(e) => return Ok(PollResult::Ready(gateway_error(&e))),
+                ForwardingState::Done => panic!("Poll called on finished result"),
+            };
+        }
+    }
+}
+
+/// `host` and `port`, joined as an authority. IPv6 addresses are put in
+/// brackets.
+fn join_authority(host: &str, port: u16) -> String {
+    match host.contains(':') {
+        true => format!("[{}]:{}", host, port),
+        false => format!("{}:{}", host, port),
+    }
+}
+
+/// Decides whether requests can be forwarded to a host and port.
+type Allow = Arc<dyn Fn(&str, u16) -> bool + Send + Sync>;
+
+/// Forwards requests on behalf of clients, to the servers they name.
+/// Serve it with a `TunnelProto`.
+///
+/// A `CONNECT` request opens a tunnel to the server it names, as a
+/// `Tunnel` does. Other requests name their server with an absolute
+/// `http` URI, e.g. `GET http://example.com/ HTTP/1.1`, and are
+/// forwarded to it. Requests that don't are answered with `400 Bad
+/// Request`.
+///
+/// By default, nothing is allowed: every request is answered with `403
+/// Forbidden` until `allow` says where requests can go. Otherwise, the
+/// proxy could be used to reach servers that only the server running it
+/// can, such as those on its loopback or private networks. Bear in mind
+/// that a host name can resolve to such an address, too.
+pub struct ForwardProxy {
+    tunnel: Tunnel,
+    dialer: Dialer,
+    pool: Pool,
+    allow: Allow,
+    forwarded: Forwarded,
+}
+
+impl Default for ForwardProxy {
+    fn default() -> ForwardProxy {
+        ForwardProxy {
+            tunnel: Tunnel::new().allow(|_, _| false),
+            dialer: Dialer::new(),
+            pool: Pool::new(),
+            allow: Arc::new(|_, _| false),
+            forwarded: Forwarded::new().disclose(false),
+        }
+    }
+}
+
+impl ForwardProxy {
+    pub fn new() -> ForwardProxy {
+        ForwardProxy::default()
+    }
+
+    /// Sets how servers are connected to.
+    pub fn dialer(mut self, dialer: Dialer) -> ForwardProxy {
+        self.tunnel = self.tunnel.dialer(dialer.clone());
+        self.dialer = dialer;
+        self
+    }
+
+    /// Sets the pool that connections to servers are kept in between
+    /// requests.
+    pub fn pool(mut self, pool: Pool) -> ForwardProxy {
+        self.pool = pool;
+        self
+    }
+
+    /// Sets how bytes are relayed through each tunnel.
+    pub fn twister(mut self, twister: TwisterBuilder) -> ForwardProxy {
+        self.tunnel = self.tunnel.twister(twister);
+        self
+    }
+
+    /// Only forwards requests, and opens tunnels, to the hosts and
+    /// ports `f` allows. Others are answered with `403 Forbidden`.
+    pub fn allow<F>(mut self, f: F) -> ForwardProxy where
+        F: Fn(&str, u16) -> bool + Send + Sync + 'static
+    {
+        let allow: Allow = Arc::new(f);
+        let tunnelled = allow.clone();
+        self.tunnel = self.tunnel.allow(move |host, port| tunnelled(host, port));
+        self.allow = allow;
+        self
+    }
+
+    /// Sets the headers that tell servers a request was forwarded. By
+    /// default, only `Via` is added, so that clients' addresses are
+    /// kept private.
+    pub fn forwarded(mut self, forwarded: Forwarded) -> ForwardProxy {
+        self.forwarded = forwarded;
+        self
+    }
+
+    /// Forwards `request` to the server it names, or opens a tunnel to
+    /// it, resolving to the response for the client.
+    pub fn forward(&self, mut request: Request) -> Proxying {
+        if request.method() == HttpMethod::Connect {
+            return Proxying {
+                state: ProxyingState::Tunnelling(self.tunnel.handle(request)),
+            };
+        }
+
+        let (address, host) = {
+            let uri = request.uri();
+            let (host, port) = match (uri.scheme(), uri.host(), uri.port_or_default()) {
+                (Some("http"), Some(host), Some(port)) => (host, port),
+                _ => return answered(StatusCode::BadRequest),
+            };
+            if !(self.allow)(host, port) {
+                return answered(StatusCode::Forbidden);
+            }
+
+            // The request's own `Host` header is ignored in favour of
+            // its URI, as RFC 7230, section 5.4 asks.
+            let authority = join_authority(host, port);
+            match uri.port() {
+                Some(_) => (authority.clone(), authority),
+                None if host.contains(':') => (authority, format!("[{}]", host)),
+                None => (authority, String::from(host)),
+            }
+        };
+        request.set_header("Host", &host);
+
+        let proxy = ReverseProxy::new(&address)
+            .dialer(self.dialer.clone())
+            .pool(self.pool.clone())
+            .preserve_host(true)
+            .forwarded(self.forwarded.clone());
+        Proxying {
+            state: ProxyingState::Forwarding(Box::new(proxy.forward(request))),
+        }
+    }
+}
+
+impl Handler for ForwardProxy {
+    type Request = Request;
+    type Response = Response;
+    type Error = io::Error;
+    type Pollable = Proxying;
+
+    fn handle(&self, request: Request) -> Proxying {
+        self.forward(request)
+    }
+}
+
+fn answered(status: StatusCode) -> Proxying {
+    Proxying {
+        state: ProxyingState::Answered(Some(ResponseBuilder::new(status).build())),
+    }
+}
+
+/// A request being handled by a `ForwardProxy`. It resolves to the
+/// response for the client.
+pub struct Proxying {
+    state: ProxyingState,
+}
+
+enum ProxyingState {
+    Tunnelling(Connecting),
+    Forwarding(Box<Forwarding>),
+    Answered(Option<Response>),
+}
+
+impl Pollable for Proxying {
+    type Item = Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Response>, io::Error> {
+        match self.state {
+            ProxyingState::Tunnelling(ref mut connecting) => connecting.poll(),
+            ProxyingState::Forwarding(ref mut forwarding) => forwarding.poll(),
+            ProxyingState::Answered(ref mut response) =>
+                Ok(PollResult::Ready(response.take().expect("Poll called on finished result"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod proxy_should {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::thread;
+    use bind_transport::BindTransport;
+    use http::codec::HttpCodec;
+    use http::router::{Route, Router};
+    use http::transport::HttpTransport;
+    use http::tunnel::TunnelProto;
+    use server::TcpServer;
+
+    struct Proto;
+
+    impl BindTransport<net::TcpStream> for Proto {
+        type Request = Request;
+        type Response = Response;
+        type Transport = HttpTransport<Framed<net::TcpStream, HttpCodec>>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+            Ok(HttpTransport::new(Framed::new(s, HttpCodec::new())))
+        }
+    }
+
+    /// Reads a request with a sized body from `stream`.
+    fn read_request(stream: &mut net::TcpStream) -> String {
+        let mut request = vec![];
+        while !request.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).unwrap();
+            request.push(byte[0]);
+        }
+
+        let head = String::from_utf8(request).unwrap();
+        let length = head.lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .map(|l| l.parse().unwrap())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        head + &String::from_utf8(body).unwrap()
+    }
+
+    /// Forwards `request` through `proxy`, and waits for the response.
+    fn respond(proxy: &ReverseProxy, request: Request) -> Response {
+        let mut forwarding = proxy.forward(request);
+        loop {
+            if let PollResult::Ready(response) = forwarding.poll().unwrap() {
+                return response;
+            }
+        }
+    }
+
+    /// The address of a server that reads each request, and then drops
+    /// the connection without answering.
+    fn dropping_server() -> String {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || for stream in listener.incoming() {
+            read_request(&mut stream.unwrap());
+        });
+        addr.to_string()
+    }
+
+    /// The address of a server that answers each request it reads with
+    /// `200 OK`.
+    fn answering_server() -> String {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            read_request(&mut stream);
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+        });
+        addr.to_string()
+    }
+
+    #[test]
+    fn forward_requests_to_the_upstream_server() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_request(&mut stream);
+            stream.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                               5\r\nHello\r\n6\r\n world\r\n0\r\n\r\n").unwrap();
+            request
+        });
+
+        let proxy = TcpServer::builder(TunnelProto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = proxy.local_addr().unwrap();
+        let target = upstream.to_string();
+        thread::spawn(move || proxy.run(move || Router::builder()
+            .route(Route::any("/api/*", ReverseProxy::new(&target)))
+            .build()));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"POST /api/items?x=1 HTTP/1.0\r\nHost: proxy\r\nContent-Length: 4\r\n\r\nPing").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /api/items?x=1 HTTP/1.1\r\n"));
+        assert!(request.contains(&format!("\r\nHost: {}\r\n", upstream)));
+        assert!(request.contains("\r\nX-Forwarded-For: 127.0.0.1\r\n"));
+        assert!(request.contains("\r\nVia: 1.0 server-fx\r\n"));
+        assert!(request.ends_with("\r\nContent-Length: 4\r\n\r\nPing"));
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("\r\nVia: 1.1 server-fx\r\n"));
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.ends_with("\r\n\r\nHello world"));
+    }
+
+    #[test]
+    fn answer_bad_gateway_when_the_upstream_server_answers_nonsense() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&mut stream);
+            stream.write_all(b"HTTP/1.1 OK Fine\r\n\r\n").unwrap();
+            thread::sleep(Duration::from_secs(1));
+        });
+
+        let proxy = ReverseProxy::new(&upstream.to_string()).retries(0);
+        let response = respond(&proxy, RequestBuilder::new(HttpMethod::Get, "/").build());
+        assert_eq!(StatusCode::BadGateway, response.status());
+    }
+
+    #[test]
+    fn pass_on_the_content_length_of_responses_to_head_requests() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&mut stream);
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1024\r\n\r\n").unwrap();
+            thread::sleep(Duration::from_secs(1));
+        });
+
+        let proxy = ReverseProxy::new(&upstream.to_string());
+        let response = respond(&proxy, RequestBuilder::new(HttpMethod::Head, "/").build());
+        assert_eq!(StatusCode::Ok, response.status());
+        assert_eq!(Some("1024"), response.header_value("Content-Length"));
+        assert_eq!(Some(0), response.body().content_length());
+    }
+
+    #[test]
+    fn reuse_connections_to_the_upstream_server() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = listener.local_addr().unwrap();
+        // Only one connection is accepted, for both requests.
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for _ in 0..2 {
+                let request = read_request(&mut stream);
+                let path = request.split(' ').nth(1).unwrap();
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", path.len(), path)
+                    .unwrap();
+            }
+        });
+
+        let proxy = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = proxy.local_addr().unwrap();
+        let pool = Pool::new();
+        let idle = pool.clone();
+        thread::spawn(move || proxy.run(move || ReverseProxy::new(&upstream.to_string()).pool(pool)));
+
+        for path in &["/first", "/second"] {
+            let mut client = net::TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            write!(client, "GET {} HTTP/1.0\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.ends_with(&format!("\r\n\r\n{}", path)));
+        }
+        assert_eq!(1, idle.idle());
+    }
+
+    #[test]
+    fn spread_requests_across_upstream_servers() {
+        let upstreams = ["a", "b"].iter()
+            .map(|name| {
+                let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+                let addr = listener.local_addr().unwrap();
+                thread::spawn(move || {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    loop {
+                        read_request(&mut stream);
+                        write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n{}", name)
+                            .unwrap();
+                    }
+                });
+                Upstream::new(&addr.to_string())
+            })
+            .collect::<Vec<_>>();
+        let proxy = ReverseProxy::balanced(upstreams.clone(), RoundRobin::new());
+
+        let names = (0..4)
+            .map(|_| {
+                let mut forwarding = proxy.forward(RequestBuilder::new(HttpMethod::Get, "/").build());
+                let mut response = loop {
+                    if let PollResult::Ready(response) = forwarding.poll().unwrap() {
+                        break response;
+                    }
+                };
+                assert_eq!(1, upstreams.iter().map(Upstream::active).sum::<usize>());
+                let body = loop {
+                    if let PollResult::Ready(body) = response.poll_body().unwrap() {
+                        break body.unwrap();
+                    }
+                };
+                String::from_utf8(body).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["a", "b", "a", "b"], names);
+        assert_eq!(0, upstreams.iter().map(Upstream::active).sum::<usize>());
+    }
+
+    #[test]
+    fn forward_requests_to_the_servers_they_name() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_request(&mut stream);
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nHi").unwrap();
+            request
+        });
+
+        let proxy = TcpServer::builder(TunnelProto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = proxy.local_addr().unwrap();
+        thread::spawn(move || proxy.run(|| ForwardProxy::new().allow(|_, _| true)));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        write!(client, "GET http://{}/search?q=rust HTTP/1.0\r\nHost: elsewhere\r\n\
+                        Proxy-Connection: keep-alive\r\n\r\n", origin).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /search?q=rust HTTP/1.1\r\n"));
+        assert!(request.contains(&format!("\r\nHost: {}\r\n", origin)));
+        assert!(!request.contains("Proxy-Connection"));
+        assert!(!request.contains("X-Forwarded-For"));
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nHi"));
+    }
+
+    #[test]
+    fn refuse_to_forward_requests_it_cant_or_isnt_allowed_to() {
+        let status = |proxy: &ForwardProxy, method, target| {
+            let mut proxying = proxy.forward(RequestBuilder::new(method, target).build());
+            loop {
+                if let PollResult::Ready(response) = proxying.poll().unwrap() {
+                    return response.status();
+                }
+            }
+        };
+
+        // Nothing is allowed until it's asked for.
+        let proxy = ForwardProxy::new();
+        assert_eq!(StatusCode::Forbidden, status(&proxy, HttpMethod::Connect, "example.com:443"));
+        assert_eq!(StatusCode::Forbidden, status(&proxy, HttpMethod::Get, "http://127.0.0.1/"));
+
+        let proxy = ForwardProxy::new().allow(|_, port| port == 443);
+        assert_eq!(StatusCode::Forbidden, status(&proxy, HttpMethod::Connect, "127.0.0.1:80"));
+        assert_eq!(StatusCode::Forbidden, status(&proxy, HttpMethod::Get, "http://127.0.0.1/"));
+        assert_eq!(StatusCode::BadRequest, status(&proxy, HttpMethod::Get, "/"));
+        assert_eq!(StatusCode::BadRequest, status(&proxy, HttpMethod::Get, "https://127.0.0.1/"));
+    }
+
+    #[test]
+    fn answer_bad_gateway_when_the_upstream_server_cant_be_reached() {
+        let upstream = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let proxy = ReverseProxy::new(&upstream.to_string());
+
+        let response = respond(&proxy, RequestBuilder::new(HttpMethod::Get, "/").build());
+        assert_eq!(StatusCode::BadGateway, response.status());
+    }
+
+    #[test]
+    fn retry_failed_requests_on_another_upstream_server() {
+        let unreachable = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let proxy = ReverseProxy::balanced(vec![Upstream::new(&unreachable.to_string()),
+                                                Upstream::new(&answering_server())],
+                                           RoundRobin::new());
+        let response = respond(&proxy, RequestBuilder::new(HttpMethod::Post, "/").build());
+        assert_eq!(StatusCode::Ok, response.status());
+
+        let upstreams = vec![Upstream::new(&dropping_server()), Upstream::new(&answering_server())];
+        let proxy = ReverseProxy::balanced(upstreams.clone(), RoundRobin::new());
+        let response = respond(&proxy, RequestBuilder::new(HttpMethod::Get, "/").build());
+        assert_eq!(StatusCode::Ok, response.status());
+        assert!(!upstreams[0].is_healthy());
+    }
+
+    #[test]
+    fn only_copy_requests_that_could_be_retried() {
+        let request = || RequestBuilder::new(HttpMethod::Put, "/")
+            .build_with_buffer(b"data".iter().cloned());
+        let upstream = dropping_server();
+        assert!(ReverseProxy::new(&upstream).forward(request()).replay.is_none());
+
+        let balanced = ReverseProxy::balanced(
+            vec![Upstream::new(&upstream), Upstream::new(&dropping_server())],
+            RoundRobin::new());
+        assert!(balanced.clone().retries(0).forward(request()).replay.is_none());
+        assert!(balanced.forward(request()).replay.is_some());
+    }
+
+    #[test]
+    fn not_retry_requests_that_arent_safe_to_send_again() {
+        let upstreams = vec![Upstream::new(&dropping_server()), Upstream::new(&answering_server())];
+        let proxy = ReverseProxy::balanced(upstreams, RoundRobin::new());
+
+        let response = respond(&proxy, RequestBuilder::new(HttpMethod::Post, "/")
+            .build_with_buffer(b"Ping".iter().cloned()));
+        assert_eq!(StatusCode::BadGateway, response.status());
+    }
+}
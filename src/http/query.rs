@@ -0,0 +1,94 @@
+use std::ops::Deref;
+
+use http::body::Body;
+use http::router::{FromParam, ParamError};
+use http::types::Request;
+
+/// The decoded `key=value` pairs of a request's query string, in the
+/// order they appear.
+///
+/// This mirrors [`Parameters`] so handlers can read query parameters
+/// the same way they read path parameters.
+///
+/// [`Parameters`]: ../router/struct.Parameters.html
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query(Vec<(String, String)>);
+
+impl Query {
+    /// The first value of the parameter `name`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|p| p.0 == name)
+            .map(|p| p.1.as_ref())
+    }
+
+    /// Every value of the parameter `name`. E.g. for `?tag=a&tag=b`.
+    pub fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.0.iter()
+            .filter(move |p| p.0 == name)
+            .map(|p| p.1.as_ref())
+    }
+
+    /// Converts the first value of the parameter `name` to a `T`.
+    /// Failures convert into a `400 Bad Request` response, as with
+    /// [`Parameters::get`].
+    ///
+    /// [`Parameters::get`]: ../router/struct.Parameters.html#method.get
+    pub fn get<T: FromParam>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self.value(name)
+            .ok_or_else(|| ParamError::Missing(String::from(name)))?;
+
+        T::from_param(value)
+            .map_err(|e| ParamError::Invalid(String::from(name), e))
+    }
+}
+
+impl Deref for Query {
+    type Target = [(String, String)];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Vec<(String, String)>> for Query {
+    fn from(pairs: Vec<(String, String)>) -> Query {
+        Query(pairs)
+    }
+}
+
+impl Request<Body> {
+    /// The request's query parameters. Empty if the request target
+    /// has no query string.
+    pub fn query(&self) -> Query {
+        Query(self.uri().query_pairs())
+    }
+}
+
+#[cfg(test)]
+mod query_should {
+    use super::*;
+    use http::types::{HttpMethod, RequestBuilder};
+
+    #[test]
+    fn expose_the_query_of_a_request() {
+        let request = RequestBuilder::new(HttpMethod::Get,
+                                          "/search?q=server+fx&page=2&tag=a&tag=b")
+            .build();
+        let query = request.query();
+
+        assert_eq!(Some("server fx"), query.value("q"));
+        assert_eq!(Ok(2), query.get::<u32>("page"));
+        assert_eq!(vec!["a", "b"], query.values("tag").collect::<Vec<_>>());
+        assert_eq!(Err(ParamError::Missing(String::from("sort"))),
+                   query.get::<String>("sort"));
+        assert!(query.get::<u32>("q").is_err());
+    }
+
+    #[test]
+    fn be_empty_without_a_query_string() {
+        let request = RequestBuilder::new(HttpMethod::Get, "/search").build();
+
+        assert!(request.query().is_empty());
+    }
+}
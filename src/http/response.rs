@@ -0,0 +1,413 @@
+use std::io;
+
+use http::types::{Response, ResponseBuilder, StatusCode};
+use pollable::Pollable;
+use result::PollResult;
+
+/// Conversion into a HTTP [`Response`].
+///
+/// This lets handlers return whatever is most natural to them. E.g.
+/// a `String`, a `StatusCode`, or a `(StatusCode, &str)` pair,
+/// rather than building a `Response` by hand.
+///
+/// Text converts into a `200 OK` response with a `text/plain` body,
+/// and bytes into an `application/octet-stream` body. A bare
+/// `StatusCode` produces a response with an empty body. `Result` and
+/// `Option` convert whichever variant they hold, with `None`
+/// producing a `404 Not Found`.
+///
+/// [`Response`]: ../types/struct.Response.html
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+fn text_response(status: StatusCode, text: String) -> Response {
+    ResponseBuilder::new(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .build_with_content(text)
+}
+
+fn bytes_response(status: StatusCode, bytes: Vec<u8>) -> Response {
+    ResponseBuilder::new(status)
+        .header("Content-Type", "application/octet-stream")
+        .build_with_content(bytes)
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response {
+        ResponseBuilder::new(self).build()
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> Response {
+        StatusCode::Ok.into_response()
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        text_response(StatusCode::Ok, self)
+    }
+}
+
+impl IntoResponse for &str {
+    fn into_response(self) -> Response {
+        text_response(StatusCode::Ok, String::from(self))
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        bytes_response(StatusCode::Ok, self)
+    }
+}
+
+impl IntoResponse for &[u8] {
+    fn into_response(self) -> Response {
+        bytes_response(StatusCode::Ok, self.to_vec())
+    }
+}
+
+impl IntoResponse for (StatusCode, String) {
+    fn into_response(self) -> Response {
+        text_response(self.0, self.1)
+    }
+}
+
+impl IntoResponse for (StatusCode, &str) {
+    fn into_response(self) -> Response {
+        text_response(self.0, String::from(self.1))
+    }
+}
+
+impl IntoResponse for (StatusCode, Vec<u8>) {
+    fn into_response(self) -> Response {
+        bytes_response(self.0, self.1)
+    }
+}
+
+impl<T, E> IntoResponse for Result<T, E> where
+    T: IntoResponse,
+    E: IntoResponse
+{
+    fn into_response(self) -> Response {
+        match self {
+            Ok(r) => r.into_response(),
+            Err(e) => e.into_response(),
+        }
+    }
+}
+
+impl<T> IntoResponse for Option<T> where
+    T: IntoResponse
+{
+    fn into_response(self) -> Response {
+        match self {
+            Some(r) => r.into_response(),
+            None => StatusCode::NotFound.into_response(),
+        }
+    }
+}
+
+/// A response that may not be ready yet, as returned by a
+/// [`Router`].
+///
+/// `RouteResponse` is a `Pollable`, so a `Handler` can return it
+/// directly and the connection will poll it until the response is
+/// ready, without blocking the worker.
+///
+/// [`Router`]: ../router/struct.Router.html
+pub struct RouteResponse(RouteResponseState);
+
+enum RouteResponseState {
+    Ready(Option<Response>),
+    Pending(Box<dyn Pollable<Item=Response, Error=io::Error>>),
+}
+
+impl RouteResponse {
+    /// A response that's ready straight away.
+    pub fn ready<R: IntoResponse>(response: R) -> RouteResponse {
+        RouteResponse(RouteResponseState::Ready(Some(response.into_response())))
+    }
+
+    /// A response that's ready once `pollable` is.
+    pub fn pending<P>(pollable: P) -> RouteResponse where
+        P: Pollable<Item=Response, Error=io::Error> + 'static
+    {
+        RouteResponse(RouteResponseState::Pending(Box::new(pollable)))
+    }
+}
+
+impl RouteResponse {
+    /// Applies `f` to the response once it's ready.
+    pub fn map<F>(self, f: F) -> RouteResponse where
+        F: FnOnce(Response) -> Response + 'static
+    {
+        match self.0 {
+            RouteResponseState::Ready(response) =>
+                RouteResponse(RouteResponseState::Ready(response.map(f))),
+            RouteResponseState::Pending(pollable) =>
+                RouteResponse::pending(MapResponse(pollable, Some(f))),
+        }
+    }
+
+    /// Converts any error the response fails with into a response
+    /// with `f`, rather than failing the connection.
+    pub fn recover<F>(self, f: F) -> RouteResponse where
+        F: FnOnce(io::Error) -> Response + 'static
+    {
+        match self.0 {
+            ready @ RouteResponseState::Ready(_) => RouteResponse(ready),
+            RouteResponseState::Pending(pollable) =>
+                RouteResponse::pending(RecoverResponse(pollable, Some(f))),
+        }
+    }
+}
+
+struct RecoverResponse<F>(Box<dyn Pollable<Item=Response, Error=io::Error>>, Option<F>);
+
+impl<F> Pollable for RecoverResponse<F> where
+    F: FnOnce(io::Error) -> Response
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        match self.0.poll() {
+            Ok(result) => Ok(result),
+            Err(error) => {
+                let f = self.1.take().expect("Poll called on finished result");
+                Ok(PollResult::Ready(f(error)))
+            },
+        }
+    }
+}
+
+struct MapResponse<F>(Box<dyn Pollable<Item=Response, Error=io::Error>>, Option<F>);
+
+impl<F> Pollable for MapResponse<F> where
+    F: FnOnce(Response) -> Response
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        match self.0.poll()? {
+            PollResult::NotReady => Ok(PollResult::NotReady),
+            PollResult::Ready(response) => {
+                let f = self.1.take().expect("Poll called on finished result");
+                Ok(PollResult::Ready(f(response)))
+            },
+        }
+    }
+}
+
+impl From<Response> for RouteResponse {
+    fn from(response: Response) -> RouteResponse {
+        RouteResponse::ready(response)
+    }
+}
+
+impl Pollable for RouteResponse {
+    type Item = Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        match self.0 {
+            RouteResponseState::Ready(ref mut response) => match response.take() {
+                Some(response) => Ok(PollResult::Ready(response)),
+                None => panic!("Poll called on finished result"),
+            },
+            RouteResponseState::Pending(ref mut pollable) => pollable.poll(),
+        }
+    }
+}
+
+/// Wraps a `Pollable` so a route handler can return it. The handler's
+/// response is whatever the pollable resolves to, and any error it
+/// fails with is converted into a response too.
+pub struct Deferred<P>(pub P);
+
+impl<P> Pollable for Deferred<P> where
+    P: Pollable,
+    P::Item: IntoResponse,
+    P::Error: IntoResponse
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        match self.0.poll() {
+            Ok(PollResult::NotReady) => Ok(PollResult::NotReady),
+            Ok(PollResult::Ready(item)) => Ok(PollResult::Ready(item.into_response())),
+            Err(error) => Ok(PollResult::Ready(error.into_response())),
+        }
+    }
+}
+
+/// Conversion into a [`RouteResponse`]. This is what route handlers
+/// return.
+///
+/// Anything implementing [`IntoResponse`] converts into a response
+/// that's ready straight away. A handler that needs to wait on I/O
+/// can return a [`Deferred`] pollable instead.
+///
+/// [`RouteResponse`]: struct.RouteResponse.html
+/// [`IntoResponse`]: trait.IntoResponse.html
+/// [`Deferred`]: struct.Deferred.html
+pub trait IntoRouteResponse {
+    fn into_route_response(self) -> RouteResponse;
+}
+
+impl<T: IntoResponse> IntoRouteResponse for T {
+    fn into_route_response(self) -> RouteResponse {
+        RouteResponse::ready(self)
+    }
+}
+
+impl IntoRouteResponse for RouteResponse {
+    fn into_route_response(self) -> RouteResponse {
+        self
+    }
+}
+
+impl<P> IntoRouteResponse for Deferred<P> where
+    P: Pollable + 'static,
+    P::Item: IntoResponse,
+    P::Error: IntoResponse
+{
+    fn into_route_response(self) -> RouteResponse {
+        RouteResponse::pending(self)
+    }
+}
+
+#[cfg(test)]
+mod into_response_should {
+    use super::*;
+
+    fn body_of(response: Response) -> Vec<u8> {
+        response.into_parts().1.as_bytes().unwrap().to_vec()
+    }
+
+    #[test]
+    fn convert_text() {
+        let response = "Hello".into_response();
+
+        assert_eq!(200, response.status_code());
+        assert_eq!(Some("text/plain; charset=utf-8"),
+                   response.header_value("Content-Type"));
+        assert_eq!(b"Hello".to_vec(), body_of(response));
+    }
+
+    #[test]
+    fn convert_a_status_and_text() {
+        let response = (StatusCode::BadRequest, "Missing name").into_response();
+
+        assert_eq!(400, response.status_code());
+        assert_eq!(b"Missing name".to_vec(), body_of(response));
+    }
+
+    #[test]
+    fn convert_bytes() {
+        let response = vec![1_u8, 2, 3].into_response();
+
+        assert_eq!(Some("application/octet-stream"),
+                   response.header_value("Content-Type"));
+        assert_eq!(vec![1, 2, 3], body_of(response));
+    }
+
+    #[test]
+    fn convert_either_side_of_a_result() {
+        let ok: Result<&str, StatusCode> = Ok("Found");
+        let err: Result<&str, StatusCode> = Err(StatusCode::NotFound);
+
+        assert_eq!(200, ok.into_response().status_code());
+        assert_eq!(404, err.into_response().status_code());
+    }
+
+    #[test]
+    fn convert_none_into_not_found() {
+        let none: Option<String> = None;
+
+        assert_eq!(404, none.into_response().status_code());
+    }
+}
+
+#[cfg(test)]
+mod route_response_should {
+    use super::*;
+
+    struct Countdown(usize, Result<&'static str, StatusCode>);
+
+    impl Pollable for Countdown {
+        type Item = &'static str;
+        type Error = StatusCode;
+
+        fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+            if self.0 > 0 {
+                self.0 -= 1;
+                return Ok(PollResult::NotReady);
+            }
+
+            self.1.map(PollResult::Ready)
+        }
+    }
+
+    fn poll_until_ready(mut response: RouteResponse) -> (usize, Response) {
+        let mut polls = 1;
+        loop {
+            match response.poll().unwrap() {
+                PollResult::Ready(response) => return (polls, response),
+                PollResult::NotReady => polls += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn be_ready_for_plain_responses() {
+        let (polls, response) = poll_until_ready("Hello".into_route_response());
+
+        assert_eq!(1, polls);
+        assert_eq!(200, response.status_code());
+    }
+
+    #[test]
+    fn wait_for_deferred_responses() {
+        let (polls, response) = poll_until_ready(
+            Deferred(Countdown(2, Ok("Hello"))).into_route_response());
+
+        assert_eq!(3, polls);
+        assert_eq!(Some(&b"Hello"[..]), response.into_parts().1.as_bytes());
+    }
+
+    #[test]
+    fn map_responses_once_ready() {
+        let add_header = |mut response: Response| {
+            response.set_header("X-Mapped", "yes");
+            response
+        };
+
+        let (_, ready) = poll_until_ready("Hello".into_route_response().map(add_header));
+        let (polls, pending) = poll_until_ready(
+            Deferred(Countdown(1, Ok("Hello"))).into_route_response().map(add_header));
+
+        assert_eq!(Some("yes"), ready.header_value("X-Mapped"));
+        assert_eq!(2, polls);
+        assert_eq!(Some("yes"), pending.header_value("X-Mapped"));
+    }
+
+    #[test]
+    fn convert_deferred_errors_into_responses() {
+        let (_, response) = poll_until_ready(
+            Deferred(Countdown(0, Err(StatusCode::BadGateway))).into_route_response());
+
+        assert_eq!(502, response.status_code());
+    }
+}
@@ -1,85 +1,589 @@
-use http::types;
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use handler::Handler;
+use http::errors;
+use http::middleware::{Middleware, Next};
+use http::response::{IntoResponse, IntoRouteResponse, RouteResponse};
+use http::types::{self, StatusCode};
+use http::uri::{percent_decode, percent_encode_segment};
 
 #[derive(Debug, PartialEq)]
 pub enum Part {
     Exact(String),
     Param(String),
+    /// A parameter that may be left out, along with any parts after
+    /// it.
+    Optional(String),
     Wildcard,
     Missing,
 }
 
-pub type Parameters<'a> = Vec<(&'a str, String)>;
+/// The parameters captured from a request's path, as `(name, value)`
+/// pairs in the order they appear in the route's pattern. Values are
+/// kept as they appear in the path, so they're still percent-encoded.
+///
+/// Parameter names are case-sensitive, as they're written in the
+/// pattern. Use [`value_ignore_case`] to look one up regardless of
+/// case.
+///
+/// [`value_ignore_case`]: #method.value_ignore_case
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parameters<'a>(Vec<(&'a str, String)>);
+
+impl<'a> Parameters<'a> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.value(name).is_some()
+    }
+
+    /// The `(name, value)` pairs, in the order they appear in the
+    /// route's pattern. Values are still percent-encoded.
+    pub fn iter(&self) -> ParametersIter<'_> {
+        ParametersIter(self.0.iter())
+    }
+
+    /// The raw value of the parameter `name`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|p| p.0 == name)
+            .map(|p| p.1)
+    }
+
+    /// The raw value of the first parameter named `name`, ignoring
+    /// ASCII case.
+    pub fn value_ignore_case(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|p| p.0.eq_ignore_ascii_case(name))
+            .map(|p| p.1)
+    }
+
+    /// Percent-decodes the value of the parameter `name` and converts
+    /// it to a `T`.
+    ///
+    /// The returned [`ParamError`] converts into a `400 Bad Request`
+    /// response, so handlers returning a `Result` can use `?` on it.
+    ///
+    /// [`ParamError`]: enum.ParamError.html
+    pub fn get<T: FromParam>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self.value(name)
+            .ok_or_else(|| ParamError::Missing(String::from(name)))?;
+
+        T::from_param(&percent_decode(value))
+            .map_err(|e| ParamError::Invalid(String::from(name), e))
+    }
+}
+
+/// Iterates over the `(name, value)` pairs of [`Parameters`].
+///
+/// [`Parameters`]: struct.Parameters.html
+pub struct ParametersIter<'p>(::std::slice::Iter<'p, (&'p str, String)>);
+
+impl<'p> Iterator for ParametersIter<'p> {
+    type Item = (&'p str, &'p str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|p| (p.0, p.1.as_str()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'p, 'a> IntoIterator for &'p Parameters<'a> {
+    type Item = (&'p str, &'p str);
+    type IntoIter = ParametersIter<'p>;
+
+    fn into_iter(self) -> ParametersIter<'p> {
+        self.iter()
+    }
+}
+
+impl<'a> From<Vec<(&'a str, String)>> for Parameters<'a> {
+    fn from(params: Vec<(&'a str, String)>) -> Parameters<'a> {
+        Parameters(params)
+    }
+}
+
+impl<'a> FromIterator<(&'a str, String)> for Parameters<'a> {
+    fn from_iter<I>(iter: I) -> Parameters<'a> where
+        I: IntoIterator<Item=(&'a str, String)>
+    {
+        Parameters(iter.into_iter().collect())
+    }
+}
+
+/// Conversion from the value of a path parameter. E.g. a `u32`, or
+/// an enum naming one of its variants.
+///
+/// This is implemented for every type implementing `FromStr`, so
+/// implementing `FromStr` is usually all that's needed.
+pub trait FromParam: Sized {
+    /// Converts `value`, or describes why it isn't valid.
+    fn from_param(value: &str) -> Result<Self, String>;
+}
+
+impl<T> FromParam for T where
+    T: FromStr,
+    T::Err: fmt::Display
+{
+    fn from_param(value: &str) -> Result<T, String> {
+        value.parse().map_err(|e: T::Err| e.to_string())
+    }
+}
+
+/// The error returned by [`Parameters::get`].
+///
+/// [`Parameters::get`]: struct.Parameters.html#method.get
+#[derive(Debug, PartialEq)]
+pub enum ParamError {
+    /// The route's pattern doesn't capture a parameter by that name.
+    Missing(String),
+    /// The parameter's value couldn't be converted.
+    Invalid(String, String),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParamError::Missing(ref name) =>
+                write!(f, "Missing parameter '{}'", name),
+            ParamError::Invalid(ref name, ref reason) =>
+                write!(f, "Invalid parameter '{}': {}", name, reason),
+        }
+    }
+}
+
+impl ::std::error::Error for ParamError {}
 
-pub struct Pattern(Vec<Part>, bool);
+impl IntoResponse for ParamError {
+    fn into_response(self) -> types::Response {
+        (StatusCode::BadRequest, self.to_string()).into_response()
+    }
+}
+
+/// A check that the value of a path parameter must pass for a pattern
+/// to match.
+pub type Validator = Box<dyn Fn(&str) -> bool + Send + Sync + 'static>;
+
+/// A constraint on the value of a named path parameter.
+pub struct Constraint {
+    name: String,
+    validator: Validator,
+}
+
+impl fmt::Debug for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Constraint({})", self.name)
+    }
+}
+
+#[cfg(feature = "regex")]
+fn regex_validator(param: &str, expr: &str) -> Validator {
+    let re = ::regex::Regex::new(&format!("^(?:{})$", expr))
+        .unwrap_or_else(|e| panic!("Invalid constraint on '{}': {}", param, e));
+    Box::new(move |v| re.is_match(v))
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex_validator(param: &str, _: &str) -> Validator {
+    panic!("The constraint on '{}' requires the `regex` feature", param)
+}
+
+/// A compiled route pattern. E.g. `/users/:id/*`.
+///
+/// A segment starting with `:` captures a parameter. A parameter may
+/// be followed by a regular expression in parentheses that its value
+/// must match in full, E.g. `/users/:id(\d+)`. This requires the
+/// `regex` feature. Expressions can't contain `/`. Constraints can
+/// also be added as closures using [`Pattern::constraint`].
+///
+/// A parameter ending with `?` is optional, E.g.
+/// `/archive/:year/:month?` matches both `/archive/2018` and
+/// `/archive/2018/06`. Only trailing parameters can be optional.
+///
+/// A trailing `*` matches the rest of the path, which is captured as
+/// the parameter named `*`. E.g. `css/site.css` for
+/// `/static/css/site.css` matching `/static/*`. The wildcard can be
+/// given a name, E.g. `/static/*filepath` captures the same value as
+/// the parameter `filepath`.
+///
+/// [`Pattern::constraint`]: #method.constraint
+pub struct Pattern {
+    source: String,
+    parts: Vec<Part>,
+    has_wildcard: bool,
+    wildcard: String,
+    constraints: Vec<Constraint>,
+    trailing_slash: bool,
+}
 
 #[derive(Debug, PartialEq)]
 pub struct NoMatchError;
 
+/// The error returned when a URL can't be generated for a named
+/// route. See [`Router::url_for`].
+///
+/// [`Router::url_for`]: struct.Router.html#method.url_for
+#[derive(Debug, PartialEq)]
+pub enum UrlForError {
+    /// No route has that name.
+    UnknownRoute(String),
+    /// No value was given for a parameter of the route's pattern.
+    MissingParameter(String),
+    /// The value given for a parameter doesn't satisfy its
+    /// constraints.
+    InvalidParameter(String),
+}
+
+impl fmt::Display for UrlForError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UrlForError::UnknownRoute(ref name) =>
+                write!(f, "No route named '{}'", name),
+            UrlForError::MissingParameter(ref name) =>
+                write!(f, "Missing parameter '{}'", name),
+            UrlForError::InvalidParameter(ref name) =>
+                write!(f, "Invalid value for parameter '{}'", name),
+        }
+    }
+}
+
+impl ::std::error::Error for UrlForError {}
+
+/// The path part of `uri`, which is usually a `Uri::path()` already,
+/// without any query or fragment.
+fn path_of(uri: &str) -> &str {
+    &uri[..uri.find(['?', '#']).unwrap_or(uri.len())]
+}
+
+/// Whether the path part of `uri` ends with a `/` following at least
+/// one segment. The root path, `/`, doesn't count.
+fn has_trailing_slash(uri: &str) -> bool {
+    let path = path_of(uri);
+    path.ends_with('/') && !path.trim_matches('/').is_empty()
+}
+
 impl Pattern {
     pub fn new(pattern: &str) -> Pattern {
         let mut has_wildcard = false;
+        let mut wildcard = String::from("*");
+        let mut constraints = vec![];
         let parts = pattern.split('/')
-            .filter(|p| p.len() != 0 && *p != ":")
+            .filter(|p| !p.is_empty() && *p != ":")
             .map(|p| {
-                has_wildcard = p == "*";
+                has_wildcard = p.starts_with('*');
                 if has_wildcard {
+                    if p.len() > 1 {
+                        wildcard = String::from(&p[1..]);
+                    }
                     return Part::Wildcard;
                 }
 
-                match p.starts_with(":") {
-                    true => Part::Param(String::from(&p[1..])),
-                    false => Part::Exact(String::from(p)),
+                if !p.starts_with(':') {
+                    return Part::Exact(String::from(p));
+                }
+
+                let (name, optional) = match p[1..].strip_suffix('?') {
+                    Some(name) => (name, true),
+                    None => (&p[1..], false),
+                };
+                let name = match (name.find('('), name.ends_with(')')) {
+                    (Some(open), true) => {
+                        let (name, expr) = (&name[..open], &name[open + 1..name.len() - 1]);
+                        constraints.push(Constraint {
+                            name: String::from(name),
+                            validator: regex_validator(name, expr),
+                        });
+                        name
+                    },
+                    _ => name,
+                };
+
+                match optional {
+                    true => Part::Optional(String::from(name)),
+                    false => Part::Param(String::from(name)),
                 }
             })
             .collect::<Vec<_>>();
 
-        Pattern(parts, has_wildcard)
+        Pattern {
+            source: String::from(pattern),
+            parts,
+            has_wildcard,
+            wildcard,
+            constraints,
+            trailing_slash: has_trailing_slash(pattern),
+        }
+    }
+
+    /// The pattern as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Adds a constraint to the parameter `name`. The pattern only
+    /// matches if `validator` returns `true` for the parameter's
+    /// value, which is still percent-encoded.
+    pub fn constraint<F>(&mut self, name: &str, validator: F) where
+        F: Fn(&str) -> bool + Send + Sync + 'static
+    {
+        self.constraints.push(Constraint {
+            name: String::from(name),
+            validator: Box::new(validator),
+        });
+    }
+
+    /// Ranks each part of the pattern, lower ranks being more
+    /// specific: exact segments, then constrained parameters, then
+    /// parameters, then wildcards. Comparing the ranks of two
+    /// patterns orders the more specific one first.
+    fn specificity(&self) -> Vec<u8> {
+        self.parts()
+            .map(|part| match *part {
+                Part::Exact(_) => 0,
+                Part::Param(ref p) if self.constraints.iter().any(|c| c.name == *p) => 1,
+                Part::Param(_) | Part::Optional(_) => 2,
+                Part::Wildcard | Part::Missing => 3,
+            })
+            .collect()
+    }
+
+    /// Builds a path matching the pattern from the values of its
+    /// `params`. Values are percent-encoded, and must satisfy any
+    /// constraints on their parameter. The path ends at the first
+    /// optional parameter without a value. A wildcard is replaced with
+    /// the value named after it, or `*`, if there is one, which may
+    /// span several segments.
+    pub fn generate(&self, params: &[(&str, &str)]) -> Result<String, UrlForError> {
+        let lookup = |name: &str| params.iter()
+            .find(|p| p.0 == name)
+            .map(|p| p.1);
+
+        let mut path = String::new();
+        for part in self.parts() {
+            match *part {
+                Part::Exact(ref segment) => {
+                    path.push('/');
+                    path.push_str(segment);
+                },
+                Part::Optional(ref name) if lookup(name).is_none() => break,
+                Part::Param(ref name) | Part::Optional(ref name) => {
+                    let value = lookup(name)
+                        .ok_or_else(|| UrlForError::MissingParameter(name.clone()))?;
+                    let value = percent_encode_segment(value);
+                    if !self.satisfies(name, &value) {
+                        return Err(UrlForError::InvalidParameter(name.clone()));
+                    }
+
+                    path.push('/');
+                    path.push_str(&value);
+                },
+                Part::Wildcard => {
+                    let rest = lookup(&self.wildcard).unwrap_or("");
+                    for segment in rest.split('/').filter(|s| !s.is_empty()) {
+                        path.push('/');
+                        path.push_str(&percent_encode_segment(segment));
+                    }
+                },
+                Part::Missing => {},
+            }
+        }
+
+        if path.is_empty() || (self.trailing_slash && !self.has_wildcard) {
+            path.push('/');
+        }
+
+        Ok(path)
+    }
+
+    /// Whether `uri` ends with a `/` exactly when the pattern does.
+    /// This always holds for patterns with a wildcard.
+    pub fn matches_trailing_slash(&self, uri: &str) -> bool {
+        self.has_wildcard || self.trailing_slash == has_trailing_slash(uri)
     }
 
-    fn parts(&self) -> ::std::slice::Iter<Part> {
-        self.0.iter()
+    fn satisfies(&self, name: &str, value: &str) -> bool {
+        self.constraints.iter()
+            .filter(|c| c.name == name)
+            .all(|c| (c.validator)(value))
     }
 
-    pub fn match_uri<'a, 'b>(&'a self, uri: &'b str) 
+    fn parts(&self) -> ::std::slice::Iter<'_, Part> {
+        self.parts.iter()
+    }
+
+    /// Matches the pattern against the whole of `uri`, usually a
+    /// request's `Uri::path()`, returning the parameters it captured.
+    /// Any query or fragment is ignored.
+    pub fn match_uri<'a>(&'a self, uri: &str) 
         -> Result<Parameters<'a>, NoMatchError> 
     {
-        use std::iter;
+        let segments = path_of(uri).split('/')
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<_>>();
 
-        let uri_end_pos = uri.chars()
-            .position(|c| c == '?' || c == '#')
-            .unwrap_or_else(|| uri.len());
+        let mut params = Parameters::default();
+        for (i, part) in self.parts().enumerate() {
+            match (part, segments.get(i)) {
+                (Part::Wildcard, _) if self.has_wildcard && i + 1 == self.parts.len() => {
+                    let rest = segments.get(i..).unwrap_or(&[]).join("/");
+                    params.0.push((self.wildcard.as_ref(), rest));
+                    return Ok(params);
+                },
+                (Part::Optional(_), None) => break,
+                (_, None) => return Err(NoMatchError),
+                (Part::Exact(e), Some(segment)) if e == segment => {},
+                (Part::Wildcard, Some(_)) => {},
+                (Part::Param(p), Some(segment)) |
+                (Part::Optional(p), Some(segment)) if self.satisfies(p, segment) =>
+                    params.0.push((p.as_ref(), String::from(*segment))),
+                _ => return Err(NoMatchError),
+            }
+        }
 
-        let chain = if self.1 {
-            iter::repeat(&Part::Wildcard)
+        match segments.len() > self.parts.len() {
+            true => Err(NoMatchError),
+            false => Ok(params),
         }
-        else {
-            iter::repeat(&Part::Missing)
-        };
+    }
 
-        (&uri[..uri_end_pos]).split("/")
-            .filter(|p| p.len() != 0)
-            .zip(self.parts().chain(chain))
-            .filter_map(|(uri, part)| {
-                if let Part::Missing = *part {
-                    return Some(Err(NoMatchError));
-                }
+    /// Matches the pattern against the start of `uri`, returning the
+    /// parameters it captured and the rest of the path. The rest is
+    /// either empty or starts with a `/`. A wildcard matches the whole
+    /// of the rest.
+    pub fn match_prefix<'a, 'u>(&'a self, uri: &'u str) 
+        -> Result<(Parameters<'a>, &'u str), NoMatchError> 
+    {
+        let mut params = Parameters::default();
+        let mut rest = path_of(uri);
 
-                match *part {
-                    Part::Exact(ref u) if uri == u => None,
-                    Part::Wildcard => None,
-                    Part::Param(ref p) => Some(Ok((p.as_ref(), String::from(uri)))),
-                    _ => Some(Err(NoMatchError)),
-                }
-            })
-            .collect::<_>()
+        for part in self.parts() {
+            rest = rest.trim_start_matches('/');
+            let end = rest.find('/').unwrap_or(rest.len());
+            let (segment, tail) = rest.split_at(end);
+
+            match *part {
+                Part::Wildcard => return Ok((params, "")),
+                Part::Optional(_) if segment.is_empty() => break,
+                _ if segment.is_empty() => return Err(NoMatchError),
+                Part::Exact(ref e) if e == segment => {},
+                Part::Param(ref p) | Part::Optional(ref p) if self.satisfies(p, segment) =>
+                    params.0.push((p.as_ref(), String::from(segment))),
+                _ => return Err(NoMatchError),
+            }
+
+            rest = tail;
+        }
+
+        Ok((params, rest))
     }
 }
 
+/// Handles requests matched by a [`Route`].
+///
+/// Handlers can return anything that implements [`IntoResponse`].
+/// Handlers that need to wait on I/O can return a [`Deferred`]
+/// pollable, which the connection polls until the response is ready.
+/// Closures taking the request and its path parameters are also
+/// route handlers.
+///
+/// [`Route`]: struct.Route.html
+/// [`IntoResponse`]: ../response/trait.IntoResponse.html
+/// [`Deferred`]: ../response/struct.Deferred.html
 pub trait RouteHandler {
+    type Response: IntoRouteResponse;
+
+    fn handle<'a>(&'a self, 
+                  request: types::Request, 
+                  params: &Parameters<'a>) 
+        -> Self::Response;
+}
+
+impl<F, R> RouteHandler for F where
+    F: for<'a, 'b> Fn(types::Request, &'b Parameters<'a>) -> R,
+    R: IntoRouteResponse
+{
+    type Response = R;
+
+    fn handle<'a>(&'a self, 
+                  request: types::Request, 
+                  params: &Parameters<'a>) 
+        -> Self::Response
+    {
+        self(request, params)
+    }
+}
+
+/// Erases the response type of a `RouteHandler` so handlers
+/// returning different types can be stored in the same router.
+trait BoxedRouteHandler {
+    fn handle_boxed<'a>(&'a self, 
+                        request: types::Request, 
+                        params: &Parameters<'a>) 
+        -> RouteResponse;
+}
+
+impl<H> BoxedRouteHandler for H where
+    H: RouteHandler
+{
+    fn handle_boxed<'a>(&'a self, 
+                        request: types::Request, 
+                        params: &Parameters<'a>) 
+        -> RouteResponse
+    {
+        self.handle(request, params).into_route_response()
+    }
+}
+
+/// Redirects to a path generated from the request's parameters. See
+/// [`Route::redirect`].
+///
+/// [`Route::redirect`]: struct.Route.html#method.redirect
+struct Redirect {
+    target: Pattern,
+    status: StatusCode,
+}
+
+impl RouteHandler for Redirect {
+    type Response = types::Response;
+
     fn handle<'a>(&'a self, 
                   request: types::Request, 
                   params: &Parameters<'a>) 
-        -> types::Response;
+        -> types::Response 
+    {
+        let decoded = params.iter()
+            .map(|p| (p.0, percent_decode(p.1)))
+            .collect::<Vec<_>>();
+        let decoded = decoded.iter()
+            .map(|p| (p.0, p.1.as_str()))
+            .collect::<Vec<_>>();
+
+        let path = match self.target.generate(&decoded) {
+            Ok(path) => path,
+            Err(_) => return StatusCode::InternalServerError.into_response(),
+        };
+        let location = match request.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+
+        types::ResponseBuilder::new(self.status)
+            .header("Location", &location)
+            .build()
+    }
 }
 
 pub enum HandleRouteResult<T, U> {
@@ -87,102 +591,1583 @@ pub enum HandleRouteResult<T, U> {
     NotHandled(U),
 }
 
+/// The request methods a [`Route`] accepts.
+///
+/// [`Route`]: struct.Route.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum Methods {
+    Any,
+    Only(Vec<types::HttpMethod>),
+}
+
+impl Methods {
+    pub fn contains(&self, method: types::HttpMethod) -> bool {
+        match *self {
+            Methods::Any => true,
+            Methods::Only(ref methods) => methods.contains(&method),
+        }
+    }
+}
+
+impl From<types::HttpMethod> for Methods {
+    fn from(method: types::HttpMethod) -> Methods {
+        Methods::Only(vec![method])
+    }
+}
+
+impl<'a> From<&'a [types::HttpMethod]> for Methods {
+    fn from(methods: &'a [types::HttpMethod]) -> Methods {
+        Methods::Only(methods.to_vec())
+    }
+}
+
+impl<const N: usize> From<[types::HttpMethod; N]> for Methods {
+    fn from(methods: [types::HttpMethod; N]) -> Methods {
+        Methods::Only(methods.to_vec())
+    }
+}
+
+impl From<Vec<types::HttpMethod>> for Methods {
+    fn from(methods: Vec<types::HttpMethod>) -> Methods {
+        Methods::Only(methods)
+    }
+}
+
 pub struct Route {
-    method: types::HttpMethod,
+    methods: Methods,
     pattern: Pattern,
-    handler: Box<RouteHandler + Send + Sync + 'static>,
+    handler: Box<dyn BoxedRouteHandler + Send + Sync + 'static>,
+    name: Option<String>,
 }
 
 impl Route {
-    pub fn new<H>(method: types::HttpMethod, 
-                  uri_pat: &str, 
-                  handler: H) -> Route where
+    /// Creates a route to `handler` for requests matching `uri_pat`.
+    /// `methods` can be a single method or a set of them. E.g.
+    /// `[HttpMethod::Get, HttpMethod::Head]`.
+    pub fn new<M, H>(methods: M, 
+                     uri_pat: &str, 
+                     handler: H) -> Route where
+        M: Into<Methods>,
         H: RouteHandler + Send + Sync + 'static
     {
         Route {
-            method: method,
+            methods: methods.into(),
             pattern: Pattern::new(uri_pat),
-            handler: Box::new(handler)
+            handler: Box::new(handler),
+            name: None,
+        }
+    }
+
+    /// Names the route, so URLs matching it can be generated with
+    /// [`Router::url_for`].
+    ///
+    /// [`Router::url_for`]: struct.Router.html#method.url_for
+    pub fn name(mut self, name: &str) -> Route {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    /// Adds a constraint to the parameter `name` of the route's
+    /// pattern. See [`Pattern::constraint`].
+    ///
+    /// [`Pattern::constraint`]: struct.Pattern.html#method.constraint
+    pub fn constraint<F>(mut self, name: &str, validator: F) -> Route where
+        F: Fn(&str) -> bool + Send + Sync + 'static
+    {
+        self.pattern.constraint(name, validator);
+        self
+    }
+
+    /// Creates a route to `handler` for requests of any method.
+    pub fn any<H>(uri_pat: &str, handler: H) -> Route where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        Route::new(Methods::Any, uri_pat, handler)
+    }
+
+    /// Creates a route that redirects requests matching `from` to the
+    /// path generated from `to`, with `status`. E.g.
+    /// `Route::redirect(HttpMethod::Get, "/old/:id/*", "/new/:id/*",
+    /// StatusCode::MovedPermanently)` redirects `/old/42/a/b` to
+    /// `/new/42/a/b`. Parameters and any wildcard captured by `from`
+    /// fill in those of `to`, and the request's query is kept.
+    ///
+    /// # Panics
+    ///
+    /// If `to` has a parameter or wildcard that `from` doesn't.
+    pub fn redirect<M>(methods: M, 
+                       from: &str, 
+                       to: &str, 
+                       status: StatusCode) -> Route where
+        M: Into<Methods>
+    {
+        let source = Pattern::new(from);
+        let target = Pattern::new(to);
+        let captures = |name: &str| source.parts().any(|p| match *p {
+            Part::Param(ref p) | Part::Optional(ref p) => p == name,
+            _ => false,
+        }) || (source.has_wildcard && source.wildcard == name);
+
+        let captured = target.parts().all(|part| match *part {
+            Part::Param(ref name) => captures(name),
+            Part::Wildcard => captures(&target.wildcard),
+            _ => true,
+        });
+        if !captured {
+            panic!("'{}' doesn't capture every parameter of the redirect target '{}'", 
+                   from, to);
+        }
+
+        Route {
+            methods: methods.into(),
+            pattern: source,
+            handler: Box::new(Redirect { target, status }),
+            name: None,
         }
     }
 
     pub fn handle(&self, 
                   request: types::Request) 
-        -> HandleRouteResult<types::Response, types::Request>
+        -> HandleRouteResult<RouteResponse, types::Request>
+    {
+        let path = String::from(request.path());
+        self.handle_at(request, &path, Parameters::default(), false)
+    }
+
+    /// Handles `request` if its method matches and `path` matches the
+    /// route's pattern. `params` are any parameters already captured
+    /// by the prefixes of enclosing routers. If `strict` is set, any
+    /// trailing slash on `path` must match the route's pattern.
+    fn handle_at<'a>(&'a self, 
+                     request: types::Request, 
+                     path: &str, 
+                     mut params: Parameters<'a>,
+                     strict: bool) 
+        -> HandleRouteResult<RouteResponse, types::Request>
     {
         use self::HandleRouteResult::*;
 
-        if request.method() != self.method {
+        if !self.methods.contains(request.method()) {
             return NotHandled(request);
         }
 
-        match self.pattern.match_uri(request.path()) {
-            Ok(params) => Handled(self.handler.handle(request, &params)),
-            Err(_) => NotHandled(request),
+        match self.pattern.match_uri(path) {
+            Ok(p) if !strict || self.pattern.matches_trailing_slash(path) => {
+                params.0.extend(p.0);
+                let matched = MatchedRoute(String::from(self.pattern.as_str()));
+                Handled(self.handler.handle_boxed(request, &params)
+                    .map(move |mut response| {
+                        response.extensions_mut().insert(matched);
+                        response
+                    }))
+            },
+            _ => NotHandled(request),
+        }
+    }
+
+    /// Whether `path` matches the route's pattern, honouring any
+    /// trailing slash if `strict` is set.
+    fn matches(&self, path: &str, strict: bool) -> bool {
+        self.pattern.match_uri(path).is_ok()
+            && (!strict || self.pattern.matches_trailing_slash(path))
+    }
+
+    pub fn methods(&self) -> &Methods {
+        &self.methods
+    }
+}
+
+enum Entry {
+    Route(Route),
+    Mount(Pattern, Router),
+}
+
+impl Entry {
+    /// A mount matches anything under its prefix, so it ranks as its
+    /// prefix followed by a wildcard.
+    fn specificity(&self) -> Vec<u8> {
+        match *self {
+            Entry::Route(ref route) => route.pattern.specificity(),
+            Entry::Mount(ref prefix, _) => {
+                let mut ranks = prefix.specificity();
+                ranks.push(3);
+                ranks
+            },
+        }
+    }
+}
+
+/// How a [`Router`] treats a trailing slash on a request's path.
+///
+/// [`Router`]: struct.Router.html
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TrailingSlash {
+    /// `/foo/` only matches patterns ending with a `/`, and `/foo`
+    /// only matches patterns that don't.
+    Strict,
+    /// `/foo/` and `/foo` match the same routes. This is the default.
+    #[default]
+    Normalize,
+    /// Like `Strict`, but a request that would match if its trailing
+    /// slash were added or removed is redirected there. `GET` and
+    /// `HEAD` requests get a `301 Moved Permanently`, and others a
+    /// `308 Permanent Redirect` so their method and body are kept.
+    Redirect,
+}
+
+/// Joins the path of a route to the prefix of the router it's mounted
+/// under. A route for `/` takes the prefix's path.
+fn join_paths(prefix: &str, path: &str) -> String {
+    match path {
+        "/" => String::from(prefix),
+        _ => format!("{}{}", prefix.trim_end_matches('/'), path),
+    }
+}
+
+/// The pattern of the route that handled a request, including the
+/// prefixes of any routers it's mounted under. E.g. `/users/:id`.
+///
+/// Routers insert this into the extensions of the responses their
+/// routes produce, for the benefit of middleware.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedRoute(pub String);
+
+/// Prefixes the `MatchedRoute` of `response`, produced by a mounted
+/// router, with the pattern it's mounted under.
+fn prefix_matched_route(prefix: &Pattern, response: RouteResponse) -> RouteResponse {
+    let prefix = String::from(prefix.as_str());
+    response.map(move |mut response| {
+        if let Some(route) = response.extensions_mut().get_mut::<MatchedRoute>() {
+            route.0 = join_paths(&prefix, &route.0);
         }
+        response
+    })
+}
+
+/// Adds a trailing slash to `path` if it doesn't have one, or removes
+/// it if it does.
+fn toggle_trailing_slash(path: &str) -> Option<String> {
+    match path.trim_end_matches('/') {
+        "" => None,
+        trimmed if trimmed.len() < path.len() => Some(String::from(trimmed)),
+        _ => Some(format!("{}/", path)),
     }
 }
 
+/// Routes requests to the most specific route matching them.
+///
+/// Routes are ordered by their patterns, segment by segment, with
+/// exact segments taking priority over constrained parameters, then
+/// parameters, then wildcards. So `/static/special` is tried before
+/// `/static/*`, regardless of which was registered first. Routes that
+/// are equally specific are tried in the order they're registered.
+///
+/// A `Router` is a [`Handler`], so it can be passed straight to
+/// `TcpServer::serve`. Requests that no route handles are passed to
+/// its [fallback], or answered with a `404 Not Found`.
+///
+/// [`Handler`]: ../../handler/trait.Handler.html
+/// [fallback]: #method.fallback
 pub struct Router {
-    routes: Vec<Route>,
+    entries: Vec<Entry>,
+    trailing_slash: TrailingSlash,
+    middleware: Vec<Box<dyn Middleware + Send + Sync>>,
+    fallback: Option<Box<dyn BoxedRouteHandler + Send + Sync + 'static>>,
+    on_error: Option<Arc<ErrorHandler>>,
+    error_pages: Vec<(StatusCode, Arc<ErrorPage>)>,
+    auto_options: bool,
+}
+
+/// Converts the error a response failed with into a response.
+type ErrorHandler = dyn Fn(io::Error) -> types::Response + Send + Sync;
+
+/// Renders the page for an error status.
+type ErrorPage = dyn Fn(StatusCode) -> types::Response + Send + Sync;
+
+/// Gives `response` the body of the page registered for its status,
+/// if it has one and the response's body is empty. The response keeps
+/// its own status and headers, but takes the page's `Content-Type`.
+fn render_error_page(pages: &[(StatusCode, Arc<ErrorPage>)], 
+                     response: types::Response) 
+    -> types::Response 
+{
+    let status = response.status();
+    let page = match pages.iter().find(|p| p.0 == status) {
+        Some(page) if response.body().content_length() == Some(0) => &page.1,
+        _ => return response,
+    };
+
+    let (mut head, _) = response.into_parts();
+    let (page_head, body) = page(status).into_parts();
+    head.remove_header("Content-Length");
+    head.remove_header("Content-Type");
+    if let Some(content_type) = page_head.header_value("Content-Type") {
+        head.set_header("Content-Type", content_type);
+    }
+
+    types::Response::from_parts(head, body)
 }
 
 impl Router {
+    /// Starts building a router. E.g.
+    ///
+    /// ```rust
+    /// # use server_fx::http::router::{Parameters, Router};
+    /// # use server_fx::http::types::{Request, StatusCode};
+    /// fn show_user(_: Request, params: &Parameters) -> String {
+    ///     format!("User {}", params.value("id").unwrap())
+    /// }
+    ///
+    /// let router = Router::builder()
+    ///     .get("/users/:id", show_user)
+    ///     .post("/users", |_: Request, _: &Parameters| StatusCode::Created)
+    ///     .build();
+    /// ```
+    pub fn builder() -> RouterBuilder {
+        RouterBuilder::default()
+    }
+
     pub fn new<I>(routes: I) -> Router where
         I: IntoIterator<Item=Route>
     {
-        Router {
-            routes: routes.into_iter().collect(),
-        }
+        let mut router = Router {
+            entries: routes.into_iter().map(Entry::Route).collect(),
+            trailing_slash: TrailingSlash::default(),
+            middleware: vec![],
+            fallback: None,
+            on_error: None,
+            error_pages: vec![],
+            auto_options: true,
+        };
+        router.sort();
+        router
     }
 
-    pub fn route(&self, 
-                 req: types::Request) 
-        -> HandleRouteResult<types::Response, types::Request>
-    {
-        let mut r = req;
-        for route in self.routes.iter() {
-            match route.handle(r) {
-                HandleRouteResult::Handled(response) => {
-                    return HandleRouteResult::Handled(response);
-                },
-                HandleRouteResult::NotHandled(request) => {
-                    r = request;
-                },
-            }
-        }
+    fn sort(&mut self) {
+        self.entries.sort_by_cached_key(Entry::specificity);
+    }
 
-        HandleRouteResult::NotHandled(r)
+    /// Mounts `router` under `prefix`. Requests whose path starts with
+    /// `prefix` are routed through `router`, which matches its
+    /// patterns against the rest of the path. Any parameters in
+    /// `prefix` (E.g. `/users/:id`) are passed to the handlers of
+    /// `router` along with their own.
+    ///
+    /// Mounted routers are ordered alongside the router's own routes
+    /// as if their prefix ended with a wildcard.
+    pub fn mount(mut self, prefix: &str, router: Router) -> Router {
+        self.entries.push(Entry::Mount(Pattern::new(prefix), router));
+        self.sort();
+        self
     }
-}
 
-#[cfg(test)]
-mod route_should {
-    use super::*;
+    /// Sets how trailing slashes are treated. The policy of the
+    /// router that `route` is called on applies to any routers mounted
+    /// under it.
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Router {
+        self.trailing_slash = policy;
+        self
+    }
 
-    #[test]
-    fn compile_pattern() {
-        let p = Pattern::new("/api/:item");
+    /// Sets whether `OPTIONS` requests are answered automatically,
+    /// which they are by default. An `OPTIONS` request for a path that
+    /// routes match, but that no `OPTIONS` route handles, gets a `204
+    /// No Content` response with an `Allow` header listing the
+    /// methods of those routes. `OPTIONS` is then also listed in the
+    /// `Allow` header of `405 Method Not Allowed` responses.
+    ///
+    /// The response passes back through the router's middleware, so a
+    /// CORS middleware can answer preflight requests itself, or add
+    /// its headers to the automatic response.
+    ///
+    /// Like the trailing-slash policy, the setting of the router that
+    /// `route` is called on applies to any routers mounted under it.
+    pub fn auto_options(mut self, enabled: bool) -> Router {
+        self.auto_options = enabled;
+        self
+    }
 
-        let mut pattern_iter = p.parts();
+    /// Adds `middleware` to the router. Middleware runs in the order
+    /// it's added, before the request is routed.
+    ///
+    /// Middleware on the router that `route` is called on sees every
+    /// request, and requests that no route handles are answered with a
+    /// `404 Not Found` after passing through it. Middleware on a
+    /// mounted router only sees the requests that one of its routes
+    /// will handle.
+    pub fn middleware<M>(mut self, middleware: M) -> Router where
+        M: Middleware + Send + Sync + 'static
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
 
-        assert_eq!(Some(&Part::Exact("api".to_owned())), pattern_iter.next());
-        assert_eq!(Some(&Part::Param("item".to_owned())), pattern_iter.next());
+    /// Sets the handler for requests that no route matches, in place
+    /// of the `404 Not Found` response. Its parameters are always
+    /// empty.
+    ///
+    /// Only the fallback of the router that `route` is called on is
+    /// used. It isn't called for requests answered with a `405 Method
+    /// Not Allowed`, or redirected by the trailing-slash policy.
+    pub fn fallback<H>(mut self, handler: H) -> Router where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.fallback = Some(Box::new(handler));
+        self
     }
 
-    #[test]
-    fn match_wildcard() {
-        let p = Pattern::new("/static/*");
-        assert!(p.1);
+    /// Sets how errors are handled when the router is used as a
+    /// `Handler`. A response that fails, E.g. because a pollable
+    /// returned by middleware failed, is replaced by the response `f`
+    /// returns. Without this, the error closes the connection.
+    pub fn on_error<F>(mut self, f: F) -> Router where
+        F: Fn(io::Error) -> types::Response + Send + Sync + 'static
+    {
+        self.on_error = Some(Arc::new(f));
+        self
+    }
 
-        assert!(p.match_uri("/static/css/site.css").is_ok());
+    /// Renders the body of responses with `status` using `page`, when
+    /// the router is used as a `Handler`. E.g.
+    /// `router.error_page(StatusCode::NotFound, |_| "Nothing here")`.
+    ///
+    /// Pages are only rendered for responses whose body is empty, such
+    /// as the `404 Not Found` and `405 Method Not Allowed` responses
+    /// the router produces, or a bare `StatusCode` returned by a
+    /// handler. The response keeps its status and headers. If a page
+    /// is registered for `500 Internal Server Error` and no
+    /// [`on_error`] handler is set, failed responses are replaced by a
+    /// `500` rather than closing the connection.
+    ///
+    /// [`on_error`]: #method.on_error
+    pub fn error_page<F, R>(mut self, status: StatusCode, page: F) -> Router where
+        F: Fn(StatusCode) -> R + Send + Sync + 'static,
+        R: IntoResponse
+    {
+        self.error_pages.retain(|p| p.0 != status);
+        self.error_pages.push((status, Arc::new(move |s| page(s).into_response())));
+        self
     }
 
-    #[test]
+    /// Renders the router's error pages onto responses that don't pass
+    /// through it, as they are onto its own. E.g. to give the responses
+    /// a transport answers the requests it refuses with, such as a `413
+    /// Payload Too Large`, the same pages:
+    /// `HttpTransport::new(framed).on_refusal(router.error_pages())`.
+    pub fn error_pages(&self)
+        -> impl Fn(types::Response) -> types::Response + Clone + Send + Sync + 'static
+    {
+        let pages = self.error_pages.clone();
+        move |response| render_error_page(&pages, response)
+    }
+
+    /// Routes `req` to the first route matching its method and path.
+    ///
+    /// If no route matches, but the path matches routes registered for
+    /// other methods, a `405 Method Not Allowed` response is returned
+    /// with an `Allow` header listing those methods, or a `204 No
+    /// Content` response for an `OPTIONS` request. See
+    /// [`auto_options`]. Otherwise, `req` is passed to the router's
+    /// fallback, if it has one.
+    ///
+    /// [`auto_options`]: #method.auto_options
+    pub fn route(&self, 
+                 req: types::Request) 
+        -> HandleRouteResult<RouteResponse, types::Request>
+    {
+        if self.middleware.is_empty() {
+            return self.route_unwrapped(req);
+        }
+
+        let endpoint = |req| match self.route_unwrapped(req) {
+            HandleRouteResult::Handled(response) => response,
+            HandleRouteResult::NotHandled(_) => 
+                RouteResponse::from(types::StatusCode::NotFound.into_response()),
+        };
+
+        HandleRouteResult::Handled(Next::new(&self.middleware, &endpoint).run(req))
+    }
+
+    /// Routes `req` without running the router's own middleware.
+    fn route_unwrapped(&self, 
+                       req: types::Request) 
+        -> HandleRouteResult<RouteResponse, types::Request>
+    {
+        let strict = self.trailing_slash != TrailingSlash::Normalize;
+        let path = String::from(req.path());
+        let req = match self.dispatch(req, &path, Parameters::default(), strict) {
+            HandleRouteResult::NotHandled(req) => req,
+            handled => return handled,
+        };
+
+        let mut allowed = vec![];
+        self.allowed_methods(&path, strict, &mut allowed);
+        if allowed.is_empty() {
+            let result = match self.trailing_slash {
+                TrailingSlash::Redirect => self.redirect_trailing_slash(req, &path),
+                _ => HandleRouteResult::NotHandled(req),
+            };
+
+            return match (result, self.fallback.as_ref()) {
+                (HandleRouteResult::NotHandled(req), Some(fallback)) =>
+                    HandleRouteResult::Handled(
+                        fallback.handle_boxed(req, &Parameters::default())),
+                (result, _) => result,
+            };
+        }
+
+        if self.auto_options && !allowed.contains(&types::HttpMethod::Options) {
+            allowed.push(types::HttpMethod::Options);
+        }
+
+        let allow = allowed.iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let status = match req.method() {
+            types::HttpMethod::Options if self.auto_options => StatusCode::NoContent,
+            _ => StatusCode::MethodNotAllowed,
+        };
+
+        HandleRouteResult::Handled(RouteResponse::from(
+            types::ResponseBuilder::new(status)
+                .header("Allow", &allow)
+                .build()))
+    }
+
+    /// Generates the path of the route named `name`, filling in its
+    /// parameters from `params`. E.g.
+    /// `router.url_for("user_detail", &[("id", "42")])` for a route
+    /// registered as `/users/:id`. Routes in mounted routers include
+    /// their prefix, whose parameters are also taken from `params`.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) 
+        -> Result<String, UrlForError> 
+    {
+        self.find_url(name, params)
+            .unwrap_or_else(|| Err(UrlForError::UnknownRoute(String::from(name))))
+    }
+
+    fn find_url(&self, name: &str, params: &[(&str, &str)]) 
+        -> Option<Result<String, UrlForError>> 
+    {
+        self.entries.iter().filter_map(|entry| match *entry {
+            Entry::Route(ref route) if route.name.as_deref() == Some(name) =>
+                Some(route.pattern.generate(params)),
+            Entry::Route(_) => None,
+            Entry::Mount(ref prefix, ref router) =>
+                router.find_url(name, params).map(|rest| {
+                    Ok(join_paths(&prefix.generate(params)?, &rest?))
+                }),
+        }).next()
+    }
+
+    /// Redirects `req` to `path` with its trailing slash added or
+    /// removed, if a route for its method matches that path.
+    fn redirect_trailing_slash(&self, req: types::Request, path: &str)
+        -> HandleRouteResult<RouteResponse, types::Request>
+    {
+        use self::types::HttpMethod::{Get, Head};
+
+        let target = match toggle_trailing_slash(path) {
+            Some(target) => target,
+            None => return HandleRouteResult::NotHandled(req),
+        };
+
+        let mut found = false;
+        self.visit_matches(&target, true, &mut |route| {
+            found = found || route.methods().contains(req.method());
+        });
+        if !found {
+            return HandleRouteResult::NotHandled(req);
+        }
+
+        let location = match req.uri().query() {
+            Some(query) => format!("{}?{}", target, query),
+            None => target,
+        };
+        let status = match req.method() {
+            Get | Head => types::StatusCode::MovedPermanently,
+            _ => types::StatusCode::PermanentRedirect,
+        };
+
+        HandleRouteResult::Handled(RouteResponse::from(
+            types::ResponseBuilder::new(status)
+                .header("Location", &location)
+                .build()))
+    }
+
+    /// Collects the methods of the routes whose patterns match `path`.
+    fn allowed_methods(&self, 
+                       path: &str, 
+                       strict: bool, 
+                       methods: &mut Vec<types::HttpMethod>) 
+    {
+        self.visit_matches(path, strict, &mut |route| {
+            if let Methods::Only(ref only) = *route.methods() {
+                for method in only {
+                    if !methods.contains(method) {
+                        methods.push(*method);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Calls `visit` with each route, including those of mounted
+    /// routers, whose pattern matches `path`.
+    fn visit_matches(&self, path: &str, strict: bool, visit: &mut dyn FnMut(&Route)) {
+        for entry in self.entries.iter() {
+            match *entry {
+                Entry::Route(ref route) => {
+                    if route.matches(path, strict) {
+                        visit(route);
+                    }
+                },
+                Entry::Mount(ref prefix, ref router) => {
+                    if let Ok((_, rest)) = prefix.match_prefix(path) {
+                        router.visit_matches(rest, strict, visit);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Routes `req` as a mounted router, running the router's
+    /// middleware only if one of its routes will handle `req`.
+    fn route_at<'a>(&'a self, 
+                    req: types::Request, 
+                    path: &str, 
+                    params: Parameters<'a>,
+                    strict: bool) 
+        -> HandleRouteResult<RouteResponse, types::Request>
+    {
+        if self.middleware.is_empty() {
+            return self.dispatch(req, path, params, strict);
+        }
+
+        let mut found = false;
+        self.visit_matches(path, strict, &mut |route| {
+            found = found || route.methods().contains(req.method());
+        });
+        if !found {
+            return HandleRouteResult::NotHandled(req);
+        }
+
+        let endpoint = |req| match self.dispatch(req, path, params.clone(), strict) {
+            HandleRouteResult::Handled(response) => response,
+            HandleRouteResult::NotHandled(_) => 
+                RouteResponse::from(types::StatusCode::NotFound.into_response()),
+        };
+
+        HandleRouteResult::Handled(Next::new(&self.middleware, &endpoint).run(req))
+    }
+
+    fn dispatch<'a>(&'a self, 
+                    req: types::Request, 
+                    path: &str, 
+                    params: Parameters<'a>,
+                    strict: bool) 
+        -> HandleRouteResult<RouteResponse, types::Request>
+    {
+        let mut r = req;
+        for entry in self.entries.iter() {
+            let result = match *entry {
+                Entry::Route(ref route) => 
+                    route.handle_at(r, path, params.clone(), strict),
+                Entry::Mount(ref prefix, ref router) => 
+                    match prefix.match_prefix(path) {
+                        Ok((mut p, rest)) => {
+                            p.0.splice(..0, params.0.iter().cloned());
+                            match router.route_at(r, rest, p, strict) {
+                                HandleRouteResult::Handled(response) => 
+                                    HandleRouteResult::Handled(
+                                        prefix_matched_route(prefix, response)),
+                                not_handled => not_handled,
+                            }
+                        },
+                        Err(_) => HandleRouteResult::NotHandled(r),
+                    },
+            };
+
+            match result {
+                HandleRouteResult::Handled(response) => {
+                    return HandleRouteResult::Handled(response);
+                },
+                HandleRouteResult::NotHandled(request) => {
+                    r = request;
+                },
+            }
+        }
+
+        HandleRouteResult::NotHandled(r)
+    }
+}
+
+/// Builds a [`Router`] one route at a time.
+///
+/// [`Router`]: struct.Router.html
+pub struct RouterBuilder {
+    entries: Vec<Entry>,
+    trailing_slash: TrailingSlash,
+    middleware: Vec<Box<dyn Middleware + Send + Sync>>,
+    fallback: Option<Box<dyn BoxedRouteHandler + Send + Sync + 'static>>,
+    on_error: Option<Arc<ErrorHandler>>,
+    error_pages: Vec<(StatusCode, Arc<ErrorPage>)>,
+    auto_options: bool,
+}
+
+impl Default for RouterBuilder {
+    fn default() -> RouterBuilder {
+        RouterBuilder {
+            entries: vec![],
+            trailing_slash: TrailingSlash::default(),
+            middleware: vec![],
+            fallback: None,
+            on_error: None,
+            error_pages: vec![],
+            auto_options: true,
+        }
+    }
+}
+
+impl RouterBuilder {
+    pub fn new() -> RouterBuilder {
+        RouterBuilder::default()
+    }
+
+    /// Adds `route` to the router.
+    pub fn route(mut self, route: Route) -> RouterBuilder {
+        self.entries.push(Entry::Route(route));
+        self
+    }
+
+    /// Adds a route to `handler` for requests matching `uri_pat` with
+    /// any of `methods`.
+    pub fn methods<M, H>(self, methods: M, uri_pat: &str, handler: H) -> RouterBuilder where
+        M: Into<Methods>,
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.route(Route::new(methods, uri_pat, handler))
+    }
+
+    /// Adds a route to `handler` for requests of any method.
+    pub fn any<H>(self, uri_pat: &str, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.route(Route::any(uri_pat, handler))
+    }
+
+    pub fn get<H>(self, uri_pat: &str, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.methods(types::HttpMethod::Get, uri_pat, handler)
+    }
+
+    pub fn post<H>(self, uri_pat: &str, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.methods(types::HttpMethod::Post, uri_pat, handler)
+    }
+
+    pub fn put<H>(self, uri_pat: &str, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.methods(types::HttpMethod::Put, uri_pat, handler)
+    }
+
+    pub fn delete<H>(self, uri_pat: &str, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.methods(types::HttpMethod::Delete, uri_pat, handler)
+    }
+
+    pub fn patch<H>(self, uri_pat: &str, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.methods(types::HttpMethod::Patch, uri_pat, handler)
+    }
+
+    pub fn head<H>(self, uri_pat: &str, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.methods(types::HttpMethod::Head, uri_pat, handler)
+    }
+
+    pub fn options<H>(self, uri_pat: &str, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.methods(types::HttpMethod::Options, uri_pat, handler)
+    }
+
+    /// Adds a route redirecting requests matching `from` to `to`. See
+    /// [`Route::redirect`].
+    ///
+    /// [`Route::redirect`]: struct.Route.html#method.redirect
+    pub fn redirect<M>(self, 
+                       methods: M, 
+                       from: &str, 
+                       to: &str, 
+                       status: StatusCode) -> RouterBuilder where
+        M: Into<Methods>
+    {
+        self.route(Route::redirect(methods, from, to, status))
+    }
+
+    /// Mounts `router` under `prefix`. See [`Router::mount`].
+    ///
+    /// [`Router::mount`]: struct.Router.html#method.mount
+    pub fn mount(mut self, prefix: &str, router: Router) -> RouterBuilder {
+        self.entries.push(Entry::Mount(Pattern::new(prefix), router));
+        self
+    }
+
+    /// Names the route added last. See [`Route::name`].
+    ///
+    /// # Panics
+    ///
+    /// If the last thing added was a mounted router, or nothing.
+    ///
+    /// [`Route::name`]: struct.Route.html#method.name
+    pub fn name(mut self, name: &str) -> RouterBuilder {
+        match self.entries.last_mut() {
+            Some(&mut Entry::Route(ref mut route)) => 
+                route.name = Some(String::from(name)),
+            _ => panic!("There's no route to name '{}'", name),
+        }
+        self
+    }
+
+    /// Sets how trailing slashes are treated. See [`TrailingSlash`].
+    ///
+    /// [`TrailingSlash`]: enum.TrailingSlash.html
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> RouterBuilder {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Sets whether `OPTIONS` requests are answered automatically. See
+    /// [`Router::auto_options`].
+    ///
+    /// [`Router::auto_options`]: struct.Router.html#method.auto_options
+    pub fn auto_options(mut self, enabled: bool) -> RouterBuilder {
+        self.auto_options = enabled;
+        self
+    }
+
+    /// Adds `middleware` to the router. See [`Router::middleware`].
+    ///
+    /// [`Router::middleware`]: struct.Router.html#method.middleware
+    pub fn middleware<M>(mut self, middleware: M) -> RouterBuilder where
+        M: Middleware + Send + Sync + 'static
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Sets the handler for requests that no route matches. See
+    /// [`Router::fallback`].
+    ///
+    /// [`Router::fallback`]: struct.Router.html#method.fallback
+    pub fn fallback<H>(mut self, handler: H) -> RouterBuilder where
+        H: RouteHandler + Send + Sync + 'static
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets how failed responses are handled. See [`Router::on_error`].
+    ///
+    /// [`Router::on_error`]: struct.Router.html#method.on_error
+    pub fn on_error<F>(mut self, f: F) -> RouterBuilder where
+        F: Fn(io::Error) -> types::Response + Send + Sync + 'static
+    {
+        self.on_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the page rendered for responses with `status`. See
+    /// [`Router::error_page`].
+    ///
+    /// [`Router::error_page`]: struct.Router.html#method.error_page
+    pub fn error_page<F, R>(mut self, status: StatusCode, page: F) -> RouterBuilder where
+        F: Fn(StatusCode) -> R + Send + Sync + 'static,
+        R: IntoResponse
+    {
+        self.error_pages.retain(|p| p.0 != status);
+        self.error_pages.push((status, Arc::new(move |s| page(s).into_response())));
+        self
+    }
+
+    pub fn build(self) -> Router {
+        let mut router = Router {
+            entries: self.entries,
+            trailing_slash: self.trailing_slash,
+            middleware: self.middleware,
+            fallback: self.fallback,
+            on_error: self.on_error,
+            error_pages: self.error_pages,
+            auto_options: self.auto_options,
+        };
+        router.sort();
+        router
+    }
+}
+
+impl Handler for Router {
+    type Request = types::Request;
+    type Response = types::Response;
+    type Error = io::Error;
+    type Pollable = RouteResponse;
+
+    fn handle(&self, request: types::Request) -> RouteResponse {
+        let response = match self.route(request) {
+            HandleRouteResult::Handled(response) => response,
+            HandleRouteResult::NotHandled(_) =>
+                RouteResponse::from(StatusCode::NotFound.into_response()),
+        };
+
+        let has_500_page = self.error_pages.iter()
+            .any(|p| p.0 == StatusCode::InternalServerError);
+        let response = match self.on_error {
+            Some(ref on_error) => {
+                let on_error = on_error.clone();
+                response.recover(move |e| on_error(e))
+            },
+            None if has_500_page => response.recover(|_| 
+                StatusCode::InternalServerError.into_response()),
+            None => response,
+        };
+
+        if self.error_pages.is_empty() {
+            return response;
+        }
+
+        let pages = self.error_pages.clone();
+        response.map(move |response| render_error_page(&pages, response))
+    }
+
+    fn timed_out(&self) -> Option<types::Response> {
+        Some(render_error_page(&self.error_pages, errors::timed_out()))
+    }
+}
+
+#[cfg(test)]
+mod route_should {
+    use super::*;
+
+    #[test]
+    fn compile_pattern() {
+        let p = Pattern::new("/api/:item");
+
+        let mut pattern_iter = p.parts();
+
+        assert_eq!(Some(&Part::Exact("api".to_owned())), pattern_iter.next());
+        assert_eq!(Some(&Part::Param("item".to_owned())), pattern_iter.next());
+    }
+
+    #[test]
+    fn match_wildcard() {
+        let p = Pattern::new("/static/*");
+        assert!(p.has_wildcard);
+
+        assert_eq!(Some("css/site.css"),
+                   p.match_uri("/static/css/site.css").unwrap().value("*"));
+    }
+
+    #[test]
     fn match_uri() {
         let p = Pattern::new("/api/:item");
         let params = p.match_uri("/api/resource?_filter=hello+world");
         assert!(params.is_ok());
-        assert_eq!(("item", "resource".to_string()), params.unwrap()[0]);
+        assert_eq!(Some(("item", "resource")), params.unwrap().iter().next());
+    }
+
+    #[test]
+    fn match_paths_with_multibyte_characters() {
+        let p = Pattern::new("/users/:name");
+        assert_eq!(Some("zoë"), p.match_uri("/users/zoë?tab=posts").unwrap().value("name"));
+        assert_eq!(Some("zoë"), p.match_uri("/users/zoë#bio").unwrap().value("name"));
+        assert!(p.match_uri("/users/zoë/ö").is_err());
+    }
+
+    #[test]
+    fn match_a_prefix() {
+        let p = Pattern::new("/users/:id");
+
+        let (params, rest) = p.match_prefix("/users/42/posts?page=2").unwrap();
+        assert_eq!(vec![("id", "42")], params.iter().collect::<Vec<_>>());
+        assert_eq!("/posts", rest);
+
+        assert_eq!("", p.match_prefix("/users/42").unwrap().1);
+        assert!(p.match_prefix("/users").is_err());
+        assert!(p.match_prefix("/usersx/42").is_err());
+    }
+
+    fn ready(mut response: RouteResponse) -> types::Response {
+        use pollable::Pollable;
+        use result::PollResult;
+
+        match response.poll().unwrap() {
+            PollResult::Ready(response) => response,
+            PollResult::NotReady => panic!("Response not ready"),
+        }
+    }
+
+    fn route_to_string(router: &Router, method: types::HttpMethod, path: &str) 
+        -> Option<String> 
+    {
+        let request = types::RequestBuilder::new(method, path).build();
+        match router.route(request) {
+            HandleRouteResult::Handled(response) => Some(String::from_utf8(
+                ready(response).into_parts().1.as_bytes().unwrap().to_vec()).unwrap()),
+            HandleRouteResult::NotHandled(_) => None,
+        }
+    }
+
+    fn describe(_: types::Request, params: &Parameters) -> String {
+        params.iter()
+            .map(|p| format!("{}={}", p.0, p.1))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    #[test]
+    fn route_through_mounted_routers() {
+        use self::types::HttpMethod::Get;
+
+        let posts = Router::new(vec![
+            Route::new(Get, "/", describe),
+            Route::new(Get, "/posts/:post", describe),
+        ]);
+        let router = Router::new(vec![Route::new(Get, "/about", describe)])
+            .mount("/users/:id", posts);
+
+        assert_eq!(Some("id=7".to_string()), 
+                   route_to_string(&router, Get, "/users/7"));
+        assert_eq!(Some("id=7,post=3".to_string()), 
+                   route_to_string(&router, Get, "/users/7/posts/3"));
+        assert_eq!(Some("".to_string()), 
+                   route_to_string(&router, Get, "/about"));
+        assert_eq!(None, route_to_string(&router, Get, "/posts/3"));
+    }
+
+    #[test]
+    fn reject_unsupported_methods_with_allow() {
+        use self::types::HttpMethod::{Delete, Get, Post};
+
+        let router = Router::new(vec![
+            Route::new(Get, "/items/:id", describe),
+            Route::new([Get, Post], "/items", describe),
+        ]);
+
+        let request = types::RequestBuilder::new(Delete, "/items").build();
+        match router.route(request) {
+            HandleRouteResult::Handled(response) => {
+                let response = ready(response);
+                assert_eq!(405, response.status_code());
+                assert_eq!(Some("GET, POST, OPTIONS"), response.header_value("Allow"));
+            },
+            HandleRouteResult::NotHandled(_) => panic!("Expected a 405"),
+        }
+
+        assert_eq!(None, route_to_string(&router, Delete, "/other"));
+    }
+
+    #[test]
+    fn route_multiple_methods_to_one_handler() {
+        use self::types::HttpMethod::{Delete, Get, Head, Post};
+
+        let router = Router::new(vec![
+            Route::new([Get, Head], "/page", describe),
+            Route::any("/echo", describe),
+        ]);
+
+        assert!(route_to_string(&router, Get, "/page").is_some());
+        assert!(route_to_string(&router, Head, "/page").is_some());
+        assert!(route_to_string(&router, Post, "/echo").is_some());
+        assert!(route_to_string(&router, Delete, "/echo").is_some());
+    }
+
+    #[test]
+    fn apply_parameter_constraints() {
+        use self::types::HttpMethod::Get;
+
+        let router = Router::new(vec![
+            Route::new(Get, "/pages/:page", describe)
+                .constraint("page", |v| v.bytes().all(|b| b.is_ascii_alphanumeric())),
+        ]);
+
+        assert!(route_to_string(&router, Get, "/pages/about").is_some());
+        assert!(route_to_string(&router, Get, "/pages/..").is_none());
+        assert!(route_to_string(&router, Get, "/pages/%2e%2e").is_none());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn apply_regex_constraints() {
+        let p = Pattern::new("/users/:id(\\d+)/:tab(posts|likes)");
+
+        assert!(p.match_uri("/users/42/posts").is_ok());
+        assert!(p.match_uri("/users/42x/posts").is_err());
+        assert!(p.match_uri("/users/42/other").is_err());
+        assert!(p.match_prefix("/users/x/likes").is_err());
+    }
+
+    #[test]
+    fn convert_handler_results_into_responses() {
+        let router = Router::new(vec![
+            Route::new(types::HttpMethod::Get, "/hello/:name", 
+                       |_: types::Request, params: &Parameters| 
+                           format!("Hello, {}!", params.value("name").unwrap())),
+        ]);
+
+        let request = types::RequestBuilder::new(types::HttpMethod::Get, 
+                                                 "/hello/world")
+            .build();
+
+        match router.route(request) {
+            HandleRouteResult::Handled(response) => {
+                let response = ready(response);
+                assert_eq!(200, response.status_code());
+                assert_eq!(Some(&b"Hello, world!"[..]), 
+                           response.into_parts().1.as_bytes());
+            },
+            HandleRouteResult::NotHandled(_) => panic!("Route not handled"),
+        }
+    }
+
+    #[test]
+    fn prefer_more_specific_routes() {
+        use self::types::HttpMethod::Get;
+
+        let router = Router::new(vec![
+            Route::new(Get, "/static/*", |_: types::Request, _: &Parameters| "wildcard"),
+            Route::new(Get, "/static/:file", |_: types::Request, _: &Parameters| "param"),
+            Route::new(Get, "/static/:file", |_: types::Request, _: &Parameters| "shadowed"),
+            Route::new(Get, "/static/special", |_: types::Request, _: &Parameters| "exact"),
+        ]).mount("/static/nested", Router::new(vec![
+            Route::new(Get, "/page", |_: types::Request, _: &Parameters| "mounted"),
+        ]));
+
+        let route = |path| route_to_string(&router, Get, path);
+        assert_eq!(Some(String::from("exact")), route("/static/special"));
+        assert_eq!(Some(String::from("param")), route("/static/other"));
+        assert_eq!(Some(String::from("wildcard")), route("/static/nested/other"));
+        assert_eq!(Some(String::from("mounted")), route("/static/nested/page"));
+    }
+
+    fn location_of(router: &Router, method: types::HttpMethod, path: &str) 
+        -> Option<(usize, String)> 
+    {
+        let request = types::RequestBuilder::new(method, path).build();
+        match router.route(request) {
+            HandleRouteResult::Handled(response) => Some(ready(response)).map(|response| (
+                response.status_code(),
+                String::from(response.header_value("Location").unwrap_or("")))),
+            HandleRouteResult::NotHandled(_) => None,
+        }
+    }
+
+    #[test]
+    fn apply_a_trailing_slash_policy() {
+        use self::types::HttpMethod::{Get, Post};
+
+        let routes = || vec![
+            Route::new(Get, "/docs/", describe),
+            Route::new([Get, Post], "/users/:id", describe),
+            Route::new(Get, "/static/*", describe),
+        ];
+
+        let normalize = Router::new(routes());
+        assert!(route_to_string(&normalize, Get, "/docs").is_some());
+        assert!(route_to_string(&normalize, Get, "/users/42/").is_some());
+
+        let strict = Router::new(routes()).trailing_slash(TrailingSlash::Strict);
+        assert!(route_to_string(&strict, Get, "/docs/").is_some());
+        assert!(route_to_string(&strict, Get, "/docs").is_none());
+        assert!(route_to_string(&strict, Get, "/users/42").is_some());
+        assert!(route_to_string(&strict, Get, "/users/42/").is_none());
+        assert!(route_to_string(&strict, Get, "/static/css/").is_some());
+
+        let redirect = Router::new(routes()).trailing_slash(TrailingSlash::Redirect);
+        assert_eq!(Some((301, String::from("/docs/?q=1"))),
+                   location_of(&redirect, Get, "/docs?q=1"));
+        assert_eq!(Some((308, String::from("/users/42"))),
+                   location_of(&redirect, Post, "/users/42/"));
+        assert_eq!(Some((200, String::new())),
+                   location_of(&redirect, Get, "/users/42"));
+        assert_eq!(None, location_of(&redirect, Post, "/docs"));
+    }
+
+    #[test]
+    fn build_routers_fluently() {
+        use self::types::HttpMethod::{Delete, Get, Post, Put};
+
+        let router = Router::builder()
+            .get("/users/:id", |_: types::Request, _: &Parameters| "show")
+            .post("/users", |_: types::Request, _: &Parameters| "create")
+            .methods([Put, Delete], "/users/:id", |_: types::Request, _: &Parameters| "modify")
+            .mount("/admin", Router::builder()
+                .any("/*", |_: types::Request, _: &Parameters| "admin")
+                .build())
+            .trailing_slash(TrailingSlash::Strict)
+            .build();
+
+        let route = |method, path| route_to_string(&router, method, path);
+        assert_eq!(Some(String::from("show")), route(Get, "/users/42"));
+        assert_eq!(Some(String::from("create")), route(Post, "/users"));
+        assert_eq!(Some(String::from("modify")), route(Delete, "/users/42"));
+        assert_eq!(Some(String::from("admin")), route(Put, "/admin/settings"));
+        assert_eq!(None, route(Get, "/users/42/"));
+    }
+
+    #[test]
+    fn route_to_deferred_handlers() {
+        use pollable::PollableResult;
+        use http::response::Deferred;
+
+        let router = Router::builder()
+            .get("/slow", |_: types::Request, _: &Parameters|
+                 Deferred(PollableResult::<_, types::StatusCode>::Ok(Some("done"))))
+            .build();
+
+        assert_eq!(Some(String::from("done")),
+                   route_to_string(&router, types::HttpMethod::Get, "/slow"));
+    }
+
+    #[test]
+    fn generate_urls_for_named_routes() {
+        use self::types::HttpMethod::Get;
+
+        let router = Router::builder()
+            .get("/users/:id", describe).name("user_detail")
+            .get("/docs/", describe).name("docs")
+            .route(Route::new(Get, "/files/*", describe).name("files"))
+            .mount("/orgs/:org", Router::builder()
+                .get("/", describe).name("org")
+                .get("/repos/:repo", describe).name("repo")
+                .build())
+            .build();
+
+        assert_eq!(Ok(String::from("/users/42")),
+                   router.url_for("user_detail", &[("id", "42")]));
+        assert_eq!(Ok(String::from("/users/Jo%20Bloggs")),
+                   router.url_for("user_detail", &[("id", "Jo Bloggs")]));
+        assert_eq!(Ok(String::from("/docs/")), router.url_for("docs", &[]));
+        assert_eq!(Ok(String::from("/files/css/site.css")),
+                   router.url_for("files", &[("*", "css/site.css")]));
+        assert_eq!(Ok(String::from("/orgs/acme")),
+                   router.url_for("org", &[("org", "acme")]));
+        assert_eq!(Ok(String::from("/orgs/acme/repos/fx")),
+                   router.url_for("repo", &[("org", "acme"), ("repo", "fx")]));
+        assert_eq!(Err(UrlForError::MissingParameter(String::from("org"))),
+                   router.url_for("repo", &[("repo", "fx")]));
+        assert_eq!(Err(UrlForError::UnknownRoute(String::from("nope"))),
+                   router.url_for("nope", &[]));
+    }
+
+    #[test]
+    fn refuse_urls_breaking_constraints() {
+        let router = Router::new(vec![
+            Route::new(types::HttpMethod::Get, "/pages/:page", describe)
+                .constraint("page", |v| !v.contains('.'))
+                .name("page"),
+        ]);
+
+        assert_eq!(Err(UrlForError::InvalidParameter(String::from("page"))),
+                   router.url_for("page", &[("page", "..")]));
+    }
+
+    #[test]
+    fn run_middleware_around_routes() {
+        use self::types::HttpMethod::Get;
+        use http::middleware::Next;
+
+        let tag = |name: &'static str| move |mut req: types::Request, next: Next| {
+            let seen = format!("{}{}", req.header_value("X-Seen").unwrap_or(""), name);
+            req.set_header("X-Seen", &seen);
+            next.run(req).map(move |mut response| {
+                response.add_header("X-Seen", name);
+                response
+            })
+        };
+        let seen = |_: types::Request, _: &Parameters| -> String { String::new() };
+        let echo = |req: types::Request, _: &Parameters|
+            String::from(req.header_value("X-Seen").unwrap_or(""));
+
+        let router = Router::builder()
+            .middleware(tag("a"))
+            .middleware(tag("b"))
+            .get("/echo", echo)
+            .mount("/admin", Router::builder()
+                .middleware(tag("c"))
+                .get("/echo", echo)
+                .build())
+            .get("/admin/other", seen)
+            .build();
+
+        assert_eq!(Some(String::from("ab")), route_to_string(&router, Get, "/echo"));
+        assert_eq!(Some(String::from("abc")), route_to_string(&router, Get, "/admin/echo"));
+        assert_eq!(Some(String::new()), route_to_string(&router, Get, "/admin/other"));
+
+        let request = types::RequestBuilder::new(Get, "/missing").build();
+        match router.route(request) {
+            HandleRouteResult::Handled(response) => {
+                let response = ready(response);
+                assert_eq!(404, response.status_code());
+                assert_eq!(vec!["b", "a"], response.headers()
+                    .filter(|h| h.0 == "X-Seen")
+                    .map(|h| h.1)
+                    .collect::<Vec<_>>());
+            },
+            HandleRouteResult::NotHandled(_) => panic!("Expected a 404"),
+        }
+    }
+
+    #[test]
+    fn extract_typed_parameters() {
+        use self::types::HttpMethod::Get;
+
+        let router = Router::new(vec![
+            Route::new(Get, "/users/:id/:name",
+                       |_: types::Request, params: &Parameters|
+                           -> Result<String, ParamError> {
+                Ok(format!("{}:{}", params.get::<u32>("id")? + 1,
+                           params.get::<String>("name")?))
+            }),
+        ]);
+
+        assert_eq!(Some(String::from("42:Jo Bloggs")),
+                   route_to_string(&router, Get, "/users/41/Jo%20Bloggs"));
+        assert_eq!(Some(String::from("Invalid parameter 'id': invalid digit found in string")),
+                   route_to_string(&router, Get, "/users/x/Jo"));
+
+        let params = Parameters::from(vec![("id", String::from("7"))]);
+        assert_eq!(Err(ParamError::Missing(String::from("page"))),
+                   params.get::<u32>("page"));
+        assert_eq!(400, params.get::<u8>("page").unwrap_err()
+                   .into_response().status_code());
+    }
+
+    #[test]
+    fn handle_requests_as_a_handler() {
+        use pollable::{Pollable, PollableResult};
+        use self::types::HttpMethod::Get;
+
+        fn status_of(router: &Router, path: &str) -> Result<u16, io::Error> {
+            let mut response = Handler::handle(
+                router, types::RequestBuilder::new(Get, path).build());
+            match response.poll()? {
+                ::result::PollResult::Ready(response) => Ok(response.status().as_u16()),
+                ::result::PollResult::NotReady => panic!("Response not ready"),
+            }
+        }
+
+        let failing = |req: types::Request, next: Next| match req.path() {
+            "/broken" => RouteResponse::pending(PollableResult::<types::Response, _>::Err(
+                Some(io::Error::other("Broken")))),
+            _ => next.run(req),
+        };
+
+        let router = Router::builder()
+            .middleware(failing)
+            .get("/hello", |_: types::Request, _: &Parameters| "Hello")
+            .build();
+        assert_eq!(200, status_of(&router, "/hello").unwrap());
+        assert_eq!(404, status_of(&router, "/missing").unwrap());
+        assert!(status_of(&router, "/broken").is_err());
+
+        let router = Router::builder()
+            .middleware(failing)
+            .get("/hello", |_: types::Request, _: &Parameters| "Hello")
+            .fallback(|_: types::Request, _: &Parameters| StatusCode::Gone)
+            .on_error(|_| StatusCode::InternalServerError.into_response())
+            .build();
+        assert_eq!(200, status_of(&router, "/hello").unwrap());
+        assert_eq!(410, status_of(&router, "/missing").unwrap());
+        assert_eq!(500, status_of(&router, "/broken").unwrap());
+    }
+
+    #[test]
+    fn render_registered_error_pages() {
+        use pollable::{Pollable, PollableResult};
+        use self::types::HttpMethod::{Get, Post};
+
+        let failing = |req: types::Request, next: Next| match req.path() {
+            "/broken" => RouteResponse::pending(PollableResult::<types::Response, _>::Err(
+                Some(io::Error::other("Broken")))),
+            _ => next.run(req),
+        };
+
+        let router = Router::builder()
+            .middleware(failing)
+            .get("/upload", |_: types::Request, _: &Parameters| StatusCode::PayloadTooLarge)
+            .get("/missing", |_: types::Request, _: &Parameters| 
+                 (StatusCode::NotFound, "No such user"))
+            .error_page(StatusCode::NotFound, |_| "Not here")
+            .error_page(StatusCode::MethodNotAllowed, |_| "Wrong method")
+            .error_page(StatusCode::PayloadTooLarge, |s: StatusCode| 
+                        format!("Error {}", s.as_u16()))
+            .error_page(StatusCode::InternalServerError, |_| 
+                        types::ResponseBuilder::new(StatusCode::Ok)
+                            .header("Content-Type", "text/html")
+                            .build_with_content("<h1>Oops</h1>"))
+            .build();
+
+        let send = |method, path| {
+            let mut response = Handler::handle(
+                &router, types::RequestBuilder::new(method, path).build());
+            match response.poll().unwrap() {
+                ::result::PollResult::Ready(response) => response,
+                ::result::PollResult::NotReady => panic!("Response not ready"),
+            }
+        };
+        let body_of = |response: types::Response| String::from_utf8(
+            response.into_parts().1.as_bytes().unwrap().to_vec()).unwrap();
+
+        let response = send(Get, "/nowhere");
+        assert_eq!(404, response.status_code());
+        assert_eq!("Not here", body_of(response));
+
+        let response = send(Post, "/upload");
+        assert_eq!(405, response.status_code());
+        assert_eq!(Some("GET, OPTIONS"), response.header_value("Allow"));
+        assert_eq!("Wrong method", body_of(response));
+
+        let response = send(Get, "/upload");
+        assert_eq!(413, response.status_code());
+        assert_eq!("Error 413", body_of(response));
+
+        let response = send(Get, "/broken");
+        assert_eq!(500, response.status_code());
+        assert_eq!(Some("text/html"), response.header_value("Content-Type"));
+        assert_eq!("<h1>Oops</h1>", body_of(response));
+
+        assert_eq!("No such user", body_of(send(Get, "/missing")));
+
+        // Responses the router didn't produce, e.g. a transport's
+        // refusals, get the same pages.
+        let refused = router.error_pages()(types::ResponseBuilder::new(StatusCode::PayloadTooLarge)
+            .header("Connection", "close")
+            .build());
+        assert_eq!(Some("close"), refused.header_value("Connection"));
+        assert_eq!("Error 413", body_of(refused));
+    }
+
+    #[test]
+    fn redirect_to_generated_paths() {
+        use self::types::HttpMethod::{Get, Post};
+
+        let router = Router::builder()
+            .redirect(Get, "/old/:id/*", "/new/:id/*", StatusCode::MovedPermanently)
+            .route(Route::redirect([Get, Post], "/users/:id", "/people/:id",
+                                   StatusCode::PermanentRedirect))
+            .build();
+
+        assert_eq!(Some((301, String::from("/new/42/a/b%20c"))),
+                   location_of(&router, Get, "/old/42/a/b%20c"));
+        assert_eq!(Some((301, String::from("/new/42"))),
+                   location_of(&router, Get, "/old/42"));
+        assert_eq!(Some((308, String::from("/people/Jo%20Bloggs?tab=repos"))),
+                   location_of(&router, Post, "/users/Jo%20Bloggs?tab=repos"));
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't capture every parameter")]
+    fn refuse_redirects_to_uncaptured_parameters() {
+        Route::redirect(types::HttpMethod::Get, "/old/*", "/new/:id/*",
+                        StatusCode::MovedPermanently);
+    }
+
+    #[test]
+    fn match_optional_segments_and_named_wildcards() {
+        let archive = Pattern::new("/archive/:year/:month?");
+        assert_eq!(vec![("year", "2018")],
+                   archive.match_uri("/archive/2018").unwrap().iter().collect::<Vec<_>>());
+        assert_eq!(vec![("year", "2018"), ("month", "06")],
+                   archive.match_uri("/archive/2018/06").unwrap().iter().collect::<Vec<_>>());
+        assert!(archive.match_uri("/archive").is_err());
+        assert!(archive.match_uri("/archive/2018/06/01").is_err());
+
+        let files = Pattern::new("/static/*filepath");
+        assert_eq!(vec![("filepath", "css/site.css")],
+                   files.match_uri("/static/css/site.css").unwrap().iter().collect::<Vec<_>>());
+        assert_eq!(vec![("filepath", "")],
+                   files.match_uri("/static").unwrap().iter().collect::<Vec<_>>());
+
+        assert!(Pattern::new("/users/:id").match_uri("/users").is_err());
+    }
+
+    #[test]
+    fn generate_optional_segments_and_named_wildcards() {
+        let archive = Pattern::new("/archive/:year/:month?");
+        assert_eq!(Ok(String::from("/archive/2018")),
+                   archive.generate(&[("year", "2018")]));
+        assert_eq!(Ok(String::from("/archive/2018/06")),
+                   archive.generate(&[("year", "2018"), ("month", "06")]));
+
+        let router = Router::builder()
+            .redirect(types::HttpMethod::Get, "/assets/*path", "/static/*path",
+                      StatusCode::MovedPermanently)
+            .build();
+        assert_eq!(Some((301, String::from("/static/css/site.css"))),
+                   location_of(&router, types::HttpMethod::Get, "/assets/css/site.css"));
+    }
+
+    #[test]
+    fn look_up_parameters() {
+        let pattern = Pattern::new("/users/:userId/posts/:postId");
+        let params = pattern.match_uri("/users/42/posts/Hello%20World").unwrap();
+
+        assert_eq!(2, params.len());
+        assert!(params.contains("userId"));
+        assert!(!params.contains("userid"));
+        assert_eq!(None, params.value("postid"));
+        assert_eq!(Some("Hello%20World"), params.value_ignore_case("postid"));
+        assert_eq!(Ok(String::from("Hello World")), params.get::<String>("postId"));
+
+        let mut names = vec![];
+        for (name, _) in &params {
+            names.push(name);
+        }
+        assert_eq!(vec!["userId", "postId"], names);
+    }
+
+    #[test]
+    fn answer_options_requests_automatically() {
+        use self::types::HttpMethod::{Get, Options, Post};
+
+        let allow_of = |router: &Router, path: &str| {
+            match router.route(types::RequestBuilder::new(Options, path).build()) {
+                HandleRouteResult::Handled(response) => {
+                    let response = ready(response);
+                    Some((response.status_code(),
+                          String::from(response.header_value("Allow").unwrap_or(""))))
+                },
+                HandleRouteResult::NotHandled(_) => None,
+            }
+        };
+
+        let router = Router::builder()
+            .get("/items", describe)
+            .post("/items", describe)
+            .options("/custom", |_: types::Request, _: &Parameters| "custom")
+            .get("/custom", describe)
+            .mount("/users", Router::builder().put("/:id", describe).build())
+            .build();
+
+        assert_eq!(Some((204, String::from("GET, POST, OPTIONS"))), allow_of(&router, "/items"));
+        assert_eq!(Some((204, String::from("PUT, OPTIONS"))), allow_of(&router, "/users/42"));
+        assert_eq!(None, allow_of(&router, "/missing"));
+        assert_eq!(Some(String::from("custom")), route_to_string(&router, Options, "/custom"));
+
+        let router = Router::builder()
+            .auto_options(false)
+            .get("/items", describe)
+            .build();
+        assert_eq!(Some((405, String::from("GET"))), allow_of(&router, "/items"));
+        assert!(route_to_string(&router, Post, "/items").is_some());
+        assert!(route_to_string(&router, Get, "/items").is_some());
     }
 }
@@ -0,0 +1,321 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use http::base64;
+use http::cookie::{Cookie, SameSite};
+use http::middleware::{Middleware, Next};
+use http::response::RouteResponse;
+use http::types::Request;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The default name of the session cookie.
+pub const DEFAULT_COOKIE_NAME: &str = "session";
+
+#[derive(Default)]
+struct SessionState {
+    values: BTreeMap<String, String>,
+    changed: bool,
+}
+
+/// The session of a request, as a map of string keys to string
+/// values.
+///
+/// [`Sessions`] inserts a `Session` into the extensions of each
+/// request. Handles to it are cheap to clone, and share the same
+/// values, so changes made while handling the request are saved into
+/// the session cookie of the response.
+///
+/// [`Sessions`]: struct.Sessions.html
+#[derive(Clone, Default)]
+pub struct Session(Arc<Mutex<SessionState>>);
+
+impl Session {
+    fn from_values(values: BTreeMap<String, String>) -> Session {
+        Session(Arc::new(Mutex::new(SessionState {
+            values,
+            changed: false,
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state().values.get(key).cloned()
+    }
+
+    /// Sets `key` to `value`, returning the previous value.
+    pub fn insert(&self, key: &str, value: &str) -> Option<String> {
+        let mut state = self.state();
+        state.changed = true;
+        state.values.insert(String::from(key), String::from(value))
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state();
+        let previous = state.values.remove(key);
+        state.changed |= previous.is_some();
+        previous
+    }
+
+    /// Removes every value. An empty session's cookie is removed from
+    /// the client.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.changed |= !state.values.is_empty();
+        state.values.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state().values.is_empty()
+    }
+}
+
+/// Middleware that keeps a [`Session`] in a cookie signed with
+/// HMAC-SHA256.
+///
+/// The session's values are readable by the client, but can't be
+/// changed without the secret, so they shouldn't hold anything
+/// confidential. Cookies that fail verification are ignored, giving
+/// the request an empty session. A `Set-Cookie` header is only added
+/// to a response if the session changed.
+///
+/// Requires the `sessions` feature.
+///
+/// [`Session`]: struct.Session.html
+#[derive(Clone)]
+pub struct Sessions {
+    secret: Vec<u8>,
+    cookie_name: String,
+    path: String,
+    max_age: Option<u64>,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Sessions {
+    /// Creates sessions signed with `secret`, which should be at least
+    /// 32 random bytes.
+    pub fn new(secret: &[u8]) -> Sessions {
+        Sessions {
+            secret: secret.to_vec(),
+            cookie_name: String::from(DEFAULT_COOKIE_NAME),
+            path: String::from("/"),
+            max_age: None,
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> Sessions {
+        self.cookie_name = String::from(name);
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Sessions {
+        self.path = String::from(path);
+        self
+    }
+
+    /// Sets how many seconds the session cookie lasts. By default, it
+    /// lasts until the client's session ends.
+    pub fn max_age(mut self, seconds: u64) -> Sessions {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Sets whether the cookie is only sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Sessions {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Sessions {
+        self.same_site = same_site;
+        self
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    /// Encodes `values` as `payload.signature`, where the payload is
+    /// `key=value` pairs joined by `&`, with keys and values base64
+    /// encoded.
+    fn encode(&self, values: &BTreeMap<String, String>) -> String {
+        let payload = values.iter()
+            .map(|(k, v)| format!("{}={}",
+                                  base64::encode(k.as_bytes(), true),
+                                  base64::encode(v.as_bytes(), true)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = base64::encode(&mac.finalize().into_bytes(), true);
+
+        format!("{}.{}", payload, signature)
+    }
+
+    fn decode(&self, cookie: &str) -> Option<BTreeMap<String, String>> {
+        let split = cookie.rfind('.')?;
+        let (payload, signature) = (&cookie[..split], &cookie[split + 1..]);
+
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&base64::decode(signature, true)?).ok()?;
+
+        let decode = |s: &str| base64::decode(s, true).and_then(|b| String::from_utf8(b).ok());
+        payload.split('&')
+            .filter(|p| !p.is_empty())
+            .map(|pair| {
+                let split = pair.find('=')?;
+                Some((decode(&pair[..split])?, decode(&pair[split + 1..])?))
+            })
+            .collect()
+    }
+
+    fn cookie(&self, value: &str) -> Cookie {
+        let cookie = Cookie::new(&self.cookie_name, value)
+            .path(&self.path)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site);
+
+        match (value.is_empty(), self.max_age) {
+            (true, _) => cookie.max_age(0),
+            (false, Some(max_age)) => cookie.max_age(max_age),
+            (false, None) => cookie,
+        }
+    }
+}
+
+impl Middleware for Sessions {
+    fn call(&self, mut request: Request, next: Next) -> RouteResponse {
+        let values = request.cookie(&self.cookie_name)
+            .and_then(|c| self.decode(&c))
+            .unwrap_or_default();
+
+        let session = Session::from_values(values);
+        request.extensions_mut().insert(session.clone());
+
+        // The session may still change while a deferred response is
+        // pending, so it's only saved once the response is ready.
+        let sessions = self.clone();
+        next.run(request).map(move |mut response| {
+            let state = session.state();
+            if state.changed {
+                let value = match state.values.is_empty() {
+                    true => String::new(),
+                    false => sessions.encode(&state.values),
+                };
+                response.set_cookie(&sessions.cookie(&value));
+            }
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod sessions_should {
+    use super::*;
+    use http::router::{HandleRouteResult, Parameters, Router};
+    use http::types::{self, HttpMethod, RequestBuilder};
+    use pollable::Pollable;
+    use result::PollResult;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn counter(request: Request, _: &Parameters) -> String {
+        let session = request.extensions().get::<Session>().unwrap();
+        let visits = session.get("visits")
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(0) + 1;
+        session.insert("visits", &visits.to_string());
+        visits.to_string()
+    }
+
+    fn router() -> Router {
+        Router::builder()
+            .middleware(Sessions::new(SECRET).max_age(60))
+            .get("/count", counter)
+            .get("/peek", |request: Request, _: &Parameters|
+                 request.extensions().get::<Session>().unwrap().get("visits"))
+            .get("/logout", |request: Request, _: &Parameters|
+                 request.extensions().get::<Session>().unwrap().clear())
+            .build()
+    }
+
+    fn send(router: &Router, path: &str, cookie: Option<&str>) -> types::Response {
+        let mut builder = RequestBuilder::new(HttpMethod::Get, path);
+        if let Some(cookie) = cookie {
+            builder = builder.header("Cookie", &format!("session={}", cookie));
+        }
+
+        match router.route(builder.build()) {
+            HandleRouteResult::Handled(mut response) => match response.poll().unwrap() {
+                PollResult::Ready(response) => response,
+                PollResult::NotReady => panic!("Response not ready"),
+            },
+            HandleRouteResult::NotHandled(_) => panic!("Route not handled"),
+        }
+    }
+
+    fn session_cookie(response: &types::Response) -> Option<String> {
+        let set_cookie = response.header_value("Set-Cookie")?;
+        let value = set_cookie.split(';').next().unwrap();
+        Some(String::from(&value["session=".len()..]))
+    }
+
+    fn body_of(response: types::Response) -> Vec<u8> {
+        response.into_parts().1.as_bytes().unwrap().to_vec()
+    }
+
+    #[test]
+    fn keep_values_between_requests() {
+        let router = router();
+
+        let first = send(&router, "/count", None);
+        assert!(first.header_value("Set-Cookie").unwrap()
+                .ends_with("; Path=/; Max-Age=60; HttpOnly; SameSite=Lax"));
+        let cookie = session_cookie(&first).unwrap();
+        assert_eq!(b"1".to_vec(), body_of(first));
+
+        let second = send(&router, "/count", Some(&cookie));
+        let cookie = session_cookie(&second).unwrap();
+        assert_eq!(b"2".to_vec(), body_of(second));
+
+        let peek = send(&router, "/peek", Some(&cookie));
+        assert_eq!(None, peek.header_value("Set-Cookie"));
+        assert_eq!(b"2".to_vec(), body_of(peek));
+    }
+
+    #[test]
+    fn ignore_tampered_cookies() {
+        let router = router();
+        let cookie = session_cookie(&send(&router, "/count", None)).unwrap();
+
+        let forged = Sessions::new(b"another secret").encode(
+            &vec![(String::from("visits"), String::from("99"))].into_iter().collect());
+        let tampered = cookie.replacen('M', "N", 1);
+
+        for cookie in &[forged, tampered, String::from("garbage")] {
+            assert_eq!(b"1".to_vec(), body_of(send(&router, "/count", Some(cookie))));
+        }
+    }
+
+    #[test]
+    fn remove_the_cookie_of_a_cleared_session() {
+        let router = router();
+        let cookie = session_cookie(&send(&router, "/count", None)).unwrap();
+
+        let response = send(&router, "/logout", Some(&cookie));
+        assert!(response.header_value("Set-Cookie").unwrap()
+                .starts_with("session=; Path=/; Max-Age=0;"));
+    }
+}
@@ -0,0 +1,142 @@
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use handler::Handler;
+use http::response::RouteResponse;
+use http::router::Router;
+use http::types::{Request, Response};
+
+/// A [`Router`] whose routes can be replaced while it's serving
+/// requests, through the [`RouterHandle`] returned with it.
+///
+/// Each request is routed by whichever router is current when it
+/// arrives. Swapping the router doesn't affect responses that are
+/// already in flight, and connections aren't dropped. E.g.
+///
+/// ```rust,no_run
+/// # use server_fx::http::router::Router;
+/// # use server_fx::http::swap::SwapRouter;
+/// let (router, handle) = SwapRouter::new(Router::builder().build());
+///
+/// // Serve `router`, and later, when the config changes...
+/// handle.swap(Router::builder().build());
+/// ```
+///
+/// [`Router`]: ../router/struct.Router.html
+/// [`RouterHandle`]: struct.RouterHandle.html
+pub struct SwapRouter {
+    current: Arc<RwLock<Arc<Router>>>,
+}
+
+impl SwapRouter {
+    pub fn new(router: Router) -> (SwapRouter, RouterHandle) {
+        let current = Arc::new(RwLock::new(Arc::new(router)));
+        let handle = RouterHandle {
+            current: current.clone(),
+        };
+
+        (SwapRouter { current }, handle)
+    }
+
+    /// The router requests are currently routed by.
+    pub fn current(&self) -> Arc<Router> {
+        current(&self.current)
+    }
+}
+
+impl Handler for SwapRouter {
+    type Request = Request;
+    type Response = Response;
+    type Error = io::Error;
+    type Pollable = RouteResponse;
+
+    fn handle(&self, request: Request) -> RouteResponse {
+        self.current().handle(request)
+    }
+
+    fn timed_out(&self) -> Option<Response> {
+        self.current().timed_out()
+    }
+}
+
+/// Replaces the router of a [`SwapRouter`]. Handles are cheap to
+/// clone, and can be sent to other threads.
+///
+/// [`SwapRouter`]: struct.SwapRouter.html
+#[derive(Clone)]
+pub struct RouterHandle {
+    current: Arc<RwLock<Arc<Router>>>,
+}
+
+impl RouterHandle {
+    /// Routes requests arriving from now on by `router`, returning
+    /// the router it replaces.
+    pub fn swap(&self, router: Router) -> Arc<Router> {
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        ::std::mem::replace(&mut *current, Arc::new(router))
+    }
+
+    /// The router requests are currently routed by.
+    pub fn current(&self) -> Arc<Router> {
+        current(&self.current)
+    }
+}
+
+fn current(lock: &RwLock<Arc<Router>>) -> Arc<Router> {
+    lock.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod swap_router_should {
+    use super::*;
+    use http::response::Deferred;
+    use http::router::Parameters;
+    use http::types::{HttpMethod, RequestBuilder, StatusCode};
+    use pollable::Pollable;
+    use result::PollResult;
+
+    /// Becomes ready the second time it's polled.
+    struct Slow(bool);
+
+    impl Pollable for Slow {
+        type Item = &'static str;
+        type Error = StatusCode;
+
+        fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+            match ::std::mem::replace(&mut self.0, true) {
+                true => Ok(PollResult::Ready("old")),
+                false => Ok(PollResult::NotReady),
+            }
+        }
+    }
+
+    fn body_of(mut response: RouteResponse) -> Option<String> {
+        match response.poll().unwrap() {
+            PollResult::Ready(response) => Some(String::from_utf8(
+                response.into_parts().1.as_bytes().unwrap().to_vec()).unwrap()),
+            PollResult::NotReady => None,
+        }
+    }
+
+    fn get(router: &SwapRouter, path: &str) -> RouteResponse {
+        router.handle(RequestBuilder::new(HttpMethod::Get, path).build())
+    }
+
+    #[test]
+    fn route_by_the_current_router() {
+        let (router, handle) = SwapRouter::new(Router::builder()
+            .get("/page", |_: Request, _: &Parameters| Deferred(Slow(false)))
+            .build());
+
+        let mut in_flight = get(&router, "/page");
+        assert!(matches!(in_flight.poll().unwrap(), PollResult::NotReady));
+
+        let previous = handle.clone().swap(Router::builder()
+            .get("/page", |_: Request, _: &Parameters| "new")
+            .build());
+        assert!(!Arc::ptr_eq(&previous, &router.current()));
+
+        assert_eq!(Some(String::from("new")), body_of(get(&router, "/page")));
+        assert_eq!(Some(String::from("old")), body_of(in_flight));
+    }
+}
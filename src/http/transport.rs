@@ -0,0 +1,846 @@
+use std::io;
+use std::net;
+
+use connected::{ConnectionState, Shutdown};
+use http::body::Body;
+use http::codec::Refused;
+use http::extensions::Extensions;
+use http::upgrade::OnUpgrade;
+use http::types::{BodyChunk, HttpMethod, HttpVersion, Request, Response, ResponseBuilder, ResponseHead,
+                  StatusCode};
+use pollable::Pollable;
+use readiness;
+use result::PollResult;
+use sink::{Sink, SinkResult};
+use upgrade::{IntoUpgraded, Upgrading};
+
+/// The units a response is broken into when it is written to a
+/// framed transport by [`HttpTransport`].
+///
+/// [`HttpTransport`]: struct.HttpTransport.html
+pub enum Frame {
+    /// The status line and headers of a response, along with the
+    /// length of its body. A length of `None` means the body will
+    /// follow as a series of `Frame::Chunk`s or, for HTTP/1.0
+    /// responses, as `Frame::Data` delimited by closing the
+    /// connection.
+    Head(ResponseHead, Option<usize>),
+    /// Body data for a response whose length is known, or for a
+    /// close-delimited HTTP/1.0 response.
+    Data(BodyChunk),
+    /// Body data for a response whose length isn't known.
+    Chunk(BodyChunk),
+    /// Terminates a chunked body.
+    LastChunk,
+}
+
+enum WriteState {
+    Idle,
+    Writing {
+        pending: Option<Frame>,
+        body: Option<Body>,
+        chunked: bool,
+    },
+}
+
+/// Returns `true` if the comma separated `Connection` header value,
+/// `value`, contains `option`.
+pub(crate) fn has_connection_option(value: &str, option: &str) -> bool {
+    value.split(',')
+        .any(|o| o.trim().eq_ignore_ascii_case(option))
+}
+
+/// Notes that the connection's socket is blocked, if writing to it is
+/// `NotReady`.
+fn blocked<E>(written: Result<PollResult<()>, E>) -> Result<PollResult<()>, E> {
+    if let Ok(PollResult::NotReady) = written {
+        readiness::socket_blocked();
+    }
+    written
+}
+
+/// Decides whether the connection should persist after responding to
+/// `request`, according to RFC 7230, section 6.3.
+fn request_keeps_alive(request: &Request) -> bool {
+    let connection = request.header_value("Connection").unwrap_or("");
+
+    if has_connection_option(connection, "close") {
+        return false;
+    }
+
+    match request.version() {
+        HttpVersion::Http11 => true,
+        HttpVersion::Http1 => has_connection_option(connection, "keep-alive"),
+    }
+}
+
+/// Adds a value to the extensions of a request.
+type InsertExtension = Box<dyn Fn(&mut Extensions)>;
+
+/// Changes the response to a request the transport refused.
+type OnRefusal = Box<dyn Fn(Response) -> Response>;
+
+/// Adapts a transport of [`Frame`]s into one that accepts whole
+/// responses.
+///
+/// The body of each response is polled chunk-by-chunk as the
+/// transport is flushed, so responses can be written without first
+/// buffering the entire body in memory.
+///
+/// `HttpTransport` also implements HTTP's connection persistence
+/// rules. Once a response that closes the connection has been
+/// written, the transport yields `None` rather than reading another
+/// request. The same happens after the last of `max_requests`, and
+/// after the current request once the transport is drained. A
+/// response with an [`OnUpgrade`] extension hands the connection over
+/// to another protocol once it's written.
+///
+/// Only the head of the response to a `HEAD` request is written, with
+/// the `Content-Length` a `GET` would have had, so one handler can
+/// answer both.
+///
+/// [`Frame`]: enum.Frame.html
+/// [`OnUpgrade`]: ../upgrade/struct.OnUpgrade.html
+pub struct HttpTransport<T> {
+    inner: T,
+    state: WriteState,
+    keep_alive: bool,
+    closing: bool,
+    version: HttpVersion,
+    /// The method of the request being answered.
+    method: HttpMethod,
+    extensions: Vec<InsertExtension>,
+    on_refusal: Option<OnRefusal>,
+    connection: Option<ConnectionState>,
+    upgrade: Option<OnUpgrade>,
+    /// The requests read so far.
+    requests: usize,
+    max_requests: Option<usize>,
+    draining: bool,
+}
+
+impl<T> HttpTransport<T> {
+    pub fn new(inner: T) -> HttpTransport<T> {
+        HttpTransport {
+            inner,
+            state: WriteState::Idle,
+            keep_alive: true,
+            closing: false,
+            version: HttpVersion::Http11,
+            method: HttpMethod::Get,
+            extensions: vec![],
+            on_refusal: None,
+            connection: None,
+            upgrade: None,
+            requests: 0,
+            max_requests: None,
+            draining: false,
+        }
+    }
+
+    /// Inserts a clone of `value` into the extensions of each request
+    /// read from the transport. This passes details of the connection,
+    /// like the certificates of a TLS peer, on to handlers.
+    pub fn extension<E: Clone + 'static>(mut self, value: E) -> HttpTransport<T> {
+        self.extensions.push(Box::new(move |extensions| {
+            extensions.insert(value.clone());
+        }));
+        self
+    }
+
+    /// Passes each response the transport answers a request it refused
+    /// with, e.g. a `400 Bad Request`, through `f` before writing it.
+    /// E.g. to give it the error page a router has for its status, with
+    /// [`Router::error_pages`]. The connection is still closed once
+    /// it's written.
+    ///
+    /// [`Router::error_pages`]: ../router/struct.Router.html#method.error_pages
+    pub fn on_refusal<F>(mut self, f: F) -> HttpTransport<T> where
+        F: Fn(Response) -> Response + 'static
+    {
+        self.on_refusal = Some(Box::new(f));
+        self
+    }
+
+    /// Shares `state` with each request read from the transport, as an
+    /// extension, counting the requests as they arrive.
+    pub fn connection_state(mut self, state: ConnectionState) -> HttpTransport<T> {
+        self.connection = Some(state);
+        self
+    }
+
+    /// Closes the connection once it's served `max` requests, with
+    /// `Connection: close` on the last response. This bounds what a
+    /// long-lived connection accumulates, and lets load balancers move
+    /// its client elsewhere. A server's `max_requests_per_connection`
+    /// is passed on with this.
+    pub fn max_requests(mut self, max: usize) -> HttpTransport<T> {
+        self.max_requests = Some(max);
+        self
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// A request that can't be parsed is answered with a `400 Bad Request`
+/// by the transport itself, which then closes the connection. One that
+/// was `Refused`, e.g. as it was too large, is answered with the
+/// refusal's status instead. See `on_refusal`.
+impl<T> Pollable for HttpTransport<T> where
+    T: Pollable<Item=Option<Request>, Error=io::Error> + Sink<Item=Frame, Error=io::Error>,
+    T: IntoUpgraded,
+{
+    type Item = Option<Request>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        if self.closing {
+            return match self.state {
+                WriteState::Idle => Ok(PollResult::Ready(None)),
+                // Only the transport's own responses are still being
+                // written when it's polled.
+                WriteState::Writing { .. } => match self.poll_complete()? {
+                    PollResult::Ready(()) => Ok(PollResult::Ready(None)),
+                    PollResult::NotReady => Ok(PollResult::NotReady),
+                },
+            };
+        }
+
+        let mut request = match self.inner.poll() {
+            Ok(PollResult::Ready(Some(request))) => request,
+            Ok(other) => return Ok(other),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                let status = e.get_ref()
+                    .and_then(|e| e.downcast_ref::<Refused>())
+                    .map(Refused::status)
+                    .unwrap_or(StatusCode::BadRequest);
+                let response = ResponseBuilder::new(status)
+                    .header("Connection", "close")
+                    .build();
+                let response = match self.on_refusal {
+                    Some(ref on_refusal) => on_refusal(response),
+                    None => response,
+                };
+                self.keep_alive = false;
+                self.start_send(response)?;
+                return self.poll();
+            },
+            Err(e) => return Err(e),
+        };
+
+        for insert in &self.extensions {
+            insert(request.extensions_mut());
+        }
+        if let Some(ref connection) = self.connection {
+            connection.count_request();
+            request.extensions_mut().insert(connection.clone());
+        }
+
+        self.requests += 1;
+        self.keep_alive = request_keeps_alive(&request) && !self.draining &&
+            self.max_requests.map(|max| self.requests < max).unwrap_or(true);
+        self.version = request.version();
+        self.method = request.method();
+        Ok(PollResult::Ready(Some(request)))
+    }
+
+    fn head_received(&self) -> bool {
+        self.inner.head_received()
+    }
+}
+
+impl<T> Sink for HttpTransport<T> where
+    T: Sink<Item=Frame, Error=io::Error> + IntoUpgraded
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn start_send(&mut self, item: Self::Item)
+        -> Result<SinkResult<Self::Item>, Self::Error>
+    {
+        if let WriteState::Writing { .. } = self.state {
+            return Ok(SinkResult::NotReady(item));
+        }
+
+        let mut item = item;
+        self.upgrade = item.extensions_mut().remove::<OnUpgrade>();
+
+        let (mut head, body) = item.into_parts();
+        let mut length = body.content_length();
+
+        // A `304 Not Modified` never has a body, whatever the handler
+        // gave it, and neither does the response to a `HEAD` request.
+        // The latter keeps any `Content-Length` it was given, e.g. by
+        // the server a proxy forwarded it to...
+        let bodiless = head.status() == StatusCode::NotModified ||
+            self.method == HttpMethod::Head;
+        if self.method == HttpMethod::Head {
+            if let Some(n) = head.header_value("Content-Length").and_then(|v| v.trim().parse().ok()) {
+                length = Some(n);
+            }
+        }
+
+        // Respond in the version of the request. HTTP/1.0 peers don't
+        // understand chunked encoding, so a body of unknown length is
+        // delimited by closing the connection instead...
+        head.set_version(self.version);
+        let close_delimited =
+            length.is_none() && self.version == HttpVersion::Http1 && !bodiless;
+
+        let response_closes = head.header_value("Connection")
+            .map(|v| has_connection_option(v, "close"))
+            .unwrap_or(false);
+
+        // The connection is handed over after the response to an
+        // upgrade, so it keeps its `Connection: Upgrade` header...
+        if self.upgrade.is_none() {
+            if !self.keep_alive || response_closes || close_delimited {
+                self.closing = true;
+                head.set_header("Connection", "close");
+            }
+            else if head.version() == HttpVersion::Http1 {
+                head.set_header("Connection", "keep-alive");
+            }
+        }
+
+        self.state = WriteState::Writing {
+            pending: Some(Frame::Head(head, length)),
+            body: match bodiless {
+                true => None,
+                false => Some(body),
+            },
+            chunked: length.is_none() && !close_delimited && !bodiless,
+        };
+
+        Ok(SinkResult::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Result<PollResult<()>, Self::Error> {
+        loop {
+            let (pending, body, chunked) = match self.state {
+                WriteState::Idle => return blocked(self.inner.poll_complete()),
+                WriteState::Writing {
+                    ref mut pending,
+                    ref mut body,
+                    chunked
+                } => (pending, body, chunked),
+            };
+
+            if let Some(frame) = pending.take() {
+                if let SinkResult::NotReady(frame) =
+                    self.inner.start_send(frame)?
+                {
+                    *pending = Some(frame);
+                    if let PollResult::NotReady = self.inner.poll_complete()? {
+                        readiness::socket_blocked();
+                        return Ok(PollResult::NotReady);
+                    }
+                }
+                continue;
+            }
+
+            let next = match *body {
+                Some(ref mut b) => match b.poll()? {
+                    PollResult::NotReady => {
+                        self.inner.poll_complete()?;
+                        return Ok(PollResult::NotReady);
+                    },
+                    // A zero-length chunk would terminate a chunked
+                    // body early, so skip it...
+                    PollResult::Ready(Some(ref chunk)) if chunk.is_empty() =>
+                        continue,
+                    PollResult::Ready(Some(chunk)) => match chunked {
+                        true => Some(Frame::Chunk(chunk)),
+                        false => Some(Frame::Data(chunk)),
+                    },
+                    PollResult::Ready(None) => match chunked {
+                        true => Some(Frame::LastChunk),
+                        false => None,
+                    },
+                },
+                None => None,
+            };
+
+            match next {
+                Some(Frame::LastChunk) => {
+                    *body = None;
+                    *pending = Some(Frame::LastChunk);
+                },
+                Some(frame) => *pending = Some(frame),
+                None if body.is_some() => *body = None,
+                None => self.state = WriteState::Idle,
+            }
+        }
+    }
+
+    fn upgrade(mut self) -> Result<Upgrading<io::Error>, Self> {
+        match self.upgrade.take() {
+            Some(upgrade) => Ok(upgrade.upgrade(self.inner.into_upgraded())),
+            None => Err(self),
+        }
+    }
+
+    /// The next response, which may be the one being waited on, has
+    /// `Connection: close`.
+    fn drain(&mut self) {
+        self.draining = true;
+        self.keep_alive = false;
+    }
+}
+
+/// The write side can't be closed while a response is being written,
+/// so this fails with `WouldBlock` until `poll_complete` is ready.
+impl<T> Shutdown for HttpTransport<T> where
+    T: Shutdown
+{
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+        if let (WriteState::Writing { .. }, false) = (&self.state, how == net::Shutdown::Read) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.inner.shutdown(how)
+    }
+}
+
+#[cfg(test)]
+mod http_transport_should {
+    use super::*;
+    use http::types::{parse_request, ResponseBuilder, StatusCode};
+
+    #[derive(Default)]
+    struct MockTransport {
+        requests: Vec<Request>,
+        written: Vec<String>,
+        malformed: bool,
+    }
+
+    impl MockTransport {
+        fn with_requests(raw: &[u8]) -> MockTransport {
+            let mut buffer = raw.to_vec();
+            let mut requests = vec![];
+            while let Some(r) = parse_request(&mut buffer) {
+                requests.push(r);
+            }
+
+            MockTransport {
+                requests,
+                written: vec![],
+                malformed: !buffer.is_empty(),
+            }
+        }
+    }
+
+    impl Pollable for MockTransport {
+        type Item = Option<Request>;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+            match self.requests.len() {
+                0 if self.malformed => Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                          "Malformed request")),
+                0 => Ok(PollResult::NotReady),
+                _ => Ok(PollResult::Ready(Some(self.requests.remove(0)))),
+            }
+        }
+    }
+
+    impl Sink for MockTransport {
+        type Item = Frame;
+        type Error = io::Error;
+
+        fn start_send(&mut self, item: Self::Item)
+            -> Result<SinkResult<Self::Item>, Self::Error>
+        {
+            let s = match item {
+                Frame::Head(r, len) => {
+                    let mut s = format!("{} {:?}", r.status_code(), len);
+                    if let Some(c) = r.header_value("Connection") {
+                        s.push_str(&format!(" {}", c));
+                    }
+                    s
+                },
+                Frame::Data(d) => format!("data {}", String::from_utf8(d).unwrap()),
+                Frame::Chunk(d) => format!("chunk {}", String::from_utf8(d).unwrap()),
+                Frame::LastChunk => String::from("last"),
+            };
+            self.written.push(s);
+            Ok(SinkResult::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Result<PollResult<()>, Self::Error> {
+            Ok(PollResult::Ready(()))
+        }
+    }
+
+    impl IntoUpgraded for MockTransport {
+        fn into_upgraded(self) -> ::upgrade::Upgraded {
+            ::upgrade::Upgraded::new(::std::io::Cursor::new(vec![]), vec![])
+        }
+    }
+
+    struct Countdown(usize);
+
+    impl Pollable for Countdown {
+        type Item = Option<BodyChunk>;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+            match self.0 {
+                0 => Ok(PollResult::Ready(None)),
+                n => {
+                    self.0 -= 1;
+                    Ok(PollResult::Ready(Some(format!("{}", n).into_bytes())))
+                }
+            }
+        }
+    }
+
+    fn respond(transport: &mut HttpTransport<MockTransport>, response: Response) {
+        assert!(transport.start_send(response).is_ok());
+        assert_eq!(PollResult::Ready(()), transport.poll_complete().unwrap());
+    }
+
+    #[test]
+    fn write_a_sized_body() {
+        let mut transport = HttpTransport::new(MockTransport::default());
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok)
+            .build_with_content("Hello"));
+
+        assert_eq!(
+            vec!["200 Some(5)", "data Hello"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn write_a_streamed_body_in_chunks() {
+        let mut transport = HttpTransport::new(MockTransport::default());
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok)
+            .build_with_pollable(Countdown(3)));
+
+        assert_eq!(
+            vec!["200 None", "chunk 3", "chunk 2", "chunk 1", "last"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn not_write_the_body_of_a_not_modified_response() {
+        let mut transport = HttpTransport::new(MockTransport::default());
+        respond(&mut transport, ResponseBuilder::new(StatusCode::NotModified)
+            .build_with_content("Hello"));
+        respond(&mut transport, ResponseBuilder::new(StatusCode::NotModified)
+            .build_with_pollable(Countdown(2)));
+
+        assert_eq!(
+            vec!["304 Some(5)", "304 None"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn only_write_the_head_of_the_response_to_a_head_request() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"HEAD /a HTTP/1.1\r\n\r\nHEAD /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n\r\n"));
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(Some(_))));
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok)
+            .build_with_content("Hello"));
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(Some(_))));
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok)
+            .header("Content-Length", "1024")
+            .build());
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(Some(_))));
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok)
+            .build_with_content("Hello"));
+
+        assert_eq!(
+            vec!["200 Some(5)", "200 Some(1024)", "200 Some(5)", "data Hello"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn keep_http11_connections_alive_by_default() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n"));
+
+        for _ in 0..2 {
+            match transport.poll().unwrap() {
+                PollResult::Ready(Some(_)) => {},
+                _ => panic!("Expected a request"),
+            }
+            respond(&mut transport, ResponseBuilder::new(StatusCode::Ok).build());
+        }
+
+        assert_eq!(
+            vec!["200 Some(0)", "200 Some(0)"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn close_when_the_request_asks_to() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.1\r\nConnection: close\r\n\r\n\
+              GET /b HTTP/1.1\r\n\r\n"));
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(Some(_))));
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok).build());
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(None)));
+        assert_eq!(
+            vec!["200 Some(0) close"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn close_after_the_last_request_allowed() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n\r\n"))
+            .max_requests(2);
+
+        for _ in 0..2 {
+            assert!(matches!(transport.poll().unwrap(), PollResult::Ready(Some(_))));
+            respond(&mut transport, ResponseBuilder::new(StatusCode::Ok).build());
+        }
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(None)));
+        assert_eq!(
+            vec!["200 Some(0)", "200 Some(0) close"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn close_after_the_request_being_handled_once_drained() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n"));
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(Some(_))));
+        transport.drain();
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok).build());
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(None)));
+        assert_eq!(
+            vec!["200 Some(0) close"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn close_connections_when_the_server_drains() {
+        use std::io::{Read, Write};
+        use std::time::Duration;
+        use bind_transport::BindTransport;
+        use framed::Framed;
+        use handler::Handler;
+        use http::codec::HttpCodec;
+        use server::TcpServer;
+
+        struct Proto;
+
+        impl BindTransport<net::TcpStream> for Proto {
+            type Request = Request;
+            type Response = Response;
+            type Transport = HttpTransport<Framed<net::TcpStream, HttpCodec>>;
+            type Result = io::Result<Self::Transport>;
+
+            fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+                Ok(HttpTransport::new(Framed::new(s, HttpCodec::new())))
+            }
+        }
+
+        struct Hello;
+
+        impl Handler for Hello {
+            type Request = Request;
+            type Response = Response;
+            type Error = io::Error;
+            type Pollable = io::Result<Response>;
+
+            fn handle(&self, _: Request) -> Self::Pollable {
+                Ok(ResponseBuilder::new(StatusCode::Ok).build_with_content("Hello"))
+            }
+        }
+
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        ::std::thread::spawn(move || server.run(|| Hello));
+
+        let connect = || {
+            let client = net::TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client
+        };
+        let get = |client: &mut net::TcpStream| {
+            client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = vec![];
+            while !response.ends_with(b"Hello") {
+                let mut byte = [0; 1];
+                client.read_exact(&mut byte).unwrap();
+                response.push(byte[0]);
+            }
+            String::from_utf8(response).unwrap().contains("\r\nConnection: close\r\n")
+        };
+
+        let mut client = connect();
+        assert!(!get(&mut client));
+
+        // Connections waiting for a request are closed straight away,
+        // and the rest after their next response.
+        handle.drain();
+        assert_eq!(0, client.read(&mut [0; 1]).unwrap());
+        let mut client = connect();
+        assert!(get(&mut client));
+        assert_eq!(0, client.read(&mut [0; 1]).unwrap());
+        handle.stop();
+    }
+
+    #[test]
+    fn share_the_connection_state_with_each_request() {
+        let info = ::connected::ConnectionInfo {
+            peer_addr: "127.0.0.1:4000".parse().unwrap(),
+            local_addr: "127.0.0.1:80".parse().unwrap(),
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls: None,
+        };
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n"))
+            .connection_state(ConnectionState::new(info));
+
+        for expected in 1..3 {
+            let request = match transport.poll().unwrap() {
+                PollResult::Ready(Some(request)) => request,
+                _ => panic!("Expected a request"),
+            };
+            let state = request.extensions().get::<ConnectionState>().unwrap();
+            assert_eq!(expected, state.requests());
+            state.extensions_mut().insert(expected);
+            respond(&mut transport, ResponseBuilder::new(StatusCode::Ok).build());
+        }
+
+        let state = transport.connection.unwrap();
+        assert_eq!(Some(&2), state.extensions().get::<u64>());
+    }
+
+    #[test]
+    fn respond_to_requests_that_are_too_large_with_413() {
+        use std::io::{Read, Write};
+        use framed::Framed;
+        use http::codec::HttpCodec;
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let mut transport = HttpTransport::new(
+            Framed::new(server, HttpCodec::new().max_body_size(4)));
+
+        client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n").unwrap();
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(None)));
+        drop(transport);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+    }
+
+    #[test]
+    fn respond_to_malformed_requests_with_400_and_close() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.1\r\n\r\nNONSENSE\r\n\r\n"));
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(Some(_))));
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok).build());
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(None)));
+        assert_eq!(
+            vec!["200 Some(0)", "400 Some(0) close"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn pass_refusals_through_the_hook() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(b"NONSENSE\r\n\r\n"))
+            .on_refusal(|response| {
+                let (mut head, _) = response.into_parts();
+                head.remove_header("Connection");
+                Response::from_parts(head, "Bad".into())
+            });
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(None)));
+        assert_eq!(
+            vec!["400 Some(3) close", "data Bad"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn close_when_the_response_asks_to() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.1\r\n\r\n"));
+
+        assert!(transport.poll().is_ok());
+        respond(&mut transport, ResponseBuilder::new(StatusCode::NotFound)
+            .header("Connection", "Close")
+            .build());
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(None)));
+    }
+
+    #[test]
+    fn keep_http10_connections_alive_when_asked() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"));
+
+        assert!(transport.poll().is_ok());
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok).build());
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::NotReady));
+        assert_eq!(
+            vec!["200 Some(0) keep-alive"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn close_delimit_unsized_http10_bodies() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"));
+
+        assert!(transport.poll().is_ok());
+        respond(&mut transport, ResponseBuilder::new(StatusCode::Ok)
+            .build_with_pollable(Countdown(2)));
+
+        assert!(matches!(transport.poll().unwrap(), PollResult::Ready(None)));
+        assert_eq!(
+            vec!["200 None close", "data 2", "data 1"],
+            transport.into_inner().written
+        );
+    }
+
+    #[test]
+    fn attach_extensions_to_each_request() {
+        let mut transport = HttpTransport::new(MockTransport::with_requests(
+            b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n"))
+            .extension(String::from("peer"));
+
+        for _ in 0..2 {
+            match transport.poll().unwrap() {
+                PollResult::Ready(Some(request)) => assert_eq!(
+                    Some(&String::from("peer")),
+                    request.extensions().get::<String>()
+                ),
+                _ => panic!("Expected a request"),
+            }
+            respond(&mut transport, ResponseBuilder::new(StatusCode::Ok).build());
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Tunnelling connections through `CONNECT` requests.
+//!
+//! A client asks a proxy for a tunnel with a request such as
+//! `CONNECT docs.rs:443 HTTP/1.1`. [`Tunnel`] handles it by connecting
+//! to `docs.rs:443`, answering `200 OK` once it has, and then relaying
+//! bytes between the client and the server with a `Twister` for the
+//! rest of the connection. [`TunnelProto`] binds the streams a server
+//! accepts to transports that can be handed over to a tunnel. E.g.
+//!
+//! ```rust,no_run
+//! # use server_fx::http::tunnel::{Tunnel, TunnelProto};
+//! # use server_fx::server::TcpServer;
+//! let server = TcpServer::builder(TunnelProto).build()
+//!     .bind("127.0.0.1:8080")
+//!     .unwrap();
+//! server.run(|| Tunnel::new().allow(|_, port| port == 443)).unwrap();
+//! ```
+//!
+//! [`Tunnel`]: struct.Tunnel.html
+//! [`TunnelProto`]: struct.TunnelProto.html
+
+use std::cmp;
+use std::io::{self, Read};
+use std::mem;
+use std::net;
+
+use bind_transport::BindTransport;
+use client::{Dial, Dialer};
+use connected::Connected;
+use framed::Framed;
+use handler::Handler;
+use http::codec::HttpCodec;
+use http::proxy::gateway_error;
+use http::transport::HttpTransport;
+use http::types::{HttpMethod, Request, Response, ResponseBuilder, StatusCode};
+use http::upgrade::OnUpgrade;
+use pollable::Pollable;
+use readiness::Watch;
+use result::PollResult;
+use server::ServerConfig;
+use split::{self, ReadHalf, Split, WriteHalf};
+use twist::{Twister, TwisterBuilder};
+use upgrade::Upgraded;
+
+/// Binds TCP streams to HTTP transports whose connections can be
+/// handed over to a tunnel. Serve them with a [`Tunnel`].
+///
+/// [`Tunnel`]: struct.Tunnel.html
+pub struct TunnelProto;
+
+impl BindTransport<net::TcpStream> for TunnelProto {
+    type Request = Request;
+    type Response = Response;
+    type Transport = HttpTransport<Framed<net::TcpStream, HttpCodec>>;
+    type Result = io::Result<Self::Transport>;
+
+    fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+        self.bind_transport_with(s, &ServerConfig::default())
+    }
+
+    fn bind_transport_with(&self, s: net::TcpStream, config: &ServerConfig) -> Self::Result {
+        let info = s.connection_info()?;
+        let framed = Framed::with_capacity(s,
+                                           HttpCodec::new(),
+                                           config.read_buffer_size,
+                                           config.write_buffer_size);
+        Ok(HttpTransport::new(framed).extension(info))
+    }
+}
+
+/// Decides whether a tunnel can be opened to a host and port.
+type Allow = Box<dyn Fn(&str, u16) -> bool + Send + Sync>;
+
+/// Opens a tunnel for each `CONNECT` request, to the host and port it
+/// names. Other requests are answered with `405 Method Not Allowed`.
+///
+/// By default, a tunnel can be opened to anywhere the server can
+/// reach, so a public proxy should restrict where, with `allow`.
+pub struct Tunnel {
+    dialer: Dialer,
+    twister: TwisterBuilder,
+    allow: Allow,
+}
+
+impl Default for Tunnel {
+    fn default() -> Tunnel {
+        Tunnel {
+            dialer: Dialer::new(),
+            twister: TwisterBuilder::new(),
+            allow: Box::new(|_, _| true),
+        }
+    }
+}
+
+impl Tunnel {
+    pub fn new() -> Tunnel {
+        Tunnel::default()
+    }
+
+    /// Sets how the servers that tunnels lead to are connected to.
+    pub fn dialer(mut self, dialer: Dialer) -> Tunnel {
+        self.dialer = dialer;
+        self
+    }
+
+    /// Sets how bytes are relayed through each tunnel, e.g. to limit
+    /// how many, or how fast.
+    pub fn twister(mut self, twister: TwisterBuilder) -> Tunnel {
+        self.twister = twister;
+        self
+    }
+
+    /// Only opens tunnels to the hosts and ports `f` allows. Others
+    /// are answered with `403 Forbidden`.
+    pub fn allow<F>(mut self, f: F) -> Tunnel where
+        F: Fn(&str, u16) -> bool + Send + Sync + 'static
+    {
+        self.allow = Box::new(f);
+        self
+    }
+}
+
+fn answer(response: Response) -> Connecting {
+    Connecting {
+        state: ConnectingState::Answered(response),
+    }
+}
+
+impl Handler for Tunnel {
+    type Request = Request;
+    type Response = Response;
+    type Error = io::Error;
+    type Pollable = Connecting;
+
+    fn handle(&self, request: Request) -> Connecting {
+        if request.method() != HttpMethod::Connect {
+            return answer(ResponseBuilder::new(StatusCode::MethodNotAllowed)
+                .header("Allow", "CONNECT")
+                .build());
+        }
+
+        let uri = request.uri();
+        let (host, port) = match (uri.host(), uri.port()) {
+            (Some(host), Some(port)) => (host, port),
+            _ => return answer(ResponseBuilder::new(StatusCode::BadRequest).build()),
+        };
+
+        if !(self.allow)(host, port) {
+            return answer(ResponseBuilder::new(StatusCode::Forbidden).build());
+        }
+
+        Connecting {
+            state: ConnectingState::Dialing(self.dialer.dial((host, port)), self.twister),
+        }
+    }
+}
+
+/// A tunnel's server being connected to. It resolves to the response
+/// to the `CONNECT` request, which hands the connection over to the
+/// tunnel if the server was reached.
+pub struct Connecting {
+    state: ConnectingState,
+}
+
+enum ConnectingState {
+    Dialing(Dial, TwisterBuilder),
+    Answered(Response),
+    Done,
+}
+
+impl Pollable for Connecting {
+    type Item = Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Response>, io::Error> {
+        match mem::replace(&mut self.state, ConnectingState::Done) {
+            ConnectingState::Dialing(mut dial, twister) => match dial.poll() {
+                Ok(PollResult::Ready(server)) => {
+                    let mut response = ResponseBuilder::new(StatusCode::Ok).build();
+                    response.extensions_mut().insert(
+                        OnUpgrade::new(move |io| Relay::new(io, server, twister)));
+                    Ok(PollResult::Ready(response))
+                },
+                Ok(PollResult::NotReady) => {
+                    self.state = ConnectingState::Dialing(dial, twister);
+                    Ok(PollResult::NotReady)
+                },
+                Err(e) => Ok(PollResult::Ready(gateway_error(&e))),
+            },
+            ConnectingState::Answered(response) => Ok(PollResult::Ready(response)),
+            ConnectingState::Done => panic!("Poll called on finished result"),
+        }
+    }
+}
+
+/// The client's end of a tunnel, along with what it sent before the
+/// tunnel was opened.
+struct Client {
+    stream: net::TcpStream,
+    early: Vec<u8>,
+}
+
+/// Reads what the client sent early, before anything more from its
+/// stream.
+struct Early {
+    early: Vec<u8>,
+    read: ReadHalf<net::TcpStream>,
+}
+
+impl Read for Early {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.early.is_empty() {
+            return self.read.read(buf);
+        }
+
+        let n = cmp::min(buf.len(), self.early.len());
+        buf[..n].copy_from_slice(&self.early[..n]);
+        self.early.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Split for Client {
+    type ReadHalf = Early;
+    type WriteHalf = WriteHalf<net::TcpStream>;
+
+    fn split(self) -> (Early, WriteHalf<net::TcpStream>) {
+        let (read, write) = split::share(self.stream);
+        (Early { early: self.early, read }, write)
+    }
+
+    fn unsplit(read: Early, write: WriteHalf<net::TcpStream>) -> Client {
+        Client {
+            stream: split::unshare(read.read, write),
+            early: read.early,
+        }
+    }
+}
+
+/// Relays bytes between a client and its tunnel's server, until both
+/// have finished sending.
+struct Relay {
+    twister: Result<Twister<Client, net::TcpStream>, Option<io::Error>>,
+    /// A handle to the server's stream, which the twister has, to watch
+    /// it with.
+    server: Option<net::TcpStream>,
+    watch: Watch,
+}
+
+impl Relay {
+    fn new(io: Upgraded, server: net::TcpStream, twister: TwisterBuilder) -> Relay {
+        let handle = server.try_clone().ok();
+        Relay {
+            twister: match io.downcast::<net::TcpStream>() {
+                Ok((stream, early)) => Ok(twister.build(Client { stream, early }, server)),
+                Err(_) => Err(Some(io::Error::new(io::ErrorKind::Unsupported,
+                                                  "tunnels are only relayed from TCP streams"))),
+            },
+            server: handle,
+            watch: Watch::new(),
+        }
+    }
+}
+
+impl Pollable for Relay {
+    type Item = ();
+    type Error = io::Error;
+
+    /// The client's stream is the connection's own, so only the
+    /// server's is watched.
+    fn poll(&mut self) -> Result<PollResult<()>, io::Error> {
+        match self.twister {
+            Ok(ref mut twister) => match twister.poll()? {
+                PollResult::Ready(_) => Ok(PollResult::Ready(())),
+                PollResult::NotReady => {
+                    if let Some(ref server) = self.server {
+                        self.watch.watch(server);
+                    }
+                    Ok(PollResult::NotReady)
+                },
+            },
+            Err(ref mut e) => Err(e.take().expect("Poll called on finished result")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tunnel_should {
+    use super::*;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::thread;
+    use std::time::Duration;
+    use server::TcpServer;
+
+    fn serve(tunnel: Tunnel) -> SocketAddr {
+        let server = TcpServer::builder(TunnelProto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run(move || tunnel));
+        addr
+    }
+
+    fn connect(addr: SocketAddr) -> net::TcpStream {
+        let client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client
+    }
+
+    /// Reads the head of a response, up to the blank line that ends it.
+    fn head(client: &mut net::TcpStream) -> String {
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            client.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    #[test]
+    fn relay_between_the_client_and_the_server_it_asked_for() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut hello = vec![];
+            stream.read_to_end(&mut hello).unwrap();
+            stream.write_all(b"Hi").unwrap();
+            hello
+        });
+
+        let mut client = connect(serve(Tunnel::new()));
+        // What's sent straight after the request is relayed, too.
+        write!(client, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\nHel", target).unwrap();
+        assert!(head(&mut client).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        client.write_all(b"lo").unwrap();
+        client.shutdown(net::Shutdown::Write).unwrap();
+        let mut hi = vec![];
+        client.read_to_end(&mut hi).unwrap();
+
+        assert_eq!(b"Hi", &hi[..]);
+        assert_eq!(b"Hello", &server.join().unwrap()[..]);
+    }
+
+    #[test]
+    fn sleep_while_the_tunnel_is_idle() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _stream = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(1));
+        });
+
+        let server = TcpServer::builder(TunnelProto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        thread::spawn(move || server.run(Tunnel::new));
+
+        let mut client = connect(addr);
+        write!(client, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target).unwrap();
+        assert!(head(&mut client).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let before = handle.workers()[0].iterations;
+        thread::sleep(Duration::from_millis(200));
+        let iterations = handle.workers()[0].iterations - before;
+        assert!(iterations < 10, "The worker turned {} times", iterations);
+        handle.stop();
+    }
+
+    #[test]
+    fn refuse_tunnels_that_arent_allowed() {
+        let mut client = connect(serve(Tunnel::new().allow(|_, port| port == 443)));
+
+        client.write_all(b"CONNECT 127.0.0.1:80 HTTP/1.1\r\nHost: 127.0.0.1:80\r\n\r\n").unwrap();
+        assert!(head(&mut client).starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+        client.write_all(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").unwrap();
+        let head = head(&mut client);
+        assert!(head.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(head.contains("\r\nAllow: CONNECT\r\n"));
+    }
+
+    #[test]
+    fn answer_bad_gateway_when_the_server_cant_be_reached() {
+        let target = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut client = connect(serve(Tunnel::new()));
+
+        write!(client, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target).unwrap();
+        assert!(head(&mut client).starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+    }
+}
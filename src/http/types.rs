@@ -1,15 +1,20 @@
 use std::fmt;
 
+use http::body::Body;
 use http::parser;
 
 mod v2 {
     use std::fmt;
+    use std::io;
 
-    use super::HttpMethod;
+    use super::{HttpMethod, StatusCode};
     use super::to_lower;
 
+    use http::body::Body;
+    use http::extensions::Extensions;
+    use http::uri::Uri;
     use result::PollResult;
-    use pollable::{IntoPollable, Pollable, PollableResult};
+    use pollable::{IntoPollable, Pollable};
 
     #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum HttpVersion {
@@ -17,6 +22,21 @@ mod v2 {
         Http11,
     }
 
+    impl HttpVersion {
+        /// Parses the version of a request or status line. E.g.
+        /// `HTTP/1.0`. Any other `HTTP/1.x` version is treated as
+        /// `HTTP/1.1`, the highest version supported. Other major
+        /// versions, and malformed versions, are `None`.
+        pub fn from_bytes(version: &[u8]) -> Option<HttpVersion> {
+            match version {
+                b"HTTP/1.0" => Some(HttpVersion::Http1),
+                &[b'H', b'T', b'T', b'P', b'/', b'1', b'.', minor] if minor.is_ascii_digit() =>
+                    Some(HttpVersion::Http11),
+                _ => None,
+            }
+        }
+    }
+
     impl fmt::Display for HttpVersion {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match *self {
@@ -26,7 +46,7 @@ mod v2 {
         }
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct Header(String, String);
 
     pub type BodyChunk = Vec<u8>;
@@ -42,105 +62,120 @@ mod v2 {
         }
     }
 
+    fn eq_ignore_case(a: &str, b: &str) -> bool {
+        a.as_bytes()
+            .iter()
+            .map(|b| to_lower(*b))
+            .eq(b.as_bytes()
+                .iter()
+                .map(|b| to_lower(*b))
+            )
+    }
+
     struct Object<B> {
         version: HttpVersion,
         headers: Vec<Header>,
+        extensions: Extensions,
         body: B,
     }
 
-    impl<B> Object<B> where
-        B: Pollable<Item=BodyChunk>
-    {
+    impl<B> Object<B> {
         fn version(&self) -> HttpVersion {
             self.version
         }
 
+        fn set_version(&mut self, version: HttpVersion) {
+            self.version = version;
+        }
+
         fn add_header(&mut self, name: &str, value: &str) {
             self.headers.push(Header(name.to_owned(), value.to_owned()));
         }
 
-        fn headers(&self) -> HeaderIter {
+        fn set_header(&mut self, name: &str, value: &str) {
+            self.remove_header(name);
+            self.add_header(name, value);
+        }
+
+        fn remove_header(&mut self, name: &str) -> usize {
+            let before = self.headers.len();
+            self.headers.retain(|h| !eq_ignore_case(&h.0, name));
+            before - self.headers.len()
+        }
+
+        fn headers(&self) -> HeaderIter<'_> {
             HeaderIter(self.headers.iter())
         }
 
         fn header_value(&self, name: &str) -> Option<&str> {
             self.headers()
-                .position(|(n, _)| {
-                    n.as_bytes()
-                        .iter()
-                        .map(|b| to_lower(*b))
-                        .eq(name.as_bytes()
-                            .iter()
-                            .map(|b| to_lower(*b))
-                        )
-                })
+                .position(|(n, _)| eq_ignore_case(n, name))
                 .map(|i| &*self.headers[i].1)
         }
 
-        fn poll_body(&mut self) -> Result<PollResult<B::Item>, B::Error> {
-            self.body.poll()
+        fn replace_body<T>(self, body: T) -> (Object<T>, B) {
+            let Object { version, headers, extensions, body: old } = self;
+            (Object { version, headers, extensions, body }, old)
         }
     }
 
-    impl<B> IntoPollable for Response<B> where
-        B: Pollable<Item=BodyChunk>
-    {
-        type Item = (Self, BodyChunk);
-        type Error = B::Error;
-        type Pollable = ResponsePollable<B>;
-
-        fn into_pollable(self) -> Self::Pollable {
-            ResponsePollable(Some(self))
-        }
-    }
-
-    pub struct ResponsePollable<B>(Option<Response<B>>);
-
-    impl<B> Pollable for ResponsePollable<B> where
-        B: Pollable<Item=BodyChunk>
+    impl<B> Object<B> where
+        B: Pollable
     {
-        type Item = (Response<B>, B::Item);
-        type Error = B::Error;
-        
-        fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
-            match self.0.take() {
-                Some(mut r) => match r.poll_body()? {
-                    PollResult::Ready(body) => return Ok(PollResult::Ready((r, body))),
-                    PollResult::NotReady => self.0 = Some(r),
-                },
-                None => panic!("Poll called on finished result"),
-            }
-
-            Ok(PollResult::NotReady)
+        fn poll_body(&mut self) -> Result<PollResult<B::Item>, B::Error> {
+            self.body.poll()
         }
     }
 
-    pub struct Response<B = PollableResult<BodyChunk, ()>> {
+    /// A HTTP response. 
+    ///
+    /// The body, `B`, is polled repeatedly for chunks of data until it
+    /// yields `None`. This lets a response be written to the peer 
+    /// incrementally, as its body is produced.
+    pub struct Response<B = Body> {
         inner: Object<B>,
-        status_code: usize,
+        status: StatusCode,
         status_text: String,
     }
 
-    impl<B> Response<B> where
-        B: Pollable<Item=BodyChunk>
-    {
+    impl<B> Response<B> {
         pub fn version(&self) -> HttpVersion {
             self.inner.version()
         }
 
+        pub fn set_version(&mut self, version: HttpVersion) {
+            self.inner.set_version(version);
+        }
+
+        pub fn status(&self) -> StatusCode {
+            self.status
+        }
+
         pub fn status_code(&self) -> usize {
-            self.status_code
+            self.status.as_u16() as usize
         }
 
         pub fn status_text(&self) ->  &str {
-            &*self.status_text
+            &self.status_text
         }
 
         pub fn add_header(&mut self, name: &str, value: &str) {
             self.inner.add_header(name, value);
         }
 
-        pub fn headers(&self) -> HeaderIter {
+        /// Replaces any existing headers named `name` with a single 
+        /// header. Names are compared case-insensitively.
+        pub fn set_header(&mut self, name: &str, value: &str) {
+            self.inner.set_header(name, value);
+        }
+
+        /// Removes all headers named `name`, returning how many were
+        /// removed. Names are compared case-insensitively.
+        pub fn remove_header(&mut self, name: &str) -> usize {
+            self.inner.remove_header(name)
+        }
+
+        pub fn headers(&self) -> HeaderIter<'_> {
             self.inner.headers()
         }
 
@@ -148,26 +183,95 @@ mod v2 {
             self.inner.header_value(name)
         }
 
+        pub fn extensions(&self) -> &Extensions {
+            &self.inner.extensions
+        }
+
+        pub fn extensions_mut(&mut self) -> &mut Extensions {
+            &mut self.inner.extensions
+        }
+
+        pub fn body(&self) -> &B {
+            &self.inner.body
+        }
+
+        pub fn body_mut(&mut self) -> &mut B {
+            &mut self.inner.body
+        }
+
+        /// Separates the response into its head (status line, headers
+        /// and extensions) and its body.
+        pub fn into_parts(self) -> (ResponseHead, B) {
+            let Response { inner, status, status_text } = self;
+            let (inner, body) = inner.replace_body(());
+            (Response { inner, status, status_text }, body)
+        }
+
+        /// Reassembles a response from a head and a body. See 
+        /// [`Response::into_parts`].
+        ///
+        /// [`Response::into_parts`]: #method.into_parts
+        pub fn from_parts(head: ResponseHead, body: B) -> Response<B> {
+            let Response { inner, status, status_text } = head;
+            let (inner, _) = inner.replace_body(body);
+            Response { inner, status, status_text }
+        }
+
+        /// Replaces the body of the response with the result of `f`.
+        pub fn map_body<F, T>(self, f: F) -> Response<T> where
+            F: FnOnce(B) -> T
+        {
+            let (head, body) = self.into_parts();
+            Response::from_parts(head, f(body))
+        }
+    }
+
+    impl<B> Response<B> where
+        B: Pollable
+    {
         pub fn poll_body(&mut self) -> Result<PollResult<B::Item>, B::Error> {
             self.inner.poll_body()
         }
     }
 
-    pub struct Request<B = PollableResult<BodyChunk, ()>> {
+    /// The status line, headers and extensions of a response, without
+    /// a body.
+    pub type ResponseHead = Response<()>;
+
+    /// The request line, headers and extensions of a request, without
+    /// a body.
+    pub type RequestHead = Request<()>;
+
+    pub struct Request<B = Body> {
         inner: Object<B>,
         method: HttpMethod,
-        path: String,
+        uri: Uri,
     }
 
-    impl<B> Request<B> where
-        B: Pollable<Item=BodyChunk>
-    {
+    impl<B> Request<B> {
         pub fn version(&self) -> HttpVersion {
             self.inner.version()
         }
 
+        pub fn set_version(&mut self, version: HttpVersion) {
+            self.inner.set_version(version);
+        }
+
+        /// The path of the request target, still percent-encoded and
+        /// without any query string. See [`Request::uri`].
+        ///
+        /// [`Request::uri`]: #method.uri
         pub fn path(&self) -> &str {
-            &*self.path
+            self.uri.path()
+        }
+
+        /// The request target.
+        pub fn uri(&self) -> &Uri {
+            &self.uri
+        }
+
+        pub fn set_uri(&mut self, uri: Uri) {
+            self.uri = uri;
         }
 
         pub fn method(&self) ->  HttpMethod {
@@ -178,120 +282,265 @@ mod v2 {
             self.inner.add_header(name, value);
         }
 
-        pub fn headers(&self) -> HeaderIter {
+        /// Replaces any existing headers named `name` with a single 
+        /// header. Names are compared case-insensitively.
+        pub fn set_header(&mut self, name: &str, value: &str) {
+            self.inner.set_header(name, value);
+        }
+
+        /// Removes all headers named `name`, returning how many were
+        /// removed. Names are compared case-insensitively.
+        pub fn remove_header(&mut self, name: &str) -> usize {
+            self.inner.remove_header(name)
+        }
+
+        pub fn headers(&self) -> HeaderIter<'_> {
             self.inner.headers()
         }
 
         pub fn header_value(&self, name: &str) -> Option<&str> {
             self.inner.header_value(name)
         }
+
+        pub fn extensions(&self) -> &Extensions {
+            &self.inner.extensions
+        }
+
+        pub fn extensions_mut(&mut self) -> &mut Extensions {
+            &mut self.inner.extensions
+        }
+
+        pub fn body(&self) -> &B {
+            &self.inner.body
+        }
+
+        pub fn body_mut(&mut self) -> &mut B {
+            &mut self.inner.body
+        }
+
+        pub fn into_body(self) -> B {
+            self.inner.body
+        }
+
+        /// Separates the request into its head (request line, headers
+        /// and extensions) and its body.
+        pub fn into_parts(self) -> (RequestHead, B) {
+            let Request { inner, method, uri } = self;
+            let (inner, body) = inner.replace_body(());
+            (Request { inner, method, uri }, body)
+        }
+
+        /// Reassembles a request from a head and a body. See 
+        /// [`Request::into_parts`].
+        ///
+        /// [`Request::into_parts`]: #method.into_parts
+        pub fn from_parts(head: RequestHead, body: B) -> Request<B> {
+            let Request { inner, method, uri } = head;
+            let (inner, _) = inner.replace_body(body);
+            Request { inner, method, uri }
+        }
+
+        /// Replaces the body of the request with the result of `f`.
+        pub fn map_body<F, T>(self, f: F) -> Request<T> where
+            F: FnOnce(B) -> T
+        {
+            let (head, body) = self.into_parts();
+            Request::from_parts(head, f(body))
+        }
+    }
+
+    impl<B> Request<B> where
+        B: Pollable
+    {
+        pub fn poll_body(&mut self) -> Result<PollResult<B::Item>, B::Error> {
+            self.inner.poll_body()
+        }
     }
 
     pub struct ResponseBuilder<'a> {
         version: HttpVersion,
-        status_code: usize,
-        status_text: &'a str,
+        status: StatusCode,
+        status_text: Option<&'a str>,
+        headers: Vec<Header>,
     }
     
     impl<'a> ResponseBuilder<'a> {
-        pub fn new(status_code: usize, 
-                   status_text: &'a str) -> ResponseBuilder<'a>
-        {
+        /// Creates a builder for a response with the given status. The
+        /// status text will be the canonical reason phrase for `status`.
+        pub fn new(status: StatusCode) -> ResponseBuilder<'a> {
             ResponseBuilder {
                 version: HttpVersion::Http11,
-                status_code: status_code,
-                status_text: status_text,
+                status,
+                status_text: None,
+                headers: vec![],
             }
         }
 
+        /// Creates a builder for a response with the given status and a
+        /// custom status text.
+        pub fn with_status_text(status: StatusCode, 
+                                status_text: &'a str) -> ResponseBuilder<'a>
+        {
+            ResponseBuilder {
+                status_text: Some(status_text),
+                ..ResponseBuilder::new(status)
+            }
+        }
+
+        /// Sets the status of the response. The status text reverts to
+        /// the canonical reason phrase for `status`.
+        pub fn status(mut self, status: StatusCode) -> ResponseBuilder<'a> {
+            self.status = status;
+            self.status_text = None;
+            self
+        }
+
+        pub fn version(mut self, version: HttpVersion) -> ResponseBuilder<'a> {
+            self.version = version;
+            self
+        }
+
+        /// Adds a header to the response. Headers are emitted in the
+        /// order they're added.
+        pub fn header(mut self, name: &str, value: &str) -> ResponseBuilder<'a> {
+            self.headers.push(Header(name.to_owned(), value.to_owned()));
+            self
+        }
+
         pub fn build(&self) -> Response {
-            self._build(Ok(vec![]))
+            self.build_with_body(Body::empty())
         }
 
         pub fn build_with_content<T>(&self, t: T) -> Response where
             T: AsRef<[u8]>
         {
-            self._build(Ok(t.as_ref().to_vec()))
+            self.build_with_body(t.as_ref().to_vec())
         }
 
         pub fn build_with_stream<I>(&self, body: I) -> Response where
                 I: IntoIterator<Item=u8>
         {
-            self._build(Ok(body.into_iter().collect::<BodyChunk>()))
+            self.build_with_body(body.into_iter().collect::<BodyChunk>())
+        }
+
+        /// Builds a response whose body is produced by polling `body`
+        /// until it yields `None`. The length of the body isn't known
+        /// up front, so it will be sent to the peer in chunks.
+        pub fn build_with_pollable<B>(&self, body: B) -> Response where
+                B: IntoPollable<Item=Option<BodyChunk>, Error=io::Error>,
+                B::Pollable: 'static
+        {
+            self.build_with_body(Body::from_pollable(body))
         }
 
-        fn _build<B>(&self, body: B)
-            -> Response<B::Pollable> where
-                B: IntoPollable<Item=BodyChunk>
+        /// Builds a response whose body is read from `reader` in
+        /// bounded chunks as the response is written, rather than
+        /// being read into memory up front.
+        pub fn build_with_reader<R>(&self, reader: R) -> Response where
+                R: io::Read + 'static
+        {
+            self.build_with_body(Body::from_reader(reader))
+        }
+
+        pub fn build_with_body<B>(&self, body: B) -> Response where
+                B: Into<Body>
         {
             Response {
                 inner: Object {
                     version: self.version,
-                    headers: vec![],
-                    body: body.into_pollable(),
+                    headers: self.headers.clone(),
+                    extensions: Extensions::new(),
+                    body: body.into(),
                 },
-                status_code: self.status_code,
-                status_text: String::from(self.status_text),
+                status: self.status,
+                status_text: String::from(
+                    self.status_text
+                        .or_else(|| self.status.canonical_reason())
+                        .unwrap_or("")
+                ),
             }
         }
-
-        pub fn build_with_pollable<B>(&self, body: B) 
-            -> Response<B::Pollable> where
-                B: IntoPollable<Item=BodyChunk>
-        {
-            self._build(body)
-        }
     }
 
-    pub struct RequestBuilder<'a> {
+    pub struct RequestBuilder {
         method: HttpMethod,
-        path: &'a str,
+        uri: Uri,
         version: HttpVersion,
+        headers: Vec<Header>,
     }
     
-    impl<'a> RequestBuilder<'a> {
+    impl RequestBuilder {
+        /// Creates a builder for a request to `target`. If `target`
+        /// can't be parsed as a [`Uri`], it is used verbatim as the
+        /// path of the request.
+        ///
+        /// [`Uri`]: ../uri/struct.Uri.html
         pub fn new<M>(method: M, 
-                      path: &'a str) -> RequestBuilder<'a> where
+                      target: &str) -> RequestBuilder where
             M: Into<HttpMethod>
         {
+            let uri = Uri::parse(target)
+                .unwrap_or_else(|_| Uri::from_raw_path(target));
+
             RequestBuilder {
                 method: method.into(),
-                path: path,
+                uri,
                 version: HttpVersion::Http11,
+                headers: vec![],
             }
         }
 
+        pub fn uri(mut self, uri: Uri) -> RequestBuilder {
+            self.uri = uri;
+            self
+        }
+
+        pub fn version(mut self, version: HttpVersion) -> RequestBuilder {
+            self.version = version;
+            self
+        }
+
+        /// Adds a header to the request. Headers are emitted in the
+        /// order they're added.
+        pub fn header(mut self, name: &str, value: &str) -> RequestBuilder {
+            self.headers.push(Header(name.to_owned(), value.to_owned()));
+            self
+        }
+
         pub fn build(&self) -> Request {
-            self.build_with_pollable(Ok(vec![]))
+            self.build_with_body(Body::empty())
         }
 
         pub fn build_with_buffer<I>(&self, body: I) -> Request where
                 I: IntoIterator<Item=u8>
         {
-            self.build_with_pollable(Ok(body.into_iter().collect::<BodyChunk>()))
+            self.build_with_body(body.into_iter().collect::<BodyChunk>())
+        }
+
+        pub fn build_with_pollable<B>(&self, body: B) -> Request where
+                B: IntoPollable<Item=Option<BodyChunk>, Error=io::Error>,
+                B::Pollable: 'static
+        {
+            self.build_with_body(Body::from_pollable(body))
         }
 
-        pub fn build_with_pollable<B>(&self, body: B) 
-            -> Request<B::Pollable> where
-                B: IntoPollable<Item=BodyChunk>
+        pub fn build_with_body<B>(&self, body: B) -> Request where
+                B: Into<Body>
         {
             Request {
                 inner: Object {
                     version: self.version,
-                    headers: vec![],
-                    body: body.into_pollable(),
+                    headers: self.headers.clone(),
+                    extensions: Extensions::new(),
+                    body: body.into(),
                 },
                 method: self.method,
-                path: String::from(self.path),
+                uri: self.uri.clone(),
             }
         }
     }
 }
 
-trait FromBytes : Sized {
-    fn from_bytes(bytes: &[u8]) -> Option<Self>;
-}
-
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum HttpMethod {
     Connect,
@@ -305,9 +554,137 @@ pub enum HttpMethod {
     Unsupported,
 }
 
+macro_rules! status_codes {
+    ($($variant:ident => ($code:expr, $reason:expr),)+) => {
+        /// A HTTP response status code.
+        ///
+        /// Every status code registered with IANA that this crate
+        /// knows about has its own variant. Any other code is
+        /// represented by `StatusCode::Other`.
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum StatusCode {
+            $($variant,)+
+            Other(u16),
+        }
+
+        impl StatusCode {
+            /// Returns the numeric value of the status code. E.g.
+            /// `404` for `StatusCode::NotFound`.
+            pub fn as_u16(&self) -> u16 {
+                match *self {
+                    $(StatusCode::$variant => $code,)+
+                    StatusCode::Other(code) => code,
+                }
+            }
+
+            /// Returns the reason phrase recommended by the 
+            /// specification for this status code, or `None` if
+            /// the code isn't known.
+            pub fn canonical_reason(&self) -> Option<&'static str> {
+                match *self {
+                    $(StatusCode::$variant => Some($reason),)+
+                    StatusCode::Other(_) => None,
+                }
+            }
+        }
+
+        impl From<u16> for StatusCode {
+            fn from(code: u16) -> StatusCode {
+                match code {
+                    $($code => StatusCode::$variant,)+
+                    other => StatusCode::Other(other),
+                }
+            }
+        }
+    }
+}
+
+status_codes! {
+    Continue => (100, "Continue"),
+    SwitchingProtocols => (101, "Switching Protocols"),
+    Ok => (200, "OK"),
+    Created => (201, "Created"),
+    Accepted => (202, "Accepted"),
+    NonAuthoritativeInformation => (203, "Non-Authoritative Information"),
+    NoContent => (204, "No Content"),
+    ResetContent => (205, "Reset Content"),
+    PartialContent => (206, "Partial Content"),
+    MultipleChoices => (300, "Multiple Choices"),
+    MovedPermanently => (301, "Moved Permanently"),
+    Found => (302, "Found"),
+    SeeOther => (303, "See Other"),
+    NotModified => (304, "Not Modified"),
+    TemporaryRedirect => (307, "Temporary Redirect"),
+    PermanentRedirect => (308, "Permanent Redirect"),
+    BadRequest => (400, "Bad Request"),
+    Unauthorized => (401, "Unauthorized"),
+    PaymentRequired => (402, "Payment Required"),
+    Forbidden => (403, "Forbidden"),
+    NotFound => (404, "Not Found"),
+    MethodNotAllowed => (405, "Method Not Allowed"),
+    NotAcceptable => (406, "Not Acceptable"),
+    ProxyAuthenticationRequired => (407, "Proxy Authentication Required"),
+    RequestTimeout => (408, "Request Timeout"),
+    Conflict => (409, "Conflict"),
+    Gone => (410, "Gone"),
+    LengthRequired => (411, "Length Required"),
+    PreconditionFailed => (412, "Precondition Failed"),
+    PayloadTooLarge => (413, "Payload Too Large"),
+    UriTooLong => (414, "URI Too Long"),
+    UnsupportedMediaType => (415, "Unsupported Media Type"),
+    RangeNotSatisfiable => (416, "Range Not Satisfiable"),
+    ExpectationFailed => (417, "Expectation Failed"),
+    UnprocessableEntity => (422, "Unprocessable Entity"),
+    UpgradeRequired => (426, "Upgrade Required"),
+    PreconditionRequired => (428, "Precondition Required"),
+    TooManyRequests => (429, "Too Many Requests"),
+    RequestHeaderFieldsTooLarge => (431, "Request Header Fields Too Large"),
+    InternalServerError => (500, "Internal Server Error"),
+    NotImplemented => (501, "Not Implemented"),
+    BadGateway => (502, "Bad Gateway"),
+    ServiceUnavailable => (503, "Service Unavailable"),
+    GatewayTimeout => (504, "Gateway Timeout"),
+    HttpVersionNotSupported => (505, "HTTP Version Not Supported"),
+}
+
+impl StatusCode {
+    /// `true` for `1xx` status codes.
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.as_u16())
+    }
+
+    /// `true` for `2xx` status codes.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.as_u16())
+    }
+
+    /// `true` for `3xx` status codes.
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.as_u16())
+    }
+
+    /// `true` for `4xx` status codes.
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.as_u16())
+    }
+
+    /// `true` for `5xx` status codes.
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.as_u16())
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", 
+               self.as_u16(), 
+               self.canonical_reason().unwrap_or(""))
+    }
+}
+
 fn to_lower(v: u8) -> u8 {
     match v {
-        b'A'...b'Z' => v + (b'a' - b'A'),
+        b'A'..=b'Z' => v + (b'a' - b'A'),
         o => o
     }
 }
@@ -325,8 +702,9 @@ fn which_of(to_find: &[u8], in_set: &[&[u8]]) -> Option<usize> {
     None
 }
 
-impl<'a> From<&'a [u8]> for HttpMethod {
-    fn from(bytes: &'a [u8]) -> HttpMethod {
+impl HttpMethod {
+    /// The method named `bytes`, if it's one that's supported.
+    pub fn from_bytes(bytes: &[u8]) -> Option<HttpMethod> {
         let valid: &[&[u8]] = &[
             b"connect",
             b"Get",
@@ -338,30 +716,30 @@ impl<'a> From<&'a [u8]> for HttpMethod {
             b"options",
         ];
 
-        if let Some(n) = which_of(bytes, valid) {
-            return match n {
-                0 => HttpMethod::Connect,
-                1 => HttpMethod::Get,
-                2 => HttpMethod::Post,
-                3 => HttpMethod::Put,
-                4 => HttpMethod::Delete,
-                5 => HttpMethod::Patch,
-                6 => HttpMethod::Head,
-                7 => HttpMethod::Options,
-                _ => panic!("Unsupported HTTP method '{}'", 
-                            ::std::str::from_utf8(bytes).unwrap()),
-            }
-        }
+        which_of(bytes, valid).map(|n| match n {
+            0 => HttpMethod::Connect,
+            1 => HttpMethod::Get,
+            2 => HttpMethod::Post,
+            3 => HttpMethod::Put,
+            4 => HttpMethod::Delete,
+            5 => HttpMethod::Patch,
+            6 => HttpMethod::Head,
+            _ => HttpMethod::Options,
+        })
+    }
+}
 
-        panic!("Unsupported HTTP method '{}'", 
-               ::std::str::from_utf8(bytes).unwrap());
-//        HttpMethod::Unsupported
+/// Methods that aren't supported, such as `TRACE`, are
+/// `HttpMethod::Unsupported`.
+impl<'a> From<&'a [u8]> for HttpMethod {
+    fn from(bytes: &'a [u8]) -> HttpMethod {
+        HttpMethod::from_bytes(bytes).unwrap_or(HttpMethod::Unsupported)
     }
 }
 
-impl<'a> Into<&'static str> for &'a HttpMethod {
-    fn into(self) -> &'static str {
-        match *self {
+impl From<&HttpMethod> for &'static str {
+    fn from(val: &HttpMethod) -> Self {
+        match *val {
             HttpMethod::Connect => "CONNECT", 
             HttpMethod::Get => "GET",
             HttpMethod::Post => "POST",
@@ -370,7 +748,7 @@ impl<'a> Into<&'static str> for &'a HttpMethod {
             HttpMethod::Patch => "PATCH",
             HttpMethod::Head => "HEAD",
             HttpMethod::Options => "OPTIONS",
-            o => panic!("Unsupported HTTP method {:?}", o),
+            HttpMethod::Unsupported => "UNSUPPORTED",
         }
     }
 }
@@ -396,7 +774,7 @@ fn convert_slice_to_indices<T>(s: &[T], source: &[T]) -> Slice {
 }
 
 trait FromParsed<Source> {
-    fn from_parsed(source: Source, header: &[u8], body: &[u8]) -> Self;
+    fn from_parsed(source: Source, header: &[u8]) -> Self;
 }
 
 struct Slice(usize, usize);
@@ -441,7 +819,6 @@ struct DetachedRequest {
     path: Slice,
     version: Slice,
     headers: Vec<Header>,
-    body: Slice,
 }
 
 impl DetachedRequest {
@@ -454,9 +831,8 @@ impl DetachedRequest {
             &buffer[self.path.0..self.path.1]).unwrap()
     }
 
-    fn version<'a>(&'a self, buffer: &'a [u8]) -> &'a str {
-        ::std::str::from_utf8(
-            &buffer[self.version.0..self.version.1]).unwrap()
+    fn version(&self, buffer: &[u8]) -> Option<HttpVersion> {
+        HttpVersion::from_bytes(&buffer[self.version.0..self.version.1])
     }
 
     fn headers<'a>(&'a self, buffer: &'a [u8]) -> DetachedHeaderIter<'a> {
@@ -469,7 +845,6 @@ struct DetachedResponse {
     status_code: Slice,
     status_text: Slice,
     headers: Vec<Header>,
-    body: Slice,
 }
 
 impl DetachedResponse {
@@ -483,9 +858,8 @@ impl DetachedResponse {
             &buffer[self.status_text.0..self.status_text.1]).unwrap()
     }
 
-    fn version<'a>(&'a self, buffer: &'a [u8]) -> &'a str {
-        ::std::str::from_utf8(
-            &buffer[self.version.0..self.version.1]).unwrap()
+    fn version(&self, buffer: &[u8]) -> Option<HttpVersion> {
+        HttpVersion::from_bytes(&buffer[self.version.0..self.version.1])
     }
 
     fn headers<'a>(&'a self, buffer: &'a [u8]) -> DetachedHeaderIter<'a> {
@@ -495,16 +869,18 @@ impl DetachedResponse {
 
 pub use self::v2::{
     BodyChunk, 
+    HttpVersion,
     Request, 
     RequestBuilder, 
+    RequestHead,
     Response, 
-    ResponseBuilder
+    ResponseBuilder,
+    ResponseHead,
 };
 
 impl<'h, 'b: 'h> FromParsed<parser::Request<'h, 'b>> for DetachedRequest {
     fn from_parsed(source: parser::Request<'h, 'b>, 
-                   header: &[u8],
-                   body: &[u8]) -> DetachedRequest
+                   header: &[u8]) -> DetachedRequest
     {
         let method = source.method().into();
         let path = convert_slice_to_indices(source.path(), header);
@@ -515,14 +891,12 @@ impl<'h, 'b: 'h> FromParsed<parser::Request<'h, 'b>> for DetachedRequest {
                 value: convert_slice_to_indices(h.1, header),
             })
             .collect::<Vec<_>>();
-        let body = convert_slice_to_indices(body, header);
 
         DetachedRequest {
-            method: method,
-            path: path,
-            version: version,
-            headers: headers,
-            body: body,
+            method,
+            path,
+            version,
+            headers,
         }
     }
 }
@@ -530,8 +904,7 @@ impl<'h, 'b: 'h> FromParsed<parser::Request<'h, 'b>> for DetachedRequest {
 impl<'h, 'b: 'h> FromParsed<parser::Response<'h, 'b>> for DetachedResponse {
 
     fn from_parsed(source: parser::Response<'h, 'b>,
-                   header: &[u8],
-                   body: &[u8]) -> DetachedResponse
+                   header: &[u8]) -> DetachedResponse
     {
         let version = convert_slice_to_indices(source.version(), header);
         let status_code = convert_slice_to_indices(source.status_code(), header);
@@ -542,35 +915,230 @@ impl<'h, 'b: 'h> FromParsed<parser::Response<'h, 'b>> for DetachedResponse {
                 value: convert_slice_to_indices(h.1, header),
             })
             .collect::<Vec<_>>();
-        let body = convert_slice_to_indices(body, header);
 
         DetachedResponse {
-            version: version,
-            status_code: status_code,
-            status_text: status_text,
-            headers: headers,
-            body: body,
+            version,
+            status_code,
+            status_text,
+            headers,
         }
     }
 }
 
+/// How the body of a request is delimited.
+pub(crate) enum BodyFraming {
+    Sized(usize),
+    Chunked,
+}
+
+/// How the body of a request with `headers` is delimited. `None` if
+/// its framing headers are malformed, or could be read more than one
+/// way, e.g. with several `Content-Length`s, or with both a
+/// `Content-Length` and a `Transfer-Encoding`. A server and a proxy in
+/// front of it could otherwise disagree about where the request ends,
+/// and the rest of it be taken for another request.
+pub(crate) fn body_framing(headers: &[parser::Header]) -> Option<BodyFraming> {
+    let values_of = |name: &[u8]| headers.iter()
+        .filter(|h| which_of(h.0, &[name]).is_some())
+        .map(|h| ::std::str::from_utf8(h.1).ok())
+        .collect::<Option<Vec<_>>>();
+
+    let encodings = values_of(b"transfer-encoding")?;
+    let lengths = values_of(b"content-length")?;
+
+    match (encodings.last(), &lengths[..]) {
+        (None, &[]) => Some(BodyFraming::Sized(0)),
+        (None, &[length]) if !length.trim().is_empty() &&
+            length.trim().bytes().all(|b| b.is_ascii_digit()) =>
+            length.trim().parse().ok().map(BodyFraming::Sized),
+        // The body must be chunked, or its end couldn't be found...
+        (Some(encoding), &[]) if encoding.rsplit(',')
+            .next()
+            .map(|c| c.trim().eq_ignore_ascii_case("chunked"))
+            .unwrap_or(false) => Some(BodyFraming::Chunked),
+        _ => None,
+    }
+}
+
+/// The longest line, either the size of a chunk or a trailer, that a
+/// `chunked` body can have.
+const MAX_CHUNKED_LINE: usize = 8 * 1024;
+
+/// How far a walk through a `chunked` body has got, so that it can
+/// carry on from there once more of the body has arrived.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct ChunkedProgress {
+    /// Where the first line that hasn't been read starts.
+    pos: usize,
+    /// The size of the chunks that have been read.
+    size: usize,
+    /// Whether the last chunk has been read, leaving its trailers.
+    trailers: bool,
+}
+
+impl ChunkedProgress {
+    /// How far into the body the walk has got, in bytes.
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+/// Walks the `chunked` body at the start of `data`, from where
+/// `progress` got to, passing the data of each chunk to `f`. Returns
+/// the number of bytes of `data` the body occupies, or `None` if it
+/// hasn't all arrived. Fails if the size of a chunk is malformed, a
+/// chunk is longer than its size, or a line is too long.
+fn walk_chunked_body<F>(data: &[u8], progress: &mut ChunkedProgress, mut f: F)
+    -> Result<Option<usize>, ()> where
+    F: FnMut(&[u8])
+{
+    while !progress.trailers {
+        let line_len = match chunked_line(&data[progress.pos..])? {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+        let size = chunk_size(&data[progress.pos..progress.pos + line_len]).ok_or(())?;
+        let pos = progress.pos + line_len + 2;
+
+        if size == 0 {
+            progress.pos = pos;
+            progress.trailers = true;
+            break;
+        }
+
+        let end = size.checked_add(pos + 2).ok_or(())?;
+        if data.len() < end {
+            return Ok(None);
+        }
+        if &data[end - 2..end] != b"\r\n" {
+            return Err(());
+        }
+
+        f(&data[pos..pos + size]);
+        progress.size += size;
+        progress.pos = end;
+    }
+
+    // Skip any trailers, up to and including the terminating empty line
+    loop {
+        let line_len = match chunked_line(&data[progress.pos..])? {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+        progress.pos += line_len + 2;
+        if line_len == 0 {
+            return Ok(Some(progress.pos));
+        }
+    }
+}
+
+/// The length of the line at the start of `data`, without its `CRLF`,
+/// or `None` if it hasn't all arrived. Fails if it's longer than
+/// `MAX_CHUNKED_LINE`. Only that much of `data` is searched, so a line
+/// that arrives a little at a time isn't searched from the start over
+/// and over.
+fn chunked_line(data: &[u8]) -> Result<Option<usize>, ()> {
+    let searched = &data[..data.len().min(MAX_CHUNKED_LINE + 2)];
+    match searched.windows(2).position(|w| w == b"\r\n") {
+        Some(n) => Ok(Some(n)),
+        None if data.len() > MAX_CHUNKED_LINE + 1 => Err(()),
+        None => Ok(None),
+    }
+}
+
+/// Parses the size of a chunk from its size line: one or more hex
+/// digits, optionally followed by `;` and an extension, which is
+/// ignored.
+fn chunk_size(line: &[u8]) -> Option<usize> {
+    let digits = line.split(|&b| b == b';').next().unwrap_or(line);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+
+    ::std::str::from_utf8(digits).ok()
+        .and_then(|s| usize::from_str_radix(s, 16).ok())
+}
+
+/// Decodes a complete `chunked` body from the start of `data`, 
+/// returning the decoded body along with the number of bytes of 
+/// `data` it occupied. Returns `None` if `data` doesn't yet contain
+/// the whole body, or it's malformed.
+fn parse_chunked_body(data: &[u8]) -> Option<(BodyChunk, usize)> {
+    let mut body = vec![];
+    let mut progress = ChunkedProgress::default();
+    let n = walk_chunked_body(data, &mut progress, |chunk| body.extend(chunk)).ok()??;
+    Some((body, n))
+}
+
+/// The size of the `chunked` body at the start of `data`, carrying on
+/// from where `progress` got to, and whether all of it has arrived.
+/// Once it has, its size is that of its chunks, and until then, that of
+/// `data`. `None` if the body is malformed.
+pub(crate) fn chunked_body_size(data: &[u8], progress: &mut ChunkedProgress)
+    -> Option<(usize, bool)>
+{
+    match walk_chunked_body(data, progress, |_| {}) {
+        Ok(Some(_)) => Some((progress.size, true)),
+        Ok(None) => Some((data.len(), false)),
+        Err(()) => None,
+    }
+}
+
+fn read_body(headers: &[parser::Header], data: &[u8]) -> Option<(Body, usize)> {
+    match body_framing(headers)? {
+        BodyFraming::Sized(0) => Some((Body::empty(), 0)),
+        BodyFraming::Sized(n) if data.len() >= n => 
+            Some((Body::from(&data[..n]), n)),
+        BodyFraming::Sized(_) => None,
+        BodyFraming::Chunked => parse_chunked_body(data)
+            .map(|(body, n)| (Body::from(body), n)),
+    }
+}
+
+/// The most headers a request or response can have. Requests with
+/// more are malformed.
+pub const MAX_HEADERS: usize = 32;
+
+/// Whether `version`, from a request line, is well formed, `HTTP/`
+/// followed by a digit, `.` and a digit, but isn't `HTTP/1.x`. Requests
+/// with one are answered with `505 HTTP Version Not Supported`.
+pub(crate) fn is_unsupported_version(version: &[u8]) -> bool {
+    match *version {
+        [b'H', b'T', b'T', b'P', b'/', major, b'.', minor] =>
+            major.is_ascii_digit() && minor.is_ascii_digit() && major != b'1',
+        _ => false,
+    }
+}
+
+/// Whether the parsed head of a request can be made into a `Request`:
+/// its method and version are supported, and its path and headers are
+/// UTF-8.
+pub(crate) fn is_supported(request: &parser::Request) -> bool {
+    use std::str::from_utf8;
+
+    HttpMethod::from_bytes(request.method()).is_some() &&
+        HttpVersion::from_bytes(request.version()).is_some() &&
+        from_utf8(request.path()).is_ok() &&
+        request.headers().iter().all(|h| from_utf8(h.0).is_ok() && from_utf8(h.1).is_ok())
+}
+
 pub fn parse_request(buffer: &mut Vec<u8>) -> Option<Request> {
-    let (r, consumed) = {
-        let mut headers = [parser::Header::default(); 32];
+    let (r, body, consumed) = {
+        let mut headers = [parser::Header::default(); MAX_HEADERS];
         let mut request = parser::Request::new(&mut headers);
-        //  TODO:
-        //      Properly parse the body...
-        if let Some(n) = request.parse(buffer) {
-            (DetachedRequest::from_parsed(request, buffer, &buffer[n..n]), n)
-        }
-        else {
+        let n = request.parse(buffer)?;
+        if !is_supported(&request) {
             return None;
         }
+        let (body, body_len) = read_body(request.headers(), &buffer[n..])?;
+
+        (DetachedRequest::from_parsed(request, buffer), body, n + body_len)
     };
 
     let mut request = 
         RequestBuilder::new(r.method(), r.path(buffer))
-            .build();
+            .version(r.version(buffer)?)
+            .build_with_body(body);
 
     for (name, value) in r.headers(buffer) {
         request.add_header(name, value);
@@ -580,23 +1148,40 @@ pub fn parse_request(buffer: &mut Vec<u8>) -> Option<Request> {
     Some(request)
 }
 
+/// Whether the parsed head of a response can be made into a
+/// `Response`: its version is supported, its status is three digits,
+/// and its status text and headers are UTF-8.
+pub(crate) fn is_well_formed(response: &parser::Response) -> bool {
+    use std::str::from_utf8;
+
+    let status = response.status_code();
+    HttpVersion::from_bytes(response.version()).is_some() &&
+        status.len() == 3 && status.iter().all(u8::is_ascii_digit) &&
+        from_utf8(response.status_text()).is_ok() &&
+        response.headers().iter().all(|h| from_utf8(h.0).is_ok() && from_utf8(h.1).is_ok())
+}
+
 pub fn parse_response(buffer: &mut Vec<u8>) -> Option<Response> {
     let (r, consumed) = {
-        let mut headers = [parser::Header::default(); 32];
+        let mut headers = [parser::Header::default(); MAX_HEADERS];
         let mut response = parser::Response::new(&mut headers);
         //  TODO:
         //      Properly parse the body...
         if let Some(n) = response.parse(buffer) {
-            (DetachedResponse::from_parsed(response, buffer, &buffer[n..n]), n)
+            if !is_well_formed(&response) {
+                return None;
+            }
+            (DetachedResponse::from_parsed(response, buffer), n)
         }
         else {
             return None;
         }
     };
 
+    let status = StatusCode::from(r.status_code(buffer).parse::<u16>().ok()?);
     let mut response = 
-        ResponseBuilder::new(r.status_code(buffer).parse().unwrap(), 
-                             r.status_text(buffer))
+        ResponseBuilder::with_status_text(status, r.status_text(buffer))
+            .version(r.version(buffer)?)
             .build();
 
     for (name, value) in r.headers(buffer) {
@@ -610,6 +1195,7 @@ pub fn parse_response(buffer: &mut Vec<u8>) -> Option<Response> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use result::PollResult;
 
     #[test]
     fn add_header() {
@@ -624,16 +1210,31 @@ mod tests {
 
         assert_eq!(3, r.headers().count());
         assert_eq!(
-            ("Accept".as_ref(), "text/json".as_ref()), 
+            ("Accept", "text/json"), 
             r.headers().nth(1).unwrap()
         );
 
         assert_eq!(
-            ("X-Some-Header".as_ref(), "1234567890".as_ref()), 
+            ("X-Some-Header", "1234567890"), 
             r.headers().nth(2).unwrap()
         );
     }
 
+    #[test]
+    fn not_parse_requests_it_cant_represent() {
+        assert_eq!(None, HttpMethod::from_bytes(b"TRACE"));
+        assert_eq!(HttpMethod::Unsupported, HttpMethod::from(&b"TRACE"[..]));
+        assert_eq!(Some(HttpMethod::Delete), HttpMethod::from_bytes(b"DELETE"));
+
+        for raw in &[&b"TRACE / HTTP/1.1\r\n\r\n"[..],
+                     &b"GET /\xc3 HTTP/1.1\r\n\r\n"[..],
+                     &b"GET / HTTP/1.1\r\nX-Name: \xff\xfe\r\n\r\n"[..]] {
+            let mut buffer = raw.to_vec();
+            assert!(parse_request(&mut buffer).is_none());
+            assert_eq!(*raw, &*buffer);
+        }
+    }
+
     #[test]
     fn convert_a_parsed_request() {
         let mut buffer = b"GET /a HTTP/1.1\r\n\
@@ -647,13 +1248,127 @@ Accept-Language: en-US,en;q=0.5\r\n\r\n".to_vec();
         assert_eq!("/a", r.path());
         assert_eq!(v2::HttpVersion::Http11, r.version());
         assert_eq!(
-            ("Accept-Encoding".as_ref(), "gzip, deflate".as_ref()), 
+            ("Accept-Encoding", "gzip, deflate"), 
             r.headers().nth(1).unwrap()
         );
-        println!("{}", ::std::str::from_utf8(&*buffer).unwrap());
+        println!("{}", ::std::str::from_utf8(&buffer).unwrap());
         assert_eq!(b"", &*buffer);
     }
 
+    #[test]
+    fn parse_the_request_version() {
+        let mut buffer = b"GET /a HTTP/1.0\r\n\r\n".to_vec();
+        assert_eq!(HttpVersion::Http1, parse_request(&mut buffer).unwrap().version());
+
+        let mut buffer = b"HTTP/1.0 200 OK\r\n\r\n".to_vec();
+        assert_eq!(HttpVersion::Http1, parse_response(&mut buffer).unwrap().version());
+
+        assert_eq!(Some(HttpVersion::Http11), HttpVersion::from_bytes(b"HTTP/1.1"));
+        assert_eq!(None, HttpVersion::from_bytes(b"SPDY/3"));
+        assert_eq!(None, HttpVersion::from_bytes(b"HTTP/1.1x"));
+        assert_eq!(None, HttpVersion::from_bytes(b"HTTP/2.0"));
+
+        let mut buffer = b"GET /a HTTP/1.x\r\n\r\n".to_vec();
+        assert!(parse_request(&mut buffer).is_none());
+        let mut buffer = b"HTTP/3.0 200 OK\r\n\r\n".to_vec();
+        assert!(parse_response(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn parse_the_request_target() {
+        let mut buffer = b"GET /search?q=a+b HTTP/1.1\r\n\r\n".to_vec();
+        let r = parse_request(&mut buffer).unwrap();
+
+        assert_eq!("/search", r.path());
+        assert_eq!(Some("q=a+b"), r.uri().query());
+
+        let mut buffer = b"CONNECT docs.rs:443 HTTP/1.1\r\n\r\n".to_vec();
+        let r = parse_request(&mut buffer).unwrap();
+
+        assert_eq!(Some("docs.rs"), r.uri().host());
+        assert_eq!(Some(443), r.uri().port());
+    }
+
+    #[test]
+    fn read_a_sized_request_body() {
+        let mut buffer = b"POST /form HTTP/1.1\r\n\
+            Content-Length: 13\r\n\
+            \r\n\
+            Hello, World!GET / HTTP/1.1\r\n\r\n".to_vec();
+
+        let r = parse_request(&mut buffer).unwrap();
+
+        assert_eq!(Some(&b"Hello, World!"[..]), r.body().as_bytes());
+        assert_eq!(b"GET / HTTP/1.1\r\n\r\n", &*buffer);
+    }
+
+    #[test]
+    fn wait_for_the_whole_request_body() {
+        let mut buffer = b"POST /form HTTP/1.1\r\n\
+            Content-Length: 13\r\n\
+            \r\n\
+            Hello".to_vec();
+
+        assert!(parse_request(&mut buffer).is_none());
+        buffer.extend(b", World!");
+        assert!(parse_request(&mut buffer).is_some());
+        assert_eq!(b"", &*buffer);
+    }
+
+    #[test]
+    fn read_a_chunked_request_body() {
+        let mut buffer = b"POST /form HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            7\r\nHello, \r\n\
+            6;ext=1\r\nWorld!\r\n\
+            0\r\n\
+            \r\n".to_vec();
+
+        let mut r = parse_request(&mut buffer).unwrap();
+
+        assert_eq!(
+            PollResult::Ready(Some(b"Hello, World!".to_vec())),
+            r.poll_body().unwrap()
+        );
+        assert_eq!(PollResult::Ready(None), r.poll_body().unwrap());
+        assert_eq!(b"", &*buffer);
+    }
+
+    #[test]
+    fn carry_extensions_with_a_request() {
+        struct RequestId(u64);
+
+        let mut buffer = b"GET /a HTTP/1.1\r\n\r\n".to_vec();
+        let mut r = parse_request(&mut buffer).unwrap();
+        assert!(r.extensions().is_empty());
+
+        r.extensions_mut().insert(RequestId(7));
+        assert_eq!(7, r.extensions().get::<RequestId>().unwrap().0);
+    }
+
+    #[test]
+    fn decompose_and_reassemble_a_request() {
+        let mut buffer = b"POST /a HTTP/1.1\r\n\
+            Content-Length: 5\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            Hello".to_vec();
+
+        let r = parse_request(&mut buffer).unwrap();
+        let (mut head, body) = r.into_parts();
+        assert_eq!(Some(&b"Hello"[..]), body.as_bytes());
+
+        head.set_header("content-length", "6");
+        head.remove_header("Content-Type");
+        let r = Request::from_parts(head, Body::from("Hello!"));
+
+        assert_eq!("/a", r.path());
+        assert_eq!(1, r.headers().count());
+        assert_eq!(Some("6"), r.header_value("Content-Length"));
+        assert_eq!(Some(&b"Hello!"[..]), r.body().as_bytes());
+    }
+
     #[test]
     fn convert_a_parsed_response() {
         let mut buffer = b"HTTP/1.1 404 Not found\r\n\
@@ -665,11 +1380,65 @@ Accept-Language: en-US,en;q=0.5\r\n\r\n".to_vec();
 
         assert_eq!(v2::HttpVersion::Http11, r.version());
         assert_eq!(404, r.status_code());
+        assert_eq!(StatusCode::NotFound, r.status());
         assert_eq!("Not found", r.status_text());
         assert_eq!(
-            ("Host".as_ref(), "www.someserver.com".as_ref()), 
+            ("Host", "www.someserver.com"), 
             r.headers().next().unwrap()
         );
         assert_eq!(b"Hello, World!", &*buffer);
     }
 }
+
+#[cfg(test)]
+mod status_code_should {
+    use super::*;
+
+    #[test]
+    fn round_trip_known_codes() {
+        assert_eq!(StatusCode::NotFound, StatusCode::from(404));
+        assert_eq!(404, StatusCode::NotFound.as_u16());
+        assert_eq!(Some("Not Found"), StatusCode::NotFound.canonical_reason());
+    }
+
+    #[test]
+    fn preserve_unknown_codes() {
+        let status = StatusCode::from(599);
+        assert_eq!(StatusCode::Other(599), status);
+        assert_eq!(599, status.as_u16());
+        assert_eq!(None, status.canonical_reason());
+        assert!(status.is_server_error());
+    }
+
+    #[test]
+    fn classify_codes() {
+        assert!(StatusCode::Continue.is_informational());
+        assert!(StatusCode::NoContent.is_success());
+        assert!(StatusCode::Found.is_redirection());
+        assert!(StatusCode::Gone.is_client_error());
+        assert!(!StatusCode::Gone.is_server_error());
+    }
+
+    #[test]
+    fn chain_builder_methods() {
+        let r = ResponseBuilder::with_status_text(StatusCode::Ok, "Fine")
+            .status(StatusCode::Created)
+            .version(HttpVersion::Http1)
+            .header("Content-Type", "text/plain")
+            .header("Location", "/items/1")
+            .build_with_content("Created");
+
+        assert_eq!(StatusCode::Created, r.status());
+        assert_eq!("Created", r.status_text());
+        assert_eq!(HttpVersion::Http1, r.version());
+        assert_eq!(Some("/items/1"), r.header_value("location"));
+        assert_eq!(2, r.headers().count());
+    }
+
+    #[test]
+    fn use_canonical_reason_when_building() {
+        let r = ResponseBuilder::new(StatusCode::MethodNotAllowed).build();
+        assert_eq!(405, r.status_code());
+        assert_eq!("Method Not Allowed", r.status_text());
+    }
+}
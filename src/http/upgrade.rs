@@ -0,0 +1,139 @@
+//! Upgrading HTTP connections to other protocols.
+//!
+//! A response with an [`OnUpgrade`] extension hands its connection
+//! over, once it's written, to the pollable that `OnUpgrade` creates.
+//! E.g. a handler accepting a WebSocket session responds with
+//! `switching_protocols("websocket", |io| Session::new(io))`, along
+//! with the headers of the handshake.
+//!
+//! [`OnUpgrade`]: struct.OnUpgrade.html
+
+use std::io;
+
+use http::types::{Response, ResponseBuilder, StatusCode};
+use pollable::Pollable;
+use upgrade::{Upgraded, Upgrading};
+
+/// Creates the pollable that serves a connection once it's upgraded,
+/// from the connection's stream.
+pub struct OnUpgrade(Box<dyn FnOnce(Upgraded) -> Upgrading<io::Error>>);
+
+impl OnUpgrade {
+    pub fn new<F, P>(f: F) -> OnUpgrade where
+        F: FnOnce(Upgraded) -> P + 'static,
+        P: Pollable<Item=(), Error=io::Error> + 'static
+    {
+        OnUpgrade(Box::new(move |io| Box::new(f(io)) as Upgrading<io::Error>))
+    }
+
+    pub(crate) fn upgrade(self, io: Upgraded) -> Upgrading<io::Error> {
+        (self.0)(io)
+    }
+}
+
+/// A `101 Switching Protocols` response, that upgrades the connection
+/// to `protocol`, served by the pollable `f` creates.
+pub fn switching_protocols<F, P>(protocol: &str, f: F) -> Response where
+    F: FnOnce(Upgraded) -> P + 'static,
+    P: Pollable<Item=(), Error=io::Error> + 'static
+{
+    let mut response = ResponseBuilder::new(StatusCode::SwitchingProtocols)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", protocol)
+        .build();
+    response.extensions_mut().insert(OnUpgrade::new(f));
+    response
+}
+
+#[cfg(test)]
+mod upgrade_should {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net;
+    use std::thread;
+    use std::time::Duration;
+    use bind_transport::BindTransport;
+    use framed::Framed;
+    use handler::Handler;
+    use http::codec::HttpCodec;
+    use http::transport::HttpTransport;
+    use http::types::Request;
+    use result::PollResult;
+    use server::TcpServer;
+
+    struct Proto;
+
+    impl BindTransport<net::TcpStream> for Proto {
+        type Request = Request;
+        type Response = Response;
+        type Transport = HttpTransport<Framed<net::TcpStream, HttpCodec>>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+            Ok(HttpTransport::new(Framed::new(s, HttpCodec::new())))
+        }
+    }
+
+    /// Echoes whatever arrives, once upgraded.
+    struct Echo(Upgraded);
+
+    impl Pollable for Echo {
+        type Item = ();
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Result<PollResult<()>, io::Error> {
+            let mut buf = [0; 64];
+            loop {
+                match try_poll_io!(self.0.read(&mut buf)) {
+                    0 => return Ok(PollResult::Ready(())),
+                    n => self.0.write_all(&buf[..n])?,
+                }
+            }
+        }
+    }
+
+    struct Upgrade;
+
+    impl Handler for Upgrade {
+        type Request = Request;
+        type Response = Response;
+        type Error = io::Error;
+        type Pollable = io::Result<Response>;
+
+        fn handle(&self, _: Request) -> Self::Pollable {
+            Ok(switching_protocols("echo", Echo))
+        }
+    }
+
+    #[test]
+    fn hand_the_connection_over_once_the_response_is_written() {
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run(|| Upgrade));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // Data sent straight after the request isn't lost.
+        client.write_all(b"GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\nEarly").unwrap();
+
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            client.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("\r\nUpgrade: echo\r\n"));
+
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"Early", &buf);
+
+        client.write_all(b"Later").unwrap();
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"Later", &buf);
+    }
+}
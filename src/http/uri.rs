@@ -0,0 +1,498 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// The error returned when a string can't be parsed as a [`Uri`].
+///
+/// [`Uri`]: struct.Uri.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidUri(&'static str);
+
+impl fmt::Display for InvalidUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid URI: {}", self.0)
+    }
+}
+
+impl Error for InvalidUri {}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes `%XX` escapes in `input`. If `plus_as_space` is set, `+`
+/// is decoded as a space, as it is in query strings and form bodies.
+/// Malformed escapes are left as they are.
+pub(crate) fn decode(input: &[u8], plus_as_space: bool) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < input.len() {
+        match input[i] {
+            b'%' if i + 2 < input.len() => {
+                match (hex_value(input[i + 1]), hex_value(input[i + 2])) {
+                    (Some(h), Some(l)) => {
+                        output.push(h << 4 | l);
+                        i += 3;
+                        continue;
+                    },
+                    _ => output.push(b'%'),
+                }
+            },
+            b'+' if plus_as_space => output.push(b' '),
+            b => output.push(b),
+        }
+        i += 1;
+    }
+
+    output
+}
+
+/// Decodes the `%XX` escapes in `input`. Any bytes that don't form
+/// valid UTF-8 once decoded are replaced with `U+FFFD`.
+pub fn percent_decode(input: &str) -> String {
+    String::from_utf8_lossy(&decode(input.as_bytes(), false)).into_owned()
+}
+
+/// Percent-encodes `input` for use as a single path segment. Only
+/// unreserved characters and the sub-delimiters allowed in a segment
+/// are left as they are, so a `/` in `input` is encoded.
+pub fn percent_encode_segment(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for b in input.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' |
+            b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' |
+            b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@' =>
+                output.push(b as char),
+            _ => output.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    output
+}
+
+/// Splits `key=value&key=value` data, as found in query strings and
+/// urlencoded form bodies, into decoded pairs.
+pub(crate) fn decode_pairs(input: &[u8]) -> Vec<(String, String)> {
+    let decode_lossy = |s: &[u8]|
+        String::from_utf8_lossy(&decode(s, true)).into_owned();
+
+    input.split(|b| *b == b'&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let mut kv = p.splitn(2, |b| *b == b'=');
+            let k = kv.next().unwrap_or(b"");
+            let v = kv.next().unwrap_or(b"");
+            (decode_lossy(k), decode_lossy(v))
+        })
+        .collect()
+}
+
+/// A parsed request target or URL.
+///
+/// `Uri` understands each form a HTTP request target can take
+/// (RFC 7230, section 5.3):
+///
+/// - *origin-form*: `/path?query`
+/// - *absolute-form*: `http://host:port/path?query`
+/// - *authority-form*: `host:port`, as used by `CONNECT`
+/// - *asterisk-form*: `*`, as used by `OPTIONS`
+///
+/// Components are stored as they appeared on the wire. Use
+/// [`decoded_path`] and [`query_pairs`] for their percent-decoded
+/// values.
+///
+/// [`decoded_path`]: #method.decoded_path
+/// [`query_pairs`]: #method.query_pairs
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Uri {
+    scheme: Option<String>,
+    authority: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+/// Splits an authority into its host and port, dropping any user
+/// info. IPv6 hosts are returned without their brackets.
+fn parse_authority(authority: &str) -> Result<(String, Option<u16>), InvalidUri> {
+    let host_port = match authority.rfind('@') {
+        Some(p) => &authority[p + 1..],
+        None => authority,
+    };
+
+    let (host, port) = if let Some(rest) = host_port.strip_prefix('[') {
+        let end = rest.find(']')
+            .ok_or(InvalidUri("unterminated IPv6 address"))?;
+        let port = &rest[end + 1..];
+        if !port.is_empty() && !port.starts_with(':') {
+            return Err(InvalidUri("unexpected data after IPv6 address"));
+        }
+        (&rest[..end], port.get(1..))
+    }
+    else {
+        match host_port.rfind(':') {
+            Some(p) => (&host_port[..p], Some(&host_port[p + 1..])),
+            None => (host_port, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(InvalidUri("missing host"));
+    }
+
+    let port = match port {
+        None | Some("") => None,
+        Some(p) => Some(p.parse().map_err(|_| InvalidUri("invalid port"))?),
+    };
+
+    Ok((String::from(host), port))
+}
+
+fn is_scheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().map(|c| c.is_ascii_alphabetic()).unwrap_or(false) &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+impl Uri {
+    pub fn parse(s: &str) -> Result<Uri, InvalidUri> {
+        if s.is_empty() {
+            return Err(InvalidUri("empty"));
+        }
+
+        if s == "*" {
+            return Ok(Uri::from_path("*"));
+        }
+
+        if s.starts_with('/') {
+            return Ok(Uri::from_path(s));
+        }
+
+        if let Some(p) = s.find("://") {
+            if !is_scheme(&s[..p]) {
+                return Err(InvalidUri("invalid scheme"));
+            }
+
+            let rest = &s[p + 3..];
+            let end = rest.find(['/', '?', '#'])
+                .unwrap_or(rest.len());
+            let authority = &rest[..end];
+            let (host, port) = parse_authority(authority)?;
+
+            let mut uri = Uri::from_path(&rest[end..]);
+            if uri.path.is_empty() {
+                uri.path.push('/');
+            }
+            uri.scheme = Some(s[..p].to_ascii_lowercase());
+            uri.authority = Some(String::from(authority));
+            uri.host = Some(host);
+            uri.port = port;
+            return Ok(uri);
+        }
+
+        if s.contains(['/', '?', '#']) {
+            return Err(InvalidUri("unrecognised request target"));
+        }
+
+        let (host, port) = parse_authority(s)?;
+        Ok(Uri {
+            authority: Some(String::from(s)),
+            host: Some(host),
+            port,
+            ..Uri::default()
+        })
+    }
+
+    /// Splits an origin-form target into its path, query and fragment.
+    fn from_path(s: &str) -> Uri {
+        let (s, fragment) = match s.find('#') {
+            Some(p) => (&s[..p], Some(String::from(&s[p + 1..]))),
+            None => (s, None),
+        };
+        let (path, query) = match s.find('?') {
+            Some(p) => (&s[..p], Some(String::from(&s[p + 1..]))),
+            None => (s, None),
+        };
+
+        Uri {
+            path: String::from(path),
+            query,
+            fragment,
+            ..Uri::default()
+        }
+    }
+
+    /// Creates a `Uri` whose path is `path`, exactly as given.
+    pub(crate) fn from_raw_path(path: &str) -> Uri {
+        Uri {
+            path: String::from(path),
+            ..Uri::default()
+        }
+    }
+
+    /// The scheme, in lower case. E.g. `http`.
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    /// The authority as it appeared in the URI. E.g.
+    /// `user@example.com:8080`.
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_deref()
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// The explicit port or, failing that, the default port of the
+    /// `http` and `https` schemes.
+    pub fn port_or_default(&self) -> Option<u16> {
+        self.port.or_else(|| match self.scheme() {
+            Some("http") => Some(80),
+            Some("https") => Some(443),
+            _ => None,
+        })
+    }
+
+    /// The path, still percent-encoded. Empty for authority-form
+    /// targets.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn decoded_path(&self) -> String {
+        percent_decode(&self.path)
+    }
+
+    /// The query string, without the leading `?`, still
+    /// percent-encoded.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// The decoded `key=value` pairs of the query string, in the order
+    /// they appear.
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        self.query()
+            .map(|q| decode_pairs(q.as_bytes()))
+            .unwrap_or_default()
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// The path and query, as they'd appear in an origin-form request
+    /// target. E.g. `/search?q=rust`.
+    pub fn path_and_query(&self) -> String {
+        match self.query {
+            Some(ref q) => format!("{}?{}", self.path, q),
+            None => self.path.clone(),
+        }
+    }
+}
+
+impl FromStr for Uri {
+    type Err = InvalidUri;
+
+    fn from_str(s: &str) -> Result<Uri, InvalidUri> {
+        Uri::parse(s)
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref scheme) = self.scheme {
+            write!(f, "{}://", scheme)?;
+        }
+        if let Some(ref authority) = self.authority {
+            write!(f, "{}", authority)?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(ref query) = self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(ref fragment) = self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds a [`Uri`] from its components, E.g. for the target of a
+/// client request. Components are used as given, so they should
+/// already be percent-encoded.
+///
+/// [`Uri`]: struct.Uri.html
+#[derive(Default)]
+pub struct UriBuilder {
+    scheme: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    path: String,
+    query: Option<String>,
+}
+
+impl UriBuilder {
+    pub fn new() -> UriBuilder {
+        UriBuilder::default()
+    }
+
+    pub fn scheme(mut self, scheme: &str) -> UriBuilder {
+        self.scheme = Some(scheme.to_ascii_lowercase());
+        self
+    }
+
+    pub fn host(mut self, host: &str) -> UriBuilder {
+        self.host = Some(String::from(host));
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> UriBuilder {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> UriBuilder {
+        self.path = String::from(path);
+        self
+    }
+
+    pub fn query(mut self, query: &str) -> UriBuilder {
+        self.query = Some(String::from(query));
+        self
+    }
+
+    pub fn build(&self) -> Uri {
+        let authority = self.host.as_ref().map(|h| {
+            let host = match h.contains(':') {
+                true => format!("[{}]", h),
+                false => h.clone(),
+            };
+            match self.port {
+                Some(p) => format!("{}:{}", host, p),
+                None => host,
+            }
+        });
+
+        let path = match (authority.is_some(), self.path.starts_with('/')) {
+            (true, false) => format!("/{}", self.path),
+            _ => self.path.clone(),
+        };
+
+        Uri {
+            scheme: self.scheme.clone(),
+            authority,
+            host: self.host.clone(),
+            port: self.port,
+            path,
+            query: self.query.clone(),
+            fragment: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod uri_should {
+    use super::*;
+
+    #[test]
+    fn parse_origin_form() {
+        let uri = Uri::parse("/a%20b/c?x=1&y=hello+world#top").unwrap();
+
+        assert_eq!(None, uri.host());
+        assert_eq!("/a%20b/c", uri.path());
+        assert_eq!("/a b/c", uri.decoded_path());
+        assert_eq!(Some("x=1&y=hello+world"), uri.query());
+        assert_eq!(Some("top"), uri.fragment());
+        assert_eq!(
+            vec![("x".to_string(), "1".to_string()),
+                 ("y".to_string(), "hello world".to_string())],
+            uri.query_pairs()
+        );
+    }
+
+    #[test]
+    fn parse_absolute_form() {
+        let uri = Uri::parse("HTTP://user@example.com:8080?q").unwrap();
+
+        assert_eq!(Some("http"), uri.scheme());
+        assert_eq!(Some("user@example.com:8080"), uri.authority());
+        assert_eq!(Some("example.com"), uri.host());
+        assert_eq!(Some(8080), uri.port());
+        assert_eq!("/", uri.path());
+        assert_eq!(Some("q"), uri.query());
+    }
+
+    #[test]
+    fn parse_authority_form() {
+        let uri = Uri::parse("docs.rs:443").unwrap();
+
+        assert_eq!(Some("docs.rs"), uri.host());
+        assert_eq!(Some(443), uri.port());
+        assert_eq!("", uri.path());
+
+        let uri = Uri::parse("[::1]:8080").unwrap();
+        assert_eq!(Some("::1"), uri.host());
+        assert_eq!(Some(8080), uri.port());
+    }
+
+    #[test]
+    fn default_ports_by_scheme() {
+        assert_eq!(Some(443), Uri::parse("https://example.com/").unwrap()
+            .port_or_default());
+        assert_eq!(None, Uri::parse("/").unwrap().port_or_default());
+    }
+
+    #[test]
+    fn reject_invalid_uris() {
+        assert!(Uri::parse("").is_err());
+        assert!(Uri::parse("http://example.com:http/").is_err());
+        assert!(Uri::parse("http://[::1/").is_err());
+        assert!(Uri::parse("1http://example.com/").is_err());
+        assert!(Uri::parse("example.com/path").is_err());
+    }
+
+    #[test]
+    fn leave_malformed_escapes_alone() {
+        assert_eq!("100%", percent_decode("100%"));
+        assert_eq!("%zz%4", percent_decode("%zz%4"));
+        assert_eq!("a+b", percent_decode("a+b"));
+    }
+
+    #[test]
+    fn encode_path_segments() {
+        assert_eq!("Jo%20Bloggs", percent_encode_segment("Jo Bloggs"));
+        assert_eq!("a%2Fb%3Fc%23d", percent_encode_segment("a/b?c#d"));
+        assert_eq!("caf%C3%A9", percent_encode_segment("café"));
+        assert_eq!("café", percent_decode(&percent_encode_segment("café")));
+    }
+
+    #[test]
+    fn build_and_display() {
+        let uri = UriBuilder::new()
+            .scheme("http")
+            .host("example.com")
+            .port(8080)
+            .path("search")
+            .query("q=rust")
+            .build();
+
+        assert_eq!("http://example.com:8080/search?q=rust", uri.to_string());
+        assert_eq!("/search?q=rust", uri.path_and_query());
+        assert_eq!(uri, Uri::parse(&uri.to_string()).unwrap());
+    }
+}
@@ -0,0 +1,205 @@
+//! Filtering connections by the peer's address, as they're accepted.
+//!
+//! A [`TcpServer`] with an [`AcceptFilter`] closes the connections it
+//! rejects before they're queued, so no transport is bound and nothing
+//! they send is parsed. Closures taking the peer's address are
+//! filters, and [`IpFilter`] allows or denies ranges of addresses.
+//!
+//! [`TcpServer`]: ../server/struct.TcpServer.html
+//! [`AcceptFilter`]: trait.AcceptFilter.html
+//! [`IpFilter`]: struct.IpFilter.html
+
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// Decides whether a connection from `peer` is served.
+pub trait AcceptFilter {
+    fn accept(&self, peer: &SocketAddr) -> bool;
+}
+
+impl<F: Fn(&SocketAddr) -> bool> AcceptFilter for F {
+    fn accept(&self, peer: &SocketAddr) -> bool {
+        self(peer)
+    }
+}
+
+/// The error returned when a string can't be parsed as a [`Cidr`].
+///
+/// [`Cidr`]: struct.Cidr.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidCidr(String);
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid CIDR block: {}", self.0)
+    }
+}
+
+impl Error for InvalidCidr {}
+
+/// A block of IP addresses sharing a prefix. E.g. `10.0.0.0/8`, or
+/// `2001:db8::/32`. A single address, without a prefix length, is a
+/// block of one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// The block of addresses whose first `prefix` bits are those of
+    /// `addr`. Returns `None` if `prefix` is longer than the address.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Cidr> {
+        match prefix <= max_prefix(&addr) {
+            true => Some(Cidr { addr, prefix }),
+            false => None,
+        }
+    }
+
+    /// Whether `addr` is in the block. IPv4 addresses mapped to IPv6,
+    /// as they are on dual-stack listeners, are matched as IPv4.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(block), IpAddr::V4(addr)) =>
+                prefix_matches(&block.octets(), &addr.octets(), self.prefix),
+            (IpAddr::V6(block), IpAddr::V6(addr)) =>
+                prefix_matches(&block.octets(), &addr.octets(), self.prefix),
+            _ => false,
+        }
+    }
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    match *addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn prefix_matches(block: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let whole = (prefix / 8) as usize;
+    if block[..whole] != addr[..whole] {
+        return false;
+    }
+
+    match prefix % 8 {
+        0 => true,
+        bits => {
+            let mask = 0xffu8 << (8 - bits);
+            block[whole] & mask == addr[whole] & mask
+        },
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Cidr, InvalidCidr> {
+        let invalid = || InvalidCidr(String::from(s));
+        let (addr, prefix) = match s.find('/') {
+            Some(index) => (&s[..index], Some(&s[index + 1..])),
+            None => (s, None),
+        };
+
+        let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max_prefix(&addr),
+        };
+
+        Cidr::new(addr, prefix).ok_or_else(invalid)
+    }
+}
+
+/// Allows or denies connections by the block their peer's address is
+/// in. Addresses that are denied are rejected. Otherwise, if any
+/// blocks are allowed, only addresses in them are accepted. E.g.
+/// `IpFilter::new().allow("10.0.0.0/8".parse()?).deny("10.0.0.13".parse()?)`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IpFilter {
+    allowed: Vec<Cidr>,
+    denied: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Creates a filter that accepts every connection.
+    pub fn new() -> IpFilter {
+        IpFilter::default()
+    }
+
+    pub fn allow(mut self, block: Cidr) -> IpFilter {
+        self.allowed.push(block);
+        self
+    }
+
+    pub fn deny(mut self, block: Cidr) -> IpFilter {
+        self.denied.push(block);
+        self
+    }
+}
+
+impl AcceptFilter for IpFilter {
+    fn accept(&self, peer: &SocketAddr) -> bool {
+        let ip = peer.ip();
+        if self.denied.iter().any(|block| block.contains(&ip)) {
+            return false;
+        }
+
+        self.allowed.is_empty() || self.allowed.iter().any(|block| block.contains(&ip))
+    }
+}
+
+#[cfg(test)]
+mod ip_filter_should {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn peer(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 4000)
+    }
+
+    #[test]
+    fn parse_blocks() {
+        assert_eq!(Cidr::new("10.0.0.0".parse().unwrap(), 8), Some(cidr("10.0.0.0/8")));
+        assert_eq!(Cidr::new("::1".parse().unwrap(), 128), Some(cidr("::1")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn match_addresses_by_prefix() {
+        let block = cidr("192.168.4.0/22");
+        assert!(block.contains(&"192.168.7.255".parse().unwrap()));
+        assert!(!block.contains(&"192.168.8.0".parse().unwrap()));
+        assert!(block.contains(&"::ffff:192.168.5.1".parse().unwrap()));
+        assert!(!block.contains(&"2001:db8::1".parse().unwrap()));
+
+        assert!(cidr("2001:db8::/32").contains(&"2001:db8:ffff::1".parse().unwrap()));
+        assert!(cidr("0.0.0.0/0").contains(&"8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn deny_before_allowing() {
+        let filter = IpFilter::new()
+            .allow(cidr("10.0.0.0/8"))
+            .deny(cidr("10.0.0.13"));
+
+        assert!(filter.accept(&peer("10.1.2.3")));
+        assert!(!filter.accept(&peer("10.0.0.13")));
+        assert!(!filter.accept(&peer("192.168.0.1")));
+    }
+
+    #[test]
+    fn accept_everything_not_denied_without_allowed_blocks() {
+        let filter = IpFilter::new().deny(cidr("203.0.113.0/24"));
+
+        assert!(filter.accept(&peer("198.51.100.7")));
+        assert!(!filter.accept(&peer("203.0.113.7")));
+    }
+}
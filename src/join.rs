@@ -21,8 +21,8 @@ pub struct Join<L: Pollable, R: Pollable> {
 impl<L: Pollable, R: Pollable> Join<L, R> {
     pub fn new(left: L, right: R) -> Join<L, R> {
         Join {
-            left: left,
-            right: right,
+            left,
+            right,
             state: JoinState::Niether,
         }
 //        Join::Neither(left, right)
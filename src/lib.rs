@@ -1,3 +1,26 @@
+extern crate mio;
+extern crate socket2;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "regex")]
+extern crate regex;
+#[cfg(feature = "compression")]
+extern crate flate2;
+#[cfg(feature = "sessions")]
+extern crate hmac;
+#[cfg(feature = "sessions")]
+extern crate sha2;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate rustls_pki_types;
+#[cfg(feature = "native-tls")]
+extern crate native_tls;
+#[cfg(unix)]
+extern crate libc;
+
 #[macro_export]
 macro_rules! try_poll_io {
     ($e:expr) => {{
@@ -12,17 +35,40 @@ macro_rules! try_poll_io {
 }
 
 pub mod server;
+pub mod client;
+pub mod resolver;
 pub mod bind_transport;
+pub mod blocking;
+pub mod budget;
+pub mod readiness;
 pub mod handler;
 pub mod pollable;
 pub mod codec;
 pub mod framed;
 pub mod sink;
+pub mod split;
 pub mod join;
 pub mod and_then;
 pub mod result;
 pub mod twist;
 pub mod http;
 pub mod connection;
+pub mod connected;
+pub mod current_thread;
+pub mod executor;
+pub mod ip_filter;
+pub mod lifecycle;
+pub mod load;
 pub mod map_err;
-mod thread_pool;
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+pub mod tls;
+pub mod udp;
+pub mod upgrade;
+#[cfg(all(unix, feature = "signals"))]
+pub mod signal;
+#[cfg(all(target_os = "linux", feature = "affinity"))]
+pub mod affinity;
+#[cfg(all(target_os = "linux", feature = "splice"))]
+mod splice;
+pub mod thread_pool;
+mod timer;
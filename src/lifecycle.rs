@@ -0,0 +1,192 @@
+//! Following connections as they open and close.
+//!
+//! A server with [`ConnectionHooks`] tells them about each connection
+//! it serves, once it's accepted and again when it's closed, along
+//! with why. Applications can use them to track who's connected, count
+//! connections for their metrics, or release what they hold for each
+//! one. They're also told when a connection can't be accepted because
+//! the process has run out of file descriptors.
+//!
+//! [`ConnectionHooks`]: trait.ConnectionHooks.html
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Numbers connections, across every server in the process.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A connection that's being served.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionContext {
+    /// Identifies the connection, among those the process has served.
+    pub id: u64,
+    pub peer_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    /// When the connection was accepted.
+    pub opened: Instant,
+}
+
+impl ConnectionContext {
+    pub(crate) fn new(peer_addr: SocketAddr, local_addr: SocketAddr) -> ConnectionContext {
+        ConnectionContext {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            peer_addr,
+            local_addr,
+            opened: Instant::now(),
+        }
+    }
+}
+
+/// Why a connection was closed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Disconnect {
+    /// The connection finished, e.g. because the peer closed it.
+    Closed,
+    /// The connection was past one of its timeouts.
+    TimedOut,
+    /// The server was stopped.
+    Stopped,
+    /// Binding the transport, or serving a request, failed with the
+    /// error described.
+    Failed(String),
+}
+
+/// Called as a server's connections open and close. Each does nothing
+/// by default. They're called on the thread serving the connection, so
+/// they shouldn't block.
+pub trait ConnectionHooks {
+    fn on_connect(&self, _ctx: &ConnectionContext) {}
+
+    fn on_disconnect(&self, _ctx: &ConnectionContext, _reason: &Disconnect) {}
+
+    /// Called, on the thread accepting connections, when the process
+    /// has run out of file descriptors, or memory, for another. The
+    /// server stops accepting for a moment, then tries again.
+    fn on_accept_error(&self, _error: &io::Error) {}
+}
+
+#[cfg(test)]
+mod connection_hooks_should {
+    use super::*;
+    use std::io::{self, Read, Write};
+    use std::net;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use server::TcpServer;
+
+    struct Proto;
+
+    impl ::bind_transport::BindTransport<net::TcpStream> for Proto {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Transport = ::framed::Framed<net::TcpStream, Codec>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+            Ok(::framed::Framed::new(s, Codec))
+        }
+    }
+
+    struct Codec;
+
+    impl ::codec::Decode for Codec {
+        type Item = Vec<u8>;
+
+        fn decode(&self, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+            Some(::std::mem::take(buffer))
+        }
+    }
+
+    impl ::codec::Encode for Codec {
+        type Item = Vec<u8>;
+
+        fn encode(&self, item: Vec<u8>, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item);
+            Ok(())
+        }
+    }
+
+    struct Echo;
+
+    impl ::handler::Handler for Echo {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = io::Result<Vec<u8>>;
+
+        fn handle(&self, request: Vec<u8>) -> Self::Pollable {
+            Ok(request)
+        }
+    }
+
+    /// A connection's id, and why it closed, if it has.
+    type Event = (u64, Option<Disconnect>);
+
+    #[derive(Clone, Default)]
+    struct Record(Arc<Mutex<Vec<Event>>>);
+
+    impl ConnectionHooks for Record {
+        fn on_connect(&self, ctx: &ConnectionContext) {
+            self.0.lock().unwrap().push((ctx.id, None));
+        }
+
+        fn on_disconnect(&self, ctx: &ConnectionContext, reason: &Disconnect) {
+            self.0.lock().unwrap().push((ctx.id, Some(reason.clone())));
+        }
+    }
+
+    impl Record {
+        fn wait_for(&self, events: usize) -> Vec<Event> {
+            for _ in 0..500 {
+                if self.0.lock().unwrap().len() >= events {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn report_connections_opening_and_closing() {
+        let record = Record::default();
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .keep_alive_timeout(Duration::from_millis(50))
+            .hooks(record.clone())
+            .build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let running = thread::spawn(move || server.run(|| Echo));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.write_all(b"Hello").unwrap();
+        client.read_exact(&mut [0; 5]).unwrap();
+        drop(client);
+        let events = record.wait_for(2);
+        let closed = events[0].0;
+        assert_eq!(vec![(closed, None), (closed, Some(Disconnect::Closed))], events);
+
+        // Left idle, until it's closed.
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.write_all(b"Hello").unwrap();
+        client.read_exact(&mut [0; 5]).unwrap();
+        let events = record.wait_for(4);
+        let idle = events[2].0;
+        assert_eq!(vec![(idle, None), (idle, Some(Disconnect::TimedOut))], events[2..]);
+
+        let _open = net::TcpStream::connect(addr).unwrap();
+        let events = record.wait_for(5);
+        handle.stop();
+        running.join().unwrap().unwrap();
+
+        let stopped = events[4].0;
+        assert_eq!(vec![(stopped, None), (stopped, Some(Disconnect::Stopped))],
+                   record.wait_for(6)[4..]);
+    }
+}
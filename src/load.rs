@@ -0,0 +1,171 @@
+//! Counting what a server's workers are doing.
+//!
+//! Each worker keeps count of the connections it's serving, and of the
+//! requests among them that are being handled or responded to, along
+//! with how hard it's working to serve them. A [`ServerHandle`] reads
+//! the counts, for capacity planning or to decide when to shed load,
+//! without stopping the workers.
+//!
+//! [`ServerHandle`]: ../server/struct.ServerHandle.html
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use handler::Handler;
+use pollable::{IntoPollable, Pollable};
+use result::PollResult;
+
+/// What a worker was doing when its counts were read.
+///
+/// `iterations` and `handler_time` only ever grow. Rates, such as
+/// iterations per second, come from the difference between two
+/// readings. A worker whose loop turns far more often than its
+/// handlers take time is spinning on busy connections, rather than
+/// doing the handlers' work.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkerLoad {
+    /// The connections the worker is serving.
+    pub connections: usize,
+    /// The requests that have been read, and whose responses haven't
+    /// been written yet.
+    pub requests: usize,
+    /// The connections handed to the worker that it hasn't started
+    /// serving yet.
+    pub queued: usize,
+    /// How many times the worker's loop has turned.
+    pub iterations: u64,
+    /// The time spent in the handler, creating and polling responses.
+    pub handler_time: Duration,
+}
+
+/// The counts a worker updates as it serves connections.
+#[derive(Debug, Default)]
+pub(crate) struct LoadCounters {
+    connections: AtomicUsize,
+    requests: AtomicUsize,
+    queued: AtomicUsize,
+    iterations: AtomicU64,
+    /// In nanoseconds.
+    handler_time: AtomicU64,
+}
+
+impl LoadCounters {
+    pub(crate) fn load(&self) -> WorkerLoad {
+        WorkerLoad {
+            connections: self.connections.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            iterations: self.iterations.load(Ordering::Relaxed),
+            handler_time: Duration::from_nanos(self.handler_time.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub(crate) fn queued(&self, connections: usize) {
+        self.queued.fetch_add(connections, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dequeued(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn turned(&self) {
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handled_for(&self, time: Duration) {
+        self.handler_time.fetch_add(time.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn closed(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn started(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn finished(&self) {
+        self.requests.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Forgets every connection and request, once the worker has
+    /// stopped.
+    pub(crate) fn clear(&self) {
+        self.connections.store(0, Ordering::Relaxed);
+        self.requests.store(0, Ordering::Relaxed);
+        self.queued.store(0, Ordering::Relaxed);
+    }
+}
+
+/// A handler whose time is added to a worker's `handler_time`.
+pub(crate) struct Timed<H> {
+    handler: Arc<H>,
+    load: Arc<LoadCounters>,
+}
+
+impl<H> Timed<H> {
+    pub(crate) fn new(handler: Arc<H>, load: Arc<LoadCounters>) -> Timed<H> {
+        Timed {
+            handler,
+            load,
+        }
+    }
+}
+
+impl<H: Handler> Handler for Timed<H> {
+    type Request = H::Request;
+    type Response = H::Response;
+    type Error = H::Error;
+    type Pollable = TimedPoll<<H::Pollable as IntoPollable>::Pollable>;
+
+    fn handle(&self, request: H::Request) -> Self::Pollable {
+        let started = Instant::now();
+        let inner = self.handler.handle(request).into_pollable();
+        self.load.handled_for(started.elapsed());
+        TimedPoll {
+            inner,
+            load: self.load.clone(),
+        }
+    }
+
+    fn timed_out(&self) -> Option<H::Response> {
+        self.handler.timed_out()
+    }
+}
+
+pub(crate) struct TimedPoll<P> {
+    inner: P,
+    load: Arc<LoadCounters>,
+}
+
+impl<P: Pollable> Pollable for TimedPoll<P> {
+    type Item = P::Item;
+    type Error = P::Error;
+
+    fn poll(&mut self) -> Result<PollResult<P::Item>, P::Error> {
+        let started = Instant::now();
+        let result = self.inner.poll();
+        self.load.handled_for(started.elapsed());
+        result
+    }
+}
+
+/// The counters of a running server's workers. They're replaced each
+/// time the server is run.
+#[derive(Clone, Default)]
+pub(crate) struct Loads(Arc<Mutex<Vec<Arc<LoadCounters>>>>);
+
+impl Loads {
+    pub(crate) fn set(&self, workers: Vec<Arc<LoadCounters>>) {
+        *self.0.lock().unwrap() = workers;
+    }
+
+    pub(crate) fn workers(&self) -> Vec<WorkerLoad> {
+        self.0.lock().unwrap().iter().map(|w| w.load()).collect()
+    }
+}
@@ -32,6 +32,14 @@ pub trait Pollable {
     {
         MapErr::new(self, f)
     }
+
+    /// For a stream of requests, whether the head of the next one has
+    /// arrived while the rest of it, e.g. its body, is still to come.
+    /// Servers stop timing how long a head takes to arrive once it
+    /// has. By default, nothing has arrived until the whole item has.
+    fn head_received(&self) -> bool {
+        false
+    }
 }
 
 impl<P: Pollable + ?Sized> Pollable for Box<P> {
@@ -39,7 +47,11 @@ impl<P: Pollable + ?Sized> Pollable for Box<P> {
     type Error = P::Error;
 
     fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
-        (&mut **self).poll()
+        (**self).poll()
+    }
+
+    fn head_received(&self) -> bool {
+        (**self).head_received()
     }
 }
 
@@ -0,0 +1,352 @@
+//! Waking a worker's connections once they can make progress.
+//!
+//! A worker sleeps until one of its sockets is ready, and only polls a
+//! connection again once there's a reason to. The connection's own
+//! socket is one, which the worker watches itself. A pollable that
+//! returns `NotReady` while it waits on anything else arranges to be
+//! woken, while its connection is being polled, with one of:
+//!
+//! - a [`Watch`], for a TCP stream of its own, e.g. to an upstream
+//!   server, which wakes the connection whenever the stream is ready;
+//! - [`wake_at`], for a deadline, e.g. a delay before trying again;
+//! - a [`Wakeup`], or the [`Notify`] from [`current`], for anything
+//!   else, e.g. work done on another thread;
+//! - [`socket_blocked`], for a transport whose writes to the
+//!   connection's own socket would block.
+//!
+//! Tasks that a worker runs arrange to be woken in the same way. A
+//! connection or task that waits without arranging anything is polled
+//! again on each turn of its worker, which costs its worker a little
+//! each time. Pollables that aren't polled by a worker have nothing to
+//! arrange, and these do nothing for them.
+//!
+//! [`Watch`]: struct.Watch.html
+//! [`wake_at`]: fn.wake_at.html
+//! [`Wakeup`]: struct.Wakeup.html
+//! [`Notify`]: struct.Notify.html
+//! [`current`]: fn.current.html
+//! [`socket_blocked`]: fn.socket_blocked.html
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use mio::{Registry, Waker};
+
+/// What a worker shares with its connections' `Notify`s.
+struct Shared {
+    /// The connections that have been notified since the worker's last
+    /// turn.
+    woken: Mutex<Vec<usize>>,
+    waker: Arc<Waker>,
+    /// Registers the streams that connections watch, if the worker's
+    /// registry could be shared.
+    registry: Option<Registry>,
+}
+
+/// What's been arranged to wake the connection being polled.
+#[derive(Default)]
+pub(crate) struct Arranged {
+    /// Whether anything has been.
+    pub(crate) any: bool,
+    pub(crate) deadlines: Vec<Instant>,
+}
+
+/// The connection being polled on this thread's worker.
+struct Polling {
+    index: usize,
+    shared: Arc<Shared>,
+    arranged: Arranged,
+}
+
+thread_local! {
+    static POLLING: RefCell<Option<Polling>> = const { RefCell::new(None) };
+}
+
+/// Calls `f` with the connection being polled, if there is one, and
+/// counts a wake as arranged if `f` returns `true`.
+fn arrange<F: FnOnce(&mut Polling) -> bool>(f: F) {
+    POLLING.with(|polling| if let Some(ref mut polling) = *polling.borrow_mut() {
+        if f(polling) {
+            polling.arranged.any = true;
+        }
+    });
+}
+
+/// Wakes a worker's connection from any thread. See [`current`].
+///
+/// [`current`]: fn.current.html
+#[derive(Clone)]
+pub struct Notify {
+    index: usize,
+    shared: Arc<Shared>,
+}
+
+impl Notify {
+    /// Has the connection polled on its worker's next turn.
+    pub fn notify(&self) {
+        self.shared.woken.lock().unwrap().push(self.index);
+        let _ = self.shared.waker.wake();
+    }
+}
+
+/// Returns a `Notify` that wakes the connection being polled, if
+/// there is one. Taking it counts as arranging to wake the connection,
+/// so the pollable must see that it's called once it can make
+/// progress.
+pub fn current() -> Option<Notify> {
+    let mut notify = None;
+    arrange(|polling| {
+        notify = Some(Notify {
+            index: polling.index,
+            shared: polling.shared.clone(),
+        });
+        true
+    });
+    notify
+}
+
+/// Wakes the connection being polled once `deadline` has passed.
+pub fn wake_at(deadline: Instant) {
+    arrange(|polling| {
+        polling.arranged.deadlines.push(deadline);
+        true
+    });
+}
+
+/// Notes that the connection being polled is waiting for its own
+/// socket to take more of what it's writing. The connection's worker
+/// watches the socket while it's writing, so this arranges a wake.
+///
+/// Only transports, or pollables given the connection's `Upgraded`
+/// stream, write to the connection's own socket.
+pub fn socket_blocked() {
+    arrange(|_| true);
+}
+
+/// Wakes whichever connection is waiting on it, from any thread, e.g.
+/// once work done on another thread has finished. Clones share the
+/// connection that's waiting.
+///
+/// A pollable calls `wait` before checking whether what it's waiting
+/// on has finished, and whatever finishes it calls `wake` after it has,
+/// so the wake can't be missed.
+#[derive(Clone, Default)]
+pub struct Wakeup(Arc<Mutex<Option<Notify>>>);
+
+impl Wakeup {
+    pub fn new() -> Wakeup {
+        Wakeup::default()
+    }
+
+    /// Arranges for the connection being polled, if there is one, to be
+    /// woken by the next `wake`.
+    pub fn wait(&self) {
+        if let Some(notify) = current() {
+            *self.0.lock().unwrap() = Some(notify);
+        }
+    }
+
+    /// Wakes the connection that last waited, if there is one.
+    pub fn wake(&self) {
+        if let Some(notify) = self.0.lock().unwrap().take() {
+            notify.notify();
+        }
+    }
+}
+
+/// Wakes the connection being polled whenever a TCP stream of its own
+/// is ready to be read or written, e.g. a stream to an upstream server.
+/// A pollable keeps a `Watch` for as long as it has the stream.
+///
+/// Only `std` and `mio` TCP streams can be watched. For other streams,
+/// nothing is arranged.
+#[derive(Debug, Default)]
+pub struct Watch {
+    /// The connection the stream is registered for, if it is.
+    registered: Option<usize>,
+}
+
+impl Watch {
+    pub fn new() -> Watch {
+        Watch::default()
+    }
+
+    /// Arranges for the connection being polled to be woken once
+    /// `stream` is ready. The stream's registered with the connection's
+    /// worker the first time.
+    pub fn watch<S: Any>(&mut self, stream: &S) {
+        let registered = &mut self.registered;
+        arrange(|polling| {
+            if *registered == Some(polling.index) {
+                return true;
+            }
+
+            let registry = match polling.shared.registry {
+                Some(ref registry) => registry,
+                None => return false,
+            };
+            let watched = register(registry, stream as &dyn Any, polling.index);
+            if watched {
+                *registered = Some(polling.index);
+            }
+            watched
+        });
+    }
+}
+
+/// The streams that connections watch are registered with their
+/// connection's index plus `WATCHED`, so the worker can tell them from
+/// the connection's own socket.
+pub(crate) const WATCHED: usize = 1 << (usize::BITS - 2);
+
+/// Registers `stream`, if it's a TCP stream, for the connection at
+/// `index`. A stream that's already registered, e.g. for a connection
+/// that was served before, is registered again.
+#[cfg(unix)]
+fn register(registry: &Registry, stream: &dyn Any, index: usize) -> bool {
+    use std::io;
+    use std::net;
+    use std::os::unix::io::AsRawFd;
+    use mio::{Interest, Token};
+    use mio::unix::SourceFd;
+
+    let fd = match (stream.downcast_ref::<net::TcpStream>(),
+                    stream.downcast_ref::<mio::net::TcpStream>()) {
+        (Some(stream), _) => stream.as_raw_fd(),
+        (_, Some(stream)) => stream.as_raw_fd(),
+        _ => return false,
+    };
+
+    let token = Token(index | WATCHED);
+    let interest = Interest::READABLE | Interest::WRITABLE;
+    match registry.register(&mut SourceFd(&fd), token, interest) {
+        Ok(()) => true,
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists =>
+            registry.reregister(&mut SourceFd(&fd), token, interest).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn register(_: &Registry, _: &dyn Any, _: usize) -> bool {
+    false
+}
+
+/// Lets the connections a worker polls arrange to be woken.
+pub(crate) struct Readiness(Arc<Shared>);
+
+impl Readiness {
+    /// Connections are woken through `waker`, and the streams they
+    /// watch are registered with a clone of `registry`.
+    pub(crate) fn new(registry: &Registry, waker: Arc<Waker>) -> Readiness {
+        Readiness(Arc::new(Shared {
+            woken: Mutex::default(),
+            waker,
+            registry: registry.try_clone().ok(),
+        }))
+    }
+
+    /// Calls `f`, which polls the connection at `index`, returning what
+    /// it returns, and what it arranged.
+    pub(crate) fn poll<F, T>(&self, index: usize, f: F) -> (T, Arranged) where
+        F: FnOnce() -> T
+    {
+        let polling = Polling {
+            index,
+            shared: self.0.clone(),
+            arranged: Arranged::default(),
+        };
+        let outer = POLLING.with(|p| p.replace(Some(polling)));
+        let result = f();
+        let polled = POLLING.with(|p| p.replace(outer));
+        (result, polled.map(|p| p.arranged).unwrap_or_default())
+    }
+
+    /// The connections that have been notified since this was last
+    /// called.
+    pub(crate) fn woken(&self) -> Vec<usize> {
+        mem::take(&mut *self.0.woken.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod readiness_should {
+    use super::*;
+    use mio::{Poll, Token};
+    use std::time::Duration;
+
+    fn readiness(poll: &Poll) -> Readiness {
+        let waker = Arc::new(Waker::new(poll.registry(), Token(usize::MAX)).unwrap());
+        Readiness::new(poll.registry(), waker)
+    }
+
+    #[test]
+    fn arrange_nothing_outside_a_worker() {
+        assert!(current().is_none());
+        wake_at(Instant::now());
+        socket_blocked();
+        Wakeup::new().wait();
+    }
+
+    #[test]
+    fn note_what_the_connection_arranged() {
+        let poll = Poll::new().unwrap();
+        let readiness = readiness(&poll);
+
+        let ((), arranged) = readiness.poll(3, || {});
+        assert!(!arranged.any);
+
+        let deadline = Instant::now();
+        let ((), arranged) = readiness.poll(3, || wake_at(deadline));
+        assert!(arranged.any);
+        assert_eq!(vec![deadline], arranged.deadlines);
+
+        let ((), arranged) = readiness.poll(3, socket_blocked);
+        assert!(arranged.any);
+    }
+
+    #[test]
+    fn wake_the_connection_that_waited() {
+        let mut poll = Poll::new().unwrap();
+        let readiness = readiness(&poll);
+        let wakeup = Wakeup::new();
+
+        let ((), arranged) = readiness.poll(7, || wakeup.wait());
+        assert!(arranged.any);
+        assert!(readiness.woken().is_empty());
+
+        ::std::thread::spawn(move || wakeup.wake()).join().unwrap();
+        let mut events = ::mio::Events::with_capacity(4);
+        poll.poll(&mut events, Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(1, events.iter().count());
+        assert_eq!(vec![7], readiness.woken());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wake_the_connection_once_a_stream_it_watches_is_ready() {
+        use std::io::Write;
+        use std::net;
+
+        let mut poll = Poll::new().unwrap();
+        let readiness = readiness(&poll);
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        let mut watch = Watch::new();
+        let ((), arranged) = readiness.poll(2, || watch.watch(&stream));
+        assert!(arranged.any);
+        let ((), arranged) = readiness.poll(2, || Watch::new().watch(&"Not a stream"));
+        assert!(!arranged.any);
+
+        peer.write_all(b"Hello").unwrap();
+        let mut events = ::mio::Events::with_capacity(4);
+        poll.poll(&mut events, Some(Duration::from_secs(5))).unwrap();
+        assert!(events.iter().all(|e| e.token() == Token(2 | WATCHED)));
+        assert!(events.iter().any(|e| e.is_readable()));
+    }
+}
@@ -0,0 +1,183 @@
+//! Resolving the names of servers to their addresses, without blocking.
+//!
+//! `ToSocketAddrs` blocks while a name is looked up, which would stall
+//! every connection on a worker. A [`Resolver`] returns a pollable
+//! instead. [`ThreadedResolver`] looks names up on threads of their
+//! own, and other implementations can query DNS servers directly, or
+//! answer from a cache or a fixed table.
+//!
+//! [`Resolver`]: trait.Resolver.html
+//! [`ThreadedResolver`]: struct.ThreadedResolver.html
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+use pollable::{IntoPollable, Pollable};
+use readiness::Wakeup;
+use result::PollResult;
+
+/// A lookup in progress, that resolves to a name's addresses.
+pub type Resolving = Box<dyn Pollable<Item=Vec<SocketAddr>, Error=io::Error>>;
+
+/// Looks up the addresses of a host's name.
+pub trait Resolver {
+    /// Resolves `host` to its addresses, each with `port`.
+    fn resolve(&self, host: &str, port: u16) -> Resolving;
+}
+
+/// What a client connects to: either addresses, or a name to resolve.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Addrs(Vec<SocketAddr>),
+    Name(String, u16),
+}
+
+/// Converts to an [`Endpoint`]. Like `ToSocketAddrs`, but names are
+/// left for a [`Resolver`], rather than looked up straight away.
+///
+/// [`Endpoint`]: enum.Endpoint.html
+/// [`Resolver`]: trait.Resolver.html
+pub trait ToEndpoint {
+    fn to_endpoint(&self) -> io::Result<Endpoint>;
+}
+
+impl ToEndpoint for Endpoint {
+    fn to_endpoint(&self) -> io::Result<Endpoint> {
+        Ok(self.clone())
+    }
+}
+
+impl ToEndpoint for SocketAddr {
+    fn to_endpoint(&self) -> io::Result<Endpoint> {
+        Ok(Endpoint::Addrs(vec![*self]))
+    }
+}
+
+impl ToEndpoint for &[SocketAddr] {
+    fn to_endpoint(&self) -> io::Result<Endpoint> {
+        Ok(Endpoint::Addrs(self.to_vec()))
+    }
+}
+
+impl ToEndpoint for (&str, u16) {
+    fn to_endpoint(&self) -> io::Result<Endpoint> {
+        let (host, port) = *self;
+        match host.parse::<IpAddr>() {
+            Ok(ip) => Ok(Endpoint::Addrs(vec![SocketAddr::new(ip, port)])),
+            Err(_) => Ok(Endpoint::Name(String::from(host), port)),
+        }
+    }
+}
+
+/// Parses `"host:port"`, where `host` is a name or an address. IPv6
+/// addresses are in brackets, e.g. `"[::1]:80"`.
+impl ToEndpoint for str {
+    fn to_endpoint(&self) -> io::Result<Endpoint> {
+        if let Ok(addr) = self.parse::<SocketAddr>() {
+            return Ok(Endpoint::Addrs(vec![addr]));
+        }
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput,
+                                        "expected an address or name, with a port");
+        let index = self.rfind(':').ok_or_else(invalid)?;
+        let port = self[index + 1..].parse::<u16>().map_err(|_| invalid())?;
+        match &self[..index] {
+            "" => Err(invalid()),
+            host => Ok(Endpoint::Name(String::from(host), port)),
+        }
+    }
+}
+
+impl ToEndpoint for &str {
+    fn to_endpoint(&self) -> io::Result<Endpoint> {
+        (**self).to_endpoint()
+    }
+}
+
+impl ToEndpoint for String {
+    fn to_endpoint(&self) -> io::Result<Endpoint> {
+        self.as_str().to_endpoint()
+    }
+}
+
+/// Looks each name up with `ToSocketAddrs`, on a thread of its own.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadedResolver;
+
+impl Resolver for ThreadedResolver {
+    fn resolve(&self, host: &str, port: u16) -> Resolving {
+        let (sender, receiver) = channel();
+        let host = String::from(host);
+        let wakeup = Wakeup::new();
+        let done = wakeup.clone();
+        let spawned = thread::Builder::new()
+            .name(String::from("server-fx resolver"))
+            .spawn(move || {
+                let addrs = (host.as_str(), port).to_socket_addrs().map(Iterator::collect);
+                let _ = sender.send(addrs);
+                done.wake();
+            });
+
+        match spawned {
+            Ok(_) => Box::new(Lookup { receiver, wakeup }),
+            Err(e) => Box::new(Err(e).into_pollable()),
+        }
+    }
+}
+
+/// A lookup running on another thread, which wakes the connection
+/// waiting on it once it's done.
+struct Lookup {
+    receiver: Receiver<io::Result<Vec<SocketAddr>>>,
+    wakeup: Wakeup,
+}
+
+impl Pollable for Lookup {
+    type Item = Vec<SocketAddr>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        self.wakeup.wait();
+        match self.receiver.try_recv() {
+            Ok(addrs) => addrs.map(PollResult::Ready),
+            Err(TryRecvError::Empty) => Ok(PollResult::NotReady),
+            Err(TryRecvError::Disconnected) =>
+                Err(io::Error::other("the lookup's thread panicked")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod resolver_should {
+    use super::*;
+    use std::time::Duration;
+
+    fn wait(mut resolving: Resolving) -> io::Result<Vec<SocketAddr>> {
+        loop {
+            if let PollResult::Ready(addrs) = resolving.poll()? {
+                return Ok(addrs);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn leave_names_to_be_resolved() {
+        let addr = "127.0.0.1:80".parse().unwrap();
+        assert_eq!(Endpoint::Addrs(vec![addr]), "127.0.0.1:80".to_endpoint().unwrap());
+        assert_eq!(Endpoint::Addrs(vec![addr]), ("127.0.0.1", 80).to_endpoint().unwrap());
+        assert_eq!(Endpoint::Name("example.com".into(), 443),
+                   "example.com:443".to_endpoint().unwrap());
+        assert!("example.com".to_endpoint().is_err());
+        assert!(":80".to_endpoint().is_err());
+    }
+
+    #[test]
+    fn resolve_names_on_another_thread() {
+        let addrs = wait(ThreadedResolver.resolve("localhost", 8080)).unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback() && addr.port() == 8080));
+    }
+}
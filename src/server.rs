@@ -1,29 +1,214 @@
-use std::net::{self, ToSocketAddrs};
-use std::io;
-use std::sync::Arc;
+use std::io::{self, Write};
+use std::net::{self, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+use mio::{Events, Interest, Poll, Token, Waker};
+use socket2::{Domain, SockRef, Socket, TcpKeepalive, Type};
+
+#[cfg(all(target_os = "linux", feature = "affinity"))]
+use affinity::Affinity;
 use bind_transport::BindTransport;
+use current_thread::CurrentThreadServer;
+use executor::{Executor, Inbox, Spawn};
 use handler::Handler;
+use ip_filter::AcceptFilter;
 use pollable::{IntoPollable, Pollable};
+use result::PollResult;
 use sink::Sink;
-use thread_pool::ThreadPool;
+use lifecycle::ConnectionHooks;
+use load::{Loads, WorkerLoad};
+use readiness::{Arranged, Readiness};
+use thread_pool::{Hooks, Queue, ThreadPool};
+
+/// The settings a [`TcpServer`] runs with. See [`ServerBuilder`] for
+/// what each one means, and its default.
+///
+/// [`TcpServer`]: struct.TcpServer.html
+/// [`ServerBuilder`]: struct.ServerBuilder.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub threads: usize,
+    pub backlog: i32,
+    pub read_buffer_size: usize,
+    pub write_buffer_size: usize,
+    pub read_timeout: Option<Duration>,
+    pub header_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub keep_alive_timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub max_requests_per_connection: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub shutdown_grace: Duration,
+    pub overload: Overload,
+    pub socket: SocketOptions,
+    #[cfg(all(target_os = "linux", feature = "affinity"))]
+    pub affinity: Option<Affinity>,
+}
 
-const NUM_THREADS: usize = 4;
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            threads: 4,
+            backlog: 128,
+            read_buffer_size: 1024,
+            write_buffer_size: 1024,
+            read_timeout: None,
+            header_timeout: None,
+            write_timeout: None,
+            keep_alive_timeout: None,
+            request_timeout: None,
+            max_requests_per_connection: None,
+            max_connections: None,
+            shutdown_grace: Duration::from_secs(30),
+            overload: Overload::Close,
+            socket: SocketOptions::default(),
+            #[cfg(all(target_os = "linux", feature = "affinity"))]
+            affinity: None,
+        }
+    }
+}
 
 pub struct TcpServer<P> {
-    proto: Arc<P>,
+    pub(crate) proto: Arc<P>,
+    pub(crate) config: Arc<ServerConfig>,
+    pub(crate) hooks: Hooks,
+    filter: Option<Box<dyn AcceptFilter + Send + Sync>>,
 }
 
-impl<P> TcpServer<P> 
-    where P: BindTransport<net::TcpStream> + Send + Sync + 'static,
+impl<P> TcpServer<P>
+    where P: BindTransport<net::TcpStream>,
 {
+    /// Creates a server with the default [`ServerConfig`].
+    ///
+    /// [`ServerConfig`]: struct.ServerConfig.html
     pub fn new(proto: P) -> TcpServer<P> {
-        TcpServer { 
-            proto: Arc::new(proto) 
+        TcpServer::builder(proto).build()
+    }
+
+    pub fn builder(proto: P) -> ServerBuilder<P> {
+        ServerBuilder {
+            proto,
+            config: ServerConfig::default(),
+            hooks: None,
+            filter: None,
         }
     }
 
-    pub fn serve<S, F, H>(self, s: S, f: F) -> io::Result<()> where 
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+}
+
+impl<P> TcpServer<P>
+    where P: BindTransport<net::TcpStream> + Send + Sync + 'static,
+{
+    pub fn serve<S, F, H>(self, s: S, f: F) -> io::Result<()> where
+        S: ToSocketAddrs,
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        self.bind(s)?.run(f)
+    }
+
+    /// Listens on each of `addrs`, serving connections from all of
+    /// them with the same thread pool and handler. E.g.
+    /// `server.serve_all(&["127.0.0.1:8080", "[::1]:8080"], f)`.
+    ///
+    /// To use a different transport on some addresses, the protocol
+    /// can choose one in `bind_transport` from the stream's
+    /// `local_addr`.
+    pub fn serve_all<S, F, H>(self, addrs: &[S], f: F) -> io::Result<()> where
+        S: ToSocketAddrs,
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        self.bind_all(addrs)?.run(f)
+    }
+
+    /// Serves connections from a listener that's already bound. E.g.
+    /// one inherited from a parent process, so the server can restart
+    /// without refusing connections, or bind a privileged port without
+    /// running as root.
+    pub fn serve_listener<F, H>(self, listener: net::TcpListener, f: F) -> io::Result<()> where
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        self.bind_listeners(vec![listener])?.run(f)
+    }
+
+    /// Serves connections from several listeners that are already
+    /// bound. E.g. those passed by systemd, from [`listen_fds`].
+    ///
+    /// [`listen_fds`]: fn.listen_fds.html
+    pub fn serve_listeners<F, H>(self, listeners: Vec<net::TcpListener>, f: F)
+        -> io::Result<()> where
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        self.bind_listeners(listeners)?.run(f)
+    }
+
+    /// Serves connections on another thread, returning once the
+    /// listener is bound. The server runs until the returned
+    /// [`BackgroundServer`] is stopped or dropped.
+    ///
+    /// [`BackgroundServer`]: struct.BackgroundServer.html
+    pub fn serve_background<S, F, H>(self, s: S, f: F) -> io::Result<BackgroundServer> where
+        S: ToSocketAddrs,
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        self.bind(s)?.run_background(f)
+    }
+
+    /// Serves connections until `shutdown` resolves, e.g. a timer or a
+    /// signal. See `BoundServer::run_until`.
+    pub fn serve_until<S, F, H, U>(self, s: S, f: F, shutdown: U) -> io::Result<()> where
+        S: ToSocketAddrs,
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+        U: Pollable,
+    {
+        self.bind(s)?.run_until(f, shutdown)
+    }
+
+    /// Serves connections until the process receives `SIGINT` (e.g.
+    /// Ctrl-C) or `SIGTERM`, then shuts down as
+    /// [`ServerHandle::shutdown`] does, within the configured
+    /// `shutdown_grace`. A second signal stops the server straight
+    /// away. See [`stop_on_signals`].
+    ///
+    /// [`ServerHandle::shutdown`]: struct.ServerHandle.html#method.shutdown
+    /// [`stop_on_signals`]: ../signal/fn.stop_on_signals.html
+    #[cfg(all(unix, feature = "signals"))]
+    pub fn serve_with_ctrl_c<S, F, H>(self, s: S, f: F) -> io::Result<()> where
         S: ToSocketAddrs,
         F: FnOnce() -> H,
         H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
@@ -32,16 +217,1870 @@ impl<P> TcpServer<P>
         H::Error: From<<P::Result as IntoPollable>::Error>,
         H::Error: ::std::fmt::Debug,
     {
-        let listener = net::TcpListener::bind(s)?;
-        let handler = Arc::new(f());
-        let mut pool = ThreadPool::new(NUM_THREADS, 
-                                       self.proto.clone(), 
-                                       handler.clone());
+        let grace = self.config.shutdown_grace;
+        let bound = self.bind(s)?;
+        ::signal::stop_on_signals(bound.handle(), grace)?;
+        bound.run(f)
+    }
+}
+
+impl<P> TcpServer<P>
+    where P: BindTransport<net::TcpStream>,
+{
+    /// Binds a listener to `s`, without serving connections yet. E.g.
+    /// to find the port of `127.0.0.1:0`, or to get a handle to stop
+    /// the server with.
+    pub fn bind<S: ToSocketAddrs>(self, s: S) -> io::Result<BoundServer<P>> {
+        self.bind_all(&[s])
+    }
+
+    /// Binds a listener to each of `addrs`. See `serve_all`.
+    pub fn bind_all<S: ToSocketAddrs>(self, addrs: &[S]) -> io::Result<BoundServer<P>> {
+        let listeners = addrs.iter()
+            .map(|addr| listen(addr, self.config.backlog, &self.config.socket))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.listen_on(listeners)
+    }
+
+    /// Prepares to serve listeners that are already bound. The sizes of
+    /// the listeners' socket buffers are set, if they're configured,
+    /// but their backlogs are left as they are.
+    pub fn bind_listeners(self, listeners: Vec<net::TcpListener>)
+        -> io::Result<BoundServer<P>>
+    {
+        for listener in &listeners {
+            set_buffer_sizes(&SockRef::from(listener), &self.config.socket)?;
+        }
+        self.listen_on(listeners)
+    }
+
+    fn listen_on(self, listeners: Vec<net::TcpListener>) -> io::Result<BoundServer<P>> {
+        if listeners.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no addresses to listen on"));
+        }
+
+        // Listeners are identified by their index.
+        let poll = Poll::new()?;
+        let mut listeners = listeners.into_iter()
+            .map(|listener| {
+                listener.set_nonblocking(true)?;
+                Ok(mio::net::TcpListener::from_std(listener))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        for (index, listener) in listeners.iter_mut().enumerate() {
+            poll.registry().register(listener, Token(index), Interest::READABLE)?;
+        }
+
+        let handle = ServerHandle {
+            stopped: Arc::new(AtomicBool::new(false)),
+            waker: Arc::new(Waker::new(poll.registry(), STOP)?),
+            draining: Arc::new(AtomicBool::new(false)),
+            threads: Arc::new(AtomicUsize::new(0)),
+            grace: Arc::new(Mutex::new(None)),
+            loads: Loads::default(),
+            tasks: Inbox::default(),
+        };
+
+        Ok(BoundServer {
+            server: self,
+            poll,
+            listeners,
+            handle,
+        })
+    }
+
+    /// Accepts the connections waiting on `listener`, until it would
+    /// block. Returns `false` if some were left waiting because the
+    /// server is full, or the process has run out of file descriptors.
+    ///
+    /// Errors that only concern one connection, such as a client
+    /// aborting before it's accepted, are skipped. Running out of file
+    /// descriptors, or memory, is passed to the server's hooks, and the
+    /// listener tried again once the caller's paused, as connections
+    /// may have closed by then. Only errors with the listener itself
+    /// are returned.
+    ///
+    /// Connections are handed to the workers in batches, rather than
+    /// one at a time, so a flood of them doesn't wake the workers for
+    /// each. The last batch is left for the caller to flush.
+    pub(crate) fn accept<Q: Queue>(&self, listener: &mio::net::TcpListener, pool: &mut Q)
+        -> io::Result<bool>
+    {
+        let mut queued = 0;
+        loop {
+            let full = self.config.max_connections
+                .map(|max| pool.connections() >= max)
+                .unwrap_or(false);
+
+            if full && self.config.overload == Overload::Backpressure {
+                return Ok(false);
+            }
+
+            let (mut stream, peer) = match listener.accept() {
+                Ok((stream, peer)) => (net::TcpStream::from(stream), peer),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(ref e) if is_exhausted(e) => {
+                    if let Some(ref hooks) = self.hooks {
+                        hooks.on_accept_error(e);
+                    }
+                    return Ok(false);
+                },
+                Err(e) => return Err(e),
+            };
+
+            // Rejected connections are closed without a response, even
+            // if the server is full.
+            if !self.filter.as_ref().map(|f| f.accept(&peer)).unwrap_or(true) {
+                continue;
+            }
+
+            if !full {
+                // A connection whose options can't be set is dropped.
+                if configure(&stream, &self.config.socket).is_ok() {
+                    pool.queue(stream);
+                    queued += 1;
+                    if queued % ACCEPT_BATCH == 0 {
+                        pool.flush();
+                    }
+                }
+                continue;
+            }
+
+            // Dropping the stream closes it. The response is written
+            // without waiting, as the socket's buffer is empty.
+            if let Overload::Respond(ref response) = self.config.overload {
+                let _ = stream.write(response);
+                let _ = stream.shutdown(net::Shutdown::Write);
+            }
+        }
+    }
+}
+
+/// A [`TcpServer`] whose listeners are bound, ready to serve
+/// connections with `run`.
+///
+/// [`TcpServer`]: struct.TcpServer.html
+pub struct BoundServer<P> {
+    pub(crate) server: TcpServer<P>,
+    pub(crate) poll: Poll,
+    pub(crate) listeners: Vec<mio::net::TcpListener>,
+    pub(crate) handle: ServerHandle,
+}
+
+impl<P> BoundServer<P>
+    where P: BindTransport<net::TcpStream>,
+{
+    /// The address of the first listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    /// The addresses of the listeners, in the order they were bound.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(|l| l.local_addr()).collect()
+    }
+
+    /// A handle that stops the server from another thread.
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+}
+
+impl<P> BoundServer<P>
+    where P: BindTransport<net::TcpStream> + Send + Sync + 'static,
+{
+    /// Serves connections until the server is stopped, with the
+    /// handler that `f` creates.
+    pub fn run<F, H>(self, f: F) -> io::Result<()> where
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        self.run_with(f, None)
+    }
+
+    /// Serves connections until `shutdown` resolves, or fails, and
+    /// then shuts down as `ServerHandle::shutdown` does, within the
+    /// configured `shutdown_grace`: new connections are refused, and
+    /// open ones are closed once they've answered the requests they're
+    /// on. The server can still be stopped through a handle.
+    ///
+    /// `shutdown` is polled on the acceptor's thread, and arranges to be
+    /// woken as a connection's pollables do. See [`readiness`]. One
+    /// that arranges nothing is polled every few milliseconds.
+    ///
+    /// [`readiness`]: ../readiness/index.html
+    pub fn run_until<F, H, U>(self, f: F, mut shutdown: U) -> io::Result<()> where
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+        U: Pollable,
+    {
+        self.run_with(f, Some(&mut move || !matches!(shutdown.poll(), Ok(PollResult::NotReady))))
+    }
+
+    /// Serves connections until the server is stopped, or `shutdown`
+    /// returns `true`.
+    fn run_with<F, H>(self, f: F, shutdown: Option<&mut dyn FnMut() -> bool>)
+        -> io::Result<()> where
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        let mut pool = ThreadPool::new(self.server.config.clone(),
+                                       self.server.proto.clone(),
+                                       Arc::new(f()),
+                                       self.server.hooks.clone())?;
+        pool.report_to(self.handle.loads.clone());
+        pool.stop_with(self.handle.stopped.clone());
+        self.serve(pool, shutdown)
+    }
+}
+
+impl<P> BoundServer<P>
+    where P: BindTransport<net::TcpStream>,
+{
+    /// Serves connections on `queue` until the server is stopped,
+    /// rather than on a pool of its own. The queue's protocol and
+    /// handler serve them, and the server's are unused. The server's
+    /// settings still decide which connections are accepted. Only a
+    /// pool the server creates itself is counted in
+    /// `ServerHandle::workers`.
+    pub fn run_on<Q: Queue>(self, queue: Q) -> io::Result<()> {
+        self.serve(queue, None)
+    }
+
+    /// Serves connections on `pool` until the server is stopped, or
+    /// `shutdown` returns `true`, when it shuts down within its
+    /// `shutdown_grace`.
+    fn serve<Q: Queue>(mut self, mut pool: Q, shutdown: Option<&mut dyn FnMut() -> bool>)
+        -> io::Result<()>
+    {
+        let mut events = Events::with_capacity(self.listeners.len() + 1);
+        let mut paused = false;
+        let mut drained = false;
+        // `shutdown` wakes the acceptor through the handle's waker.
+        let mut shutdown = shutdown
+            .map(|done| (done, Readiness::new(self.poll.registry(), self.handle.waker())));
+        let mut recheck = shutdown.as_ref().map(|_| Duration::from_secs(0));
+        loop {
+            // Listeners don't become ready again for connections that
+            // were left in the backlog, so a paused server checks them
+            // all every so often.
+            let timeout = match (paused, recheck) {
+                (true, Some(recheck)) => Some(recheck.min(RESUME_INTERVAL)),
+                (true, None) => Some(RESUME_INTERVAL),
+                (false, recheck) => recheck,
+            };
+
+            if let Err(e) = self.poll.poll(&mut events, timeout) {
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+
+            if let Some((ref mut done, ref readiness)) = shutdown {
+                let (shut_down, arranged) = readiness.poll(0, done);
+                readiness.woken();
+                recheck = shutdown_recheck(arranged);
+                if shut_down && self.handle.grace().is_none() {
+                    self.handle.shutdown(self.server.config.shutdown_grace);
+                }
+            }
+
+            if self.handle.is_stopped() {
+                pool.shutdown();
+                return Ok(());
+            }
+
+            // New connections are refused while the workers finish.
+            if let Some(grace) = self.handle.grace() {
+                self.listeners.clear();
+                pool.shutdown_within(grace);
+                return Ok(());
+            }
+
+            if !drained && self.handle.is_draining() {
+                pool.drain();
+                drained = true;
+            }
+
+            for spawn in self.handle.tasks.take() {
+                pool.execute(spawn);
+            }
+
+            let resized = self.handle.resized().filter(|&threads| threads != pool.threads());
+            if let Some(threads) = resized {
+                // If workers can't be started, the pool keeps those it
+                // has.
+                if pool.resize(threads).is_err() {
+                    self.handle.threads.store(pool.threads(), Ordering::SeqCst);
+                }
+            }
+
+            let ready = match paused {
+                true => (0..self.listeners.len()).collect(),
+                false => events.iter()
+                    .map(|e| e.token().0)
+                    .filter(|&index| index < self.listeners.len())
+                    .collect::<Vec<_>>(),
+            };
+
+            paused = false;
+            for index in ready {
+                if !self.server.accept(&self.listeners[index], &mut pool)? {
+                    paused = true;
+                    break;
+                }
+            }
+            pool.flush();
+        }
+    }
+}
+
+impl<P> BoundServer<P>
+    where P: BindTransport<net::TcpStream> + Send + Sync + 'static,
+{
+    /// Serves connections on another thread, like `run`.
+    pub fn run_background<F, H>(self, f: F) -> io::Result<BackgroundServer> where
+        F: FnOnce() -> H,
+        H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+        H::Error: From<<P::Transport as Sink>::Error>,
+        H::Error: From<<P::Transport as Pollable>::Error>,
+        H::Error: From<<P::Result as IntoPollable>::Error>,
+        H::Error: ::std::fmt::Debug,
+    {
+        let local_addrs = self.local_addrs()?;
+        let handle = self.handle();
+        let handler = f();
+        let thread = thread::Builder::new()
+            .name(String::from("server-fx acceptor"))
+            .spawn(move || self.run(move || handler))?;
+
+        Ok(BackgroundServer {
+            local_addrs,
+            handle,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A server running on its own thread. Dropping it stops the server,
+/// and waits for it to finish.
+pub struct BackgroundServer {
+    local_addrs: Vec<SocketAddr>,
+    handle: ServerHandle,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl BackgroundServer {
+    /// The address of the first listener.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Stops the server, and waits for it to finish. Returns the error
+    /// that stopped it, if it failed before being asked to stop.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.handle.stop();
+        self.wait()
+    }
+
+    /// Waits for the server to be stopped, e.g. through a handle.
+    pub fn join(mut self) -> io::Result<()> {
+        self.wait()
+    }
+
+    fn wait(&mut self) -> io::Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("The acceptor thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for BackgroundServer {
+    fn drop(&mut self) {
+        self.handle.stop();
+        let _ = self.thread.take().map(JoinHandle::join);
+    }
+}
+
+/// Stops a running [`BoundServer`], and reports how busy it is.
+/// Handles can be cloned, and sent to other threads.
+///
+/// [`BoundServer`]: struct.BoundServer.html
+#[derive(Clone)]
+pub struct ServerHandle {
+    stopped: Arc<AtomicBool>,
+    waker: Arc<Waker>,
+    draining: Arc<AtomicBool>,
+    /// The number of workers asked for, or 0 to keep those the server
+    /// was configured with.
+    threads: Arc<AtomicUsize>,
+    /// How long the server has to stop, once it's been asked to
+    /// shut down.
+    grace: Arc<Mutex<Option<Duration>>>,
+    pub(crate) loads: Loads,
+    pub(crate) tasks: Inbox,
+}
+
+impl ServerHandle {
+    /// Stops accepting connections, and closes those that are open,
+    /// even if the server's shutting down. `run` then returns. This
+    /// doesn't wait for the server to stop.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = self.waker.wake();
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Wakes the thread that's accepting connections.
+    pub(crate) fn waker(&self) -> Arc<Waker> {
+        self.waker.clone()
+    }
+
+    /// Stops accepting connections, and closes those that are open
+    /// once they've answered the requests they're on, as `drain` does.
+    /// Connections still open after `grace` are closed, and `run` then
+    /// returns. This doesn't wait for the server to stop.
+    pub fn shutdown(&self, grace: Duration) {
+        *self.grace.lock().unwrap() = Some(grace);
+        let _ = self.waker.wake();
+    }
+
+    /// How long the server has to stop, if it's been asked to shut
+    /// down.
+    pub(crate) fn grace(&self) -> Option<Duration> {
+        *self.grace.lock().unwrap()
+    }
+
+    /// Starts winding the server down, without stopping it. Each open
+    /// connection is closed once it's answered the request it's on,
+    /// as are those accepted from now on. HTTP transports say so with
+    /// `Connection: close` on their next response, so peers move on
+    /// before the server is stopped, rather than being cut off.
+    /// Connections waiting for their next request are closed straight
+    /// away.
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        let _ = self.waker.wake();
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Grows or shrinks the server's pool to `threads` workers, while
+    /// it runs. Connections stay on the worker that accepted them, so
+    /// workers that are removed are drained, as with `drain`, and
+    /// stop once their connections have closed. A server running on
+    /// the current thread ignores this.
+    ///
+    /// # Panics
+    ///
+    /// If `threads` is 0.
+    pub fn resize(&self, threads: usize) {
+        assert!(threads > 0, "A server needs at least one thread");
+        self.threads.store(threads, Ordering::SeqCst);
+        let _ = self.waker.wake();
+    }
+
+    /// The number of workers asked for with `resize`, if any.
+    fn resized(&self) -> Option<usize> {
+        match self.threads.load(Ordering::SeqCst) {
+            0 => None,
+            threads => Some(threads),
+        }
+    }
+
+    /// The connections being served by every worker.
+    pub fn connections(&self) -> usize {
+        self.workers().iter().map(|w| w.connections).sum()
+    }
+
+    /// The requests in flight on every worker.
+    pub fn requests(&self) -> usize {
+        self.workers().iter().map(|w| w.requests).sum()
+    }
+
+    /// What each worker is doing. This is empty until the server is
+    /// run.
+    pub fn workers(&self) -> Vec<WorkerLoad> {
+        self.loads.workers()
+    }
+}
+
+/// Tasks are handed to the server's `Queue`, e.g. to its workers in
+/// turn. Those spawned before the server runs wait until it does, and
+/// those spawned once it's stopped are dropped.
+impl Executor for ServerHandle {
+    fn execute(&self, spawn: Spawn) {
+        self.tasks.push(spawn);
+        let _ = self.waker.wake();
+    }
+}
+
+/// The token of the `Waker` that stops the acceptor. Listeners are
+/// identified by their index.
+pub(crate) const STOP: Token = Token(usize::MAX);
+
+/// How often a server that runs until a pollable resolves polls it,
+/// if the pollable arranged nothing to wake it.
+const SHUTDOWN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long the acceptor can sleep before it polls the pollable it
+/// runs until again, once the pollable has `arranged` to be woken.
+fn shutdown_recheck(arranged: Arranged) -> Option<Duration> {
+    match arranged.deadlines.iter().min() {
+        Some(deadline) => Some(deadline.saturating_duration_since(Instant::now())),
+        None if arranged.any => None,
+        None => Some(SHUTDOWN_INTERVAL),
+    }
+}
+
+/// How many connections the acceptor queues before handing them to
+/// the workers.
+const ACCEPT_BATCH: usize = 64;
+
+/// How often a server that's stopped accepting connections checks if
+/// it can start again.
+pub(crate) const RESUME_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a [`TcpServer`] does with new connections once it has
+/// `max_connections` open.
+///
+/// [`TcpServer`]: struct.TcpServer.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum Overload {
+    /// Accept them, and close them straight away.
+    Close,
+    /// Accept them, write `response`, and close them. E.g. an HTTP
+    /// `503 Service Unavailable` with `Connection: close`.
+    Respond(Vec<u8>),
+    /// Stop accepting them until some of the open connections close.
+    /// New connections wait in the listen backlog, and are refused
+    /// once it's full.
+    Backpressure,
+}
+
+/// Options for the sockets of a [`TcpServer`]'s connections. Those
+/// left unset keep the system's defaults.
+///
+/// [`TcpServer`]: struct.TcpServer.html
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SocketOptions {
+    /// Sets `TCP_NODELAY`, so small writes are sent straight away
+    /// rather than being coalesced.
+    pub nodelay: bool,
+    pub keepalive: Option<Keepalive>,
+    /// The size, in bytes, of `SO_RCVBUF`. This is set on the
+    /// listener, so that accepted connections can advertise a window
+    /// of this size from the start.
+    pub recv_buffer_size: Option<usize>,
+    /// The size, in bytes, of `SO_SNDBUF`. This is set on the
+    /// listener, and inherited by accepted connections.
+    pub send_buffer_size: Option<usize>,
+}
+
+/// TCP keepalive probes, which detect peers that have gone away
+/// without closing their connections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keepalive {
+    /// How long a connection is idle before the first probe is sent.
+    pub time: Duration,
+    /// How long to wait between unanswered probes. This is ignored on
+    /// platforms that don't support it.
+    pub interval: Option<Duration>,
+}
+
+/// Whether `e`, from accepting a connection, means there wasn't a file
+/// descriptor, or the memory, for another. Accepting can succeed again
+/// once some are freed.
+fn is_exhausted(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    const CODES: &[i32] = &[libc::EMFILE, libc::ENFILE, libc::ENOMEM, libc::ENOBUFS];
+    // WSAEMFILE and WSAENOBUFS.
+    #[cfg(windows)]
+    const CODES: &[i32] = &[10024, 10055];
+
+    e.kind() == io::ErrorKind::OutOfMemory ||
+        e.raw_os_error().map(|code| CODES.contains(&code)).unwrap_or(false)
+}
+
+/// Sets the options of an accepted stream that aren't inherited from
+/// its listener.
+fn configure(stream: &net::TcpStream, options: &SocketOptions) -> io::Result<()> {
+    let socket = SockRef::from(stream);
+    if options.nodelay {
+        socket.set_nodelay(true)?;
+    }
+
+    if let Some(keepalive) = options.keepalive {
+        let mut params = TcpKeepalive::new().with_time(keepalive.time);
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd",
+                  target_os = "macos", target_os = "ios", target_os = "windows"))]
+        {
+            if let Some(interval) = keepalive.interval {
+                params = params.with_interval(interval);
+            }
+        }
+        socket.set_tcp_keepalive(&params)?;
+    }
+
+    Ok(())
+}
+
+fn set_buffer_sizes(socket: &SockRef, options: &SocketOptions) -> io::Result<()> {
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    Ok(())
+}
+
+/// Takes the listeners passed to the process by systemd's socket
+/// activation, in the order they're configured. Returns none if the
+/// process wasn't passed any.
+///
+/// The `LISTEN_PID` and `LISTEN_FDS` environment variables are
+/// removed, so child processes don't take the listeners too. This
+/// should only be called once.
+#[cfg(unix)]
+pub fn listen_fds() -> io::Result<Vec<net::TcpListener>> {
+    use std::env;
+    use std::os::unix::io::FromRawFd;
 
-        for stream in listener.incoming() {
-            pool.queue(stream?);
+    let fds = inherited_fds(env::var("LISTEN_PID").ok(), env::var("LISTEN_FDS").ok())?;
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    fds.map(|fd| {
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        match socket.r#type()? == Type::STREAM {
+            true => Ok(socket.into()),
+            false => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                        format!("fd {} isn't a stream socket", fd))),
         }
+    }).collect()
+}
+
+/// The file descriptors passed by systemd, given the values of
+/// `LISTEN_PID` and `LISTEN_FDS`. They're numbered from 3.
+#[cfg(unix)]
+fn inherited_fds(pid: Option<String>, fds: Option<String>)
+    -> io::Result<::std::ops::Range<::std::os::unix::io::RawFd>>
+{
+    const FIRST: ::std::os::unix::io::RawFd = 3;
+
+    let invalid = |name| io::Error::new(io::ErrorKind::InvalidData,
+                                        format!("{} is invalid", name));
+
+    // The variables are meant for another process, if the PID isn't
+    // this one.
+    let pid = match pid {
+        Some(pid) => pid.parse::<u32>().map_err(|_| invalid("LISTEN_PID"))?,
+        None => return Ok(FIRST..FIRST),
+    };
+    if pid != ::std::process::id() {
+        return Ok(FIRST..FIRST);
+    }
+
+    let count = fds.unwrap_or_default()
+        .parse::<::std::os::unix::io::RawFd>()
+        .map_err(|_| invalid("LISTEN_FDS"))?;
+    Ok(FIRST..FIRST + count)
+}
+
+/// Binds a listener to the first of `addrs` that it can, with a queue
+/// of up to `backlog` pending connections.
+fn listen<S: ToSocketAddrs>(addrs: S, backlog: i32, options: &SocketOptions)
+    -> io::Result<net::TcpListener>
+{
+    let mut error = None;
+    for addr in addrs.to_socket_addrs()? {
+        match listen_on(addr, backlog, options) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => error = Some(e),
+        }
+    }
+
+    Err(error.unwrap_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput, "could not resolve to any addresses")))
+}
+
+fn listen_on(addr: SocketAddr, backlog: i32, options: &SocketOptions)
+    -> io::Result<net::TcpListener>
+{
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    set_buffer_sizes(&SockRef::from(&socket), options)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    Ok(socket.into())
+}
+
+/// Configures a [`TcpServer`]. E.g.
+/// `TcpServer::builder(proto).threads(8).max_connections(1000).build()`.
+///
+/// [`TcpServer`]: struct.TcpServer.html
+pub struct ServerBuilder<P> {
+    proto: P,
+    config: ServerConfig,
+    hooks: Hooks,
+    filter: Option<Box<dyn AcceptFilter + Send + Sync>>,
+}
+
+impl<P> ServerBuilder<P>
+    where P: BindTransport<net::TcpStream>,
+{
+    /// Sets how many worker threads handle connections. Defaults to
+    /// 4.
+    ///
+    /// # Panics
+    ///
+    /// If `threads` is 0.
+    pub fn threads(mut self, threads: usize) -> ServerBuilder<P> {
+        assert!(threads > 0, "A server needs at least one thread");
+        self.config.threads = threads;
+        self
+    }
+
+    /// Sets how many connections can wait to be accepted before new
+    /// ones are refused. Defaults to 128.
+    pub fn backlog(mut self, backlog: i32) -> ServerBuilder<P> {
+        self.config.backlog = backlog;
+        self
+    }
+
+    /// Sets the initial capacity, in bytes, of each connection's
+    /// buffer for incoming data. Defaults to 1024.
+    pub fn read_buffer_size(mut self, size: usize) -> ServerBuilder<P> {
+        self.config.read_buffer_size = size;
+        self
+    }
+
+    /// Sets the initial capacity, in bytes, of each connection's
+    /// buffer for outgoing data. Defaults to 1024.
+    pub fn write_buffer_size(mut self, size: usize) -> ServerBuilder<P> {
+        self.config.write_buffer_size = size;
+        self
+    }
+
+    /// Sets how long a connection has to send each request, from when
+    /// it opens or the previous response is written, before it's
+    /// closed. This includes the time it's idle between requests. By
+    /// default, connections can wait indefinitely.
+    pub fn read_timeout(mut self, timeout: Duration) -> ServerBuilder<P> {
+        self.config.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long a connection has to send the head of each
+    /// request, from when it opens or the previous response is
+    /// written, before it's closed. Unlike `read_timeout`, this stops
+    /// once the head has arrived, so it can be kept short without
+    /// limiting how long bodies take to upload. It protects against
+    /// peers that hold connections open by sending their requests a
+    /// few bytes at a time. Only transports that can tell when a head
+    /// has arrived, such as HTTP's, stop timing it before the whole
+    /// request has. By default, heads can take as long as they like.
+    pub fn header_timeout(mut self, timeout: Duration) -> ServerBuilder<P> {
+        self.config.header_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long a connection has to receive each response before
+    /// it's closed. By default, a slow peer can take as long as it
+    /// likes.
+    pub fn write_timeout(mut self, timeout: Duration) -> ServerBuilder<P> {
+        self.config.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long a connection can wait for its next request,
+    /// after writing a response, before it's closed if nothing
+    /// arrives. Unlike `read_timeout`, this doesn't limit how long a
+    /// request takes to arrive once it's started. By default, idle
+    /// connections are kept open.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> ServerBuilder<P> {
+        self.config.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long a request can take, from when it's read until its
+    /// response is written. A request still being handled after this is
+    /// abandoned, and answered with the handler's `timed_out` response,
+    /// if it has one. Otherwise, or if its response is being written,
+    /// the connection is closed. By default, requests can take as long
+    /// as they like.
+    pub fn request_timeout(mut self, timeout: Duration) -> ServerBuilder<P> {
+        self.config.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how many requests each connection can send before it's
+    /// closed. This is up to the transport, which is passed it in the
+    /// `ServerConfig` given to `bind_transport_with`, e.g. for
+    /// `HttpTransport::max_requests`. By default, there's no limit.
+    pub fn max_requests_per_connection(mut self, max: usize) -> ServerBuilder<P> {
+        self.config.max_requests_per_connection = Some(max);
+        self
+    }
+
+    /// Sets how many connections can be open at once. What happens to
+    /// connections beyond this is set by `on_overload`. By default,
+    /// there's no limit.
+    pub fn max_connections(mut self, max: usize) -> ServerBuilder<P> {
+        self.config.max_connections = Some(max);
+        self
+    }
+
+    /// Sets how long a server that's shutting down on its own, e.g.
+    /// once the pollable given to `run_until` resolves, or on a signal
+    /// with `serve_with_ctrl_c`, gives its open connections to answer
+    /// the requests they're on before they're closed. See
+    /// `ServerHandle::shutdown`. 30 seconds by default.
+    pub fn shutdown_grace(mut self, grace: Duration) -> ServerBuilder<P> {
+        self.config.shutdown_grace = grace;
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on each connection, so small writes are sent
+    /// straight away. Latency-sensitive protocols should enable this.
+    /// Disabled by default.
+    pub fn nodelay(mut self, nodelay: bool) -> ServerBuilder<P> {
+        self.config.socket.nodelay = nodelay;
+        self
+    }
+
+    /// Enables TCP keepalive on each connection, probing peers that
+    /// have been idle for `time`, then every `interval`. By default,
+    /// keepalive is disabled.
+    pub fn keepalive(mut self, time: Duration, interval: Option<Duration>) -> ServerBuilder<P> {
+        self.config.socket.keepalive = Some(Keepalive { time, interval });
+        self
+    }
+
+    /// Sets the size of each connection socket's receive buffer
+    /// (`SO_RCVBUF`). Not to be confused with `read_buffer_size`. By
+    /// default, the system decides.
+    pub fn socket_recv_buffer_size(mut self, size: usize) -> ServerBuilder<P> {
+        self.config.socket.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the size of each connection socket's send buffer
+    /// (`SO_SNDBUF`). By default, the system decides.
+    pub fn socket_send_buffer_size(mut self, size: usize) -> ServerBuilder<P> {
+        self.config.socket.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Closes connections that `filter` rejects as soon as they're
+    /// accepted. E.g. an `IpFilter`, or a closure taking the peer's
+    /// `&SocketAddr`. By default, every connection is served.
+    pub fn accept_filter<F>(mut self, filter: F) -> ServerBuilder<P> where
+        F: AcceptFilter + Send + Sync + 'static
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Calls `hooks` as each connection is opened and closed. See
+    /// [`ConnectionHooks`]. By default, there are none.
+    ///
+    /// [`ConnectionHooks`]: ../lifecycle/trait.ConnectionHooks.html
+    pub fn hooks<C>(mut self, hooks: C) -> ServerBuilder<P> where
+        C: ConnectionHooks + Send + Sync + 'static
+    {
+        self.hooks = Some(Arc::new(hooks));
+        self
+    }
+
+    /// Pins each worker thread to the CPUs `affinity` gives it, so the
+    /// system doesn't move it between cores. Workers added as the pool
+    /// grows are pinned too. By default, workers run wherever the
+    /// system puts them. A server that runs on the current thread
+    /// leaves it where it is.
+    #[cfg(all(target_os = "linux", feature = "affinity"))]
+    pub fn affinity(mut self, affinity: Affinity) -> ServerBuilder<P> {
+        self.config.affinity = Some(affinity);
+        self
+    }
+
+    /// Sets what happens to new connections once `max_connections`
+    /// are open. Defaults to `Overload::Close`.
+    pub fn on_overload(mut self, overload: Overload) -> ServerBuilder<P> {
+        self.config.overload = overload;
+        self
+    }
+
+    pub fn build(self) -> TcpServer<P> {
+        TcpServer {
+            proto: Arc::new(self.proto),
+            config: Arc::new(self.config),
+            hooks: self.hooks,
+            filter: self.filter,
+        }
+    }
+
+    /// Builds a server that runs on the thread that serves it. The
+    /// number of threads is ignored.
+    pub fn build_current_thread(self) -> CurrentThreadServer<P> {
+        CurrentThreadServer::from(self.build())
+    }
+}
+
+#[cfg(test)]
+mod server_builder_should {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+
+    struct Proto;
+
+    impl BindTransport<net::TcpStream> for Proto {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Transport = ::framed::Framed<net::TcpStream, Codec>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+            Ok(::framed::Framed::new(s, Codec))
+        }
+    }
+
+    struct Codec;
+
+    impl ::codec::Decode for Codec {
+        type Item = Vec<u8>;
+
+        fn decode(&self, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+            Some(::std::mem::take(buffer))
+        }
+    }
+
+    impl ::codec::Encode for Codec {
+        type Item = Vec<u8>;
+
+        fn encode(&self, item: Vec<u8>, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn configure_the_server() {
+        let server = TcpServer::builder(Proto)
+            .threads(2)
+            .backlog(16)
+            .read_buffer_size(4096)
+            .read_timeout(Duration::from_secs(5))
+            .max_requests_per_connection(100)
+            .max_connections(10)
+            .on_overload(Overload::Backpressure)
+            .nodelay(true)
+            .build();
+
+        assert_eq!(&ServerConfig {
+            threads: 2,
+            backlog: 16,
+            read_buffer_size: 4096,
+            write_buffer_size: 1024,
+            read_timeout: Some(Duration::from_secs(5)),
+            header_timeout: None,
+            write_timeout: None,
+            keep_alive_timeout: None,
+            request_timeout: None,
+            max_requests_per_connection: Some(100),
+            max_connections: Some(10),
+            shutdown_grace: Duration::from_secs(30),
+            overload: Overload::Backpressure,
+            socket: SocketOptions {
+                nodelay: true,
+                ..SocketOptions::default()
+            },
+            #[cfg(all(target_os = "linux", feature = "affinity"))]
+            affinity: None,
+        }, server.config());
+        assert_eq!(&ServerConfig::default(), TcpServer::new(Proto).config());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one thread")]
+    fn reject_zero_threads() {
+        TcpServer::builder(Proto).threads(0);
+    }
+
+    #[test]
+    fn listen_with_a_backlog() {
+        let listener = listen("127.0.0.1:0", 4, &SocketOptions::default()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        let (mut accepted, _) = listener.accept().unwrap();
+        accepted.write_all(b"hi").unwrap();
+
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"hi", &buf);
+    }
+
+    struct Echo;
+
+    impl Handler for Echo {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = io::Result<Vec<u8>>;
+
+        fn handle(&self, request: Vec<u8>) -> Self::Pollable {
+            Ok(request)
+        }
+    }
+
+    #[test]
+    fn serve_several_listeners() {
+        let listeners = vec![
+            listen("127.0.0.1:0", 4, &SocketOptions::default()).unwrap(),
+            listen("127.0.0.1:0", 4, &SocketOptions::default()).unwrap(),
+        ];
+        let addrs = listeners.iter()
+            .map(|l| l.local_addr().unwrap())
+            .collect::<Vec<_>>();
+
+        let server = TcpServer::builder(Proto).threads(1).build();
+        ::std::thread::spawn(move || server.serve_listeners(listeners, || Echo));
+
+        for addr in addrs {
+            let mut client = net::TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client.write_all(b"hi").unwrap();
+
+            let mut buf = [0; 2];
+            client.read_exact(&mut buf).unwrap();
+            assert_eq!(b"hi", &buf);
+        }
+    }
+
+    /// Serves one connection on a server limited to one, with
+    /// `overload`, and connects another. Returns what the second
+    /// client reads, up to 2 bytes.
+    fn overload(overload: Overload) -> io::Result<Vec<u8>> {
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .max_connections(1)
+            .on_overload(overload)
+            .build();
+        let addr = start(server, Echo);
+
+        let mut first = net::TcpStream::connect(addr).unwrap();
+        first.write_all(b"hi").unwrap();
+        first.read_exact(&mut [0; 2]).unwrap();
+
+        let second = net::TcpStream::connect(addr).unwrap();
+        second.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let mut reply = vec![];
+        second.take(2).read_to_end(&mut reply)?;
+        Ok(reply)
+    }
+
+    #[test]
+    fn close_connections_beyond_the_limit() {
+        assert_eq!(Vec::<u8>::new(), overload(Overload::Close).unwrap());
+    }
+
+    #[test]
+    fn respond_to_connections_beyond_the_limit() {
+        assert_eq!(b"no".to_vec(), overload(Overload::Respond(b"no".to_vec())).unwrap());
+    }
+
+    #[test]
+    fn leave_connections_beyond_the_limit_in_the_backlog() {
+        let error = overload(Overload::Backpressure).err().unwrap();
+        assert!(matches!(error.kind(),
+                         io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut));
+    }
+
+    fn start<H>(server: TcpServer<Proto>, handler: H) -> SocketAddr where
+        H: Handler<Request=Vec<u8>, Response=Vec<u8>, Error=io::Error> + Send + Sync + 'static,
+    {
+        let server = server.bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        ::std::thread::spawn(move || server.run(|| handler));
+        addr
+    }
+
+    #[test]
+    fn close_connections_that_are_slow_to_send_a_request() {
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .read_timeout(Duration::from_millis(100))
+            .build();
+        let mut client = net::TcpStream::connect(start(server, Echo)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+        assert_eq!(0, client.read(&mut [0; 2]).unwrap());
+    }
+
+    /// Responds to every request with 64MB.
+    struct Flood;
+
+    impl Handler for Flood {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = io::Result<Vec<u8>>;
+
+        fn handle(&self, _: Vec<u8>) -> Self::Pollable {
+            Ok(vec![0; 64 << 20])
+        }
+    }
+
+    #[test]
+    fn close_connections_that_are_slow_to_send_a_head() {
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .read_timeout(Duration::from_secs(10))
+            .header_timeout(Duration::from_millis(100))
+            .build();
+        let mut client = net::TcpStream::connect(start(server, Echo)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // The timeout starts again once the response is written.
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+        assert_eq!(0, client.read(&mut [0; 2]).unwrap());
+    }
+
+    #[test]
+    fn close_connections_that_are_slow_to_receive_a_response() {
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .write_timeout(Duration::from_millis(100))
+            .build();
+        let mut client = net::TcpStream::connect(start(server, Flood)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        client.write_all(b"hi").unwrap();
+        ::std::thread::sleep(Duration::from_millis(300));
+
+        let mut response = vec![];
+        match client.read_to_end(&mut response) {
+            Ok(read) => assert!(read < 64 << 20),
+            Err(e) => assert_eq!(io::ErrorKind::ConnectionReset, e.kind()),
+        }
+    }
+
+    /// Never responds to a request, unless it times out.
+    struct Stall(Option<&'static [u8]>);
+
+    struct Pending;
+
+    impl Pollable for Pending {
+        type Item = Vec<u8>;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Result<PollResult<Vec<u8>>, io::Error> {
+            Ok(PollResult::NotReady)
+        }
+    }
+
+    impl Handler for Stall {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = Pending;
+
+        fn handle(&self, _: Vec<u8>) -> Pending {
+            Pending
+        }
+
+        fn timed_out(&self) -> Option<Vec<u8>> {
+            self.0.map(<[u8]>::to_vec)
+        }
+    }
+
+    #[test]
+    fn abandon_requests_that_take_too_long() {
+        let connect = |handler| {
+            let server = TcpServer::builder(Proto)
+                .threads(1)
+                .request_timeout(Duration::from_millis(100))
+                .build();
+            let mut client = net::TcpStream::connect(start(server, handler)).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client.write_all(b"hi").unwrap();
+            client
+        };
+
+        let mut client = connect(Stall(None));
+        assert_eq!(0, client.read(&mut [0; 4]).unwrap());
+
+        let mut client = connect(Stall(Some(b"late")));
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"late", &buf);
+    }
+
+    #[test]
+    fn count_open_connections_and_requests_in_flight() {
+        let server = TcpServer::builder(Proto).threads(2).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        assert!(handle.workers().is_empty());
+        let running = ::std::thread::spawn(move || server.run(|| Stall(None)));
+
+        let _idle = net::TcpStream::connect(addr).unwrap();
+        let mut busy = net::TcpStream::connect(addr).unwrap();
+        busy.write_all(b"hi").unwrap();
+
+        for _ in 0..500 {
+            if handle.connections() == 2 && handle.requests() == 1 {
+                break;
+            }
+            ::std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!((2, 1), (handle.connections(), handle.requests()));
+        // Connections are shared between the workers in turn.
+        assert_eq!(vec![1, 1], handle.workers().iter().map(|w| w.connections).collect::<Vec<_>>());
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+        assert_eq!((0, 0), (handle.connections(), handle.requests()));
+    }
+
+    /// Echoes each request, after taking a while over it.
+    struct Slow;
+
+    impl Handler for Slow {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = io::Result<Vec<u8>>;
+
+        fn handle(&self, request: Vec<u8>) -> Self::Pollable {
+            ::std::thread::sleep(Duration::from_millis(20));
+            Ok(request)
+        }
+    }
+
+    #[test]
+    fn measure_the_work_of_each_worker() {
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        ::std::thread::spawn(move || server.run(|| Slow));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+
+        let load = handle.workers()[0];
+        assert_eq!(0, load.queued);
+        assert!(load.iterations > 0);
+        assert!(load.handler_time >= Duration::from_millis(20));
+        handle.stop();
+    }
+
+    /// Echoes each request, once it's been passed through the blocking
+    /// pool, which takes a while.
+    struct Offloaded;
+
+    impl Handler for Offloaded {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = ::blocking::Blocking<Vec<u8>>;
+
+        fn handle(&self, request: Vec<u8>) -> Self::Pollable {
+            ::blocking::spawn_blocking(move || {
+                ::std::thread::sleep(Duration::from_millis(200));
+                request
+            })
+        }
+    }
+
+    #[test]
+    fn sleep_until_a_request_being_handled_is_woken() {
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        ::std::thread::spawn(move || server.run(|| Offloaded));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        let mut echoed = [0; 2];
+        client.read_exact(&mut echoed).unwrap();
+        assert_eq!(b"hi", &echoed);
+
+        // Polling the request until it was ready would have taken
+        // thousands of turns.
+        let iterations = handle.workers()[0].iterations;
+        assert!(iterations < 50, "The worker turned {} times", iterations);
+        handle.stop();
+    }
+
+    #[test]
+    fn answer_requests_without_waiting_for_the_next_turn() {
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        ::std::thread::spawn(move || server.run(|| Echo));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.set_nodelay(true).unwrap();
+
+        // Each step of a request would otherwise wait for a timer,
+        // which takes at least a millisecond.
+        let start = Instant::now();
+        for _ in 0..200 {
+            client.write_all(b"hi").unwrap();
+            client.read_exact(&mut [0; 2]).unwrap();
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed < Duration::from_millis(150), "200 requests took {:?}", elapsed);
+        handle.stop();
+    }
+
+    /// Echoes each request once a while has passed, without blocking.
+    struct Later(Duration);
+
+    struct Delay(Instant, Option<Vec<u8>>);
+
+    impl Pollable for Delay {
+        type Item = Vec<u8>;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Result<PollResult<Vec<u8>>, io::Error> {
+            match Instant::now() >= self.0 {
+                true => Ok(PollResult::Ready(self.1.take().unwrap())),
+                false => Ok(PollResult::NotReady),
+            }
+        }
+    }
+
+    impl Handler for Later {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = Delay;
+
+        fn handle(&self, request: Vec<u8>) -> Delay {
+            Delay(Instant::now() + self.0, Some(request))
+        }
+    }
+
+    #[test]
+    fn finish_requests_in_flight_when_shutting_down() {
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let running = ::std::thread::spawn(move || {
+            server.run(|| Later(Duration::from_millis(100)))
+        });
+
+        let mut idle = net::TcpStream::connect(addr).unwrap();
+        let mut busy = net::TcpStream::connect(addr).unwrap();
+        for client in &[&idle, &busy] {
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        }
+        busy.write_all(b"hi").unwrap();
+        for _ in 0..500 {
+            if handle.connections() == 2 && handle.requests() == 1 {
+                break;
+            }
+            ::std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let started = Instant::now();
+        handle.shutdown(Duration::from_secs(10));
+        assert_eq!(0, idle.read(&mut [0; 2]).unwrap());
+        let mut reply = [0; 2];
+        busy.read_exact(&mut reply).unwrap();
+        assert_eq!(b"hi", &reply);
+        assert_eq!(0, busy.read(&mut [0; 2]).unwrap());
+
+        running.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(net::TcpStream::connect(addr).is_err());
+    }
+
+    /// Echoes each request, unless it asks the handler to panic.
+    struct Panicky;
+
+    impl Handler for Panicky {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = io::Result<Vec<u8>>;
+
+        fn handle(&self, request: Vec<u8>) -> Self::Pollable {
+            assert!(request != b"panic", "asked to panic");
+            Ok(request)
+        }
+    }
+
+    #[test]
+    fn close_only_the_connection_whose_handler_panics() {
+        let server = TcpServer::builder(Proto).threads(1).build();
+        let addr = start(server, Panicky);
+        let connect = || {
+            let client = net::TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client
+        };
+
+        let mut other = connect();
+        other.write_all(b"hi").unwrap();
+        other.read_exact(&mut [0; 2]).unwrap();
+
+        let mut failing = connect();
+        failing.write_all(b"panic").unwrap();
+        assert_eq!(0, failing.read(&mut [0; 5]).unwrap());
+
+        other.write_all(b"hi").unwrap();
+        other.read_exact(&mut [0; 2]).unwrap();
+        let mut client = connect();
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+    }
+
+    /// Panics when the first connection opens.
+    struct PanicOnce(AtomicBool);
+
+    impl ConnectionHooks for PanicOnce {
+        fn on_connect(&self, _: &::lifecycle::ConnectionContext) {
+            assert!(self.0.swap(true, Ordering::SeqCst), "first connection");
+        }
+    }
+
+    #[test]
+    fn replace_workers_that_die() {
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .hooks(PanicOnce(AtomicBool::new(false)))
+            .build();
+        let addr = start(server, Echo);
+
+        let echo = || -> io::Result<()> {
+            let mut client = net::TcpStream::connect(addr)?;
+            client.set_read_timeout(Some(Duration::from_secs(5)))?;
+            client.write_all(b"hi")?;
+            client.read_exact(&mut [0; 2])
+        };
+        assert!(echo().is_err());
+
+        // Connections handed to the worker as it died are lost.
+        let served = (0..50).any(|_| {
+            ::std::thread::sleep(Duration::from_millis(10));
+            echo().is_ok()
+        });
+        assert!(served);
+    }
+
+    #[test]
+    fn resize_the_pool_while_running() {
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        ::std::thread::spawn(move || server.run(|| Echo));
+
+        let wait_for = |done: &dyn Fn() -> bool| {
+            for _ in 0..500 {
+                if done() {
+                    break;
+                }
+                ::std::thread::sleep(Duration::from_millis(10));
+            }
+            assert!(done());
+        };
+        let connections = || handle.workers().iter().map(|w| w.connections).collect::<Vec<_>>();
+        let echo = |client: &mut net::TcpStream| {
+            client.write_all(b"hi").unwrap();
+            client.read_exact(&mut [0; 2]).unwrap();
+        };
+
+        wait_for(&|| handle.workers().len() == 1);
+        handle.resize(3);
+        wait_for(&|| handle.workers().len() == 3);
+
+        let mut clients = (0..3)
+            .map(|_| net::TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+        for client in &mut clients {
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            echo(client);
+        }
+        assert_eq!(vec![1, 1, 1], connections());
+
+        // Workers that are removed close their connections as they
+        // finish with them.
+        handle.resize(1);
+        for client in &mut clients[1..] {
+            assert_eq!(0, client.read(&mut [0; 2]).unwrap());
+        }
+        echo(&mut clients[0]);
+        drop(clients);
+        wait_for(&|| handle.connections() == 0);
+
+        let mut clients = (0..2)
+            .map(|_| net::TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+        for client in &mut clients {
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            echo(client);
+        }
+        assert_eq!(2, connections()[0]);
+        handle.stop();
+    }
+
+    #[test]
+    fn set_socket_options() {
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: Some(Keepalive {
+                time: Duration::from_secs(30),
+                interval: Some(Duration::from_secs(5)),
+            }),
+            recv_buffer_size: Some(256 << 10),
+            send_buffer_size: Some(256 << 10),
+        };
+
+        let listener = listen("127.0.0.1:0", 4, &options).unwrap();
+        let _client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        configure(&accepted, &options).unwrap();
+
+        let socket = SockRef::from(&accepted);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.recv_buffer_size().unwrap() >= 256 << 10);
+        assert!(socket.send_buffer_size().unwrap() >= 256 << 10);
+    }
+
+    #[test]
+    fn serve_a_bound_listener() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = TcpServer::builder(Proto).threads(1).build();
+        ::std::thread::spawn(move || server.serve_listener(listener, || Echo));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"hi", &buf);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_the_listeners_passed_by_systemd() {
+        let pid = ::std::process::id().to_string();
+        let fds = |pid: Option<&str>, fds: Option<&str>| inherited_fds(
+            pid.map(String::from), fds.map(String::from));
+
+        assert_eq!(3..5, fds(Some(&pid), Some("2")).unwrap());
+        assert_eq!(3..3, fds(None, None).unwrap());
+        assert_eq!(3..3, fds(Some("1"), Some("2")).unwrap());
+        assert!(fds(Some(&pid), Some("two")).is_err());
+        assert!(fds(Some("me"), Some("2")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_accepting_once_file_descriptors_are_freed() {
+        #[cfg(unix)]
+        {
+            assert!(is_exhausted(&io::Error::from_raw_os_error(::libc::EMFILE)));
+            assert!(is_exhausted(&io::Error::from_raw_os_error(::libc::ENFILE)));
+            assert!(!is_exhausted(&io::Error::from_raw_os_error(::libc::EBADF)));
+        }
+        assert!(!is_exhausted(&io::Error::from(io::ErrorKind::ConnectionAborted)));
+    }
+
+    #[test]
+    fn close_connections_the_filter_rejects() {
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .accept_filter(|peer: &SocketAddr| peer.ip().is_loopback())
+            .build();
+        let addr = start(server, Echo);
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .accept_filter(::ip_filter::IpFilter::new().deny("127.0.0.0/8".parse().unwrap()))
+            .build();
+        let mut client = net::TcpStream::connect(start(server, Echo)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reply = vec![];
+        match client.read_to_end(&mut reply) {
+            Ok(read) => assert_eq!(0, read),
+            Err(e) => assert_eq!(io::ErrorKind::ConnectionReset, e.kind()),
+        }
+    }
+
+    #[test]
+    fn close_idle_connections() {
+        let server = TcpServer::builder(Proto)
+            .threads(1)
+            .keep_alive_timeout(Duration::from_millis(200))
+            .build();
+        let mut client = net::TcpStream::connect(start(server, Echo)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // Requests arriving more often than the timeout keep the
+        // connection open.
+        for _ in 0..4 {
+            client.write_all(b"hi").unwrap();
+            client.read_exact(&mut [0; 2]).unwrap();
+            ::std::thread::sleep(Duration::from_millis(100));
+        }
+
+        assert_eq!(0, client.read(&mut [0; 2]).unwrap());
+    }
+
+    #[test]
+    fn respond_to_peers_that_half_close() {
+        let server = TcpServer::builder(Proto).threads(1).build();
+        let mut client = net::TcpStream::connect(start(server, Echo)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        client.write_all(b"hi").unwrap();
+        client.shutdown(net::Shutdown::Write).unwrap();
+
+        // The response is written, then the connection is closed.
+        let mut reply = vec![];
+        client.read_to_end(&mut reply).unwrap();
+        assert_eq!(b"hi".to_vec(), reply);
+    }
+
+    #[test]
+    fn stop_when_asked() {
+        let server = TcpServer::builder(Proto).threads(2).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let running = ::std::thread::spawn(move || server.run(|| Echo));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+
+        handle.stop();
+        running.join().unwrap().unwrap();
+        assert_eq!(0, client.read(&mut [0; 2]).unwrap());
+        assert!(net::TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn serve_a_flood_of_connections() {
+        let server = TcpServer::builder(Proto).threads(2).backlog(256).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+
+        // The connections wait in the backlog, so they're accepted
+        // together, across more than one batch.
+        let mut clients = (0..ACCEPT_BATCH * 2 + 1)
+            .map(|_| net::TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+        ::std::thread::spawn(move || server.run(|| Echo));
+
+        for client in &mut clients {
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client.write_all(b"hi").unwrap();
+            client.read_exact(&mut [0; 2]).unwrap();
+        }
+    }
+
+    /// Resolves once the flag is set, and its wakeup woken. Counts how
+    /// often it's polled.
+    #[derive(Clone, Default)]
+    struct Flag(Arc<AtomicBool>, ::readiness::Wakeup, Arc<AtomicUsize>);
+
+    impl Flag {
+        fn set(&self) {
+            self.0.store(true, Ordering::SeqCst);
+            self.1.wake();
+        }
+    }
+
+    impl Pollable for Flag {
+        type Item = ();
+        type Error = ();
+
+        fn poll(&mut self) -> Result<PollResult<()>, ()> {
+            self.2.fetch_add(1, Ordering::SeqCst);
+            self.1.wait();
+            match self.0.load(Ordering::SeqCst) {
+                true => Ok(PollResult::Ready(())),
+                false => Ok(PollResult::NotReady),
+            }
+        }
+    }
+
+    #[test]
+    fn shut_down_when_the_shutdown_pollable_resolves() {
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let flag = Flag::default();
+        let shutdown = flag.clone();
+        let running = ::std::thread::spawn(move || {
+            server.run_until(|| Later(Duration::from_millis(100)), shutdown)
+        });
+
+        let mut idle = net::TcpStream::connect(addr).unwrap();
+        let mut busy = net::TcpStream::connect(addr).unwrap();
+        for client in &[&idle, &busy] {
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        }
+        busy.write_all(b"hi").unwrap();
+        for _ in 0..500 {
+            if handle.connections() == 2 && handle.requests() == 1 {
+                break;
+            }
+            ::std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // The pollable is only polled again once it's woken.
+        let polls = flag.2.load(Ordering::SeqCst);
+        ::std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(polls, flag.2.load(Ordering::SeqCst));
+
+        flag.set();
+        assert_eq!(0, idle.read(&mut [0; 2]).unwrap());
+        let mut reply = [0; 2];
+        busy.read_exact(&mut reply).unwrap();
+        assert_eq!(b"hi", &reply);
+        assert_eq!(0, busy.read(&mut [0; 2]).unwrap());
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn serve_in_the_background() {
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .serve_background("127.0.0.1:0", || Echo)
+            .unwrap();
+
+        let mut client = net::TcpStream::connect(server.local_addr()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+
+        drop(server);
+        assert_eq!(0, client.read(&mut [0; 2]).unwrap());
+    }
+
+    /// Polls `pollable` on this thread until it resolves.
+    fn block_on<P: Pollable>(mut pollable: P) {
+        while let Ok(PollResult::NotReady) = pollable.poll() {
+            ::std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Serves each connection, and runs each task, on a thread of its
+    /// own.
+    struct ThreadPerConnection(Arc<AtomicUsize>);
+
+    impl Queue for ThreadPerConnection {
+        fn connections(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+
+        fn queue(&mut self, stream: net::TcpStream) {
+            let open = self.0.clone();
+            open.fetch_add(1, Ordering::SeqCst);
+            ::std::thread::spawn(move || {
+                block_on(::connection::Connection::new(::framed::Framed::new(stream, Codec),
+                                                       Arc::new(Echo)));
+                open.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+
+        fn execute(&mut self, spawn: Spawn) {
+            ::std::thread::spawn(move || block_on(spawn()));
+        }
+    }
+
+    #[test]
+    fn run_on_a_queue_of_its_own() {
+        let server = TcpServer::builder(Proto).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let open = Arc::new(AtomicUsize::new(0));
+        let queue = ThreadPerConnection(open.clone());
+        let running = ::std::thread::spawn(move || server.run_on(queue));
+
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"hi").unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+        assert_eq!(1, open.load(Ordering::SeqCst));
+
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        handle.spawn_with(move || Ok::<_, ()>(sender.send(::std::thread::current().id())));
+        let ran_on = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_ne!(::std::thread::current().id(), ran_on);
 
-        Ok(())
+        handle.stop();
+        running.join().unwrap().unwrap();
     }
 }
@@ -0,0 +1,265 @@
+//! Stopping servers when the process is asked to exit.
+//!
+//! [`stop_on_signals`] stops a server when the process receives
+//! `SIGINT` (e.g. Ctrl-C) or `SIGTERM`. The first signal shuts down
+//! every server registered this way, as `ServerHandle::shutdown` does,
+//! so requests in flight are answered. A second signal stops them
+//! straight away, and restores the default handlers, so a third ends
+//! the process.
+//!
+//! [`stop_on_signals`]: fn.stop_on_signals.html
+
+use std::io;
+use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use server::ServerHandle;
+
+const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+/// The end of the pipe that signal handlers write to.
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+static WATCHER: Once = Once::new();
+/// The handles of the servers to stop, with how long each has to shut
+/// down.
+static HANDLES: Mutex<Vec<(ServerHandle, Duration)>> = Mutex::new(Vec::new());
+
+/// Shuts down the server with `handle`, within `grace`, when the
+/// process receives `SIGINT` or `SIGTERM`, and stops it if it receives
+/// another. This replaces any handlers already installed for them.
+pub fn stop_on_signals(handle: ServerHandle, grace: Duration) -> io::Result<()> {
+    let mut started = Ok(());
+    WATCHER.call_once(|| started = start_watcher());
+    started?;
+
+    if WRITE_FD.load(Ordering::SeqCst) < 0 {
+        return Err(io::Error::other("the signal watcher failed to start"));
+    }
+
+    HANDLES.lock().unwrap().push((handle, grace));
+    for &signal in &SIGNALS {
+        set_handler(signal, on_signal as *const () as libc::sighandler_t)?;
+    }
+
+    Ok(())
+}
+
+/// Only does what's async-signal-safe: writing to the pipe, which
+/// wakes the watcher. The write may set `errno`, which is restored for
+/// the code the signal interrupted.
+extern "C" fn on_signal(_: libc::c_int) {
+    let fd = WRITE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        unsafe {
+            let errno = errno();
+            let saved = errno.as_ref().copied();
+            libc::write(fd, b"!".as_ptr() as *const libc::c_void, 1);
+            if let Some(saved) = saved {
+                *errno = saved;
+            }
+        }
+    }
+}
+
+/// Where the calling thread's `errno` is, or null where it isn't known.
+#[cfg(any(target_os = "linux", target_os = "emscripten", target_os = "hurd"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "emscripten", target_os = "hurd",
+              target_os = "android", target_os = "netbsd", target_os = "openbsd",
+              target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+unsafe fn errno() -> *mut libc::c_int {
+    ::std::ptr::null_mut()
+}
+
+fn set_handler(signal: libc::c_int, handler: libc::sighandler_t) -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = ::std::mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        match libc::sigaction(signal, &action, ::std::ptr::null_mut()) {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Creates the pipe, and a thread that shuts down the registered
+/// servers when a signal handler first writes to it, and stops them
+/// the next time.
+fn start_watcher() -> io::Result<()> {
+    let mut fds = [0; 2];
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for &fd in &fds {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        // A handler mustn't block if signals arrive faster than the
+        // watcher reads them.
+        let flags = libc::fcntl(fds[1], libc::F_GETFL);
+        libc::fcntl(fds[1], libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+
+    let read_fd = fds[0];
+    let mut signalled = false;
+    thread::Builder::new()
+        .name(String::from("server-fx signals"))
+        .spawn(move || loop {
+            let mut byte = 0u8;
+            let read = unsafe {
+                libc::read(read_fd, &mut byte as *mut u8 as *mut libc::c_void, 1)
+            };
+            if read < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            if read <= 0 {
+                return;
+            }
+
+            if !signalled {
+                signalled = true;
+                for &(ref handle, grace) in HANDLES.lock().unwrap().iter() {
+                    handle.shutdown(grace);
+                }
+                continue;
+            }
+
+            for &signal in &SIGNALS {
+                let _ = set_handler(signal, libc::SIG_DFL);
+            }
+            for (handle, _) in HANDLES.lock().unwrap().drain(..) {
+                handle.stop();
+            }
+        })?;
+
+    WRITE_FD.store(fds[1], Ordering::SeqCst);
+    Ok(())
+}
+
+#[cfg(test)]
+mod signal_should {
+    use super::*;
+    use std::net;
+    use server::TcpServer;
+
+    struct Proto;
+
+    impl ::bind_transport::BindTransport<net::TcpStream> for Proto {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Transport = ::framed::Framed<net::TcpStream, Codec>;
+        type Result = io::Result<Self::Transport>;
+
+        fn bind_transport(&self, s: net::TcpStream) -> Self::Result {
+            Ok(::framed::Framed::new(s, Codec))
+        }
+    }
+
+    struct Codec;
+
+    impl ::codec::Decode for Codec {
+        type Item = Vec<u8>;
+
+        fn decode(&self, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+            Some(::std::mem::take(buffer))
+        }
+    }
+
+    impl ::codec::Encode for Codec {
+        type Item = Vec<u8>;
+
+        fn encode(&self, item: Vec<u8>, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item);
+            Ok(())
+        }
+    }
+
+    /// Echoes each request, but never answers `wait`.
+    struct Echo;
+
+    impl ::handler::Handler for Echo {
+        type Request = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = io::Error;
+        type Pollable = Answer;
+
+        fn handle(&self, request: Vec<u8>) -> Answer {
+            Answer(Some(request))
+        }
+    }
+
+    struct Answer(Option<Vec<u8>>);
+
+    impl ::pollable::Pollable for Answer {
+        type Item = Vec<u8>;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Result<::result::PollResult<Vec<u8>>, io::Error> {
+            match self.0.take() {
+                Some(ref request) if request == b"wait" => {
+                    // Nothing ever wakes it.
+                    ::readiness::Wakeup::new().wait();
+                    self.0 = Some(request.clone());
+                    Ok(::result::PollResult::NotReady)
+                },
+                Some(request) => Ok(::result::PollResult::Ready(request)),
+                None => Ok(::result::PollResult::NotReady),
+            }
+        }
+    }
+
+    #[test]
+    fn shut_down_servers_on_sigterm_and_stop_them_on_the_next() {
+        use std::io::{Read, Write};
+
+        let server = TcpServer::builder(Proto).threads(1).build()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        stop_on_signals(server.handle(), Duration::from_secs(60)).unwrap();
+        let running = thread::spawn(move || server.run(|| Echo));
+
+        let mut busy = net::TcpStream::connect(addr).unwrap();
+        busy.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        busy.write_all(b"wait").unwrap();
+        for _ in 0..500 {
+            if handle.requests() == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // The request in flight keeps the server shutting down.
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert!(!running.is_finished());
+
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+        running.join().unwrap().unwrap();
+        assert_eq!(0, busy.read(&mut [0; 4]).unwrap_or(0));
+    }
+}
@@ -1,5 +1,6 @@
 use result::PollResult;
 use pollable::Pollable;
+use upgrade::Upgrading;
 
 pub enum SinkResult<T> {
     Ready,
@@ -19,6 +20,22 @@ pub trait Sink {
     {
         SendOne::new(self, item)
     }
+
+    /// Called once the items sent have been written. If any of them
+    /// asked for the connection to be upgraded to another protocol,
+    /// returns what serves it from now on. Otherwise, returns the sink,
+    /// as it does by default.
+    fn upgrade(self) -> Result<Upgrading<Self::Error>, Self> where
+        Self: Sized
+    {
+        Err(self)
+    }
+
+    /// Asks the sink to finish with its connection once the request
+    /// it's serving, if any, has been answered, e.g. because the
+    /// server is winding down. By default, this does nothing, and the
+    /// connection is served until it closes.
+    fn drain(&mut self) {}
 }
 
 pub struct SendOne<S, I> {
@@ -29,11 +46,15 @@ pub struct SendOne<S, I> {
 impl<S, I> SendOne<S, I> {
     pub fn new(inner: S, value: I) -> SendOne<S, I> {
         SendOne {
-            inner: inner,
+            inner,
             value: Some(value),
         }
     }
 
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
     pub fn into_inner(self) -> S {
         self.inner
     }
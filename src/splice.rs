@@ -0,0 +1,57 @@
+//! Moving bytes between sockets without copying them through userspace.
+//!
+//! `splice(2)` moves bytes between a file descriptor and a pipe inside
+//! the kernel. A `Twister` relaying between two sockets splices what it
+//! reads from one into a pipe, and from the pipe into the other,
+//! rather than reading into a buffer and writing it out again.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+
+use libc;
+
+/// How many bytes a pipe is asked to hold. It's the default capacity of
+/// a pipe on Linux.
+pub(crate) const PIPE_SIZE: usize = 64 * 1024;
+
+/// A pipe that bytes are spliced through.
+#[derive(Debug)]
+pub(crate) struct Pipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl Pipe {
+    pub(crate) fn new() -> io::Result<Pipe> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(unsafe {
+            Pipe {
+                read: OwnedFd::from_raw_fd(fds[0]),
+                write: OwnedFd::from_raw_fd(fds[1]),
+            }
+        })
+    }
+
+    /// Moves up to `len` bytes from `source` into the pipe.
+    pub(crate) fn fill(&self, source: RawFd, len: usize) -> io::Result<usize> {
+        splice(source, self.write.as_raw_fd(), len)
+    }
+
+    /// Moves up to `len` bytes from the pipe to `destination`.
+    pub(crate) fn drain(&self, destination: RawFd, len: usize) -> io::Result<usize> {
+        splice(self.read.as_raw_fd(), destination, len)
+    }
+}
+
+fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    match unsafe { libc::splice(from, ptr::null_mut(), to, ptr::null_mut(), len, flags) } {
+        -1 => Err(io::Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
@@ -0,0 +1,258 @@
+//! Splitting streams into halves that read and write separately.
+//!
+//! A `Twister` reads from each of its streams while it writes to it.
+//! Rather than sharing a stream between the two, it [`Split`]s each one
+//! into a half that reads and a half that writes, and owns both.
+//!
+//! Sockets, which can be read and written through shared references,
+//! are split into halves that share the socket through an `Arc`, so a
+//! `Twister` of sockets can be sent to another thread. Streams that
+//! need `&mut` to be read or written, such as a `TlsStream`, are
+//! wrapped in a [`Locked`] first.
+//!
+//! [`Split`]: trait.Split.html
+//! [`Locked`]: struct.Locked.html
+
+use std::io::{self, Read, Write};
+use std::net;
+use std::sync::{Arc, Mutex};
+
+use connected::Shutdown;
+
+/// A stream that can be split into a half that reads and a half that
+/// writes.
+pub trait Split: Sized {
+    type ReadHalf: Read;
+    /// The half that writes. Once the stream's peer has nothing more to
+    /// send, it's shut down for writing, so the other peer knows as
+    /// well.
+    type WriteHalf: Write + Shutdown;
+
+    fn split(self) -> (Self::ReadHalf, Self::WriteHalf);
+
+    /// Puts the halves that `split` returned back together.
+    fn unsplit(read: Self::ReadHalf, write: Self::WriteHalf) -> Self;
+}
+
+/// The half of a shared stream that reads.
+#[derive(Debug)]
+pub struct ReadHalf<T>(Arc<T>);
+
+/// The half of a shared stream that writes.
+#[derive(Debug)]
+pub struct WriteHalf<T>(Arc<T>);
+
+/// Splits `stream`, which is read and written through shared
+/// references, into halves that share it.
+pub fn share<T>(stream: T) -> (ReadHalf<T>, WriteHalf<T>) {
+    let stream = Arc::new(stream);
+    (ReadHalf(stream.clone()), WriteHalf(stream))
+}
+
+/// Puts the halves that `share` returned back together.
+///
+/// # Panics
+///
+/// If the halves are of different streams.
+pub fn unshare<T>(read: ReadHalf<T>, write: WriteHalf<T>) -> T {
+    assert!(Arc::ptr_eq(&read.0, &write.0), "The halves are of different streams");
+    drop(write);
+    match Arc::try_unwrap(read.0) {
+        Ok(stream) => stream,
+        Err(_) => unreachable!("A stream is only shared by its halves"),
+    }
+}
+
+impl<T> Read for ReadHalf<T> where
+    for<'a> &'a T: Read
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self.0).read(buf)
+    }
+}
+
+impl<T> Write for WriteHalf<T> where
+    for<'a> &'a T: Write
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self.0).flush()
+    }
+}
+
+impl Shutdown for WriteHalf<net::TcpStream> {
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+        self.0.shutdown(how)
+    }
+}
+
+impl Split for net::TcpStream {
+    type ReadHalf = ReadHalf<net::TcpStream>;
+    type WriteHalf = WriteHalf<net::TcpStream>;
+
+    fn split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+        share(self)
+    }
+
+    fn unsplit(read: Self::ReadHalf, write: Self::WriteHalf) -> Self {
+        unshare(read, write)
+    }
+}
+
+impl Shutdown for WriteHalf<::mio::net::TcpStream> {
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+        self.0.shutdown(how)
+    }
+}
+
+impl Split for ::mio::net::TcpStream {
+    type ReadHalf = ReadHalf<::mio::net::TcpStream>;
+    type WriteHalf = WriteHalf<::mio::net::TcpStream>;
+
+    fn split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+        share(self)
+    }
+
+    fn unsplit(read: Self::ReadHalf, write: Self::WriteHalf) -> Self {
+        unshare(read, write)
+    }
+}
+
+#[cfg(unix)]
+impl Shutdown for WriteHalf<::std::os::unix::net::UnixStream> {
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+        self.0.shutdown(how)
+    }
+}
+
+#[cfg(unix)]
+impl Split for ::std::os::unix::net::UnixStream {
+    type ReadHalf = ReadHalf<::std::os::unix::net::UnixStream>;
+    type WriteHalf = WriteHalf<::std::os::unix::net::UnixStream>;
+
+    fn split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+        share(self)
+    }
+
+    fn unsplit(read: Self::ReadHalf, write: Self::WriteHalf) -> Self {
+        unshare(read, write)
+    }
+}
+
+/// A stream behind a lock, so that it can be read and written through
+/// shared references, and split. E.g. a `TlsStream`, whose reads and
+/// writes both go through the same session.
+#[derive(Debug)]
+pub struct Locked<T>(Mutex<T>);
+
+impl<T> Locked<T> {
+    pub fn new(stream: T) -> Locked<T> {
+        Locked(Mutex::new(stream))
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0.into_inner().unwrap()
+    }
+}
+
+impl<T: Read> Read for &Locked<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl<T: Write> Write for &Locked<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+impl<T: Shutdown> Shutdown for WriteHalf<Locked<T>> {
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+        (self.0).0.lock().unwrap().shutdown(how)
+    }
+}
+
+impl<T: Read + Write + Shutdown> Split for Locked<T> {
+    type ReadHalf = ReadHalf<Locked<T>>;
+    type WriteHalf = WriteHalf<Locked<T>>;
+
+    fn split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+        share(self)
+    }
+
+    fn unsplit(read: Self::ReadHalf, write: Self::WriteHalf) -> Self {
+        unshare(read, write)
+    }
+}
+
+#[cfg(test)]
+mod split_should {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A stream that's only read and written through `&mut`.
+    struct Exclusive {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        shutdown: Option<net::Shutdown>,
+    }
+
+    impl Read for Exclusive {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Exclusive {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shutdown for Exclusive {
+        fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+            self.shutdown = Some(how);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn split_locked_streams_into_halves_that_share_them() {
+        let stream = Locked::new(Exclusive {
+            input: Cursor::new(b"Ping".to_vec()),
+            output: vec![],
+            shutdown: None,
+        });
+
+        let (mut read, mut write) = stream.split();
+        let mut ping = vec![];
+        read.read_to_end(&mut ping).unwrap();
+        write.write_all(b"Pong").unwrap();
+        write.shutdown(net::Shutdown::Write).unwrap();
+
+        let stream = Locked::unsplit(read, write).into_inner();
+        assert_eq!(b"Ping", &ping[..]);
+        assert_eq!(b"Pong", &stream.output[..]);
+        assert_eq!(Some(net::Shutdown::Write), stream.shutdown);
+    }
+
+    #[test]
+    #[should_panic(expected = "different streams")]
+    fn refuse_to_put_halves_of_different_streams_together() {
+        let (read, _) = share(Cursor::new(vec![0]));
+        let (_, write) = share(Cursor::new(vec![1]));
+        unshare(read, write);
+    }
+}
@@ -1,21 +1,109 @@
+//! How a server's connections are spread across threads.
+//!
+//! An acceptor hands each connection it accepts to a [`Queue`], which
+//! decides where and how it's served. By default a server serves them
+//! with a [`ThreadPool`], whose workers each poll their connections on
+//! a thread of their own. An application that wants another strategy,
+//! e.g. a thread per connection or a pool shared with the rest of the
+//! application, implements `Queue` itself, and runs its server with
+//! `BoundServer::run_on`.
+//!
+//! [`Queue`]: trait.Queue.html
+//! [`ThreadPool`]: struct.ThreadPool.html
+
+use std::any::Any;
+use std::io;
+use std::mem;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::sync::Arc;
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::thread::{JoinHandle, spawn};
-use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, SendError, Sender, TryRecvError};
+use std::thread::{self, JoinHandle, sleep, spawn};
+use std::time::{Duration, Instant};
 use std::net;
 
+use mio::{Events, Interest, Poll, Token, Waker};
+
 use handler::Handler;
 use bind_transport::BindTransport;
+use budget::{self, BUDGET};
 use result::PollResult;
 use pollable::{IntoPollable, Pollable};
 use sink::Sink;
-use connection::Connection;
+use connection::{Activity, Connection};
+use executor::{LocalTasks, Spawn, Task};
+use lifecycle::{ConnectionContext, ConnectionHooks, Disconnect};
+use load::{LoadCounters, Loads, Timed};
+use readiness::{Arranged, Readiness, WATCHED};
+use server::ServerConfig;
+use timer::Timers;
+
+/// The token of each worker's `Waker`. Connections are identified by
+/// their index in the worker's list of entries.
+const WAKER: Token = Token(usize::MAX);
+
+/// Tasks are identified by their index in the worker's list of tasks
+/// plus `TASK`, so they can arrange to be woken as connections do.
+const TASK: usize = 1 << (usize::BITS - 3);
+
+/// How often a pool that's shutting down checks if its workers have
+/// finished.
+const SHUTDOWN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a worker with connections or tasks that are waiting
+/// without having arranged to be woken can sleep for before it polls
+/// them again.
+const TASK_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The hooks a server calls as its connections open and close.
+pub type Hooks = Option<Arc<dyn ConnectionHooks + Send + Sync>>;
+
+/// What the pool sends its workers.
+enum Message {
+    /// Connections to serve.
+    Connections(Vec<net::TcpStream>),
+    /// Close each connection once it's answered the request it's on.
+    Drain,
+    /// Drain, and stop once every connection has closed.
+    Retire,
+}
+
+/// A worker's thread, and how to reach it.
+struct WorkerThread {
+    thread: JoinHandle<()>,
+    sender: Sender<Message>,
+    tasks: Sender<Spawn>,
+    waker: Arc<Waker>,
+    load: Arc<LoadCounters>,
+}
+
+impl WorkerThread {
+    fn send(&self, message: Message) {
+        let _ = self.sender.send(message);
+        let _ = self.waker.wake();
+    }
+}
 
 pub struct ThreadPool<P, H> {
-    threads: Vec<JoinHandle<()>>,
-    senders: Vec<Sender<net::TcpStream>>,
+    config: Arc<ServerConfig>,
+    proto: Arc<P>,
+    handler: Arc<H>,
+    hooks: Hooks,
+    workers: Vec<WorkerThread>,
+    /// Workers that were removed when the pool shrank, serving the
+    /// connections they still have.
+    retiring: Vec<WorkerThread>,
+    /// Connections queued for each worker, but not yet sent.
+    batches: Vec<Vec<net::TcpStream>>,
     last_thread: usize,
-    _marker: PhantomData<(P, H)>,
+    connections: Arc<AtomicUsize>,
+    /// Where the counters of the workers are read from.
+    loads: Loads,
+    /// The worker that the next task is sent to.
+    next_task: usize,
+    draining: bool,
+    /// Cuts a graceful shutdown short once it's set.
+    stopped: Arc<AtomicBool>,
 }
 
 impl<P, H> ThreadPool<P, H> where
@@ -26,98 +114,1039 @@ impl<P, H> ThreadPool<P, H> where
     H::Error: From<<P::Result as IntoPollable>::Error>,
     H::Error: ::std::fmt::Debug,
 {
-    pub fn new(num_threads: usize, proto: Arc<P>, handler: Arc<H>) 
-        -> ThreadPool<P, H>
+    /// Creates a pool of `config.threads` workers, that bind the
+    /// connections they're given with `proto`, and serve them with
+    /// `handler`.
+    pub fn new(config: Arc<ServerConfig>, proto: Arc<P>, handler: Arc<H>, hooks: Hooks)
+        -> io::Result<ThreadPool<P, H>>
     {
-        let mut threads = Vec::with_capacity(num_threads);
-        let mut senders = Vec::with_capacity(num_threads);
+        let threads = config.threads;
+        let mut pool = ThreadPool {
+            config,
+            proto,
+            handler,
+            hooks,
+            workers: vec![],
+            retiring: vec![],
+            batches: vec![],
+            last_thread: 0,
+            connections: Arc::new(AtomicUsize::new(0)),
+            loads: Loads::default(),
+            next_task: 0,
+            draining: false,
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
 
-        for _ in 0..num_threads {
-            let (sender, receiver) = channel();
-            let proto = proto.clone();
-            let handler = handler.clone();
-            let t = spawn(move || connection_proc(proto, handler, receiver));
+        pool.resize(threads)?;
+        Ok(pool)
+    }
 
-            threads.push(t);
-            senders.push(sender);
-        }
+    /// Starts the `index`th worker.
+    fn spawn(&self, index: usize) -> io::Result<WorkerThread> {
+        let (sender, receiver) = channel();
+        let (tasks, spawned) = channel();
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let proto = self.proto.clone();
+        let handler = self.handler.clone();
+        let config = self.config.clone();
+        let open = self.connections.clone();
+        let hooks = self.hooks.clone();
+        let load = Arc::new(LoadCounters::default());
+        let counters = load.clone();
+        let wakes = waker.clone();
 
-        ThreadPool {
-            threads: threads,
-            senders: senders,
-            last_thread: 0,
-            _marker: PhantomData,
+        // Connections needn't be `Send`, so the worker is created
+        // on its own thread.
+        let thread = spawn(move || Worker::new(config, proto, handler, hooks, poll, wakes, open, counters)
+                           .run(receiver, spawned));
+
+        // A worker that can't be pinned stops once its sender is
+        // dropped.
+        #[cfg(all(target_os = "linux", feature = "affinity"))]
+        {
+            if let Some(ref affinity) = self.config.affinity {
+                ::affinity::pin(&thread, affinity, index)?;
+            }
         }
+        #[cfg(not(all(target_os = "linux", feature = "affinity")))]
+        let _ = index;
+
+        Ok(WorkerThread {
+            thread,
+            sender,
+            tasks,
+            waker,
+            load,
+        })
+    }
+
+    /// Shows the counters of each worker, including those that are
+    /// retiring, in `loads` from now on.
+    pub(crate) fn report_to(&mut self, loads: Loads) {
+        self.loads = loads;
+        self.publish();
+    }
+
+    /// Stops the workers straight away, rather than waiting for them,
+    /// if `stopped` is set while they shut down gracefully.
+    pub(crate) fn stop_with(&mut self, stopped: Arc<AtomicBool>) {
+        self.stopped = stopped;
+    }
+
+    /// Shows the counters of each worker, including those that are
+    /// retiring, in `loads`.
+    fn publish(&self) {
+        self.loads.set(self.workers.iter().chain(&self.retiring).map(|w| w.load.clone()).collect());
     }
+}
+
+/// Where an acceptor puts the connections it accepts, and where the
+/// tasks spawned through its server's handle are run.
+///
+/// The acceptor calls `flush` after each batch of connections it
+/// queues, and passes on what's asked of its server through the
+/// handle, such as resizing or draining, along with the tasks spawned
+/// through it. Only `connections`, `queue` and `execute` are required;
+/// a queue that has no use for the rest can ignore them.
+pub trait Queue {
+    /// The number of connections that are open.
+    fn connections(&self) -> usize;
+
+    /// Serves `stream`, or queues it to be served once the queue is
+    /// flushed.
+    fn queue(&mut self, stream: net::TcpStream);
+
+    /// Starts serving the connections that have been queued, if they
+    /// aren't already.
+    fn flush(&mut self) {}
+
+    /// Runs the task that `spawn` creates.
+    fn execute(&mut self, spawn: Spawn);
 
-    pub fn queue(&mut self, stream: net::TcpStream) {
-        self.senders[self.last_thread] .send(stream)
-            .expect("The connection thread has died!");
+    /// The number of threads that connections are handed to.
+    fn threads(&self) -> usize {
+        1
+    }
+
+    /// Grows or shrinks the queue to `threads` threads, if it can.
+    fn resize(&mut self, _threads: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Closes each connection once it's answered the request it's on,
+    /// including connections queued later.
+    fn drain(&mut self) {}
+
+    /// Stops serving connections, closing those that are open, and
+    /// waits for them to finish.
+    fn shutdown(self) where Self: Sized {}
+
+    /// Stops serving new connections, and gives those that are open up
+    /// to `grace` to answer the requests they're on, before stopping
+    /// as `shutdown` does.
+    fn shutdown_within(self, _grace: Duration) where Self: Sized {
+        self.shutdown()
+    }
+}
+
+impl<P, H> Queue for ThreadPool<P, H> where
+    P: BindTransport<net::TcpStream> + Send + Sync + 'static,
+    H: Handler<Request=P::Request, Response=P::Response> + Send + Sync + 'static,
+    H::Error: From<<P::Transport as Sink>::Error>,
+    H::Error: From<<P::Transport as Pollable>::Error>,
+    H::Error: From<<P::Result as IntoPollable>::Error>,
+    H::Error: ::std::fmt::Debug,
+{
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Queues `stream` for the next worker. It isn't served until the
+    /// queue is flushed.
+    fn queue(&mut self, stream: net::TcpStream) {
+        self.connections.fetch_add(1, Ordering::SeqCst);
+        self.batches[self.last_thread].push(stream);
         self.last_thread += 1;
-        self.last_thread %= self.threads.len();
+        self.last_thread %= self.workers.len();
+    }
+
+    /// Sends each worker the connections queued for it, waking it once
+    /// for all of them.
+    fn flush(&mut self) {
+        for index in 0..self.workers.len() {
+            if self.batches[index].is_empty() {
+                continue;
+            }
+
+            let batch = mem::take(&mut self.batches[index]);
+            let count = batch.len();
+            let mut message = Message::Connections(batch);
+
+            // A worker whose thread has died is replaced, and its
+            // connections are sent to the new one. If one can't be
+            // started, they're closed.
+            loop {
+                let worker = &self.workers[index];
+                worker.load.queued(count);
+                message = match worker.sender.send(message) {
+                    Ok(()) => {
+                        let _ = worker.waker.wake();
+                        break;
+                    },
+                    Err(SendError(unsent)) => unsent,
+                };
+
+                match self.spawn(index) {
+                    Ok(worker) => {
+                        if self.draining {
+                            worker.send(Message::Drain);
+                        }
+                        let dead = mem::replace(&mut self.workers[index], worker);
+                        let _ = dead.thread.join();
+                        self.publish();
+                    },
+                    Err(_) => {
+                        self.connections.fetch_sub(count, Ordering::SeqCst);
+                        break;
+                    },
+                }
+            }
+        }
+    }
+
+    /// Sends the task to the next worker, in turn.
+    fn execute(&mut self, spawn: Spawn) {
+        self.next_task %= self.workers.len();
+        let worker = &self.workers[self.next_task];
+        self.next_task += 1;
+        let _ = worker.tasks.send(spawn);
+        let _ = worker.waker.wake();
+    }
+
+    /// The number of workers that connections are handed to.
+    fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Grows or shrinks the pool to `threads` workers. Connections
+    /// can't move between threads, so workers that are removed stop
+    /// being handed new connections, drain those they have, and stop
+    /// once they've all closed.
+    ///
+    /// If a worker can't be started, the pool keeps those it started
+    /// before it.
+    fn resize(&mut self, threads: usize) -> io::Result<()> {
+        self.flush();
+        self.retiring.retain(|w| !w.thread.is_finished());
+
+        while self.workers.len() < threads {
+            let worker = match self.spawn(self.workers.len()) {
+                Ok(worker) => worker,
+                Err(e) => {
+                    self.publish();
+                    return Err(e);
+                },
+            };
+            if self.draining {
+                worker.send(Message::Drain);
+            }
+            self.workers.push(worker);
+            self.batches.push(Vec::new());
+        }
+
+        while self.workers.len() > threads {
+            let worker = self.workers.pop().expect("The pool has no workers!");
+            worker.send(Message::Retire);
+            self.retiring.push(worker);
+        }
+
+        self.batches.truncate(threads);
+        self.last_thread %= threads;
+        self.publish();
+        Ok(())
+    }
+
+    /// Has every worker close its connections once they've answered
+    /// the requests they're on, including connections queued later.
+    fn drain(&mut self) {
+        self.draining = true;
+        for worker in &self.workers {
+            worker.send(Message::Drain);
+        }
+    }
+
+    /// Stops the workers, closing their connections, and waits for
+    /// them to finish.
+    fn shutdown(self) {
+        // A worker stops once its channel is disconnected. The wakers
+        // are kept until then, as closing one drops its event.
+        let workers = self.workers.into_iter().chain(self.retiring);
+        let (threads, wakers): (Vec<_>, Vec<_>) = workers
+            .map(|w| (w.thread, w.waker))
+            .unzip();
+        for waker in &wakers {
+            let _ = waker.wake();
+        }
+
+        for t in threads {
+            let _ = t.join();
+        }
+    }
+
+    /// Stops handing connections to the workers, and has them close
+    /// each connection once it's answered the request it's on. Waits
+    /// up to `grace` for them to finish, or until the pool's asked to
+    /// stop, and then stops those that haven't, as `shutdown` does.
+    fn shutdown_within(mut self, grace: Duration) {
+        self.flush();
+        for worker in &self.workers {
+            worker.send(Message::Retire);
+        }
+
+        let deadline = Instant::now() + grace;
+        let finished = |pool: &ThreadPool<P, H>| pool.workers.iter()
+            .chain(&pool.retiring)
+            .all(|w| w.thread.is_finished());
+        while !finished(&self) && Instant::now() < deadline {
+            if self.stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            sleep(SHUTDOWN_INTERVAL);
+        }
+
+        self.shutdown();
+    }
+}
+
+enum State<B, H, S> where
+    H: Handler,
+    S: Pollable<Item=Option<H::Request>> + Sink<Item=H::Response> + 'static
+{
+    Binding(B),
+    Open(Connection<H, S>),
+}
+
+/// A connection being served by a worker.
+struct Entry<B, H, S> where
+    H: Handler,
+    S: Pollable<Item=Option<H::Request>> + Sink<Item=H::Response> + 'static
+{
+    state: State<B, H, S>,
+    /// A handle to the connection's socket, registered for readiness.
+    source: mio::net::TcpStream,
+    /// What the socket's registered for.
+    interest: Interest,
+    activity: Activity,
+    /// When the connection is closed if it's still reading or writing.
+    deadline: Option<Instant>,
+    /// When the connection is closed if nothing arrives while it waits
+    /// for its next request.
+    idle: Option<Instant>,
+    /// When the connection is closed if the head of the request being
+    /// read hasn't arrived.
+    header: Option<Instant>,
+    /// When the request being handled or responded to times out.
+    request: Option<Instant>,
+    /// Whether nothing has arrived since the connection started
+    /// waiting for its next request.
+    quiet: bool,
+    /// The connection, as the hooks know it. Only kept if there are
+    /// hooks.
+    context: Option<ConnectionContext>,
+}
+
+impl<B, H, S> Entry<B, H, S> where
+    H: Handler,
+    S: Pollable<Item=Option<H::Request>> + Sink<Item=H::Response> + 'static
+{
+    fn has_deadline(&self, deadline: Instant) -> bool {
+        self.deadline == Some(deadline) || self.idle == Some(deadline) ||
+            self.header == Some(deadline) || self.request == Some(deadline)
     }
 }
 
-fn connection_proc<P, H>(proto: Arc<P>, 
-                         handler: Arc<H>, 
-                         recv: Receiver<net::TcpStream>) 
-    where
-        P: BindTransport<net::TcpStream>, 
-        H: Handler<Request=P::Request, Response=P::Response>,
-        H::Error: From<<P::Transport as Sink>::Error>,
-        H::Error: From<<P::Transport as Pollable>::Error>,
-        H::Error: From<<P::Result as IntoPollable>::Error>,
-        H::Error: ::std::fmt::Debug,
+type WorkerEntry<P, H> = Entry<
+    <<P as BindTransport<net::TcpStream>>::Result as IntoPollable>::Pollable,
+    Timed<H>,
+    <P as BindTransport<net::TcpStream>>::Transport>;
+
+/// What a worker should do with a connection after polling it.
+enum Next {
+    /// Poll it when its socket is ready.
+    Sleep,
+    /// Poll it when its socket is ready, or when what it arranged wakes
+    /// it. One that arranged nothing is polled on every turn.
+    Wait(Arranged),
+    /// Poll it again straight away, as it ran out of budget before it
+    /// ran out of work.
+    Spin,
+    Close(Disconnect),
+}
+
+/// Serves connections on one thread. Sockets are registered with the
+/// worker's `Poll`, and the thread sleeps until one of them, or the
+/// `Waker` for a new connection, is ready.
+///
+/// A connection that's reading a request sleeps until its socket is
+/// readable. One that's binding, handling a request, writing a
+/// response, or has been upgraded may be waiting on something else,
+/// and sleeps until its socket is ready, or until what it arranged to
+/// be woken by is, e.g. a stream to an upstream server. Those that
+/// arranged nothing are polled on every turn of the loop. See
+/// [`readiness`]. Each poll of a connection has a budget, so one that
+/// always has work to do can't keep the others from theirs. See
+/// [`budget`].
+///
+/// [`readiness`]: ../readiness/index.html
+/// [`budget`]: ../budget/index.html
+pub(crate) struct Worker<P, H> where
+    P: BindTransport<net::TcpStream>,
+    H: Handler<Request=P::Request, Response=P::Response>,
+{
+    proto: Arc<P>,
+    handler: Arc<Timed<H>>,
+    config: Arc<ServerConfig>,
+    hooks: Hooks,
+    poll: Poll,
+    entries: Vec<Option<WorkerEntry<P, H>>>,
+    busy: Vec<usize>,
+    /// Connections and tasks that are waiting without having arranged
+    /// to be woken.
+    waiting: Vec<usize>,
+    /// The deadlines of entries, by index.
+    timers: Timers<usize>,
+    /// When connections asked to be woken, by index.
+    wakes: Timers<usize>,
+    readiness: Readiness,
+    open: Arc<AtomicUsize>,
+    load: Arc<LoadCounters>,
+    /// Pollables that aren't tied to a connection.
+    tasks: Vec<Option<Task>>,
+    local: LocalTasks,
+    /// Whether connections are closed once they've answered the
+    /// request they're on.
+    draining: bool,
+    /// Whether the worker stops once its connections have closed.
+    retiring: bool,
+}
+
+impl<P, H> Worker<P, H> where
+    P: BindTransport<net::TcpStream>,
+    H: Handler<Request=P::Request, Response=P::Response>,
+    H::Error: From<<P::Transport as Sink>::Error>,
+    H::Error: From<<P::Transport as Pollable>::Error>,
+    H::Error: From<<P::Result as IntoPollable>::Error>,
+    H::Error: ::std::fmt::Debug,
 {
-    let mut connections = vec![];
-
-    loop {
-        let msg = {
-            if connections.len() == 0 {
-                match recv.recv() {
-                    Ok(s) => Some(s),
-                    Err(_) => return,
+    /// Creates a worker that registers its connections with `poll`,
+    /// and counts them in `open`, along with every other worker's.
+    /// Its own connections, requests and work are counted in `load`.
+    /// Connections are woken from other threads with `waker`, which
+    /// wakes `poll`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(config: Arc<ServerConfig>, proto: Arc<P>, handler: Arc<H>, hooks: Hooks,
+               poll: Poll, waker: Arc<Waker>, open: Arc<AtomicUsize>, load: Arc<LoadCounters>)
+        -> Worker<P, H>
+    {
+        let readiness = Readiness::new(poll.registry(), waker);
+        Worker {
+            proto,
+            handler: Arc::new(Timed::new(handler, load.clone())),
+            config,
+            hooks,
+            poll,
+            entries: vec![],
+            busy: vec![],
+            waiting: vec![],
+            timers: Timers::new(),
+            wakes: Timers::new(),
+            readiness,
+            open,
+            load,
+            tasks: vec![],
+            local: LocalTasks::new(),
+            draining: false,
+            retiring: false,
+        }
+    }
+
+    fn run(mut self, recv: Receiver<Message>, spawned: Receiver<Spawn>) {
+        let mut events = Events::with_capacity(1024);
+
+        loop {
+            let timeout = self.timeout();
+            if let Err(e) = self.poll.poll(&mut events, timeout) {
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return;
                 }
             }
-            else {
-                match recv.try_recv() {
-                    Ok(s) => Some(s),
-                    Err(TryRecvError::Empty) => None,
-                    _ => return,
+
+            for event in events.iter() {
+                match event.token() {
+                    WAKER => {
+                        for spawn in spawned.try_iter() {
+                            self.spawn(spawn);
+                        }
+
+                        loop {
+                            match recv.try_recv() {
+                                Ok(Message::Connections(batch)) => for s in batch {
+                                    self.load.dequeued();
+                                    self.start(s);
+                                },
+                                Ok(Message::Drain) => self.drain(),
+                                Ok(Message::Retire) => {
+                                    self.drain();
+                                    self.retiring = true;
+                                },
+                                Err(TryRecvError::Empty) => break,
+                                Err(TryRecvError::Disconnected) => return,
+                            }
+                        }
+                    },
+                    token => self.ready(token),
                 }
             }
+
+            self.turn();
+
+            if self.retiring && self.is_idle() {
+                return;
+            }
+        }
+    }
+
+    pub fn poll_mut(&mut self) -> &mut Poll {
+        &mut self.poll
+    }
+
+    /// How long the worker can sleep for before it has something to
+    /// do, if it needn't wake until a socket is ready.
+    pub fn timeout(&self) -> Option<Duration> {
+        if !self.busy.is_empty() {
+            return Some(Duration::from_secs(0));
+        }
+
+        let next = match (self.timers.next(), self.wakes.next()) {
+            (Some(deadline), Some(wake)) => Some(deadline.min(wake)),
+            (deadline, wake) => deadline.or(wake),
+        };
+        let timeout = next.map(|d| d.saturating_duration_since(Instant::now()));
+        match self.waiting.is_empty() {
+            true => timeout,
+            false => Some(timeout.map_or(TASK_INTERVAL, |t| t.min(TASK_INTERVAL))),
+        }
+    }
+
+    /// Runs the task that `spawn` creates, polling it until it
+    /// resolves.
+    pub fn spawn(&mut self, spawn: Spawn) {
+        if let Ok(task) = catch_unwind(AssertUnwindSafe(spawn)) {
+            let index = self.add_task(task);
+            self.busy.push(index);
+        }
+    }
+
+    /// Adds `task` to those the worker runs, returning its index.
+    fn add_task(&mut self, task: Task) -> usize {
+        let index = self.tasks.iter()
+            .position(Option::is_none)
+            .unwrap_or(self.tasks.len());
+
+        match index == self.tasks.len() {
+            true => self.tasks.push(Some(task)),
+            false => self.tasks[index] = Some(task),
+        }
+
+        index | TASK
+    }
+
+    /// Asks each connection to close once it's answered the request
+    /// it's on, as will those that open from now on.
+    /// Connections waiting for a request that hasn't started to
+    /// arrive are closed straight away.
+    pub fn drain(&mut self) {
+        self.draining = true;
+
+        let mut closed = 0;
+        for index in 0..self.entries.len() {
+            let entry = match self.entries[index] {
+                Some(ref mut entry) => entry,
+                None => continue,
+            };
+
+            if entry.quiet && entry.activity == Activity::Reading {
+                closed += self.close(index, Disconnect::Closed);
+            }
+            else if let State::Open(ref mut connection) = entry.state {
+                connection.drain();
+            }
+        }
+        self.open.fetch_sub(closed, Ordering::SeqCst);
+    }
+
+    /// Whether the worker has no connections.
+    pub fn is_idle(&self) -> bool {
+        self.entries.iter().all(Option::is_none)
+    }
+
+    fn start(&mut self, s: net::TcpStream) {
+        if let Some(index) = self.add(s) {
+            self.busy.push(index);
+        }
+    }
+
+    /// Notes that the socket registered with `token` is ready, so its
+    /// connection, or the task that watches it, is polled on the next
+    /// turn.
+    pub fn ready(&mut self, Token(token): Token) {
+        let index = token & !WATCHED;
+        if index & TASK != 0 {
+            if let Some(&Some(_)) = self.tasks.get(index & !TASK) {
+                self.busy.push(index);
+            }
+            return;
+        }
+
+        // Anything from the peer, even a partial request, means the
+        // connection isn't idle. A stream the connection watches being
+        // ready doesn't.
+        if let Some(&mut Some(ref mut entry)) = self.entries.get_mut(index) {
+            if token & WATCHED == 0 {
+                entry.idle = None;
+                entry.quiet = false;
+            }
+            self.busy.push(index);
+        }
+    }
+
+    /// Polls the connections that are ready or busy, closes those that
+    /// are finished or past their deadlines, and then polls the tasks.
+    pub fn turn(&mut self) {
+        self.load.turned();
+        let mut ready = mem::take(&mut self.busy);
+        ready.append(&mut self.waiting);
+        ready.extend(self.readiness.woken());
+        ready.extend(self.wakes.expire(Instant::now()).into_iter().map(|(index, _)| index));
+        ready.sort_unstable();
+        ready.dedup();
+
+        // Task indices sort after those of connections.
+        let split = ready.partition_point(|&index| index & TASK == 0);
+        let mut tasks = ready.split_off(split);
+
+        let mut closed = 0;
+        for index in ready {
+            match self.pump(index) {
+                Next::Sleep => {},
+                Next::Wait(arranged) => self.wait(index, arranged),
+                Next::Spin => self.busy.push(index),
+                Next::Close(reason) => closed += self.close(index, reason),
+            }
+        }
+
+        for (index, deadline) in self.timers.expire(Instant::now()) {
+            if !self.is_current(index, deadline) {
+                continue;
+            }
+
+            match self.time_out(index, deadline) {
+                true => self.busy.push(index),
+                false => {
+                    closed += self.close(index, Disconnect::TimedOut);
+                    self.busy.retain(|&i| i != index);
+                },
+            }
+        }
+
+        // Deadlines that were replaced are dropped once there are
+        // more of them than there are connections.
+        if self.timers.len() > 2 * self.entries.len() + 64 {
+            let entries = &self.entries;
+            self.timers.retain(|index, deadline| entries[index].as_ref()
+                .map(|e| e.has_deadline(deadline))
+                .unwrap_or(false));
+        }
+        if self.wakes.len() > 2 * (self.entries.len() + self.tasks.len()) + 64 {
+            let (entries, tasks) = (&self.entries, &self.tasks);
+            self.wakes.retain(|index, _| match index & TASK {
+                0 => entries[index].is_some(),
+                _ => tasks[index & !TASK].is_some(),
+            });
+        }
+
+        self.open.fetch_sub(closed, Ordering::SeqCst);
+
+        // Tasks that handlers spawned are polled on the same turn.
+        for task in self.local.take() {
+            tasks.push(self.add_task(task));
+        }
+        for index in tasks {
+            match self.run_task(index) {
+                Next::Sleep => {},
+                Next::Wait(arranged) => self.wait(index, arranged),
+                Next::Spin => self.busy.push(index),
+                Next::Close(_) => self.tasks[index & !TASK] = None,
+            }
+        }
+    }
+
+    /// Polls the task at `index`. Like a connection, a task that's
+    /// waiting on something arranges to be woken by it. Tasks are
+    /// dropped once they resolve, fail, or panic.
+    fn run_task(&mut self, index: usize) -> Next {
+        let task = match self.tasks.get_mut(index & !TASK) {
+            Some(&mut Some(ref mut task)) => task,
+            _ => return Next::Sleep,
+        };
+
+        match self.readiness.poll(index, || poll_budgeted(task)) {
+            ((Ok(Ok(PollResult::NotReady)), true), _) => Next::Spin,
+            ((Ok(Ok(PollResult::NotReady)), false), arranged) => Next::Wait(arranged),
+            _ => Next::Close(Disconnect::Closed),
+        }
+    }
+
+    /// Starts serving `s`, returning its index if it should be polled.
+    fn add(&mut self, s: net::TcpStream) -> Option<usize> {
+        let context = match self.hooks {
+            Some(_) => match (s.peer_addr(), s.local_addr()) {
+                (Ok(peer), Ok(local)) => Some(ConnectionContext::new(peer, local)),
+                _ => {
+                    self.open.fetch_sub(1, Ordering::SeqCst);
+                    return None;
+                },
+            },
+            None => None,
+        };
+
+        let mut source = match s.set_nonblocking(true).and_then(|_| s.try_clone()) {
+            Ok(source) => mio::net::TcpStream::from_std(source),
+            Err(_) => {
+                self.open.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            },
+        };
+
+        let index = self.entries.iter()
+            .position(Option::is_none)
+            .unwrap_or(self.entries.len());
+
+        // Binding may mean writing to the socket, e.g. for a TLS
+        // handshake.
+        let interest = Interest::READABLE | Interest::WRITABLE;
+        let registered = self.poll.registry()
+            .register(&mut source, Token(index), interest);
+        if registered.is_err() {
+            self.open.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        let deadline = self.config.read_timeout.map(|t| Instant::now() + t);
+        let header = self.config.header_timeout.map(|t| Instant::now() + t);
+        for &d in deadline.iter().chain(&header) {
+            self.timers.schedule(index, d);
+        }
+
+        let entry = Entry {
+            state: State::Binding(self.proto.bind_transport_with(s, &self.config)
+                                  .into_pollable()),
+            source,
+            interest,
+            activity: Activity::Reading,
+            deadline,
+            idle: None,
+            header,
+            request: None,
+            quiet: true,
+            context,
         };
 
-        msg.map(|s| {
-            let handler = handler.clone();
-            let conn = proto.bind_transport(s)
-                .into_pollable()
-                .and_then(move |transport| Connection::new(transport, handler));
+        if let (Some(hooks), Some(context)) = (self.hooks.as_ref(), entry.context.as_ref()) {
+            hooks.on_connect(context);
+        }
 
-            connections.push(Some(conn));
-        });
+        self.load.opened();
+        match index == self.entries.len() {
+            true => self.entries.push(Some(entry)),
+            false => self.entries[index] = Some(entry),
+        }
 
-        pump_connections(&mut connections);
+        Some(index)
     }
-}
 
-fn pump_connections<P: Pollable>(connections: &mut Vec<Option<P>>) {
+    fn pump(&mut self, index: usize) -> Next {
+        let entry = match self.entries.get_mut(index) {
+            Some(&mut Some(ref mut entry)) => entry,
+            _ => return Next::Sleep,
+        };
 
-    for c in connections.iter_mut() {
-        let mut current = c.take()
-            .expect("There are no connections waiting to be polled!");
+        // A panic only fails the connection that caused it, rather
+        // than the worker's thread and every connection on it.
+        let readiness = &self.readiness;
+        let mut next = match entry.state {
+            State::Binding(ref mut binding) => match readiness.poll(index, || poll_budgeted(binding)) {
+                ((Ok(Ok(PollResult::Ready(transport))), _), _) => {
+                    let mut connection = Connection::new(transport, self.handler.clone());
+                    if self.draining {
+                        connection.drain();
+                    }
+                    entry.state = State::Open(connection);
+                    Next::Spin
+                },
+                ((Ok(Ok(PollResult::NotReady)), true), _) => Next::Spin,
+                ((Ok(Ok(PollResult::NotReady)), false), arranged) => Next::Wait(arranged),
+                ((Ok(Err(e)), _), _) => Next::Close(failed(H::Error::from(e))),
+                ((Err(panic), _), _) => Next::Close(panicked(panic)),
+            },
+            State::Open(ref mut connection) => {
+                let before = connection.activity();
+                match readiness.poll(index, || poll_budgeted(connection)) {
+                    ((Ok(Ok(PollResult::NotReady)), exhausted), arranged) => {
+                        // A connection takes a step each time it's
+                        // polled, so one that took a step, or ran out
+                        // of budget, is polled again straight away. One
+                        // that's still reading is waiting on its
+                        // socket. Others may be waiting on something
+                        // else, which they arranged to be woken by.
+                        let after = connection.activity();
+                        match (before, after, exhausted) {
+                            (_, _, true) => Next::Spin,
+                            (before, after, _) if before != after => Next::Spin,
+                            (Activity::Reading, Activity::Reading, false) => Next::Sleep,
+                            _ => Next::Wait(arranged),
+                        }
+                    },
+                    ((Ok(Ok(PollResult::Ready(()))), _), _) => Next::Close(Disconnect::Closed),
+                    ((Ok(Err(e)), _), _) => Next::Close(failed(e)),
+                    ((Err(panic), _), _) => Next::Close(panicked(panic)),
+                }
+            },
+        };
 
-        if let Ok(PollResult::NotReady) =  current.poll() {
-            *c = Some(current);
+        let activity = match entry.state {
+            State::Binding(_) => Activity::Reading,
+            State::Open(ref connection) => connection.activity(),
+        };
+
+        // The socket's watched for writes while the connection may be
+        // writing to it. A socket that can't be registered again keeps
+        // what it was registered for, and the connection still wakes
+        // for anything it arranged.
+        let interest = match (&entry.state, activity) {
+            (&State::Binding(_), _) | (_, Activity::Writing) | (_, Activity::Upgraded) =>
+                Interest::READABLE | Interest::WRITABLE,
+            _ => Interest::READABLE,
+        };
+        if interest != entry.interest &&
+            self.poll.registry().reregister(&mut entry.source, Token(index), interest).is_ok()
+        {
+            entry.interest = interest;
+        }
+
+        if activity != entry.activity {
+            match (in_flight(entry.activity), in_flight(activity)) {
+                (false, true) => self.load.started(),
+                (true, false) => self.load.finished(),
+                _ => {},
+            }
+            entry.activity = activity;
+            let timeout = match activity {
+                Activity::Reading => self.config.read_timeout,
+                Activity::Writing => self.config.write_timeout,
+                _ => None,
+            };
+            entry.deadline = timeout.map(|t| Instant::now() + t);
+            if let Some(deadline) = entry.deadline {
+                self.timers.schedule(index, deadline);
+            }
+
+            // The connection has written a response, and is waiting
+            // for the next request.
+            entry.idle = match activity {
+                Activity::Reading => self.config.keep_alive_timeout.map(|t| Instant::now() + t),
+                _ => None,
+            };
+            if let Some(idle) = entry.idle {
+                self.timers.schedule(index, idle);
+            }
+
+            entry.header = match activity {
+                Activity::Reading => self.config.header_timeout.map(|t| Instant::now() + t),
+                _ => None,
+            };
+            if let Some(header) = entry.header {
+                self.timers.schedule(index, header);
+            }
+
+            // A draining connection that's answered its request is
+            // done with.
+            entry.quiet = activity == Activity::Reading;
+            if entry.quiet && self.draining {
+                next = Next::Close(Disconnect::Closed);
+            }
+
+            // A request's deadline runs from when it's read until its
+            // response is written.
+            match activity {
+                Activity::Reading => entry.request = None,
+                Activity::Handling => {
+                    entry.request = self.config.request_timeout.map(|t| Instant::now() + t);
+                    if let Some(request) = entry.request {
+                        self.timers.schedule(index, request);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        // Once the head has arrived, the rest of the request is only
+        // limited by the read timeout.
+        if let State::Open(ref connection) = entry.state {
+            if connection.head_received() {
+                entry.header = None;
+            }
+        }
+
+        next
+    }
+
+    /// Has the connection or task at `index` polled again once what it
+    /// `arranged` wakes it, or on every turn if it arranged nothing.
+    fn wait(&mut self, index: usize, arranged: Arranged) {
+        for deadline in arranged.deadlines {
+            self.wakes.schedule(index, deadline);
+        }
+        if !arranged.any {
+            self.waiting.push(index);
+        }
+    }
+
+    /// Abandons the request of the connection at `index`, if it's past
+    /// `deadline` while it's being handled. Returns whether the
+    /// connection can go on, to write the handler's response to it.
+    fn time_out(&mut self, index: usize, deadline: Instant) -> bool {
+        let entry = match self.entries[index] {
+            Some(ref mut entry) if entry.request == Some(deadline) => entry,
+            _ => return false,
+        };
+
+        entry.request = None;
+        match entry.state {
+            State::Open(ref mut connection) => connection.time_out(),
+            State::Binding(_) => false,
+        }
+    }
+
+    /// Stops serving the connection at `index`, returning how many
+    /// connections were closed.
+    fn close(&mut self, index: usize, reason: Disconnect) -> usize {
+        match self.entries[index].take() {
+            Some(mut entry) => {
+                let _ = self.poll.registry().deregister(&mut entry.source);
+                self.load.closed();
+                if in_flight(entry.activity) {
+                    self.load.finished();
+                }
+                if let (Some(hooks), Some(context)) = (self.hooks.as_ref(), entry.context.as_ref()) {
+                    hooks.on_disconnect(context, &reason);
+                }
+                1
+            },
+            None => 0,
         }
     }
 
-    let mut n = connections.len();
-    while n > 0 {
-        n -= 1;
-        if connections[n].is_none() {
-            connections.swap_remove(n);
+    /// Whether `deadline` is the current deadline of the entry at
+    /// `index`.
+    fn is_current(&self, index: usize, deadline: Instant) -> bool {
+        self.entries[index].as_ref()
+            .map(|e| e.has_deadline(deadline))
+            .unwrap_or(false)
+    }
+}
+
+/// Connections still open when the worker is dropped are closed
+/// because the server stopped, or because the worker's thread
+/// panicked.
+impl<P, H> Drop for Worker<P, H> where
+    P: BindTransport<net::TcpStream>,
+    H: Handler<Request=P::Request, Response=P::Response>,
+{
+    fn drop(&mut self) {
+        self.load.clear();
+        let open = self.entries.iter().flatten().count();
+        self.open.fetch_sub(open, Ordering::SeqCst);
+
+        let hooks = match self.hooks {
+            Some(ref hooks) => hooks,
+            None => return,
+        };
+
+        let reason = match thread::panicking() {
+            true => Disconnect::Failed(String::from("the worker panicked")),
+            false => Disconnect::Stopped,
+        };
+        for entry in self.entries.iter().flatten() {
+            if let Some(ref context) = entry.context {
+                hooks.on_disconnect(context, &reason);
+            }
         }
     }
 }
 
+/// What polling a connection returned, or the panic it raised.
+type Polled<P> = thread::Result<Result<PollResult<<P as Pollable>::Item>, <P as Pollable>::Error>>;
+
+/// Polls `pollable` with a fresh budget, catching any panic. Also
+/// returns whether it ran out of budget.
+fn poll_budgeted<P: Pollable>(pollable: &mut P) -> (Polled<P>, bool) {
+    budget::with_budget(BUDGET, || catch_unwind(AssertUnwindSafe(|| pollable.poll())))
+}
+
+/// Whether a connection doing `activity` has a request in flight.
+fn in_flight(activity: Activity) -> bool {
+    matches!(activity, Activity::Handling | Activity::Writing)
+}
+
+fn failed<E: ::std::fmt::Debug>(e: E) -> Disconnect {
+    Disconnect::Failed(format!("{:?}", e))
+}
+
+fn panicked(panic: Box<dyn Any + Send>) -> Disconnect {
+    let message = match panic.downcast_ref::<&str>() {
+        Some(message) => String::from(*message),
+        None => panic.downcast_ref::<String>().cloned()
+            .unwrap_or_else(|| String::from("Box<dyn Any>")),
+    };
+    Disconnect::Failed(format!("panicked: {}", message))
+}
+
+/// A worker run by the acceptor's thread serves the connections it
+/// accepts straight away.
+impl<P, H> Queue for Worker<P, H> where
+    P: BindTransport<net::TcpStream>,
+    H: Handler<Request=P::Request, Response=P::Response>,
+    H::Error: From<<P::Transport as Sink>::Error>,
+    H::Error: From<<P::Transport as Pollable>::Error>,
+    H::Error: From<<P::Result as IntoPollable>::Error>,
+    H::Error: ::std::fmt::Debug,
+{
+    fn connections(&self) -> usize {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn queue(&mut self, stream: net::TcpStream) {
+        self.open.fetch_add(1, Ordering::SeqCst);
+        self.start(stream);
+    }
+
+    fn execute(&mut self, spawn: Spawn) {
+        self.spawn(spawn);
+    }
+
+    fn drain(&mut self) {
+        Worker::drain(self);
+    }
+}
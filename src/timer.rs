@@ -0,0 +1,88 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Instant;
+
+/// A queue of deadlines, each for a key. E.g. a worker's connections.
+///
+/// Deadlines can't be cancelled or moved. Instead, a new deadline is
+/// scheduled, and the owner ignores those that have expired but aren't
+/// current any more.
+pub struct Timers<K> {
+    heap: BinaryHeap<Reverse<(Instant, K)>>,
+}
+
+impl<K: Ord + Copy> Timers<K> {
+    pub fn new() -> Timers<K> {
+        Timers {
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    pub fn schedule(&mut self, key: K, deadline: Instant) {
+        self.heap.push(Reverse((deadline, key)));
+    }
+
+    /// The earliest deadline.
+    pub fn next(&self) -> Option<Instant> {
+        self.heap.peek().map(|&Reverse((deadline, _))| deadline)
+    }
+
+    /// Removes the deadlines up to `now`, earliest first.
+    pub fn expire(&mut self, now: Instant) -> Vec<(K, Instant)> {
+        let mut expired = vec![];
+        while let Some(&Reverse((deadline, key))) = self.heap.peek() {
+            if deadline > now {
+                break;
+            }
+            self.heap.pop();
+            expired.push((key, deadline));
+        }
+        expired
+    }
+
+    /// Keeps only the deadlines for which `f` returns `true`.
+    pub fn retain<F: FnMut(K, Instant) -> bool>(&mut self, mut f: F) {
+        let heap = ::std::mem::take(&mut self.heap);
+        self.heap = heap.into_iter()
+            .filter(|&Reverse((deadline, key))| f(key, deadline))
+            .collect();
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+#[cfg(test)]
+mod timers_should {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn expire_deadlines_in_order() {
+        let now = Instant::now();
+        let mut timers = Timers::new();
+        timers.schedule(1, now + Duration::from_secs(2));
+        timers.schedule(2, now + Duration::from_secs(1));
+        timers.schedule(3, now + Duration::from_secs(3));
+
+        assert_eq!(Some(now + Duration::from_secs(1)), timers.next());
+        assert_eq!(
+            vec![(2, now + Duration::from_secs(1)), (1, now + Duration::from_secs(2))],
+            timers.expire(now + Duration::from_secs(2))
+        );
+        assert_eq!(Some(now + Duration::from_secs(3)), timers.next());
+    }
+
+    #[test]
+    fn drop_deadlines_that_are_not_retained() {
+        let now = Instant::now();
+        let mut timers = Timers::new();
+        timers.schedule(1, now);
+        timers.schedule(2, now);
+
+        timers.retain(|key, _| key == 2);
+        assert_eq!(1, timers.len());
+        assert_eq!(vec![(2, now)], timers.expire(now));
+    }
+}
@@ -0,0 +1,927 @@
+//! TLS for servers, using `rustls` or the platform's TLS library.
+//!
+//! A [`TlsProto`] wraps another protocol, so each accepted connection
+//! completes a TLS handshake before it's bound to a transport. The
+//! wrapped protocol binds a [`TlsStream`], which reads and writes
+//! plain text. E.g. for HTTPS, serve
+//! `TlsProto::new(TlsAcceptor::from_pem(certs, key)?, HttpProto)`.
+//!
+//! There are two backends, each behind a feature:
+//!
+//! - `tls`: [`rustls`](https://docs.rs/rustls).
+//! - `native-tls`: [`native-tls`](https://docs.rs/native-tls), which
+//!   uses SChannel on Windows, Security.framework on macOS, and
+//!   OpenSSL elsewhere. This suits deployments that must use the
+//!   platform's certificate store, or a FIPS-validated OpenSSL.
+//!
+//! Clients can be asked for certificates too, with
+//! [`TlsAcceptor::builder`]. A protocol can pass the certificates a
+//! client presented on to its handlers, e.g. an HTTP protocol's
+//! `bind_transport` might return
+//! `HttpTransport::new(Framed::new(io, codec)).extension(certs)` where
+//! `certs` is `io.peer_certificates()`. Handlers then extract them with
+//! `Extension<Option<PeerCertificates>>`.
+//!
+//! The acceptor can also negotiate an application protocol with ALPN,
+//! e.g. to offer HTTP/2 alongside HTTP/1.1 on the same port. As the
+//! wrapped protocol binds the [`TlsStream`], it can choose a codec for
+//! the connection from [`TlsStream::alpn_protocol`].
+//!
+//! [`TlsProto`]: struct.TlsProto.html
+//! [`TlsStream`]: struct.TlsStream.html
+//! [`TlsStream::alpn_protocol`]: struct.TlsStream.html#method.alpn_protocol
+//! [`TlsAcceptor::builder`]: struct.TlsAcceptor.html#method.builder
+
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net;
+use std::sync::Arc;
+
+#[cfg(feature = "native-tls")]
+use native_tls;
+#[cfg(feature = "tls")]
+use rustls::{self, ServerConnection};
+#[cfg(feature = "tls")]
+use rustls::server::WebPkiClientVerifier;
+#[cfg(feature = "tls")]
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+#[cfg(feature = "tls")]
+use rustls_pki_types::pem::PemObject;
+
+use bind_transport::BindTransport;
+use connected::{Connected, ConnectionInfo, Shutdown};
+use pollable::{IntoPollable, Pollable};
+use result::PollResult;
+use server::ServerConfig;
+
+/// The ALPN identifier of HTTP/1.1.
+pub const ALPN_HTTP_1_1: &[u8] = b"http/1.1";
+
+/// The ALPN identifier of HTTP/2 over TLS.
+pub const ALPN_H2: &[u8] = b"h2";
+
+fn invalid_data<E>(error: E) -> io::Error where
+    E: Into<Box<dyn Error + Send + Sync>>
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(feature = "tls")]
+fn pem_certificates(pem: &[u8]) -> io::Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid_data)
+}
+
+/// Whether clients are asked for a certificate during the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Clients aren't asked for a certificate.
+    None,
+    /// Clients are asked for a certificate, but can connect without
+    /// one. A certificate that's presented must still be valid.
+    Optional,
+    /// Clients must present a valid certificate to connect.
+    Required,
+}
+
+/// The DER-encoded certificate chain a client presented, starting
+/// with its own certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCertificates(pub Vec<Vec<u8>>);
+
+impl PeerCertificates {
+    /// The client's own certificate.
+    pub fn end_entity(&self) -> Option<&[u8]> {
+        self.0.first().map(|cert| &cert[..])
+    }
+}
+
+/// What was agreed in a TLS handshake. See [`ConnectionInfo`].
+///
+/// [`ConnectionInfo`]: ../connected/struct.ConnectionInfo.html
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TlsInfo {
+    /// The server name the client asked for with SNI. Only `rustls`
+    /// reports this.
+    pub server_name: Option<String>,
+    pub alpn_protocol: Option<Vec<u8>>,
+    pub peer_certificates: Option<PeerCertificates>,
+}
+
+#[derive(Clone)]
+enum Backend {
+    #[cfg(feature = "tls")]
+    Rustls(Arc<rustls::ServerConfig>),
+    #[cfg(feature = "native-tls")]
+    Native(Arc<native_tls::TlsAcceptor>),
+}
+
+/// Starts the server side of TLS connections, with either backend.
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    backend: Backend,
+}
+
+impl TlsAcceptor {
+    /// Creates an acceptor using `rustls`.
+    #[cfg(feature = "tls")]
+    pub fn new(config: Arc<rustls::ServerConfig>) -> TlsAcceptor {
+        TlsAcceptor {
+            backend: Backend::Rustls(config),
+        }
+    }
+
+    /// Creates an acceptor using the platform's TLS library.
+    #[cfg(feature = "native-tls")]
+    pub fn native(acceptor: native_tls::TlsAcceptor) -> TlsAcceptor {
+        TlsAcceptor {
+            backend: Backend::Native(Arc::new(acceptor)),
+        }
+    }
+
+    /// Creates an acceptor presenting the PEM-encoded certificate
+    /// chain `certs`, with the PEM-encoded PKCS #8 private `key` of its
+    /// first certificate. Clients aren't asked for certificates.
+    ///
+    /// This uses `rustls` if the `tls` feature is enabled, and the
+    /// platform's TLS library otherwise.
+    pub fn from_pem(certs: &[u8], key: &[u8]) -> io::Result<TlsAcceptor> {
+        TlsAcceptor::builder(certs, key).build()
+    }
+
+    /// Configures an acceptor presenting the PEM-encoded certificate
+    /// chain `certs`, with the PEM-encoded PKCS #8 private `key` of its
+    /// first certificate. E.g. to require client certificates issued
+    /// by a CA,
+    /// `TlsAcceptor::builder(certs, key).client_auth(ClientAuth::Required).client_ca(ca).build()`.
+    pub fn builder(certs: &[u8], key: &[u8]) -> TlsAcceptorBuilder {
+        TlsAcceptorBuilder {
+            certs: certs.to_vec(),
+            key: key.to_vec(),
+            client_auth: ClientAuth::None,
+            client_cas: vec![],
+            alpn_protocols: vec![],
+        }
+    }
+
+    /// Creates an acceptor using the platform's TLS library, presenting
+    /// the certificate chain and private key of a DER-encoded PKCS #12
+    /// archive, as exported from a certificate store.
+    #[cfg(feature = "native-tls")]
+    pub fn from_pkcs12(der: &[u8], password: &str) -> io::Result<TlsAcceptor> {
+        let identity = native_tls::Identity::from_pkcs12(der, password)
+            .map_err(invalid_data)?;
+        native_tls::TlsAcceptor::new(identity)
+            .map(TlsAcceptor::native)
+            .map_err(invalid_data)
+    }
+
+    /// Starts a handshake with the client on `stream`.
+    pub fn accept<S>(&self, stream: S) -> io::Result<Handshake<S>> where
+        S: Read + Write
+    {
+        let state = match self.backend {
+            #[cfg(feature = "tls")]
+            Backend::Rustls(ref config) => {
+                let connection = ServerConnection::new(config.clone())
+                    .map_err(invalid_data)?;
+                HandshakeState::Rustls(Some(TlsStream(Stream::Rustls(Box::new(connection),
+                                                                      stream))))
+            },
+            #[cfg(feature = "native-tls")]
+            Backend::Native(ref acceptor) =>
+                HandshakeState::NativeStart(acceptor.clone(), Some(stream)),
+        };
+
+        Ok(Handshake(state))
+    }
+}
+
+/// Configures a [`TlsAcceptor`] from PEM-encoded keys and
+/// certificates.
+///
+/// This uses `rustls` if the `tls` feature is enabled, and the
+/// platform's TLS library otherwise. Only `rustls` can ask clients for
+/// certificates.
+///
+/// [`TlsAcceptor`]: struct.TlsAcceptor.html
+pub struct TlsAcceptorBuilder {
+    certs: Vec<u8>,
+    key: Vec<u8>,
+    client_auth: ClientAuth,
+    client_cas: Vec<u8>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl TlsAcceptorBuilder {
+    /// Sets whether clients are asked for a certificate. Defaults to
+    /// `ClientAuth::None`.
+    pub fn client_auth(mut self, client_auth: ClientAuth) -> TlsAcceptorBuilder {
+        self.client_auth = client_auth;
+        self
+    }
+
+    /// Trusts the PEM-encoded CA certificates in `pem` to issue client
+    /// certificates. This can be called more than once.
+    pub fn client_ca(mut self, pem: &[u8]) -> TlsAcceptorBuilder {
+        self.client_cas.extend_from_slice(pem);
+        self.client_cas.push(b'\n');
+        self
+    }
+
+    /// Sets the application protocols that can be negotiated with ALPN,
+    /// most preferred first. E.g. `&[ALPN_H2, ALPN_HTTP_1_1]`. By
+    /// default, ALPN isn't used. With `rustls`, clients that only offer
+    /// unsupported protocols are refused.
+    pub fn alpn_protocols(mut self, protocols: &[&[u8]]) -> TlsAcceptorBuilder {
+        self.alpn_protocols = protocols.iter().map(|p| p.to_vec()).collect();
+        self
+    }
+
+    /// Creates the acceptor. Fails if the keys or certificates are
+    /// invalid, or if client certificates are asked for without a CA
+    /// to verify them.
+    pub fn build(self) -> io::Result<TlsAcceptor> {
+        #[cfg(feature = "tls")]
+        {
+            let certs = pem_certificates(&self.certs)?;
+            let key = PrivateKeyDer::from_pem_slice(&self.key)
+                .map_err(invalid_data)?;
+
+            let builder = match self.client_auth {
+                ClientAuth::None => rustls::ServerConfig::builder().with_no_client_auth(),
+                auth => {
+                    let mut roots = rustls::RootCertStore::empty();
+                    for cert in pem_certificates(&self.client_cas)? {
+                        roots.add(cert).map_err(invalid_data)?;
+                    }
+
+                    let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+                    let verifier = match auth {
+                        ClientAuth::Optional => verifier.allow_unauthenticated(),
+                        _ => verifier,
+                    };
+                    let verifier = verifier.build()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+                    rustls::ServerConfig::builder().with_client_cert_verifier(verifier)
+                },
+            };
+
+            let mut config = builder.with_single_cert(certs, key)
+                .map_err(invalid_data)?;
+            config.alpn_protocols = self.alpn_protocols;
+
+            Ok(TlsAcceptor::new(Arc::new(config)))
+        }
+
+        #[cfg(not(feature = "tls"))]
+        {
+            if self.client_auth != ClientAuth::None {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "client certificates need the `tls` feature"));
+            }
+
+            let protocols = self.alpn_protocols.into_iter()
+                .map(String::from_utf8)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let identity = native_tls::Identity::from_pkcs8(&self.certs, &self.key)
+                .map_err(invalid_data)?;
+            native_tls::TlsAcceptor::builder(identity)
+                .accept_alpn(&protocols)
+                .build()
+                .map(TlsAcceptor::native)
+                .map_err(invalid_data)
+        }
+    }
+}
+
+enum Stream<S> {
+    #[cfg(feature = "tls")]
+    Rustls(Box<ServerConnection>, S),
+    #[cfg(feature = "native-tls")]
+    Native(native_tls::TlsStream<S>),
+}
+
+/// A stream whose data is encrypted with TLS. Reads and writes are of
+/// plain text.
+///
+/// Writes are encrypted into records that are sent on the underlying
+/// stream as it allows, so `flush` should be called to make sure
+/// they've all been sent.
+pub struct TlsStream<S>(Stream<S>);
+
+impl<S: Read + Write> TlsStream<S> {
+    pub fn get_ref(&self) -> &S {
+        match self.0 {
+            #[cfg(feature = "tls")]
+            Stream::Rustls(_, ref stream) => stream,
+            #[cfg(feature = "native-tls")]
+            Stream::Native(ref stream) => stream.get_ref(),
+        }
+    }
+
+    /// The certificates the client presented, if any.
+    pub fn peer_certificates(&self) -> Option<PeerCertificates> {
+        match self.0 {
+            #[cfg(feature = "tls")]
+            Stream::Rustls(ref connection, _) => connection.peer_certificates()
+                .map(|certs| PeerCertificates(certs.iter().map(|c| c.to_vec()).collect())),
+            #[cfg(feature = "native-tls")]
+            Stream::Native(ref stream) => stream.peer_certificate().ok()
+                .and_then(|cert| cert)
+                .and_then(|cert| cert.to_der().ok())
+                .map(|cert| PeerCertificates(vec![cert])),
+        }
+    }
+
+    /// The application protocol negotiated with ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        match self.0 {
+            #[cfg(feature = "tls")]
+            Stream::Rustls(ref connection, _) => connection.alpn_protocol().map(<[u8]>::to_vec),
+            #[cfg(feature = "native-tls")]
+            Stream::Native(ref stream) => stream.negotiated_alpn().ok().and_then(|p| p),
+        }
+    }
+
+    pub fn info(&self) -> TlsInfo {
+        #[cfg(feature = "tls")]
+        let server_name = self.connection()
+            .and_then(|c| c.server_name())
+            .map(String::from);
+        #[cfg(not(feature = "tls"))]
+        let server_name = None;
+
+        TlsInfo {
+            server_name,
+            alpn_protocol: self.alpn_protocol(),
+            peer_certificates: self.peer_certificates(),
+        }
+    }
+
+    /// The `rustls` state of the connection, if it uses that backend.
+    /// E.g. to find the server name the client asked for.
+    #[cfg(feature = "tls")]
+    pub fn connection(&self) -> Option<&ServerConnection> {
+        match self.0 {
+            Stream::Rustls(ref connection, _) => Some(connection),
+            #[cfg(feature = "native-tls")]
+            Stream::Native(_) => None,
+        }
+    }
+}
+
+/// Describes the underlying stream's connection, with the TLS session.
+impl<S: Read + Write + Connected> Connected for TlsStream<S> {
+    fn connection_info(&self) -> io::Result<ConnectionInfo> {
+        Ok(ConnectionInfo {
+            tls: Some(self.info()),
+            ..self.get_ref().connection_info()?
+        })
+    }
+}
+
+/// Closing the write side sends the peer a `close_notify` alert first,
+/// so it can tell the data it's read wasn't cut short.
+impl<S: Read + Write + Shutdown> Shutdown for TlsStream<S> {
+    fn shutdown(&mut self, how: net::Shutdown) -> io::Result<()> {
+        match self.0 {
+            #[cfg(feature = "tls")]
+            Stream::Rustls(ref mut connection, ref mut stream) => {
+                if how != net::Shutdown::Read {
+                    connection.send_close_notify();
+                    write_tls(connection, stream)?;
+                }
+                stream.shutdown(how)
+            },
+            #[cfg(feature = "native-tls")]
+            Stream::Native(ref mut stream) => {
+                if how != net::Shutdown::Read {
+                    stream.shutdown()?;
+                }
+                stream.get_mut().shutdown(how)
+            },
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+/// Reads records from `stream` into `connection`, and processes them.
+/// Returns `0` at the end of the stream.
+fn read_tls<S: Read + Write>(connection: &mut ServerConnection, stream: &mut S)
+    -> io::Result<usize>
+{
+    let read = connection.read_tls(stream)?;
+    if let Err(e) = connection.process_new_packets() {
+        // Tell the peer why, if it's listening.
+        let _ = write_tls(connection, stream);
+        return Err(invalid_data(e));
+    }
+    Ok(read)
+}
+
+#[cfg(feature = "tls")]
+/// Sends any records pending on `connection` to `stream`.
+fn write_tls<S: Write>(connection: &mut ServerConnection, stream: &mut S) -> io::Result<()> {
+    while connection.wants_write() {
+        if connection.write_tls(stream)? == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+fn ignore_would_block(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+        result => result,
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0 {
+            #[cfg(feature = "tls")]
+            Stream::Rustls(ref mut connection, ref mut stream) => loop {
+                match connection.reader().read(buf) {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                    result => return result,
+                }
+
+                read_tls(connection, stream)?;
+                ignore_would_block(write_tls(connection, stream))?;
+            },
+            #[cfg(feature = "native-tls")]
+            Stream::Native(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0 {
+            #[cfg(feature = "tls")]
+            Stream::Rustls(ref mut connection, ref mut stream) => {
+                // Records from previous writes are sent first, so the
+                // connection's buffer doesn't grow while the peer isn't
+                // reading.
+                write_tls(connection, stream)?;
+                let written = connection.writer().write(buf)?;
+                ignore_would_block(write_tls(connection, stream))?;
+                Ok(written)
+            },
+            #[cfg(feature = "native-tls")]
+            Stream::Native(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0 {
+            #[cfg(feature = "tls")]
+            Stream::Rustls(ref mut connection, ref mut stream) => {
+                connection.writer().flush()?;
+                write_tls(connection, stream)?;
+                stream.flush()
+            },
+            #[cfg(feature = "native-tls")]
+            Stream::Native(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+enum HandshakeState<S> {
+    #[cfg(feature = "tls")]
+    Rustls(Option<TlsStream<S>>),
+    #[cfg(feature = "native-tls")]
+    NativeStart(Arc<native_tls::TlsAcceptor>, Option<S>),
+    #[cfg(feature = "native-tls")]
+    NativeMid(Option<native_tls::MidHandshakeTlsStream<S>>),
+}
+
+/// Completes a TLS handshake, resolving to the [`TlsStream`].
+///
+/// [`TlsStream`]: struct.TlsStream.html
+pub struct Handshake<S>(HandshakeState<S>);
+
+#[cfg(feature = "native-tls")]
+impl<S> Handshake<S> {
+    /// Continues a `native-tls` handshake from the result of its last
+    /// step.
+    fn native_step(&mut self,
+                   result: Result<native_tls::TlsStream<S>, native_tls::HandshakeError<S>>)
+        -> io::Result<PollResult<TlsStream<S>>>
+    {
+        match result {
+            Ok(stream) => Ok(PollResult::Ready(TlsStream(Stream::Native(stream)))),
+            Err(native_tls::HandshakeError::WouldBlock(mid)) => {
+                self.0 = HandshakeState::NativeMid(Some(mid));
+                Ok(PollResult::NotReady)
+            },
+            Err(native_tls::HandshakeError::Failure(e)) => Err(invalid_data(e)),
+        }
+    }
+}
+
+impl<S: Read + Write> Pollable for Handshake<S> {
+    type Item = TlsStream<S>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        const FINISHED: &str = "Poll called on finished handshake";
+
+        match self.0 {
+            #[cfg(feature = "tls")]
+            HandshakeState::Rustls(ref mut tls) => {
+                if let Some(TlsStream(Stream::Rustls(ref mut connection, ref mut stream))) = *tls {
+                    loop {
+                        try_poll_io!(write_tls(connection, stream));
+                        if !connection.is_handshaking() {
+                            break;
+                        }
+                        if try_poll_io!(read_tls(connection, stream)) == 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                    }
+                }
+
+                Ok(PollResult::Ready(tls.take().expect(FINISHED)))
+            },
+            #[cfg(feature = "native-tls")]
+            HandshakeState::NativeStart(ref acceptor, ref mut stream) => {
+                let result = acceptor.accept(stream.take().expect(FINISHED));
+                self.native_step(result)
+            },
+            #[cfg(feature = "native-tls")]
+            HandshakeState::NativeMid(ref mut mid) => {
+                let result = mid.take().expect(FINISHED).handshake();
+                self.native_step(result)
+            },
+        }
+    }
+}
+
+/// A protocol whose connections are encrypted with TLS. Each
+/// connection completes a handshake, and is then bound to a transport
+/// by the wrapped protocol.
+pub struct TlsProto<P> {
+    acceptor: TlsAcceptor,
+    proto: Arc<P>,
+}
+
+impl<P> TlsProto<P> {
+    pub fn new(acceptor: TlsAcceptor, proto: P) -> TlsProto<P> {
+        TlsProto {
+            acceptor,
+            proto: Arc::new(proto),
+        }
+    }
+
+    fn bind<S>(&self, s: S, config: Option<ServerConfig>) -> TlsBind<S, P> where
+        S: Read + Write + 'static,
+        P: BindTransport<TlsStream<S>>
+    {
+        match self.acceptor.accept(s) {
+            Ok(handshake) =>
+                TlsBind::Handshaking(Box::new(handshake), self.proto.clone(),
+                                     config.map(Box::new)),
+            Err(e) => TlsBind::Failed(Some(e)),
+        }
+    }
+}
+
+impl<S, P> BindTransport<S> for TlsProto<P> where
+    S: Read + Write + 'static,
+    P: BindTransport<TlsStream<S>>,
+    <P::Result as IntoPollable>::Error: Into<io::Error>
+{
+    type Request = P::Request;
+    type Response = P::Response;
+    type Transport = P::Transport;
+    type Result = TlsBind<S, P>;
+
+    fn bind_transport(&self, s: S) -> TlsBind<S, P> {
+        self.bind(s, None)
+    }
+
+    fn bind_transport_with(&self, s: S, config: &ServerConfig) -> TlsBind<S, P> {
+        self.bind(s, Some(config.clone()))
+    }
+}
+
+/// Completes the handshake of a connection, then binds it to the
+/// transport of the wrapped protocol.
+pub enum TlsBind<S, P> where
+    S: Read + Write + 'static,
+    P: BindTransport<TlsStream<S>>
+{
+    Handshaking(Box<Handshake<S>>, Arc<P>, Option<Box<ServerConfig>>),
+    Binding(<P::Result as IntoPollable>::Pollable),
+    Failed(Option<io::Error>),
+}
+
+impl<S, P> Pollable for TlsBind<S, P> where
+    S: Read + Write + 'static,
+    P: BindTransport<TlsStream<S>>,
+    <P::Result as IntoPollable>::Error: Into<io::Error>
+{
+    type Item = P::Transport;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
+        let next = match *self {
+            TlsBind::Handshaking(ref mut handshake, ref proto, ref config) =>
+                match handshake.poll()? {
+                    PollResult::Ready(stream) => {
+                        let result = match *config {
+                            Some(ref config) => proto.bind_transport_with(stream, config),
+                            None => proto.bind_transport(stream),
+                        };
+                        TlsBind::Binding(result.into_pollable())
+                    },
+                    PollResult::NotReady => return Ok(PollResult::NotReady),
+                },
+            TlsBind::Binding(ref mut binding) =>
+                return binding.poll().map_err(Into::into),
+            TlsBind::Failed(ref mut error) =>
+                return Err(error.take().expect("Poll called on failed result")),
+        };
+
+        *self = next;
+        self.poll()
+    }
+}
+
+#[cfg(test)]
+mod tls_should {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    const CERT: &[u8] = include_bytes!("../tests/tls/cert.pem");
+    const KEY: &[u8] = include_bytes!("../tests/tls/key.pem");
+
+    #[cfg(feature = "tls")]
+    fn connect(addr: ::std::net::SocketAddr) -> Box<dyn ReadWrite> {
+        connect_as(addr, false, &[])
+    }
+
+    /// Connects with `rustls`, presenting the test certificate if
+    /// `authenticate` is set, and offering the `alpn` protocols.
+    #[cfg(feature = "tls")]
+    fn connect_as(addr: ::std::net::SocketAddr, authenticate: bool, alpn: &[&[u8]])
+        -> Box<dyn ReadWrite>
+    {
+        use std::convert::TryFrom;
+        use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+        use rustls_pki_types::ServerName;
+
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(CERT) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+
+        let config = ClientConfig::builder().with_root_certificates(roots);
+        let mut config = match authenticate {
+            true => config.with_client_auth_cert(
+                pem_certificates(CERT).unwrap(),
+                PrivateKeyDer::from_pem_slice(KEY).unwrap()).unwrap(),
+            false => config.with_no_client_auth(),
+        };
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        let name = ServerName::try_from("localhost").unwrap();
+        let connection = ClientConnection::new(Arc::new(config), name).unwrap();
+
+        Box::new(StreamOwned::new(connection, TcpStream::connect(addr).unwrap()))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn connect(addr: ::std::net::SocketAddr) -> Box<dyn ReadWrite> {
+        let connector = native_tls::TlsConnector::builder()
+            .add_root_certificate(native_tls::Certificate::from_pem(CERT).unwrap())
+            .build()
+            .unwrap();
+
+        Box::new(connector.connect("localhost", TcpStream::connect(addr).unwrap()).unwrap())
+    }
+
+    trait ReadWrite: Read + Write {}
+
+    impl<T: Read + Write> ReadWrite for T {}
+
+    /// Accepts a connection, and polls its handshake to completion.
+    fn accept(acceptor: &TlsAcceptor, listener: &TcpListener)
+        -> io::Result<TlsStream<TcpStream>>
+    {
+        let (stream, _) = listener.accept()?;
+        stream.set_nonblocking(true)?;
+
+        let mut handshake = acceptor.accept(stream)?;
+        loop {
+            match handshake.poll()? {
+                PollResult::Ready(stream) => return Ok(stream),
+                PollResult::NotReady => thread::yield_now(),
+            }
+        }
+    }
+
+    /// Retries `f` while it would block.
+    fn retry<T, F: FnMut() -> io::Result<T>>(mut f: F) -> io::Result<T> {
+        loop {
+            match f() {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+                result => return result,
+            }
+        }
+    }
+
+    fn exchange_data(acceptor: TlsAcceptor) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = thread::spawn(move || {
+            let mut client = connect(addr);
+            client.write_all(b"ping").unwrap();
+            let mut reply = [0; 4];
+            client.read_exact(&mut reply).unwrap();
+            reply
+        });
+
+        let mut stream = accept(&acceptor, &listener).unwrap();
+        let mut request = [0; 4];
+        let read = retry(|| stream.read(&mut request)).unwrap();
+        assert_eq!(b"ping", &request[..read]);
+
+        retry(|| stream.write(b"pong")).unwrap();
+        retry(|| stream.flush()).unwrap();
+        assert_eq!(b"pong", &peer.join().unwrap());
+    }
+
+    #[test]
+    fn exchange_data_after_a_handshake() {
+        exchange_data(TlsAcceptor::from_pem(CERT, KEY).unwrap());
+    }
+
+    #[cfg(feature = "native-tls")]
+    #[test]
+    fn exchange_data_with_the_native_backend() {
+        let identity = native_tls::Identity::from_pkcs8(CERT, KEY).unwrap();
+        exchange_data(TlsAcceptor::native(native_tls::TlsAcceptor::new(identity).unwrap()));
+    }
+
+    #[test]
+    fn reject_plain_text_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = thread::spawn(move || {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut reply = vec![];
+            let _ = client.read_to_end(&mut reply);
+        });
+
+        let acceptor = TlsAcceptor::from_pem(CERT, KEY).unwrap();
+        let error = accept(&acceptor, &listener).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_keys() {
+        assert!(TlsAcceptor::from_pem(CERT, b"not a key").is_err());
+    }
+
+    /// Accepts a client that presents the test certificate if
+    /// `authenticate` is set, returning the certificates it presented.
+    #[cfg(feature = "tls")]
+    fn authenticate(client_auth: ClientAuth, authenticate: bool)
+        -> io::Result<Option<PeerCertificates>>
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = thread::spawn(move || {
+            let mut client = connect_as(addr, authenticate, &[]);
+            let _ = client.write_all(b"ping");
+            let _ = client.read(&mut [0; 4]);
+        });
+
+        let acceptor = TlsAcceptor::builder(CERT, KEY)
+            .client_auth(client_auth)
+            .client_ca(CERT)
+            .build()
+            .unwrap();
+        let result = accept(&acceptor, &listener).and_then(|mut stream| {
+            // Reading finishes a TLS 1.3 handshake, where the client's
+            // certificate is sent last.
+            retry(|| stream.read(&mut [0; 4]))?;
+            retry(|| stream.write(b"pong"))?;
+            retry(|| stream.flush())?;
+            Ok(stream.peer_certificates())
+        });
+
+        peer.join().unwrap();
+        result
+    }
+
+    #[test]
+    fn describe_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = thread::spawn(move || {
+            let mut client = connect(addr);
+            client.write_all(b"ping").unwrap();
+            // Waits for the server to close.
+            let _ = client.read(&mut [0; 4]);
+        });
+
+        let acceptor = TlsAcceptor::from_pem(CERT, KEY).unwrap();
+        let info = accept(&acceptor, &listener).unwrap().connection_info().unwrap();
+        peer.join().unwrap();
+
+        assert_eq!(addr, info.local_addr);
+        assert_eq!(None, info.tls.unwrap().peer_certificates);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn verify_client_certificates() {
+        let certs = authenticate(ClientAuth::Required, true).unwrap().unwrap();
+        assert_eq!(Some(&pem_certificates(CERT).unwrap()[0][..]), certs.end_entity());
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn reject_clients_without_a_required_certificate() {
+        let error = authenticate(ClientAuth::Required, false).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn accept_clients_without_an_optional_certificate() {
+        assert_eq!(None, authenticate(ClientAuth::Optional, false).unwrap());
+        assert!(authenticate(ClientAuth::Optional, true).unwrap().is_some());
+    }
+
+    #[test]
+    fn reject_client_authentication_without_a_ca() {
+        let acceptor = TlsAcceptor::builder(CERT, KEY)
+            .client_auth(ClientAuth::Required)
+            .build();
+        assert!(acceptor.is_err());
+    }
+
+    /// Accepts a client offering the `offered` protocols, returning
+    /// the protocol that was negotiated.
+    #[cfg(feature = "tls")]
+    fn negotiate(acceptor: TlsAcceptor, offered: &'static [&'static [u8]])
+        -> io::Result<Option<Vec<u8>>>
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = thread::spawn(move || {
+            let mut client = connect_as(addr, false, offered);
+            let _ = client.write_all(b"ping");
+        });
+
+        let result = accept(&acceptor, &listener).map(|stream| stream.alpn_protocol());
+        peer.join().unwrap();
+        result
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn negotiate_an_application_protocol() {
+        let acceptor = || TlsAcceptor::builder(CERT, KEY)
+            .alpn_protocols(&[ALPN_H2, ALPN_HTTP_1_1])
+            .build()
+            .unwrap();
+
+        assert_eq!(Some(ALPN_H2.to_vec()),
+                   negotiate(acceptor(), &[ALPN_HTTP_1_1, ALPN_H2]).unwrap());
+        assert_eq!(Some(ALPN_HTTP_1_1.to_vec()),
+                   negotiate(acceptor(), &[ALPN_HTTP_1_1]).unwrap());
+        assert_eq!(None, negotiate(acceptor(), &[]).unwrap());
+        assert!(negotiate(acceptor(), &[b"spdy/3"]).is_err());
+    }
+
+    #[cfg(all(feature = "tls", feature = "native-tls"))]
+    #[test]
+    fn negotiate_an_application_protocol_with_the_native_backend() {
+        let identity = native_tls::Identity::from_pkcs8(CERT, KEY).unwrap();
+        let acceptor = native_tls::TlsAcceptor::builder(identity)
+            .accept_alpn(&["h2", "http/1.1"])
+            .build()
+            .unwrap();
+
+        assert_eq!(Some(ALPN_H2.to_vec()),
+                   negotiate(TlsAcceptor::native(acceptor), &[ALPN_H2]).unwrap());
+    }
+}
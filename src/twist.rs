@@ -1,74 +1,429 @@
-use std::rc::Rc;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use std::io::{self, Read, Write};
-use std::fmt::Debug;
+use std::net;
+#[cfg(all(target_os = "linux", feature = "splice"))]
+use std::os::unix::io::{AsRawFd, RawFd};
 
+use budget;
+use connected::Shutdown;
 use pollable::Pollable;
+use readiness;
 use result::PollResult;
-use join::Join;
+use split::Split;
+#[cfg(all(target_os = "linux", feature = "splice"))]
+use splice::{Pipe, PIPE_SIZE};
+
+/// The way bytes are copied between the two ends of a `Twister`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the source to the destination.
+    SourceToDestination,
+    /// From the destination back to the source.
+    DestinationToSource,
+}
+
+impl Direction {
+    /// Where the direction's settings and counts are kept in arrays of
+    /// both.
+    fn index(self) -> usize {
+        match self {
+            Direction::SourceToDestination => 0,
+            Direction::DestinationToSource => 1,
+        }
+    }
+}
+
+/// The most bytes a `Twister` copies in each direction, and in both
+/// together. Each is unlimited if it's `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub source_to_destination: Option<usize>,
+    pub destination_to_source: Option<usize>,
+    pub total: Option<usize>,
+}
+
+impl Limits {
+    fn direction(&self, direction: Direction) -> Option<usize> {
+        match direction {
+            Direction::SourceToDestination => self.source_to_destination,
+            Direction::DestinationToSource => self.destination_to_source,
+        }
+    }
+}
+
+/// The error a `Twister` fails with, wrapped in an `io::Error`, once
+/// more bytes arrive in `direction` than its limits allow. The bytes up
+/// to the limit are copied, and the rest aren't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub direction: Direction,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.direction {
+            Direction::SourceToDestination =>
+                write!(f, "The source sent more than the transfer limit allows"),
+            Direction::DestinationToSource =>
+                write!(f, "The destination sent more than the transfer limit allows"),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// The bytes a `Twister` has copied so far in each direction. It's
+/// updated as the bytes are written, and can be read from any thread,
+/// e.g. to show each tunnel's throughput while it's open.
+#[derive(Debug, Clone, Default)]
+pub struct TransferStats(Arc<[AtomicUsize; 2]>);
+
+impl TransferStats {
+    /// The bytes copied in `direction`.
+    pub fn copied(&self, direction: Direction) -> usize {
+        self.counter(direction).load(Ordering::Relaxed)
+    }
+
+    pub fn source_to_destination(&self) -> usize {
+        self.copied(Direction::SourceToDestination)
+    }
+
+    pub fn destination_to_source(&self) -> usize {
+        self.copied(Direction::DestinationToSource)
+    }
+
+    fn counter(&self, direction: Direction) -> &AtomicUsize {
+        &self.0[direction.index()]
+    }
+}
+
+/// Shuts down the writing half of `stream`, unless its peer has already
+/// gone. When one end of a `Twister` has nothing more to send, the
+/// other end is told, so protocols that signal the end of a request by
+/// closing one direction work through it.
+fn close_write<S: Shutdown>(stream: &mut S) -> io::Result<()> {
+    match stream.shutdown(net::Shutdown::Write) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+        result => result,
+    }
+}
+
+/// Why a direction of a `Twister` stopped copying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    /// Its source reached the end of its stream.
+    SourceEof,
+    /// Its destination stopped taking what was written to it, e.g.
+    /// because its peer had closed the connection.
+    DestinationClosed,
+    /// The other direction ended, and the `Twister` closes both when
+    /// either ends.
+    Abandoned,
+}
+
+/// What a direction of a `Twister` copied before it ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transferred {
+    pub bytes: usize,
+    pub end: End,
+}
+
+/// What a `Twister` does when one of its directions ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofPolicy {
+    /// Carry on copying in the other direction until it ends as well.
+    #[default]
+    Continue,
+    /// Stop copying in the other direction, and close its destination
+    /// for writing.
+    CloseBoth,
+}
+
+/// Whether a failed write means the destination's peer has gone.
+fn is_closed(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset)
+}
+
+/// How fast a `Twister` copies in a direction: on average, no more than
+/// `bytes_per_second`, and no more than `burst` at once after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub bytes_per_second: usize,
+    pub burst: usize,
+}
+
+/// A token bucket, holding the bytes a transfer can copy before it has
+/// to wait.
+struct Bucket {
+    rate: Rate,
+    tokens: usize,
+    /// When the tokens were last counted up to.
+    filled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Rate) -> Bucket {
+        Bucket {
+            rate,
+            tokens: rate.burst,
+            filled: Instant::now(),
+        }
+    }
+
+    /// Adds the tokens earned since the bucket was last filled, and
+    /// returns how many it has.
+    fn fill(&mut self, now: Instant) -> usize {
+        const NANOS: u128 = 1_000_000_000;
+
+        let rate = self.rate.bytes_per_second as u128;
+        let earned = now.saturating_duration_since(self.filled).as_nanos() * rate / NANOS;
+        if self.tokens as u128 + earned >= self.rate.burst as u128 {
+            self.tokens = self.rate.burst;
+            self.filled = now;
+        }
+        else if earned > 0 {
+            // Only the time the tokens took to earn is counted, so
+            // what's left of it counts towards the next.
+            self.tokens += earned as usize;
+            self.filled += Duration::from_nanos((earned * NANOS / rate) as u64);
+        }
+        self.tokens
+    }
+
+    fn take(&mut self, tokens: usize) {
+        self.tokens = self.tokens.saturating_sub(tokens);
+    }
+
+    /// When the bucket earns its next token.
+    fn next_token(&self) -> Instant {
+        const NANOS: u128 = 1_000_000_000;
+
+        let rate = self.rate.bytes_per_second as u128;
+        self.filled + Duration::from_nanos(NANOS.div_ceil(rate) as u64)
+    }
+}
 
 enum TransferState {
     Reading,
-    Writing(usize),
+    /// Writing what's been read, from the first index up to the second.
+    Writing(usize, usize),
 }
 
-struct Transfer<S, D> {
-    source: Rc<S>,
-    destination: Rc<D>,
-    buffer: Vec<u8>,
+/// Where a transfer keeps what it's read until it's written.
+enum Relay {
+    /// A buffer, and its size. It's allocated when it's first read
+    /// into.
+    Buffer(Vec<u8>, usize),
+    /// A pipe, and the descriptors of the source and the destination,
+    /// that bytes are spliced between.
+    #[cfg(all(target_os = "linux", feature = "splice"))]
+    Pipe(Pipe, RawFd, RawFd),
+}
+
+impl Relay {
+    /// The most bytes that can be read at once.
+    fn capacity(&self) -> usize {
+        match *self {
+            Relay::Buffer(_, size) => size,
+            #[cfg(all(target_os = "linux", feature = "splice"))]
+            Relay::Pipe(..) => PIPE_SIZE,
+        }
+    }
+}
+
+/// Copies from the reading half of one stream to the writing half of
+/// the other.
+struct Transfer<R, W> {
+    source: R,
+    destination: W,
+    relay: Relay,
     state: TransferState,
     transferred: usize,
+    stats: TransferStats,
+    direction: Direction,
+    limits: Limits,
+    /// The bytes read in this direction, and in both.
+    read: usize,
+    total: Arc<AtomicUsize>,
+    /// Whether more was read than the limits allow.
+    exceeded: bool,
+    throttle: Option<Bucket>,
 }
 
-const BUFFER_SIZE: usize = 1024*8;
+/// The size of a transfer's buffer, unless it's configured.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024*8;
 
-impl<S, D> Transfer<S, D> {
-    fn new(source: Rc<S>, destination: Rc<D>) -> Transfer<S, D> {
+impl<R, W> Transfer<R, W> {
+    fn new(source: R, destination: W, relay: Relay, direction: Direction,
+           limits: Limits, total: Arc<AtomicUsize>, stats: TransferStats) -> Transfer<R, W>
+    {
         Transfer {
-            source: source,
-            destination: destination,
-            buffer: vec![0_u8; BUFFER_SIZE],
+            source,
+            destination,
+            relay,
             state: TransferState::Reading,
             transferred: 0,
+            stats,
+            direction,
+            limits,
+            read: 0,
+            total,
+            exceeded: false,
+            throttle: None,
+        }
+    }
+
+    /// Copies no faster than `rate`, if there is one.
+    fn throttle(mut self, rate: Option<Rate>) -> Transfer<R, W> {
+        self.throttle = rate.map(Bucket::new);
+        self
+    }
+
+    /// The bytes that can still be read before a limit is exceeded.
+    fn allowance(&self) -> usize {
+        let direction = self.limits.direction(self.direction)
+            .map_or(usize::MAX, |limit| limit.saturating_sub(self.read));
+        let total = self.limits.total
+            .map_or(usize::MAX, |limit| limit.saturating_sub(self.total.load(Ordering::Relaxed)));
+        direction.min(total)
+    }
+
+    fn ended(&self, end: End) -> Transferred {
+        Transferred {
+            bytes: self.transferred,
+            end,
         }
     }
+
+    fn wrote(&mut self, n: usize) {
+        self.transferred += n;
+        self.stats.counter(self.direction).fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn limit_exceeded(&self) -> io::Error {
+        io::Error::other(LimitExceeded { direction: self.direction })
+    }
 }
 
-impl<S, D> Transfer<S, D> {
-    fn into_inner(self) -> (Rc<S>, Rc<D>) {
+impl<R, W> Transfer<R, W> {
+    fn into_inner(self) -> (R, W) {
         (self.source, self.destination)
     }
 }
 
-impl<S, D> Pollable for Transfer<S, D>
-    where for <'a> &'a S: Read,
-          for <'a> &'a D: Write,
+impl<R: Read, W: Write> Transfer<R, W> {
+    /// Reads up to `len` bytes from the source.
+    fn fill(&mut self, len: usize) -> io::Result<usize> {
+        match self.relay {
+            Relay::Buffer(ref mut buffer, size) => {
+                buffer.resize(size, 0);
+                self.source.read(&mut buffer[..len])
+            },
+            #[cfg(all(target_os = "linux", feature = "splice"))]
+            Relay::Pipe(ref pipe, source, _) => pipe.fill(source, len),
+        }
+    }
+
+    /// Writes what's been read, from `start` up to `end`, to the
+    /// destination.
+    fn drain(&mut self, start: usize, end: usize) -> io::Result<usize> {
+        match self.relay {
+            Relay::Buffer(ref buffer, _) => self.destination.write(&buffer[start..end]),
+            #[cfg(all(target_os = "linux", feature = "splice"))]
+            Relay::Pipe(ref pipe, _, destination) => pipe.drain(destination, end - start),
+        }
+    }
+}
+
+impl<R, W: Shutdown> Transfer<R, W> {
+    /// Stops copying, as the other direction has ended, and tells the
+    /// destination's peer that nothing more is coming.
+    fn abandon(&mut self) -> Transferred {
+        let _ = close_write(&mut self.destination);
+        self.ended(End::Abandoned)
+    }
+}
+
+impl<R, W> Pollable for Transfer<R, W>
+    where R: Read,
+          W: Write + Shutdown,
 {
-    type Item = usize;
+    type Item = Transferred;
     type Error = io::Error;
 
+    /// Each read or write spends a unit of the budget.
     fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
         loop {
+            if !budget::spend() {
+                return Ok(PollResult::NotReady);
+            }
+
             let next = match self.state {
                 TransferState::Reading => {
-                    let n = try_poll_io!((&*self.source).read(&mut self.buffer));
+                    // Reading a byte beyond the allowance shows whether
+                    // the limit is exceeded, without it being written.
+                    let allowance = self.allowance();
+                    let mut len = self.relay.capacity().min(allowance.saturating_add(1));
+
+                    // A transfer that's used up its rate waits to be
+                    // woken, once it's earned more.
+                    if let Some(ref mut bucket) = self.throttle {
+                        match bucket.fill(Instant::now()) {
+                            0 => {
+                                readiness::wake_at(bucket.next_token());
+                                return Ok(PollResult::NotReady);
+                            },
+                            tokens => len = len.min(tokens),
+                        }
+                    }
+
+                    let n = try_poll_io!(self.fill(len));
                     if 0 == n {
-                        return Ok(PollResult::Ready(self.transferred));
+                        // The destination's peer is told that nothing
+                        // more is coming, as the source's was.
+                        close_write(&mut self.destination)?;
+                        return Ok(PollResult::Ready(self.ended(End::SourceEof)));
                     }
 
-                    TransferState::Writing(n)
+                    let n = match n > allowance {
+                        true => {
+                            self.exceeded = true;
+                            allowance
+                        },
+                        false => n,
+                    };
+                    self.read += n;
+                    self.total.fetch_add(n, Ordering::Relaxed);
+                    if let Some(ref mut bucket) = self.throttle {
+                        bucket.take(n);
+                    }
+                    match n {
+                        0 => return Err(self.limit_exceeded()),
+                        n => TransferState::Writing(0, n),
+                    }
                 },
-                TransferState::Writing(remaining) => {
-                    let result = (&*self.destination).write(&self.buffer[..remaining]);
-                    
+                TransferState::Writing(start, end) => {
+                    let result = match self.drain(start, end) {
+                        Err(ref e) if is_closed(e) => Ok(0),
+                        result => result,
+                    };
+
                     match try_poll_io!(result) {
-                        0 => return Ok(PollResult::Ready(self.transferred)),
-                        n if n == remaining => {
-                            self.transferred += remaining;
+                        0 => return Ok(PollResult::Ready(self.ended(End::DestinationClosed))),
+                        n if start + n == end => {
+                            self.wrote(n);
+                            if self.exceeded {
+                                return Err(self.limit_exceeded());
+                            }
                             TransferState::Reading
                         },
                         n => {
-                            self.transferred += n;
-                            TransferState::Writing(remaining - n)
+                            self.wrote(n);
+                            TransferState::Writing(start + n, end)
                         },
                     }
                 },
@@ -79,58 +434,225 @@ impl<S, D> Pollable for Transfer<S, D>
     }
 }
 
-type Twist<S, D> = Join<Transfer<S, D>, Transfer<D, S>>;
+/// Configures a [`Twister`]. E.g.
+/// `TwisterBuilder::new().buffer_size(64 * 1024).build(source, destination)`.
+///
+/// [`Twister`]: struct.Twister.html
+#[derive(Debug, Clone, Copy)]
+pub struct TwisterBuilder {
+    limits: Limits,
+    buffer_size: usize,
+    /// The rate of each direction, by `Direction::index`.
+    rates: [Option<Rate>; 2],
+    eof: EofPolicy,
+}
+
+impl Default for TwisterBuilder {
+    fn default() -> TwisterBuilder {
+        TwisterBuilder {
+            limits: Limits::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            rates: [None; 2],
+            eof: EofPolicy::default(),
+        }
+    }
+}
+
+impl TwisterBuilder {
+    pub fn new() -> TwisterBuilder {
+        TwisterBuilder::default()
+    }
+
+    /// Fails the `Twister` with [`LimitExceeded`] once more bytes
+    /// arrive than `limits` allow. Unlimited by default.
+    ///
+    /// [`LimitExceeded`]: struct.LimitExceeded.html
+    pub fn limits(mut self, limits: Limits) -> TwisterBuilder {
+        self.limits = limits;
+        self
+    }
 
-pub struct Twister<S, D>(Twist<S, D>)
-    where for <'a> &'a S: Read + Write,
-          for <'a> &'a D: Read + Write;
+    /// Sets the size, in bytes, of the buffer that each direction is
+    /// copied through. Defaults to 8KB. Larger buffers copy more at
+    /// once, and smaller ones let more connections be open at once.
+    /// A buffer isn't allocated until its direction is first read.
+    ///
+    /// # Panics
+    ///
+    /// If `size` is 0.
+    pub fn buffer_size(mut self, size: usize) -> TwisterBuilder {
+        assert!(size > 0, "A Twister's buffer needs at least one byte");
+        self.buffer_size = size;
+        self
+    }
+
+    /// Copies no faster than `rate` in `direction`, e.g. so that one
+    /// client can't use up all of a relay's bandwidth. A direction
+    /// that's waiting for its rate to allow more is woken once it
+    /// does. Unthrottled by default.
+    ///
+    /// # Panics
+    ///
+    /// If the rate, or its burst, is 0.
+    pub fn throttle(mut self, direction: Direction, rate: Rate) -> TwisterBuilder {
+        assert!(rate.bytes_per_second > 0 && rate.burst > 0,
+                "A Twister can't be throttled to nothing");
+        self.rates[direction.index()] = Some(rate);
+        self
+    }
+
+    /// Sets what happens when one direction ends. By default, the
+    /// other carries on.
+    pub fn on_eof(mut self, eof: EofPolicy) -> TwisterBuilder {
+        self.eof = eof;
+        self
+    }
+
+    pub fn build<S, D>(self, source: S, destination: D) -> Twister<S, D>
+        where S: Split,
+              D: Split,
+    {
+        let relays = (Relay::Buffer(vec![], self.buffer_size), Relay::Buffer(vec![], self.buffer_size));
+        Twister::with_relays(source, destination, relays, self)
+    }
+
+    /// Builds a `Twister` that splices bytes between the two sockets
+    /// through a pipe in each direction, so the buffer size is unused.
+    /// Both must be non-blocking.
+    #[cfg(all(target_os = "linux", feature = "splice"))]
+    pub fn build_spliced<S, D>(self, source: S, destination: D) -> io::Result<Twister<S, D>>
+        where S: Split,
+              D: Split,
+              S: AsRawFd,
+              D: AsRawFd,
+    {
+        let (from, to) = (source.as_raw_fd(), destination.as_raw_fd());
+        let relays = (Relay::Pipe(Pipe::new()?, from, to), Relay::Pipe(Pipe::new()?, to, from));
+        Ok(Twister::with_relays(source, destination, relays, self))
+    }
+}
+
+/// Copies bytes both ways between two streams, until each direction
+/// has ended. It resolves to what was copied from the source, and from
+/// the destination, and why each stopped.
+pub struct Twister<S, D>
+    where S: Split,
+          D: Split,
+{
+    there: Transfer<S::ReadHalf, D::WriteHalf>,
+    back: Transfer<D::ReadHalf, S::WriteHalf>,
+    /// How each direction ended, once it has.
+    ended: (Option<Transferred>, Option<Transferred>),
+    eof: EofPolicy,
+    stats: TransferStats,
+}
 
 impl<S, D> Twister<S, D>
-    where for <'a> &'a S: Read + Write,
-          for <'a> &'a D: Read + Write,
+    where S: Split,
+          D: Split,
 {
     pub fn new(source: S, destination: D) -> Twister<S, D> {
-        let source = Rc::new(source);
-        let destination = Rc::new(destination);
+        TwisterBuilder::new().build(source, destination)
+    }
+
+    /// Creates a `Twister` that fails with [`LimitExceeded`] once more
+    /// bytes arrive than `limits` allow, e.g. to meter a tunnel.
+    ///
+    /// [`LimitExceeded`]: struct.LimitExceeded.html
+    pub fn with_limits(source: S, destination: D, limits: Limits) -> Twister<S, D> {
+        TwisterBuilder::new().limits(limits).build(source, destination)
+    }
+
+    fn with_relays(source: S, destination: D, (there, back): (Relay, Relay),
+                   builder: TwisterBuilder) -> Twister<S, D>
+    {
+        let (source_read, source_write) = source.split();
+        let (destination_read, destination_write) = destination.split();
+        let total = Arc::new(AtomicUsize::new(0));
+        let stats = TransferStats::default();
+        let (limits, rates) = (builder.limits, builder.rates);
 
-        let inner = 
-            Transfer::new(source.clone(), destination.clone())
-                .join(Transfer::new(destination, source));
+        Twister {
+            there: Transfer::new(source_read, destination_write, there,
+                                 Direction::SourceToDestination, limits, total.clone(),
+                                 stats.clone())
+                .throttle(rates[Direction::SourceToDestination.index()]),
+            back: Transfer::new(destination_read, source_write, back,
+                                Direction::DestinationToSource, limits, total, stats.clone())
+                .throttle(rates[Direction::DestinationToSource.index()]),
+            ended: (None, None),
+            eof: builder.eof,
+            stats,
+        }
+    }
 
-        Twister(inner)
+    /// Creates a `Twister` that splices bytes between the two sockets
+    /// through a pipe in each direction, rather than copying them
+    /// through a buffer. Both must be non-blocking.
+    #[cfg(all(target_os = "linux", feature = "splice"))]
+    pub fn spliced(source: S, destination: D, limits: Limits) -> io::Result<Twister<S, D>>
+        where S: AsRawFd,
+              D: AsRawFd,
+    {
+        TwisterBuilder::new().limits(limits).build_spliced(source, destination)
+    }
+
+    /// The running counts of the bytes copied, which can be kept and
+    /// read while the `Twister` runs, and after it's finished.
+    pub fn stats(&self) -> TransferStats {
+        self.stats.clone()
     }
 }
 
 impl<S, D> Twister<S, D>
-    where for <'a> &'a S: Read + Write,
-          for <'a> &'a D: Read + Write,
-          S: Debug,
-          D: Debug,
+    where S: Split,
+          D: Split,
 {
     pub fn into_inner(self) -> (S, D) {
-        let (src_transfer, dest_transfer) = self.0.into_inner();
-        let (src, _) = src_transfer.into_inner();
-        let (dst, _) = dest_transfer.into_inner();
-        (Rc::try_unwrap(src).unwrap(), Rc::try_unwrap(dst).unwrap())
+        let (source_read, destination_write) = self.there.into_inner();
+        let (destination_read, source_write) = self.back.into_inner();
+        (S::unsplit(source_read, source_write), D::unsplit(destination_read, destination_write))
     }
 }
 
 impl<S, D> Pollable for Twister<S, D>
-    where for <'a> &'a S: Read + Write,
-          for <'a> &'a D: Read + Write,
+    where S: Split,
+          D: Split,
 {
-    type Item = (usize, usize);
+    type Item = (Transferred, Transferred);
     type Error = io::Error;
 
     fn poll(&mut self) -> Result<PollResult<Self::Item>, Self::Error> {
-        self.0.poll()
+        if self.ended.0.is_none() {
+            if let PollResult::Ready(there) = self.there.poll()? {
+                self.ended.0 = Some(there);
+            }
+        }
+
+        if self.ended.1.is_none() {
+            if let PollResult::Ready(back) = self.back.poll()? {
+                self.ended.1 = Some(back);
+            }
+        }
+
+        if self.eof == EofPolicy::CloseBoth {
+            match self.ended {
+                (Some(_), None) => self.ended.1 = Some(self.back.abandon()),
+                (None, Some(_)) => self.ended.0 = Some(self.there.abandon()),
+                _ => {},
+            }
+        }
+
+        match self.ended {
+            (Some(there), Some(back)) => Ok(PollResult::Ready((there, back))),
+            _ => Ok(PollResult::NotReady),
+        }
     }
 }
 
 #[cfg(test)]
 mod twister_should {
     use super::*;
-    use std::cell::{Ref, RefCell};
     use std::io::{self, Cursor, Read, Write};
 
     // This type wraps a `Read` type and simulates
@@ -146,9 +668,9 @@ mod twister_should {
     impl<R> Trickle<R> {
         fn new(inner: R, ready_every: usize) -> Trickle<R> {
             Trickle {
-                inner: inner,
+                inner,
                 call_count: 0,
-                ready_every: ready_every,
+                ready_every,
             }
         }
     }
@@ -156,7 +678,7 @@ mod twister_should {
     impl<R: Read> Read for Trickle<R> {
         fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
             self.call_count += 1;
-            if 0 == ((self.call_count - 1) % self.ready_every) {
+            if (self.call_count - 1).is_multiple_of(self.ready_every) {
                 self.inner.read(&mut buffer[..1])
             }
             else {
@@ -165,45 +687,73 @@ mod twister_should {
         }
     }
 
-    // `Half` must wrap its `Read` and `Write` implementations
-    // in `RefCell`s so that we can read and write to it
-    // using a shared alias (I.E. `&self`, instead of `&mut self`).
-    // This is a requirement of `Twister`
+    // `Half` is split into what it has to send, and what it's
+    // been sent.
     #[derive(Debug)]
     struct Half {
-        output: RefCell<Trickle<Cursor<Vec<u8>>>>,
-        input: RefCell<Cursor<Vec<u8>>>,
+        output: Trickle<Cursor<Vec<u8>>>,
+        input: Written,
+    }
+
+    #[derive(Debug)]
+    struct Written {
+        buffer: Cursor<Vec<u8>>,
+        closed: bool,
     }
 
     impl Half {
         fn new(initial_content: &[u8], ready_every: usize) -> Half {
             Half {
-                output: RefCell::new(Trickle::new(Cursor::new(initial_content.to_vec()), ready_every)),
-                input: RefCell::new(Cursor::new(vec![])),
+                output: Trickle::new(Cursor::new(initial_content.to_vec()), ready_every),
+                input: Written {
+                    buffer: Cursor::new(vec![]),
+                    closed: false,
+                },
             }
         }
 
-        fn write_buffer(&self) -> Ref<Cursor<Vec<u8>>> {
-            self.input.borrow()
+        fn write_buffer(&self) -> &Cursor<Vec<u8>> {
+            &self.input.buffer
         }
     }
 
-    impl<'a> Read for &'a Half {
-        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
-            self.output.borrow_mut().read(buffer)
+    impl Split for Half {
+        type ReadHalf = Trickle<Cursor<Vec<u8>>>;
+        type WriteHalf = Written;
+
+        fn split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+            (self.output, self.input)
+        }
+
+        fn unsplit(output: Self::ReadHalf, input: Self::WriteHalf) -> Half {
+            Half {
+                output,
+                input,
+            }
         }
     }
 
-    impl<'a> Write for &'a Half {
+    impl Write for Written {
         fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
-            self.input.borrow_mut().write(buffer)
+            self.buffer.write(buffer)
         }
 
         fn flush(&mut self) -> io::Result<()> {
-            self.input.borrow_mut().flush()
+            self.buffer.flush()
+        }
+    }
+
+    impl Shutdown for Written {
+        fn shutdown(&mut self, _: net::Shutdown) -> io::Result<()> {
+            self.closed = true;
+            Ok(())
         }
     }
 
+    fn bytes((there, back): (Transferred, Transferred)) -> (usize, usize) {
+        (there.bytes, back.bytes)
+    }
+
     #[test]
     fn copy_both_halves() {
         let first_content = b"Hello, from first half";
@@ -222,8 +772,9 @@ mod twister_should {
 
         assert_eq!(
             (first_content.len(), second_content.len()),
-            value
+            bytes(value)
         );
+        assert_eq!((End::SourceEof, End::SourceEof), (value.0.end, value.1.end));
 
         assert_eq!(
             b"Hello, from second half",
@@ -235,5 +786,365 @@ mod twister_should {
             &(*second_half.write_buffer()).get_ref()[..]
         );
     }
+
+    #[test]
+    fn close_the_other_end_once_one_finishes_sending() {
+        // The destination has more to send long after the source ends.
+        let mut twister = Twister::new(Half::new(b"Hi", 1), Half::new(b"Hello", 100));
+        for _ in 0..10 {
+            assert_eq!(PollResult::NotReady, twister.poll().unwrap());
+        }
+
+        let (first_half, second_half) = twister.into_inner();
+        assert!(second_half.input.closed);
+        assert!(!first_half.input.closed);
+    }
+
+    #[test]
+    fn stop_once_the_budget_is_spent() {
+        use budget::with_budget;
+
+        let mut twister = Twister::new(Half::new(b"Hello", 1), Half::new(b"", 1));
+        let (result, exhausted) = with_budget(6, || twister.poll().unwrap());
+        assert_eq!(PollResult::NotReady, result);
+        assert!(exhausted);
+
+        // A read and a write for each byte.
+        let (first_half, second_half) = twister.into_inner();
+        assert_eq!(b"Hel", &(*second_half.write_buffer()).get_ref()[..]);
+        assert!(first_half.write_buffer().get_ref().is_empty());
+    }
+
+    /// Polls `twister` until it fails, returning the direction whose
+    /// limit was exceeded.
+    fn exceeded<S, D>(twister: &mut Twister<S, D>) -> Direction
+        where S: Split,
+              D: Split,
+    {
+        loop {
+            match twister.poll() {
+                Ok(PollResult::Ready(_)) => panic!("The transfer finished"),
+                Ok(PollResult::NotReady) => continue,
+                Err(e) => return e.get_ref()
+                    .and_then(|e| e.downcast_ref::<LimitExceeded>())
+                    .expect("Not a LimitExceeded")
+                    .direction,
+            }
+        }
+    }
+
+    #[test]
+    fn stop_once_a_direction_exceeds_its_limit() {
+        let limits = Limits {
+            destination_to_source: Some(3),
+            ..Limits::default()
+        };
+        let mut twister = Twister::with_limits(Half::new(b"Hi", 1), Half::new(b"Hello", 1), limits);
+        assert_eq!(Direction::DestinationToSource, exceeded(&mut twister));
+
+        let (first_half, _) = twister.into_inner();
+        assert_eq!(b"Hel", &(*first_half.write_buffer()).get_ref()[..]);
+    }
+
+    #[test]
+    fn stop_once_both_directions_exceed_the_total() {
+        let limits = Limits {
+            total: Some(4),
+            ..Limits::default()
+        };
+        let mut twister = Twister::with_limits(Half::new(b"Hello", 1), Half::new(b"Hi", 1), limits);
+        exceeded(&mut twister);
+
+        let (first_half, second_half) = twister.into_inner();
+        let copied = first_half.write_buffer().get_ref().len()
+            + second_half.write_buffer().get_ref().len();
+        assert_eq!(4, copied);
+    }
+
+    #[test]
+    fn copy_up_to_its_limits() {
+        let limits = Limits {
+            source_to_destination: Some(5),
+            total: Some(7),
+            ..Limits::default()
+        };
+        let mut twister = Twister::with_limits(Half::new(b"Hello", 1), Half::new(b"Hi", 1), limits);
+        let value = loop {
+            if let PollResult::Ready(v) = twister.poll().unwrap() {
+                break v;
+            }
+        };
+        assert_eq!((5, 2), bytes(value));
+    }
+
+    #[test]
+    fn count_the_bytes_copied_as_it_goes() {
+        let mut twister = Twister::new(Half::new(b"Hello", 1), Half::new(b"Hi", 1));
+        let stats = twister.stats();
+
+        // A read and a write for each byte, from the source first.
+        let (_, exhausted) = ::budget::with_budget(4, || twister.poll().unwrap());
+        assert!(exhausted);
+        assert_eq!(2, stats.source_to_destination());
+        assert_eq!(0, stats.destination_to_source());
+
+        while let PollResult::NotReady = twister.poll().unwrap() {}
+        assert_eq!(5, stats.source_to_destination());
+        assert_eq!(2, stats.copied(Direction::DestinationToSource));
+    }
+
+    /// Keeps the size of each buffer it's asked to read into, and
+    /// discards what's written to it.
+    struct Measured {
+        sizes: Vec<usize>,
+        content: Cursor<Vec<u8>>,
+    }
+
+    impl Read for Measured {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.sizes.push(buffer.len());
+            self.content.read(buffer)
+        }
+    }
+
+    struct Discard;
+
+    impl Write for Discard {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shutdown for Discard {
+        fn shutdown(&mut self, _: net::Shutdown) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Split for Measured {
+        type ReadHalf = Measured;
+        type WriteHalf = Discard;
+
+        fn split(self) -> (Measured, Discard) {
+            (self, Discard)
+        }
+
+        fn unsplit(read: Measured, _: Discard) -> Measured {
+            read
+        }
+    }
+
+    #[test]
+    fn copy_through_a_buffer_of_the_size_configured() {
+        let source = Measured {
+            sizes: vec![],
+            content: Cursor::new(b"Hello".to_vec()),
+        };
+        let destination = Measured {
+            sizes: vec![],
+            content: Cursor::new(vec![]),
+        };
+        let mut twister = TwisterBuilder::new().buffer_size(2).build(source, destination);
+        let value = loop {
+            if let PollResult::Ready(v) = twister.poll().unwrap() {
+                break v;
+            }
+        };
+        assert_eq!((5, 0), bytes(value));
+
+        let (source, destination) = twister.into_inner();
+        assert_eq!(vec![2, 2, 2, 2], source.sizes);
+        assert_eq!(vec![2], destination.sizes);
+    }
+
+    #[test]
+    fn copy_no_faster_than_its_rate() {
+        use std::thread::sleep;
+
+        // A byte every 10ms, after the first two.
+        let rate = Rate {
+            bytes_per_second: 100,
+            burst: 2,
+        };
+        let mut twister = TwisterBuilder::new()
+            .throttle(Direction::SourceToDestination, rate)
+            .build(Half::new(b"Hello", 1), Half::new(b"Hi", 1));
+        let stats = twister.stats();
+        let start = Instant::now();
+
+        for _ in 0..10 {
+            assert_eq!(PollResult::NotReady, twister.poll().unwrap());
+        }
+        assert_eq!(2, stats.source_to_destination());
+        assert_eq!(2, stats.destination_to_source());
+
+        while let PollResult::NotReady = twister.poll().unwrap() {
+            sleep(Duration::from_millis(1));
+        }
+        assert_eq!(5, stats.source_to_destination());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn refill_its_rate_up_to_the_burst() {
+        let rate = Rate {
+            bytes_per_second: 1000,
+            burst: 5,
+        };
+        let now = Instant::now();
+        let mut bucket = Bucket::new(rate);
+        bucket.filled = now;
+        bucket.take(5);
+        assert_eq!(0, bucket.fill(now + Duration::from_micros(999)));
+        assert_eq!(2, bucket.fill(now + Duration::from_micros(2500)));
+
+        // The half a millisecond left over counts towards the next.
+        assert_eq!(3, bucket.fill(now + Duration::from_micros(3000)));
+        assert_eq!(5, bucket.fill(now + Duration::from_secs(60)));
+    }
+
+    /// A pair of connected sockets, the first of them non-blocking.
+    fn pair() -> (net::TcpStream, net::TcpStream) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_nonblocking(true).unwrap();
+        (server, client)
+    }
+
+    #[test]
+    fn relay_between_sockets_on_another_thread() {
+        let (source, mut client) = pair();
+        let (destination, mut upstream) = pair();
+        let mut twister = Twister::new(source, destination);
+        let relaying = ::std::thread::spawn(move || loop {
+            if let PollResult::Ready(v) = twister.poll().unwrap() {
+                break v;
+            }
+        });
+
+        client.write_all(b"Hello").unwrap();
+        client.shutdown(net::Shutdown::Write).unwrap();
+        let mut hello = vec![];
+        upstream.read_to_end(&mut hello).unwrap();
+        upstream.write_all(b"Hi").unwrap();
+        upstream.shutdown(net::Shutdown::Write).unwrap();
+        let mut hi = vec![];
+        client.read_to_end(&mut hi).unwrap();
+
+        assert_eq!(b"Hello", &hello[..]);
+        assert_eq!(b"Hi", &hi[..]);
+        assert_eq!((5, 2), bytes(relaying.join().unwrap()));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "splice"))]
+    #[test]
+    fn splice_between_sockets() {
+        let (source, mut client) = pair();
+        let (destination, mut upstream) = pair();
+        let limits = Limits {
+            destination_to_source: Some(2),
+            ..Limits::default()
+        };
+        let mut twister = Twister::spliced(source, destination, limits).unwrap();
+
+        client.write_all(b"Hello").unwrap();
+        client.shutdown(net::Shutdown::Write).unwrap();
+        upstream.write_all(b"Hi").unwrap();
+        upstream.shutdown(net::Shutdown::Write).unwrap();
+        let value = loop {
+            if let PollResult::Ready(v) = twister.poll().unwrap() {
+                break v;
+            }
+        };
+        assert_eq!((5, 2), bytes(value));
+
+        // Each peer's FIN is passed on, so both ends are read to the
+        // end while the sockets are still open.
+        let (mut hello, mut hi) = (vec![], vec![]);
+        upstream.read_to_end(&mut hello).unwrap();
+        client.read_to_end(&mut hi).unwrap();
+        assert_eq!(b"Hello", &hello[..]);
+        assert_eq!(b"Hi", &hi[..]);
+        drop(twister);
+    }
+
+    #[test]
+    fn close_both_directions_once_either_ends_if_asked_to() {
+        let source = Half::new(b"Hi", 1);
+        let destination = Half::new(b"Hello", 100);
+        let mut twister = TwisterBuilder::new()
+            .on_eof(EofPolicy::CloseBoth)
+            .build(source, destination);
+        let (there, back) = loop {
+            if let PollResult::Ready(v) = twister.poll().unwrap() {
+                break v;
+            }
+        };
+        assert_eq!(Transferred { bytes: 2, end: End::SourceEof }, there);
+        assert_eq!(Transferred { bytes: 1, end: End::Abandoned }, back);
+
+        // The source is told nothing more is coming from the
+        // destination, even though the destination hadn't finished.
+        let (source, destination) = twister.into_inner();
+        assert!(source.input.closed);
+        assert!(destination.input.closed);
+        assert_eq!(b"H", &source.write_buffer().get_ref()[..]);
+    }
+
+    /// A peer that has gone: it has nothing to send, and writing to it
+    /// fails.
+    struct Gone;
+
+    impl Read for Gone {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Write for Gone {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shutdown for Gone {
+        fn shutdown(&mut self, _: net::Shutdown) -> io::Result<()> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    impl Split for Gone {
+        type ReadHalf = Gone;
+        type WriteHalf = Gone;
+
+        fn split(self) -> (Gone, Gone) {
+            (Gone, Gone)
+        }
+
+        fn unsplit(_: Gone, _: Gone) -> Gone {
+            Gone
+        }
+    }
+
+    #[test]
+    fn tell_a_closed_destination_from_the_end_of_the_source() {
+        let mut twister = Twister::new(Half::new(b"Hello", 1), Gone);
+        let (there, back) = loop {
+            if let PollResult::Ready(v) = twister.poll().unwrap() {
+                break v;
+            }
+        };
+        assert_eq!(Transferred { bytes: 0, end: End::DestinationClosed }, there);
+        assert_eq!(Transferred { bytes: 0, end: End::SourceEof }, back);
+    }
 }
 
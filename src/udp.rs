@@ -0,0 +1,351 @@
+//! Servers for datagram protocols, like DNS or metrics ingestion.
+//!
+//! A [`UdpServer`] decodes each packet it receives with a
+//! [`DatagramCodec`], and passes it to the handler along with the
+//! address of the peer that sent it. The handler's response, if any,
+//! is encoded and sent to the address it's paired with.
+//!
+//! [`UdpServer`]: struct.UdpServer.html
+//! [`DatagramCodec`]: trait.DatagramCodec.html
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{self, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mio::{Events, Interest, Poll, Token};
+
+use handler::Handler;
+use pollable::{IntoPollable, Pollable};
+use result::PollResult;
+
+/// Converts between packets and the items a handler works with. Each
+/// packet holds exactly one item.
+pub trait DatagramCodec {
+    type In;
+    type Out;
+
+    /// Decodes a packet. Packets that fail to decode are dropped.
+    fn decode(&self, packet: &[u8]) -> io::Result<Self::In>;
+
+    /// Encodes `item` into `buffer`, which is empty. Items that fail to
+    /// encode aren't sent.
+    fn encode(&self, item: Self::Out, buffer: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// A server that receives packets on a UDP socket.
+///
+/// Handlers take the address of the peer with each decoded packet,
+/// and respond with `Some((address, item))` to send a packet back, or
+/// `None` to send nothing.
+pub struct UdpServer<C> {
+    codec: Arc<C>,
+    threads: usize,
+    max_datagram_size: usize,
+}
+
+impl<C> UdpServer<C> where
+    C: DatagramCodec + Send + Sync + 'static,
+{
+    /// Creates a server with one thread, that accepts packets of any
+    /// size.
+    pub fn new(codec: C) -> UdpServer<C> {
+        UdpServer::builder(codec).build()
+    }
+
+    pub fn builder(codec: C) -> UdpServerBuilder<C> {
+        UdpServerBuilder {
+            codec,
+            threads: 1,
+            max_datagram_size: 65535,
+        }
+    }
+
+    pub fn serve<S, F, H>(self, s: S, f: F) -> io::Result<()> where
+        S: ToSocketAddrs,
+        F: FnOnce() -> H,
+        H: Handler<Request=(SocketAddr, C::In), Response=Option<(SocketAddr, C::Out)>>,
+        H: Send + Sync + 'static,
+    {
+        self.serve_on(net::UdpSocket::bind(s)?, Arc::new(f()))
+    }
+
+    fn serve_on<H>(&self, socket: net::UdpSocket, handler: Arc<H>) -> io::Result<()> where
+        H: Handler<Request=(SocketAddr, C::In), Response=Option<(SocketAddr, C::Out)>>,
+        H: Send + Sync + 'static,
+    {
+        socket.set_nonblocking(true)?;
+
+        let mut threads = Vec::with_capacity(self.threads);
+        for _ in 0..self.threads {
+            let socket = socket.try_clone()?;
+            let codec = self.codec.clone();
+            let handler = handler.clone();
+            let max_datagram_size = self.max_datagram_size;
+
+            threads.push(thread::spawn(move || Worker {
+                codec,
+                handler,
+                socket: mio::net::UdpSocket::from_std(socket),
+                buffer: vec![0; max_datagram_size],
+                pending: vec![],
+                outgoing: VecDeque::new(),
+            }.run()));
+        }
+
+        for t in threads {
+            t.join().expect("A datagram thread panicked")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Configures a [`UdpServer`].
+///
+/// [`UdpServer`]: struct.UdpServer.html
+pub struct UdpServerBuilder<C> {
+    codec: C,
+    threads: usize,
+    max_datagram_size: usize,
+}
+
+impl<C> UdpServerBuilder<C> where
+    C: DatagramCodec + Send + Sync + 'static,
+{
+    /// Sets how many threads receive and handle packets. Defaults to
+    /// 1.
+    ///
+    /// # Panics
+    ///
+    /// If `threads` is 0.
+    pub fn threads(mut self, threads: usize) -> UdpServerBuilder<C> {
+        assert!(threads > 0, "A server needs at least one thread");
+        self.threads = threads;
+        self
+    }
+
+    /// Sets the size, in bytes, of the largest packet that's received
+    /// whole. Longer packets are truncated. Defaults to 65535.
+    pub fn max_datagram_size(mut self, size: usize) -> UdpServerBuilder<C> {
+        self.max_datagram_size = size;
+        self
+    }
+
+    pub fn build(self) -> UdpServer<C> {
+        UdpServer {
+            codec: Arc::new(self.codec),
+            threads: self.threads,
+            max_datagram_size: self.max_datagram_size,
+        }
+    }
+}
+
+const SOCKET: Token = Token(0);
+
+/// Receives packets on one thread, and polls their handlers. The thread
+/// sleeps until the socket is ready, unless a handler hasn't finished.
+struct Worker<C, H> where
+    C: DatagramCodec,
+    H: Handler<Request=(SocketAddr, C::In), Response=Option<(SocketAddr, C::Out)>>,
+{
+    codec: Arc<C>,
+    handler: Arc<H>,
+    socket: mio::net::UdpSocket,
+    buffer: Vec<u8>,
+    pending: Vec<<H::Pollable as IntoPollable>::Pollable>,
+    /// Encoded responses waiting for the socket to be writable.
+    outgoing: VecDeque<(SocketAddr, Vec<u8>)>,
+}
+
+impl<C, H> Worker<C, H> where
+    C: DatagramCodec,
+    H: Handler<Request=(SocketAddr, C::In), Response=Option<(SocketAddr, C::Out)>>,
+{
+    fn run(mut self) -> io::Result<()> {
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(16);
+        poll.registry().register(&mut self.socket, SOCKET,
+                                 Interest::READABLE | Interest::WRITABLE)?;
+
+        loop {
+            let timeout = match self.pending.is_empty() {
+                true => None,
+                false => Some(Duration::from_secs(0)),
+            };
+
+            if let Err(e) = poll.poll(&mut events, timeout) {
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+
+            // Readiness is edge-triggered, so the socket is drained
+            // every time.
+            self.receive()?;
+            self.handle();
+            self.send()?;
+        }
+    }
+
+    fn receive(&mut self) -> io::Result<()> {
+        loop {
+            let (read, peer) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(ref e) if is_transient(e) => continue,
+                Err(e) => return Err(e),
+            };
+
+            if let Ok(item) = self.codec.decode(&self.buffer[..read]) {
+                self.pending.push(self.handler.handle((peer, item)).into_pollable());
+            }
+        }
+    }
+
+    fn handle(&mut self) {
+        let mut index = 0;
+        while index < self.pending.len() {
+            let response = match self.pending[index].poll() {
+                Ok(PollResult::NotReady) => {
+                    index += 1;
+                    continue;
+                },
+                Ok(PollResult::Ready(response)) => response,
+                Err(_) => None,
+            };
+
+            self.pending.swap_remove(index);
+            if let Some((peer, item)) = response {
+                let mut packet = vec![];
+                if self.codec.encode(item, &mut packet).is_ok() {
+                    self.outgoing.push_back((peer, packet));
+                }
+            }
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        while let Some((peer, packet)) = self.outgoing.pop_front() {
+            match self.socket.send_to(&packet, peer) {
+                Ok(_) => {},
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.outgoing.push_front((peer, packet));
+                    return Ok(());
+                },
+                // A packet that can't be sent to its peer is dropped,
+                // as it would be by the network.
+                Err(ref e) if is_transient(e) => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `e` concerns a single packet or peer, rather than the
+/// socket. E.g. an ICMP "port unreachable" for an earlier packet is
+/// reported by some platforms as `ConnectionReset`.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(e.kind(),
+             io::ErrorKind::ConnectionReset |
+             io::ErrorKind::ConnectionRefused |
+             io::ErrorKind::Interrupted |
+             io::ErrorKind::InvalidInput |
+             io::ErrorKind::PermissionDenied)
+}
+
+#[cfg(test)]
+mod udp_server_should {
+    use super::*;
+    use std::result;
+
+    struct Upper;
+
+    impl DatagramCodec for Upper {
+        type In = String;
+        type Out = String;
+
+        fn decode(&self, packet: &[u8]) -> io::Result<String> {
+            String::from_utf8(packet.to_vec())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+
+        fn encode(&self, item: String, buffer: &mut Vec<u8>) -> io::Result<()> {
+            buffer.extend(item.to_uppercase().into_bytes());
+            Ok(())
+        }
+    }
+
+    /// Echoes each packet back to its sender, except `"quiet"`.
+    struct Echo;
+
+    impl Handler for Echo {
+        type Request = (SocketAddr, String);
+        type Response = Option<(SocketAddr, String)>;
+        type Error = io::Error;
+        type Pollable = result::Result<Self::Response, io::Error>;
+
+        fn handle(&self, (peer, item): Self::Request) -> Self::Pollable {
+            match item.as_str() {
+                "quiet" => Ok(None),
+                _ => Ok(Some((peer, item))),
+            }
+        }
+    }
+
+    fn start(server: UdpServer<Upper>) -> SocketAddr {
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || server.serve_on(socket, Arc::new(Echo)));
+        addr
+    }
+
+    fn client() -> net::UdpSocket {
+        let client = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client
+    }
+
+    #[test]
+    fn respond_to_each_packet() {
+        let addr = start(UdpServer::builder(Upper).threads(2).build());
+        let client = client();
+
+        let mut buffer = [0; 16];
+        for word in &["hello", "world"] {
+            client.send_to(word.as_bytes(), addr).unwrap();
+            let (read, from) = client.recv_from(&mut buffer).unwrap();
+            assert_eq!(word.to_uppercase().as_bytes(), &buffer[..read]);
+            assert_eq!(addr, from);
+        }
+    }
+
+    #[test]
+    fn drop_packets_that_fail_to_decode_or_need_no_response() {
+        let addr = start(UdpServer::new(Upper));
+        let client = client();
+
+        client.send_to(&[0xff, 0xfe], addr).unwrap();
+        client.send_to(b"quiet", addr).unwrap();
+        client.send_to(b"loud", addr).unwrap();
+
+        let mut buffer = [0; 16];
+        let (read, _) = client.recv_from(&mut buffer).unwrap();
+        assert_eq!(b"LOUD", &buffer[..read]);
+    }
+
+    #[test]
+    fn truncate_long_packets() {
+        let addr = start(UdpServer::builder(Upper).max_datagram_size(4).build());
+        let client = client();
+
+        client.send_to(b"truncated", addr).unwrap();
+        let mut buffer = [0; 16];
+        let (read, _) = client.recv_from(&mut buffer).unwrap();
+        assert_eq!(b"TRUN", &buffer[..read]);
+    }
+}
@@ -0,0 +1,151 @@
+//! Handing a connection over to another protocol.
+//!
+//! A transport can give up its connection once a response is written,
+//! e.g. for a WebSocket session, or a tunnel. `Sink::upgrade` returns
+//! the pollable that serves the connection from then on, if the
+//! responses written asked for one. Transports that upgrade usually
+//! pass the pollable an [`Upgraded`] stream.
+//!
+//! [`Upgraded`]: struct.Upgraded.html
+
+use std::any::Any;
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use framed::Framed;
+use pollable::Pollable;
+use readiness;
+
+/// What serves a connection once it's been upgraded. It resolves when
+/// the connection is finished with.
+pub type Upgrading<E> = Box<dyn Pollable<Item=(), Error=E>>;
+
+/// A stream that can be read and written.
+pub trait Io: Read + Write + Any {}
+
+impl<T: Read + Write + Any> Io for T {}
+
+/// The stream of an upgraded connection. Data the transport had read,
+/// but not decoded, is read before anything more from the stream.
+/// Writes that would block wait for the connection's socket. See
+/// `readiness::socket_blocked`.
+pub struct Upgraded {
+    io: Box<dyn Io>,
+    buffered: Vec<u8>,
+}
+
+impl Upgraded {
+    pub fn new<S>(io: S, buffered: Vec<u8>) -> Upgraded where
+        S: Read + Write + 'static
+    {
+        Upgraded {
+            io: Box::new(io),
+            buffered,
+        }
+    }
+
+    /// The data that's still to be read from before the stream.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffered
+    }
+
+    /// Takes back the stream, if it's an `S`, along with the data
+    /// that's still to be read from before it. E.g. to split a TCP
+    /// stream into halves.
+    pub fn downcast<S: Io>(self) -> Result<(S, Vec<u8>), Upgraded> {
+        if !(&*self.io as &dyn Any).is::<S>() {
+            return Err(self);
+        }
+
+        let io: Box<dyn Any> = self.io;
+        match io.downcast::<S>() {
+            Ok(io) => Ok((*io, self.buffered)),
+            Err(_) => unreachable!("The stream is an S"),
+        }
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered.is_empty() {
+            return self.io.read(buf);
+        }
+
+        let n = cmp::min(buf.len(), self.buffered.len());
+        buf[..n].copy_from_slice(&self.buffered[..n]);
+        self.buffered.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        blocking(self.io.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        blocking(self.io.flush())
+    }
+}
+
+/// Notes that the connection's socket is blocked, if `result` is
+/// `WouldBlock`.
+fn blocking<T>(result: io::Result<T>) -> io::Result<T> {
+    if let Err(ref e) = result {
+        if e.kind() == io::ErrorKind::WouldBlock {
+            readiness::socket_blocked();
+        }
+    }
+    result
+}
+
+/// A transport that can give up its stream to be upgraded.
+pub trait IntoUpgraded {
+    fn into_upgraded(self) -> Upgraded;
+}
+
+impl<S, D> IntoUpgraded for Framed<S, D> where
+    S: Read + Write + 'static
+{
+    fn into_upgraded(self) -> Upgraded {
+        let (stream, buffered) = self.into_parts();
+        Upgraded::new(stream, buffered)
+    }
+}
+
+#[cfg(test)]
+mod upgraded_should {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_buffered_data_first() {
+        let mut upgraded = Upgraded::new(Cursor::new(b" world".to_vec()), b"Hello".to_vec());
+
+        let mut buf = [0; 3];
+        assert_eq!(3, upgraded.read(&mut buf).unwrap());
+        assert_eq!(b"Hel", &buf);
+        assert_eq!(b"lo", upgraded.buffered());
+
+        let mut rest = String::new();
+        upgraded.read_to_string(&mut rest).unwrap();
+        assert_eq!("lo world", rest);
+    }
+
+    #[test]
+    fn give_back_its_stream_as_the_type_it_was() {
+        let upgraded = Upgraded::new(Cursor::new(b"world".to_vec()), b"Hello".to_vec());
+        let upgraded = match upgraded.downcast::<::std::net::TcpStream>() {
+            Ok(_) => panic!("The stream isn't a TcpStream"),
+            Err(upgraded) => upgraded,
+        };
+
+        match upgraded.downcast::<Cursor<Vec<u8>>>() {
+            Ok((io, buffered)) => {
+                assert_eq!(b"world", &io.get_ref()[..]);
+                assert_eq!(b"Hello", &buffered[..]);
+            },
+            Err(_) => panic!("The stream is a Cursor<Vec<u8>>"),
+        }
+    }
+}